@@ -0,0 +1,50 @@
+//! Cost of requests for new keys when the rate limiter is [full](axum_gcra::gcra::RateLimiter::with_max_entries),
+//! for each [`OverflowPolicy`], which should stay flat regardless of the number of entries.
+//!
+//! Run with `cargo bench --bench insert`.
+
+use std::{
+    collections::hash_map::RandomState,
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use axum_gcra::gcra::{OverflowPolicy, Quota, RateLimiter};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+fn new_keys(c: &mut Criterion) {
+    let quota = Quota::simple(Duration::from_secs(3600));
+    let mut group = c.benchmark_group("new_key_on_full_shard");
+
+    for entries in [10_000, 100_000] {
+        for policy in [
+            OverflowPolicy::EvictOldest,
+            OverflowPolicy::RejectNew,
+            OverflowPolicy::FailOpen,
+        ] {
+            let limiter = RateLimiter::<u64>::with_shards(u64::MAX, 1, RandomState::new())
+                .with_max_entries(entries)
+                .with_overflow_policy(policy);
+
+            let now = Instant::now();
+
+            for key in 0..entries as u64 {
+                _ = limiter.req_sync(key, quota, now);
+            }
+
+            let mut key = entries as u64;
+
+            group.bench_function(BenchmarkId::new(format!("{policy:?}"), entries), |b| {
+                b.iter(|| {
+                    key += 1;
+                    black_box(limiter.req_sync(key, quota, now))
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, new_keys);
+criterion_main!(benches);
@@ -0,0 +1,48 @@
+//! Per-request overhead of [`RateLimitService::call`](axum_gcra::RateLimitService) for allowed requests,
+//! comparing built-in keys, which are decided without allocating, to other extractors, whose
+//! decision is made in a boxed future.
+//!
+//! Run with `cargo bench --bench service`.
+
+use std::{convert::Infallible, hint::black_box, num::NonZeroU64, time::Duration};
+
+use axum_gcra::{axum, gcra::Quota, real_ip::RealIp, Key, RateLimitLayer};
+use criterion::{criterion_group, criterion_main, Criterion};
+use http::{Method, Request, Response};
+use tower::{service_fn, Layer, Service};
+
+use axum::{body::Body, extract::FromRequestParts};
+
+/// Quota that never rejects, so that every request takes the allowed path.
+const UNLIMITED: Quota = Quota::new(Duration::from_nanos(1), NonZeroU64::MAX);
+
+fn request() -> Request<Body> {
+    Request::get("/").header("x-real-ip", "203.0.113.7").body(Body::empty()).unwrap()
+}
+
+fn bench_key<K: Key + FromRequestParts<()>>(c: &mut Criterion, name: &str) {
+    let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    let _guard = rt.enter();
+
+    let inner = service_fn(|_: Request<Body>| async { Ok::<_, Infallible>(Response::new(Body::empty())) });
+    let mut service = RateLimitLayer::<K>::builder().with_default_quota(UNLIMITED).build().layer(inner);
+
+    c.bench_function(name, |b| {
+        b.iter(|| match rt.block_on(service.call(request())) {
+            Ok(res) => black_box(res),
+            Err(_) => unreachable!("request was rate limited"),
+        })
+    });
+}
+
+fn call(c: &mut Criterion) {
+    // built-in keys, decided synchronously in `call`
+    bench_key::<()>(c, "call/unboxed/unit");
+    bench_key::<RealIp>(c, "call/unboxed/real_ip");
+
+    // any other extractor, decided in a boxed future, with the same trivial extraction as `()`
+    bench_key::<Method>(c, "call/boxed/method");
+}
+
+criterion_group!(benches, call);
+criterion_main!(benches);
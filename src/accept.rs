@@ -0,0 +1,182 @@
+//! Connection-level rate limiting at TCP-accept time, enabled with the `accept` cargo feature.
+//!
+//! [`AcceptLimiter`] applies a per-IP [`Quota`] to new connections, before any TLS handshake or HTTP parsing,
+//! so handshake floods can be shed cheaply, while the [`RateLimitLayer`](crate::RateLimitLayer) still enforces
+//! finer-grained limits on the requests of accepted connections. Rejected connections are closed immediately.
+//!
+//! [`RateLimitedListener`] wraps a [`TcpListener`] with an accept limiter, and with the `axum-08` feature
+//! implements `axum::serve::Listener` to be used directly with `axum::serve`. Other servers, such as custom
+//! `hyper` accept loops or TLS acceptors, can call [`AcceptLimiter::check`] on each accepted peer address.
+//!
+//! Note that the peer address is that of the immediate client, so this should not be used behind a proxy or
+//! load balancer that does not preserve client addresses, as all connections would share the same quota.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! # use axum_gcra::axum;
+//! use std::time::Duration;
+//! use axum_gcra::{accept::RateLimitedListener, gcra::Quota};
+//!
+//! # async fn example() -> std::io::Result<()> {
+//! // at most 10 new connections per second per IP, with bursts of up to 20
+//! let quota = Quota::new(Duration::from_millis(100), 20.try_into().unwrap());
+//!
+//! let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
+//! let listener = RateLimitedListener::new(listener, quota);
+//!
+//! loop {
+//!     // only returns connections within the quota
+//!     let (stream, addr) = listener.accept().await?;
+//!
+//!     // ... serve the connection with hyper, or use `axum::serve(listener, app)` with axum 0.8
+//!     # drop((stream, addr));
+//! }
+//! # }
+//! ```
+
+use std::{
+    fmt, io,
+    net::{IpAddr, SocketAddr},
+};
+
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::gcra::{KeyedRateLimiter, Quota, RateLimitError};
+
+/// Per-IP rate limiter for new connections, see the [module documentation](self).
+pub struct AcceptLimiter {
+    limiter: KeyedRateLimiter<IpAddr>,
+}
+
+impl fmt::Debug for AcceptLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AcceptLimiter").field("quota", &self.quota()).finish_non_exhaustive()
+    }
+}
+
+impl AcceptLimiter {
+    /// Create a new accept limiter with the given per-IP quota for new connections.
+    #[must_use]
+    pub fn new(quota: Quota) -> Self {
+        AcceptLimiter {
+            limiter: KeyedRateLimiter::new(quota),
+        }
+    }
+
+    /// Get the per-IP quota for new connections.
+    #[must_use]
+    pub fn quota(&self) -> Quota {
+        self.limiter.quota()
+    }
+
+    /// Check if a new connection from the given IP address is allowed, counting it against the quota if so.
+    pub fn check(&self, ip: IpAddr) -> Result<(), RateLimitError> {
+        // IPv4-mapped IPv6 addresses from dual-stack sockets are the same clients as their IPv4 addresses
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+
+        self.limiter.req_sync(ip)
+    }
+
+    /// Get the underlying rate limiter, such as to clean it up periodically.
+    #[must_use]
+    pub fn limiter(&self) -> &KeyedRateLimiter<IpAddr> {
+        &self.limiter
+    }
+}
+
+/// [`TcpListener`] that closes new connections over the per-IP quota of an [`AcceptLimiter`]
+/// as soon as they are accepted, see the [module documentation](self).
+#[derive(Debug)]
+pub struct RateLimitedListener {
+    listener: TcpListener,
+    limiter: AcceptLimiter,
+}
+
+impl RateLimitedListener {
+    /// Wrap the given listener with a new [`AcceptLimiter`] with the given per-IP quota for new connections.
+    #[must_use]
+    pub fn new(listener: TcpListener, quota: Quota) -> Self {
+        RateLimitedListener::with_limiter(listener, AcceptLimiter::new(quota))
+    }
+
+    /// Wrap the given listener with the given accept limiter.
+    #[must_use]
+    pub fn with_limiter(listener: TcpListener, limiter: AcceptLimiter) -> Self {
+        RateLimitedListener { listener, limiter }
+    }
+
+    /// Accept the next new connection within the quota, closing any connections over the quota until then.
+    pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        loop {
+            let (stream, addr) = self.listener.accept().await?;
+
+            match self.limiter.check(addr.ip()) {
+                Ok(()) => return Ok((stream, addr)),
+                Err(_) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(ip = %addr.ip(), "connection rate limited");
+
+                    // close the connection right away, without reading anything
+                    drop(stream);
+                }
+            }
+        }
+    }
+
+    /// Get the accept limiter of this listener.
+    #[must_use]
+    pub fn limiter(&self) -> &AcceptLimiter {
+        &self.limiter
+    }
+
+    /// Get the underlying listener.
+    #[must_use]
+    pub fn get_ref(&self) -> &TcpListener {
+        &self.listener
+    }
+
+    /// Unwrap the underlying listener.
+    #[must_use]
+    pub fn into_inner(self) -> TcpListener {
+        self.listener
+    }
+
+    /// Returns the local address that this listener is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+}
+
+#[cfg(feature = "axum-08")]
+impl axum::serve::Listener for RateLimitedListener {
+    type Io = TcpStream;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            match RateLimitedListener::accept(self).await {
+                Ok(accepted) => return accepted,
+                // same as `axum`, retry per-connection errors right away, and back off on others,
+                // such as running out of file descriptors
+                Err(e) if is_connection_error(&e) => continue,
+                Err(_) => tokio::time::sleep(std::time::Duration::from_secs(1)).await,
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        RateLimitedListener::local_addr(self)
+    }
+}
+
+#[cfg(feature = "axum-08")]
+fn is_connection_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset
+    )
+}
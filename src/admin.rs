@@ -0,0 +1,443 @@
+//! Built-in admin endpoints for inspecting and managing a rate limiter at runtime.
+//!
+//! See [`RateLimitLayer::admin_router`].
+
+use std::{convert::Infallible, hash::BuildHasher, str::FromStr, time::Duration};
+
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, put, Route as AxumRoute},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use tower::{Layer, Service};
+
+use crate::{gcra, Key, RateLimitLayer, Route};
+
+/// Default number of keys listed by `GET /hot`.
+const DEFAULT_HOT_KEYS: usize = 20;
+
+/// Append a `key` path parameter to a route, in the syntax of the axum version in use.
+#[cfg(not(feature = "axum-08"))]
+macro_rules! with_key {
+    ($path:literal) => {
+        concat!($path, "/:key")
+    };
+}
+
+#[cfg(feature = "axum-08")]
+macro_rules! with_key {
+    ($path:literal) => {
+        concat!($path, "/{key}")
+    };
+}
+
+impl<K, H> RateLimitLayer<K, H>
+where
+    K: Key + FromStr,
+    H: BuildHasher + Send + Sync + 'static,
+{
+    /// Create an axum [`Router`] with JSON endpoints for inspecting and managing this rate limiter,
+    /// such as to nest under `/admin/ratelimit` of an internal service.
+    ///
+    /// All endpoints are wrapped in the given `auth` layer, which should reject unauthorized requests,
+    /// as they can reveal client keys and lift any rate limit. Keys in paths are parsed with [`FromStr`],
+    /// and networks in queries with [`IpNetwork`](crate::real_ip::IpNetwork)'s [`FromStr`].
+    ///
+    /// | Endpoint | Description |
+    /// |---|---|
+    /// | `GET /quotas` | Default and per-route quotas |
+    /// | `GET /hot?limit=20` | [Heavy hitters](crate::RateLimitLayerBuilder::with_heavy_hitters), if enabled |
+    /// | `GET /keys/{key}` | Entries, ban, deny and violation state of a key |
+    /// | `DELETE /keys/{key}` | [Reset](RateLimitLayer::reset) a key on all routes |
+    /// | `PUT /deny/keys/{key}?ttl_secs=` | Deny a key, permanently without `ttl_secs` |
+    /// | `DELETE /deny/keys/{key}` | Remove a key from the deny list |
+    /// | `GET /deny/networks` | List denied networks |
+    /// | `PUT /deny/networks?network=&ttl_secs=` | Deny a network |
+    /// | `DELETE /deny/networks?network=` | Remove a network from the deny list |
+    /// | `PUT /allow/keys/{key}` | [Allow](RateLimitLayer::allow_key) a key to skip rate limiting |
+    /// | `DELETE /allow/keys/{key}` | Stop allowing a key |
+    /// | `GET /allow/networks` | List allowed networks |
+    /// | `PUT /allow/networks?network=` | Allow a network to skip rate limiting |
+    /// | `DELETE /allow/networks?network=` | Stop allowing a network |
+    ///
+    /// Network endpoints require the `real_ip` feature. Deny list endpoints respond with
+    /// `404 Not Found` if the layer has no [deny list](crate::RateLimitLayerBuilder::with_deny_list).
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use axum_gcra::axum;
+    /// use axum::{extract::Request, http::StatusCode, middleware::{from_fn, Next}, response::Response, routing::get, Router};
+    /// use axum_gcra::{RateLimitLayer, real_ip::RealIp};
+    ///
+    /// async fn auth(req: Request, next: Next) -> Result<Response, StatusCode> {
+    ///     match req.headers().get("authorization") {
+    ///         Some(token) if token == "Bearer hunter2" => Ok(next.run(req).await),
+    ///         _ => Err(StatusCode::UNAUTHORIZED),
+    ///     }
+    /// }
+    ///
+    /// let layer = RateLimitLayer::<RealIp>::builder().build();
+    ///
+    /// let app = Router::<()>::new()
+    ///     .route("/", get(|| async { "Hello, World!" }))
+    ///     .route_layer(layer.clone().default_handle_error())
+    ///     .nest("/admin/ratelimit", layer.admin_router(from_fn(auth)));
+    /// ```
+    pub fn admin_router<L>(&self, auth: L) -> Router
+    where
+        L: Layer<AxumRoute> + Clone + Send + Sync + 'static,
+        L::Service: Service<Request> + Clone + Send + Sync + 'static,
+        <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        let router = Router::new()
+            .route("/quotas", get(quotas::<K, H>))
+            .route("/hot", get(hot::<K, H>))
+            .route(with_key!("/keys"), get(inspect::<K, H>).delete(reset::<K, H>))
+            .route(
+                with_key!("/deny/keys"),
+                put(deny_key::<K, H>).delete(undeny_key::<K, H>),
+            )
+            .route(
+                with_key!("/allow/keys"),
+                put(allow_key::<K, H>).delete(unallow_key::<K, H>),
+            );
+
+        #[cfg(feature = "real_ip")]
+        let router = router
+            .route(
+                "/deny/networks",
+                get(networks::denied::<K, H>).put(networks::deny::<K, H>).delete(networks::undeny::<K, H>),
+            )
+            .route(
+                "/allow/networks",
+                get(networks::allowed::<K, H>).put(networks::allow::<K, H>).delete(networks::unallow::<K, H>),
+            );
+
+        router.layer(auth).with_state(self.clone())
+    }
+}
+
+/// Error response of the admin endpoints, with a JSON `{"error": ...}` body.
+struct AdminError(StatusCode, &'static str);
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        #[derive(Serialize)]
+        struct Body {
+            error: &'static str,
+        }
+
+        (self.0, Json(Body { error: self.1 })).into_response()
+    }
+}
+
+const NO_DENY_LIST: AdminError = AdminError(StatusCode::NOT_FOUND, "no deny list configured");
+
+fn parse_key<K: FromStr>(key: &str) -> Result<K, AdminError> {
+    key.parse().map_err(|_| AdminError(StatusCode::BAD_REQUEST, "invalid key"))
+}
+
+#[inline]
+fn millis(d: Duration) -> u64 {
+    d.as_millis().min(u64::MAX as u128) as u64
+}
+
+#[derive(Serialize)]
+struct QuotaJson {
+    burst: u64,
+    emission_interval_ms: u64,
+    tolerance_ms: u64,
+}
+
+impl From<gcra::Quota> for QuotaJson {
+    fn from(quota: gcra::Quota) -> Self {
+        QuotaJson {
+            burst: quota.burst(),
+            emission_interval_ms: millis(quota.emission_interval()),
+            tolerance_ms: millis(quota.tolerance()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RouteJson {
+    method: String,
+    path: String,
+}
+
+impl From<Route<'_>> for RouteJson {
+    fn from(route: Route<'_>) -> Self {
+        RouteJson {
+            method: route.method.to_string(),
+            path: route.path.into_owned(),
+        }
+    }
+}
+
+async fn quotas<K: Key, H: BuildHasher>(State(layer): State<RateLimitLayer<K, H>>) -> impl IntoResponse {
+    #[derive(Serialize)]
+    struct RouteQuota {
+        #[serde(flatten)]
+        route: RouteJson,
+        #[serde(flatten)]
+        quota: QuotaJson,
+    }
+
+    #[derive(Serialize)]
+    struct Quotas {
+        default: QuotaJson,
+        global_fallback: bool,
+        routes: Vec<RouteQuota>,
+    }
+
+    let builder = &layer.builder;
+
+    Json(Quotas {
+        default: builder.default_quota.into(),
+        global_fallback: builder.global_fallback,
+        routes: (builder.quotas.iter())
+            .map(|(route, &quota)| RouteQuota {
+                route: route.clone().into(),
+                quota: quota.into(),
+            })
+            .collect(),
+    })
+}
+
+#[derive(Deserialize)]
+struct HotQuery {
+    limit: Option<usize>,
+}
+
+async fn hot<K: Key, H: BuildHasher>(
+    State(layer): State<RateLimitLayer<K, H>>,
+    Query(query): Query<HotQuery>,
+) -> impl IntoResponse {
+    #[derive(Serialize)]
+    struct Hitter {
+        key: String,
+        count: u64,
+        error: u64,
+    }
+
+    let hitters = layer.heavy_hitters(query.limit.unwrap_or(DEFAULT_HOT_KEYS));
+
+    Json(
+        hitters
+            .into_iter()
+            .map(|h| Hitter {
+                key: h.key().to_owned(),
+                count: h.count(),
+                error: h.error(),
+            })
+            .collect::<Vec<_>>(),
+    )
+}
+
+async fn inspect<K: Key + FromStr, H: BuildHasher>(
+    State(layer): State<RateLimitLayer<K, H>>,
+    Path(key): Path<String>,
+) -> Result<impl IntoResponse, AdminError> {
+    #[derive(Serialize)]
+    struct Entry {
+        #[serde(flatten)]
+        route: RouteJson,
+        #[serde(flatten)]
+        quota: QuotaJson,
+        remaining: u64,
+        reset_after_ms: u64,
+    }
+
+    #[derive(Serialize)]
+    struct KeyState {
+        key: String,
+        entries: Vec<Entry>,
+        allowed: bool,
+        denied: bool,
+        banned_for_ms: Option<u64>,
+        violations: f64,
+    }
+
+    let key: K = parse_key(&key)?;
+    let debug = format!("{key:?}");
+
+    let entries = (layer.snapshot().await.into_iter())
+        .filter(|entry| entry.key() == debug)
+        .map(|entry| Entry {
+            route: entry.route().into(),
+            quota: entry.quota().into(),
+            remaining: entry.status().remaining,
+            reset_after_ms: millis(entry.status().reset_after),
+        })
+        .collect();
+
+    let builder = &layer.builder;
+
+    Ok(Json(KeyState {
+        entries,
+        allowed: builder.allow_list.allows_key(&key),
+        denied: builder.deny_list.as_ref().is_some_and(|list| list.is_denied(&key)),
+        banned_for_ms: builder.bans.as_ref().and_then(|bans| bans.ban_remaining(&key)).map(millis),
+        violations: builder.violations.as_ref().map_or(0.0, |v| v.count(&key)),
+        key: debug,
+    }))
+}
+
+#[derive(Serialize)]
+struct Changed {
+    changed: bool,
+}
+
+async fn reset<K: Key + FromStr, H: BuildHasher>(
+    State(layer): State<RateLimitLayer<K, H>>,
+    Path(key): Path<String>,
+) -> Result<impl IntoResponse, AdminError> {
+    #[derive(Serialize)]
+    struct Reset {
+        reset: usize,
+    }
+
+    let key: K = parse_key(&key)?;
+
+    Ok(Json(Reset {
+        reset: layer.reset(&key).await,
+    }))
+}
+
+#[derive(Deserialize)]
+struct TtlQuery {
+    ttl_secs: Option<u64>,
+}
+
+async fn deny_key<K: Key + FromStr, H: BuildHasher>(
+    State(layer): State<RateLimitLayer<K, H>>,
+    Path(key): Path<String>,
+    Query(query): Query<TtlQuery>,
+) -> Result<impl IntoResponse, AdminError> {
+    let list = layer.deny_list().ok_or(NO_DENY_LIST)?;
+    let key: K = parse_key(&key)?;
+
+    let changed = !list.is_denied(&key);
+    list.deny(&key, query.ttl_secs.map(Duration::from_secs));
+
+    Ok(Json(Changed { changed }))
+}
+
+async fn undeny_key<K: Key + FromStr, H: BuildHasher>(
+    State(layer): State<RateLimitLayer<K, H>>,
+    Path(key): Path<String>,
+) -> Result<impl IntoResponse, AdminError> {
+    let list = layer.deny_list().ok_or(NO_DENY_LIST)?;
+    let key: K = parse_key(&key)?;
+
+    Ok(Json(Changed {
+        changed: list.allow(&key),
+    }))
+}
+
+async fn allow_key<K: Key + FromStr, H: BuildHasher>(
+    State(layer): State<RateLimitLayer<K, H>>,
+    Path(key): Path<String>,
+) -> Result<impl IntoResponse, AdminError> {
+    let key: K = parse_key(&key)?;
+
+    let changed = !layer.builder.allow_list.allows_key(&key);
+    layer.allow_key(&key);
+
+    Ok(Json(Changed { changed }))
+}
+
+async fn unallow_key<K: Key + FromStr, H: BuildHasher>(
+    State(layer): State<RateLimitLayer<K, H>>,
+    Path(key): Path<String>,
+) -> Result<impl IntoResponse, AdminError> {
+    let key: K = parse_key(&key)?;
+
+    Ok(Json(Changed {
+        changed: layer.remove_allowed_key(&key),
+    }))
+}
+
+#[cfg(feature = "real_ip")]
+mod networks {
+    use super::*;
+
+    use crate::real_ip::IpNetwork;
+
+    #[derive(Deserialize)]
+    pub struct NetworkQuery {
+        network: String,
+        ttl_secs: Option<u64>,
+    }
+
+    impl NetworkQuery {
+        fn parse(&self) -> Result<IpNetwork, AdminError> {
+            self.network.parse().map_err(|_| AdminError(StatusCode::BAD_REQUEST, "invalid network"))
+        }
+    }
+
+    fn list(networks: Vec<IpNetwork>) -> Json<Vec<String>> {
+        Json(networks.iter().map(ToString::to_string).collect())
+    }
+
+    pub async fn denied<K: Key, H: BuildHasher>(
+        State(layer): State<RateLimitLayer<K, H>>,
+    ) -> Result<impl IntoResponse, AdminError> {
+        Ok(list(layer.deny_list().ok_or(NO_DENY_LIST)?.networks()))
+    }
+
+    pub async fn deny<K: Key, H: BuildHasher>(
+        State(layer): State<RateLimitLayer<K, H>>,
+        Query(query): Query<NetworkQuery>,
+    ) -> Result<impl IntoResponse, AdminError> {
+        let list = layer.deny_list().ok_or(NO_DENY_LIST)?;
+        let network = query.parse()?;
+
+        let changed = !list.networks().contains(&network);
+        list.deny_network(network, query.ttl_secs.map(Duration::from_secs));
+
+        Ok(Json(Changed { changed }))
+    }
+
+    pub async fn undeny<K: Key, H: BuildHasher>(
+        State(layer): State<RateLimitLayer<K, H>>,
+        Query(query): Query<NetworkQuery>,
+    ) -> Result<impl IntoResponse, AdminError> {
+        let list = layer.deny_list().ok_or(NO_DENY_LIST)?;
+
+        Ok(Json(Changed {
+            changed: list.allow_network(query.parse()?),
+        }))
+    }
+
+    pub async fn allowed<K: Key, H: BuildHasher>(State(layer): State<RateLimitLayer<K, H>>) -> impl IntoResponse {
+        list(layer.allowed_networks())
+    }
+
+    pub async fn allow<K: Key, H: BuildHasher>(
+        State(layer): State<RateLimitLayer<K, H>>,
+        Query(query): Query<NetworkQuery>,
+    ) -> Result<impl IntoResponse, AdminError> {
+        let network = query.parse()?;
+
+        let changed = !layer.allowed_networks().contains(&network);
+        layer.allow_network(network);
+
+        Ok(Json(Changed { changed }))
+    }
+
+    pub async fn unallow<K: Key, H: BuildHasher>(
+        State(layer): State<RateLimitLayer<K, H>>,
+        Query(query): Query<NetworkQuery>,
+    ) -> Result<impl IntoResponse, AdminError> {
+        Ok(Json(Changed {
+            changed: layer.remove_allowed_network(query.parse()?),
+        }))
+    }
+}
@@ -0,0 +1,284 @@
+//! Keying clients by their autonomous system number (ASN), so that all traffic from one network,
+//! such as a hosting provider, shares a bucket.
+//!
+//! Scraping operations typically rotate through many IP addresses within the same network, so keying
+//! by [`RealIp`] gives each address a fresh quota. [`AsnKey`] instead keys clients by the ASN of their
+//! IP address, as resolved by an [`AsnResolver`], such as an in-memory [`AsnTable`] loaded from a public
+//! IP-to-ASN database, or any user-supplied `Fn(IpAddr) -> Option<u32>`. Addresses without a known ASN
+//! are keyed by their IP address instead.
+//!
+//! The [`AsnLayer`] resolves the ASN of each request once and inserts the [`AsnKey`] extension, which must
+//! wrap the rate limiter. Specific networks can then be given their own quotas with
+//! [`RateLimitLayerBuilder::with_key_quota`](crate::RateLimitLayerBuilder::with_key_quota).
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! # use axum_gcra::axum;
+//! use std::time::Duration;
+//! use axum::{routing::get, Router};
+//! use axum_gcra::{asn::{AsnKey, AsnLayer, AsnTable}, gcra::Quota, RateLimitLayer};
+//!
+//! let table: AsnTable = [
+//!     ("203.0.113.0/24".parse().unwrap(), 64500),
+//!     ("2001:db8::/32".parse().unwrap(), 64501),
+//! ]
+//! .into_iter()
+//! .collect();
+//!
+//! let app = Router::<()>::new()
+//!     .route("/", get(|| async { "Hello, World!" }))
+//!     .route_layer(
+//!         RateLimitLayer::<AsnKey>::builder()
+//!             .with_default_quota(Quota::simple(Duration::from_millis(100)))
+//!             // a known scraper network gets much less
+//!             .with_key_quota(&AsnKey::Asn(64500), Quota::simple(Duration::from_secs(10)))
+//!             .default_handle_error(),
+//!     )
+//!     .layer(AsnLayer::new(table));
+//! ```
+
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::extract::FromRequestParts;
+use http::{request::Parts, Request};
+use tower::{Layer, Service};
+
+use crate::{
+    real_ip::{get_ip_from_parts, IpAddrRejection, IpNetwork, RealIp},
+    RandomState,
+};
+
+/// Resolves the autonomous system number (ASN) of IP addresses, see the [module documentation](self).
+///
+/// This is implemented for closures of `Fn(IpAddr) -> Option<u32>`.
+pub trait AsnResolver: Send + Sync + 'static {
+    /// Get the ASN of the given address, or `None` if it is unknown.
+    fn resolve(&self, ip: IpAddr) -> Option<u32>;
+}
+
+impl<F> AsnResolver for F
+where
+    F: Fn(IpAddr) -> Option<u32> + Send + Sync + 'static,
+{
+    fn resolve(&self, ip: IpAddr) -> Option<u32> {
+        self(ip)
+    }
+}
+
+/// In-memory [`AsnResolver`] mapping networks to ASNs, using the longest matching prefix.
+///
+/// Lookups take one hash table lookup per distinct prefix length, so tables with hundreds of thousands
+/// of networks, such as full IP-to-ASN databases, are still fast.
+#[derive(Default, Clone)]
+pub struct AsnTable {
+    networks: HashMap<IpNetwork, u32, RandomState>,
+
+    /// Distinct prefix lengths of the IPv4 and IPv6 networks, longest first.
+    v4_prefixes: Vec<u8>,
+    v6_prefixes: Vec<u8>,
+}
+
+impl fmt::Debug for AsnTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsnTable").field("networks", &self.networks.len()).finish_non_exhaustive()
+    }
+}
+
+impl AsnTable {
+    /// Create a new empty table.
+    #[must_use]
+    pub fn new() -> Self {
+        AsnTable::default()
+    }
+
+    /// Map the given network to the given ASN, replacing any previous ASN of the same network.
+    pub fn insert(&mut self, network: IpNetwork, asn: u32) {
+        let prefixes = match network.addr() {
+            IpAddr::V4(_) => &mut self.v4_prefixes,
+            IpAddr::V6(_) => &mut self.v6_prefixes,
+        };
+
+        if let Err(idx) = prefixes.binary_search_by(|p| network.prefix().cmp(p)) {
+            prefixes.insert(idx, network.prefix());
+        }
+
+        self.networks.insert(network, asn);
+    }
+
+    /// Returns the number of networks in the table.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.networks.len()
+    }
+
+    /// Returns `true` if the table has no networks.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty()
+    }
+}
+
+impl Extend<(IpNetwork, u32)> for AsnTable {
+    fn extend<T: IntoIterator<Item = (IpNetwork, u32)>>(&mut self, iter: T) {
+        for (network, asn) in iter {
+            self.insert(network, asn);
+        }
+    }
+}
+
+impl FromIterator<(IpNetwork, u32)> for AsnTable {
+    fn from_iter<T: IntoIterator<Item = (IpNetwork, u32)>>(iter: T) -> Self {
+        let mut table = AsnTable::new();
+        table.extend(iter);
+        table
+    }
+}
+
+impl AsnResolver for AsnTable {
+    fn resolve(&self, ip: IpAddr) -> Option<u32> {
+        let ip = ip.to_canonical();
+
+        let prefixes = match ip {
+            IpAddr::V4(_) => &self.v4_prefixes,
+            IpAddr::V6(_) => &self.v6_prefixes,
+        };
+
+        prefixes.iter().find_map(|&prefix| self.networks.get(&IpNetwork::new(ip, prefix)?).copied())
+    }
+}
+
+/// Rate limiter key of the autonomous system number (ASN) of the client IP address,
+/// or the address itself if its ASN is unknown. See the [module documentation](self).
+///
+/// Requests that did not pass through an [`AsnLayer`] are keyed by their IP address.
+/// Requests without any known IP address are rejected with [`IpAddrRejection`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AsnKey {
+    /// Client address within the autonomous system with the given number.
+    Asn(u32),
+
+    /// Client address without a known ASN.
+    Ip(IpAddr),
+}
+
+impl fmt::Display for AsnKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsnKey::Asn(asn) => write!(f, "AS{asn}"),
+            AsnKey::Ip(ip) => fmt::Display::fmt(ip, f),
+        }
+    }
+}
+
+impl AsnKey {
+    /// Resolve the key of the given client address with the given resolver.
+    #[must_use]
+    pub fn resolve(resolver: &(impl AsnResolver + ?Sized), ip: IpAddr) -> Self {
+        match resolver.resolve(ip) {
+            Some(asn) => AsnKey::Asn(asn),
+            None => AsnKey::Ip(ip),
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "axum-08"), async_trait::async_trait)]
+impl<S: Send + Sync> FromRequestParts<S> for AsnKey {
+    type Rejection = IpAddrRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(&key) = parts.extensions.get::<AsnKey>() {
+            return Ok(key);
+        }
+
+        Ok(AsnKey::Ip(RealIp::from_request_parts(parts, state).await?.0))
+    }
+}
+
+/// [`Layer`] that resolves the [`AsnKey`] of requests and adds it to the request parts, see the
+/// [module documentation](self).
+pub struct AsnLayer {
+    resolver: Arc<dyn AsnResolver>,
+}
+
+impl Clone for AsnLayer {
+    fn clone(&self) -> Self {
+        AsnLayer {
+            resolver: self.resolver.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for AsnLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsnLayer").finish_non_exhaustive()
+    }
+}
+
+impl AsnLayer {
+    /// Create a new layer resolving ASNs with the given resolver.
+    #[must_use]
+    pub fn new(resolver: impl AsnResolver) -> Self {
+        AsnLayer {
+            resolver: Arc::new(resolver),
+        }
+    }
+}
+
+impl<I> Layer<I> for AsnLayer {
+    type Service = AsnService<I>;
+
+    fn layer(&self, inner: I) -> Self::Service {
+        AsnService {
+            inner,
+            resolver: self.resolver.clone(),
+        }
+    }
+}
+
+/// [`Service`] that adds the [`AsnKey`] extension to the request parts if the client IP address is known.
+#[derive(Clone)]
+pub struct AsnService<I> {
+    inner: I,
+    resolver: Arc<dyn AsnResolver>,
+}
+
+impl<I: fmt::Debug> fmt::Debug for AsnService<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsnService").field("inner", &self.inner).finish_non_exhaustive()
+    }
+}
+
+impl<B, I> Service<Request<B>> for AsnService<I>
+where
+    I: Service<Request<B>>,
+{
+    type Response = I::Response;
+    type Error = I::Error;
+    type Future = I::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let (mut parts, body) = req.into_parts();
+
+        let ip = match parts.extensions.get::<RealIp>() {
+            Some(&ip) => Some(ip),
+            None => get_ip_from_parts(&parts),
+        };
+
+        if let Some(RealIp(ip)) = ip {
+            parts.extensions.insert(AsnKey::resolve(&*self.resolver, ip));
+        }
+
+        self.inner.call(Request::from_parts(parts, body))
+    }
+}
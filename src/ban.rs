@@ -0,0 +1,552 @@
+//! Temporary bans for keys that repeatedly exceed their rate limits.
+//!
+//! See [`RateLimitLayerBuilder::ban_after`](crate::RateLimitLayerBuilder::ban_after).
+//!
+//! Bans are kept in memory, but can be [saved to a file](Bans::save_to) to survive restarts, such as with
+//! [`RateLimitLayerBuilder::with_ban_persistence`](crate::RateLimitLayerBuilder::with_ban_persistence),
+//! or shared across replicas through a [`BanStore`], such as the `RedisBanStore` of the `redis` cargo feature,
+//! even when the rate limiter itself is local-only.
+
+use std::{
+    collections::HashSet,
+    fmt, io,
+    marker::PhantomData,
+    num::NonZeroU64,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::Duration,
+};
+
+use futures_util::future::BoxFuture;
+use scc::HashMap;
+
+use crate::{
+    clock::{Clock, Instant, Timeline},
+    gcra::{stable_hash, GCStats},
+    store::StoreResult,
+    Key, RandomState, RateLimitError,
+};
+
+/// Policy for automatically banning keys, see [`RateLimitLayerBuilder::ban_after`](crate::RateLimitLayerBuilder::ban_after).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BanPolicy {
+    /// Number of rejected requests that trigger a ban.
+    pub violations: u32,
+
+    /// Window in which the violations must occur, starting from the first violation.
+    pub within: Duration,
+
+    /// Duration of the ban.
+    pub ban_for: Duration,
+}
+
+/// Table of banned keys, shared by all clones of a [`RateLimitLayer`](crate::RateLimitLayer).
+///
+/// Banned keys are rejected before reaching the rate limiter, without consuming any quota,
+/// and their rejections do not count as further violations.
+///
+/// Keys are identified by a stable hash, so the table does not need to own copies of them. Entries also
+/// keep the [`Debug`](fmt::Debug) representation of their key, which is compared on bans and violations,
+/// so that a key whose hash collides with that of another key cannot get it banned.
+///
+/// Bans are local to this process, even if a custom [`Store`](crate::store::Store) is used,
+/// unless a [`BanStore`] is set with [`RateLimitLayerBuilder::with_ban_store`](crate::RateLimitLayerBuilder::with_ban_store).
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use axum_gcra::{RateLimitLayer, real_ip::RealIp};
+///
+/// # fn example(ip: RealIp) {
+/// let layer = RateLimitLayer::<RealIp>::builder()
+///     .ban_after(10, Duration::from_secs(60), Duration::from_secs(15 * 60))
+///     .build();
+///
+/// let bans = layer.bans().unwrap();
+///
+/// if let Some(remaining) = bans.ban_remaining(&ip) {
+///     println!("{ip:?} is banned for another {remaining:?}");
+///     bans.unban(&ip);
+/// }
+/// # }
+/// ```
+pub struct Bans<K> {
+    inner: Arc<BansInner>,
+    _marker: PhantomData<fn(&K)>,
+}
+
+struct BansInner {
+    time: Timeline,
+    policy: BanPolicy,
+    entries: HashMap<u64, BanEntry, RandomState>,
+
+    /// Number of violations recorded, used to schedule cleanup.
+    recorded: AtomicU64,
+
+    /// Store bans are shared through, set when the layer is built.
+    store: OnceLock<Arc<dyn BanStore>>,
+}
+
+struct BanEntry {
+    /// [`Debug`](fmt::Debug) representation of the key, for listing bans and
+    /// telling apart keys whose hashes collide.
+    key: Arc<str>,
+
+    violations: u32,
+    window_end: u64,
+    banned_until: u64,
+
+    /// Whether the ban was last loaded from the [`BanStore`], so it is lifted when removed from the store.
+    shared: bool,
+}
+
+impl BanEntry {
+    #[inline]
+    fn is_expired(&self, now: u64) -> bool {
+        self.banned_until <= now && self.window_end <= now
+    }
+
+    /// Start over for the given key, such as when it replaces an expired entry of another key with the same hash.
+    fn reset(&mut self, key: Arc<str>, now: u64) {
+        *self = BanEntry {
+            key,
+            violations: 0,
+            window_end: now,
+            banned_until: 0,
+            shared: false,
+        };
+    }
+}
+
+impl<K> Clone for Bans<K> {
+    fn clone(&self) -> Self {
+        Bans {
+            inner: self.inner.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<K> fmt::Debug for Bans<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bans").field("policy", &self.inner.policy).finish_non_exhaustive()
+    }
+}
+
+/// Information about a banned key, as returned by [`Bans::banned`].
+#[derive(Debug, Clone)]
+pub struct BanInfo {
+    key: Arc<str>,
+    remaining: Duration,
+}
+
+impl BanInfo {
+    /// Get the [`Debug`](fmt::Debug) representation of the banned key.
+    #[inline]
+    #[must_use]
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Get the remaining duration of the ban.
+    #[inline]
+    #[must_use]
+    pub fn remaining(&self) -> Duration {
+        self.remaining
+    }
+}
+
+/// Ban shared through a [`BanStore`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredBan {
+    /// Stable hash of the banned key, which is the same on all replicas.
+    pub hash: u64,
+
+    /// [`Debug`](fmt::Debug) representation of the banned key, for listing bans.
+    pub key: String,
+
+    /// End of the ban, in nanoseconds since the UNIX epoch, see [`store::now`](crate::store::now).
+    pub until: u64,
+}
+
+/// Shared storage for [`Bans`], so that bans survive restarts and apply to all replicas,
+/// see [`RateLimitLayerBuilder::with_ban_store`](crate::RateLimitLayerBuilder::with_ban_store).
+///
+/// Bans are written to the store in the background as they happen, and [loaded](Bans::sync) from it
+/// periodically, so requests are still only checked against the local table.
+pub trait BanStore: Send + Sync + 'static {
+    /// Insert or replace the given ban.
+    fn ban<'a>(&'a self, ban: &'a StoredBan) -> BoxFuture<'a, StoreResult<()>>;
+
+    /// Remove the ban on the key with the given hash, if any.
+    fn unban(&self, hash: u64) -> BoxFuture<'_, StoreResult<()>>;
+
+    /// Get all bans that have not expired at the given time, in nanoseconds since the UNIX epoch.
+    fn load(&self, now: u64) -> BoxFuture<'_, StoreResult<Vec<StoredBan>>>;
+}
+
+/// Number of violations between cleanups of expired entries.
+const CLEAN_EVERY: u64 = 1024;
+
+impl<K: Key> Bans<K> {
+    /// Create a new ban table with the given policy.
+    #[must_use]
+    pub fn new(policy: BanPolicy) -> Self {
+        Bans {
+            inner: Arc::new(BansInner {
+                time: Timeline::new(),
+                policy,
+                entries: HashMap::default(),
+                recorded: AtomicU64::new(0),
+                store: OnceLock::new(),
+            }),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Get the policy of this ban table.
+    #[inline]
+    #[must_use]
+    pub fn policy(&self) -> BanPolicy {
+        self.inner.policy
+    }
+
+    #[inline]
+    fn relative(&self, ts: Instant) -> u64 {
+        self.inner.time.relative(ts)
+    }
+
+    /// Ban the given key for the given duration, replacing any existing ban.
+    pub fn ban(&self, key: &K, ban_for: Duration) {
+        let now = self.relative(self.inner.time.now());
+        let until = now.saturating_add(ban_for.as_nanos() as u64);
+
+        let key_str: Arc<str> = format!("{key:?}").into();
+        let mut entry = self.entry(key, &key_str, now);
+        let entry = entry.get_mut();
+
+        // a manual ban always applies to the given key, even if another key with the same hash was tracked
+        if entry.key != key_str {
+            entry.reset(key_str, now);
+        }
+
+        entry.banned_until = until;
+        entry.shared = false;
+
+        self.publish(stable_hash(key), entry.key.to_string(), ban_for);
+
+        #[cfg(feature = "tracing")]
+        tracing::info!(key = ?key, ban_for_ms = ban_for.as_millis() as u64, "key banned manually");
+    }
+
+    /// Lift the ban on the given key, also forgetting any recent violations.
+    /// Returns `true` if the key was found.
+    pub fn unban(&self, key: &K) -> bool {
+        let hash = stable_hash(key);
+        let key_str = format!("{key:?}");
+        let found = self.inner.entries.remove_if(&hash, |entry| *entry.key == *key_str).is_some();
+
+        self.spawn(move |store| store.unban(hash));
+
+        #[cfg(feature = "tracing")]
+        tracing::info!(key = ?key, found, "key unbanned");
+
+        found
+    }
+
+    /// Get the remaining duration of the ban on the given key, if banned.
+    #[must_use]
+    pub fn ban_remaining(&self, key: &K) -> Option<Duration> {
+        self.check(key, self.inner.time.now()).map(RateLimitError::as_duration)
+    }
+
+    /// Returns `true` if the given key is currently banned.
+    #[must_use]
+    pub fn is_banned(&self, key: &K) -> bool {
+        self.check(key, self.inner.time.now()).is_some()
+    }
+
+    /// List all currently banned keys.
+    #[must_use]
+    pub fn banned(&self) -> Vec<BanInfo> {
+        let now = self.relative(self.inner.time.now());
+        let mut banned = Vec::new();
+
+        self.inner.entries.scan(|_, entry| {
+            if entry.banned_until > now {
+                banned.push(BanInfo {
+                    key: entry.key.clone(),
+                    remaining: Duration::from_nanos(entry.banned_until - now),
+                });
+            }
+        });
+
+        banned
+    }
+
+    /// Remove any bans and violation windows that have expired.
+    pub fn clean(&self) -> GCStats {
+        let now = self.relative(self.inner.time.now());
+        let mut stats = GCStats::default();
+
+        self.inner.entries.retain(|_, entry| {
+            let keep = !entry.is_expired(now);
+            stats.scanned += 1;
+            stats.evicted += !keep as usize;
+            keep
+        });
+
+        stats
+    }
+
+    /// Save all current bans to the given file, with their remaining time, so they can be
+    /// [restored](Bans::load_from) after a restart. Recent violations are not saved.
+    ///
+    /// The file is written atomically by writing to a temporary file first and then renaming it.
+    /// This performs blocking file I/O.
+    pub fn save_to(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let (now, unix) = (self.relative(self.inner.time.now()), self.inner.time.unix_now());
+        let mut out = String::new();
+
+        self.inner.entries.scan(|&hash, entry| {
+            if entry.banned_until > now {
+                let until = unix.saturating_add(entry.banned_until - now);
+                out += &format!("{hash:016x} {until} {}\n", entry.key);
+            }
+        });
+
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+
+        std::fs::write(&tmp, out)?;
+        std::fs::rename(&tmp, path)
+    }
+
+    /// Restore bans from a file previously written by [`Bans::save_to`], returning the number of
+    /// unexpired bans restored. Existing bans are only extended, never shortened.
+    ///
+    /// This performs blocking file I/O.
+    pub fn load_from(&self, path: impl AsRef<Path>) -> io::Result<usize> {
+        let data = std::fs::read_to_string(path)?;
+        let mut bans = Vec::new();
+
+        for line in data.lines().filter(|line| !line.is_empty()) {
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid ban entry");
+
+            let mut parts = line.splitn(3, ' ');
+            let (Some(hash), Some(until), Some(key)) = (parts.next(), parts.next(), parts.next()) else {
+                return Err(invalid());
+            };
+
+            bans.push(StoredBan {
+                hash: u64::from_str_radix(hash, 16).map_err(|_| invalid())?,
+                until: until.parse().map_err(|_| invalid())?,
+                key: key.to_owned(),
+            });
+        }
+
+        Ok(self.merge(bans, false))
+    }
+
+    /// Load the current bans from the [`BanStore`], if any, returning the number of unexpired bans loaded.
+    ///
+    /// Bans previously loaded from the store that have since been removed from it are lifted.
+    /// This is done periodically by the layer, see
+    /// [`RateLimitLayerBuilder::with_ban_store`](crate::RateLimitLayerBuilder::with_ban_store).
+    pub async fn sync(&self) -> StoreResult<usize> {
+        let Some(store) = self.inner.store.get() else {
+            return Ok(0);
+        };
+
+        let bans = store.load(self.inner.time.unix_now()).await?;
+
+        Ok(self.merge(bans, true))
+    }
+
+    /// Apply the given stored bans to the table, returning the number of unexpired bans.
+    fn merge(&self, bans: Vec<StoredBan>, shared: bool) -> usize {
+        let (now, unix) = (self.relative(self.inner.time.now()), self.inner.time.unix_now());
+        let mut seen = HashSet::new();
+
+        for ban in bans.into_iter().filter(|ban| ban.until > unix) {
+            let until = now.saturating_add(ban.until - unix);
+            seen.insert(ban.hash);
+
+            let key: Arc<str> = ban.key.into();
+
+            let mut entry = self.inner.entries.entry(ban.hash).or_insert_with(|| BanEntry {
+                key: key.clone(),
+                violations: 0,
+                window_end: now,
+                banned_until: 0,
+                shared,
+            });
+
+            let entry = entry.get_mut();
+
+            // entries of other keys with the same hash are replaced, rather than banned
+            if entry.key != key {
+                entry.reset(key, now);
+            }
+
+            // the store is authoritative for bans loaded from it, while local bans may not be stored yet
+            entry.banned_until = match shared && entry.shared {
+                true => until,
+                false => entry.banned_until.max(until),
+            };
+
+            entry.shared |= shared;
+        }
+
+        if shared {
+            self.inner.entries.retain(|hash, entry| {
+                if entry.shared && !seen.contains(hash) {
+                    entry.banned_until = 0;
+                    entry.shared = false;
+                }
+
+                !entry.is_expired(now)
+            });
+        }
+
+        seen.len()
+    }
+
+    /// Returns `true` if bans are shared through a [`BanStore`].
+    pub(crate) fn has_store(&self) -> bool {
+        self.inner.store.get().is_some()
+    }
+
+    /// Set the clock of the layer this ban table is attached to, if not already used.
+    pub(crate) fn set_clock(&self, clock: Arc<dyn Clock>) {
+        self.inner.time.set_clock(clock);
+    }
+
+    /// Set the store bans are shared through, if not already set.
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) fn set_store(&self, store: Arc<dyn BanStore>) {
+        _ = self.inner.store.set(store);
+    }
+
+    /// Write a new ban to the [`BanStore`] in the background, if any.
+    fn publish(&self, hash: u64, key: String, ban_for: Duration) {
+        let until = self.inner.time.unix_now().saturating_add(ban_for.as_nanos() as u64);
+
+        self.spawn(move |store| Box::pin(async move { store.ban(&StoredBan { hash, key, until }).await }));
+    }
+
+    /// Run the given operation on the [`BanStore`] in the background, if any.
+    fn spawn<F>(&self, f: F)
+    where
+        F: for<'a> FnOnce(&'a dyn BanStore) -> BoxFuture<'a, StoreResult<()>> + Send + 'static,
+    {
+        let Some(store) = self.inner.store.get() else {
+            return;
+        };
+
+        #[cfg(feature = "tokio")]
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let store = store.clone();
+
+            runtime.spawn(async move {
+                #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+                if let Err(e) = f(&*store).await {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(error = %e, "failed to update shared bans");
+                }
+            });
+        }
+
+        #[cfg(not(feature = "tokio"))]
+        let _ = (store, f);
+    }
+
+    /// Check if the given key is banned at the given time, returning the remaining ban as an error.
+    pub(crate) fn check(&self, key: &K, now: Instant) -> Option<RateLimitError> {
+        let now = self.relative(now);
+
+        let (remaining, banned) = self.inner.entries.read(&stable_hash(key), |_, entry| {
+            (entry.banned_until.saturating_sub(now), entry.key.clone())
+        })?;
+
+        // only format the key for the rare case of a ban, to rule out another key with the same hash
+        let remaining = NonZeroU64::new(remaining)?;
+
+        (*banned == format!("{key:?}")).then_some(RateLimitError(remaining))
+    }
+
+    /// Record a rate limit violation for the given key at the given time, returning the number of
+    /// violations within the current window including this one, which reaches the policy's number
+    /// of violations if this violation resulted in a ban.
+    pub(crate) fn violation(&self, key: &K, now: Instant) -> u32 {
+        let now = self.relative(now);
+        let policy = self.inner.policy;
+
+        if self.inner.recorded.fetch_add(1, Ordering::Relaxed) % CLEAN_EVERY == CLEAN_EVERY - 1 {
+            self.inner.entries.retain(|_, entry| !entry.is_expired(now));
+        }
+
+        let key_str: Arc<str> = format!("{key:?}").into();
+        let mut occupied = self.entry(key, &key_str, now);
+        let entry = occupied.get_mut();
+
+        if entry.key != key_str {
+            // another key with the same hash is tracked, which this key must not be able to get banned,
+            // so its violations are only counted once that entry has expired
+            if !entry.is_expired(now) {
+                return 0;
+            }
+
+            entry.reset(key_str, now);
+        }
+
+        if entry.banned_until > now {
+            return 0;
+        }
+
+        if entry.window_end <= now {
+            entry.violations = 0;
+            entry.window_end = now.saturating_add(policy.within.as_nanos() as u64);
+        }
+
+        entry.violations += 1;
+
+        let violations = entry.violations;
+
+        if violations >= policy.violations {
+            entry.violations = 0;
+            entry.banned_until = now.saturating_add(policy.ban_for.as_nanos() as u64);
+            entry.shared = false;
+
+            let key_str = entry.key.to_string();
+            drop(occupied);
+
+            self.publish(stable_hash(key), key_str, policy.ban_for);
+
+            #[cfg(feature = "tracing")]
+            tracing::info!(key = ?key, violations, ban_for_ms = policy.ban_for.as_millis() as u64, "key banned");
+        }
+
+        violations
+    }
+
+    fn entry(
+        &self,
+        key: &K,
+        key_str: &Arc<str>,
+        now: u64,
+    ) -> scc::hash_map::OccupiedEntry<'_, u64, BanEntry, RandomState> {
+        self.inner.entries.entry(stable_hash(key)).or_insert_with(|| BanEntry {
+            key: key_str.clone(),
+            violations: 0,
+            window_end: now,
+            banned_until: 0,
+            shared: false,
+        })
+    }
+}
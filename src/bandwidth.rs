@@ -0,0 +1,224 @@
+//! Bandwidth-based rate limiting, charging quota by the bytes of response bodies as they are streamed.
+//!
+//! See [`RateLimitLayerBuilder::with_bandwidth_limit`](crate::RateLimitLayerBuilder::with_bandwidth_limit).
+
+use std::{
+    fmt,
+    future::Future,
+    num::NonZeroU64,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::Request,
+    response::Response,
+};
+use http_body::{Frame, SizeHint};
+use tower::{Layer, Service};
+
+use crate::{
+    clock::Instant,
+    gcra::{stable_hash, Quota, RateLimitError, RateLimiter},
+};
+
+/// Number of bytes per unit of bandwidth quota.
+const UNIT: u64 = 1024;
+
+/// Per-key bandwidth buckets, in units of [`UNIT`] bytes.
+pub(crate) struct Bandwidth {
+    limiter: Arc<RateLimiter<u64>>,
+    quota: Quota,
+}
+
+impl fmt::Debug for Bandwidth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bandwidth").field("quota", &self.quota).finish_non_exhaustive()
+    }
+}
+
+impl Bandwidth {
+    pub fn new(bytes: u64, period: Duration) -> Self {
+        let units = NonZeroU64::new(bytes / UNIT).unwrap_or(NonZeroU64::MIN);
+
+        Bandwidth {
+            limiter: Arc::default(),
+            quota: Quota::new(Duration::from_nanos(period.as_nanos() as u64 / units.get()), units),
+        }
+    }
+
+    /// Check that the key has any bandwidth left, making sure it has an entry to [charge](Meter) later.
+    pub fn check<K: std::hash::Hash>(&self, key: &K, now: Instant) -> Result<(), RateLimitError> {
+        // a cost of zero is allowed exactly when a cost of one would be, without consuming anything
+        self.limiter.req_n_sync(stable_hash(key), self.quota, 0, now)
+    }
+
+    /// Create a meter charging the key for the bytes of a response.
+    pub fn meter<K: std::hash::Hash>(&self, key: &K) -> Meter {
+        Meter {
+            limiter: self.limiter.clone(),
+            hash: stable_hash(key),
+            t: self.quota.emission_interval(),
+            pending: 0,
+        }
+    }
+}
+
+/// Charges a key for the bytes streamed through a [`BandwidthLayer`], inserted into the
+/// request extensions by the rate limiter.
+#[derive(Clone)]
+pub(crate) struct Meter {
+    limiter: Arc<RateLimiter<u64>>,
+    hash: u64,
+    t: Duration,
+
+    /// Bytes not charged yet, as they are less than one unit.
+    pending: u64,
+}
+
+impl Meter {
+    fn charge(&mut self, bytes: u64) {
+        self.pending += bytes;
+
+        let units = self.pending / UNIT;
+        self.pending %= UNIT;
+
+        if units > 0 {
+            let cost = self.t.saturating_mul(units.min(u32::MAX as u64) as u32);
+            self.limiter.charge_sync(&self.hash, cost, Instant::now());
+        }
+    }
+}
+
+/// Layer counting the bytes of response bodies against the
+/// [bandwidth limit](crate::RateLimitLayerBuilder::with_bandwidth_limit) of their key.
+///
+/// This must be applied inside of the rate limiter layer, such as with [`axum::Router::layer`]
+/// before [`axum::Router::route_layer`], so that it sees requests after the rate limiter has charged them.
+/// Requests that were not charged by a rate limiter with a bandwidth limit are passed through unchanged.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use axum_gcra::axum;
+/// use std::time::Duration;
+/// use axum::{routing::get, Router};
+/// use axum_gcra::{bandwidth::BandwidthLayer, RateLimitLayer, real_ip::RealIp};
+///
+/// // cap downloads at 100 MiB per minute per client
+/// let app = Router::<()>::new()
+///     .route("/download", get(|| async { vec![0u8; 1 << 20] }))
+///     .layer(BandwidthLayer)
+///     .route_layer(
+///         RateLimitLayer::<RealIp>::builder()
+///             .with_bandwidth_limit(100 << 20, Duration::from_secs(60))
+///             .default_handle_error(),
+///     );
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BandwidthLayer;
+
+impl<S> Layer<S> for BandwidthLayer {
+    type Service = BandwidthService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        BandwidthService { inner }
+    }
+}
+
+/// Service counting the bytes of response bodies, see [`BandwidthLayer`].
+#[derive(Debug, Clone)]
+pub struct BandwidthService<S> {
+    inner: S,
+}
+
+impl<S, B, ResBody> Service<Request<B>> for BandwidthService<S>
+where
+    S: Service<Request<B>, Response = http::Response<ResBody>>,
+    ResBody: http_body::Body<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<axum::BoxError>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        ResponseFuture {
+            meter: req.extensions_mut().remove::<Meter>(),
+            f: self.inner.call(req),
+        }
+    }
+}
+
+pin_project_lite::pin_project! {
+    /// Response future of [`BandwidthService`].
+    pub struct ResponseFuture<F> {
+        #[pin]
+        f: F,
+        meter: Option<Meter>,
+    }
+}
+
+impl<F, E, ResBody> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<http::Response<ResBody>, E>>,
+    ResBody: http_body::Body<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<axum::BoxError>,
+{
+    type Output = Result<Response, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let res = ready!(this.f.poll(cx))?;
+
+        Poll::Ready(Ok(match this.meter.take() {
+            Some(meter) => res.map(|inner| Body::new(MeteredBody { inner, meter })),
+            None => res.map(Body::new),
+        }))
+    }
+}
+
+pin_project_lite::pin_project! {
+    /// Body charging its [`Meter`] for every data frame.
+    struct MeteredBody<B> {
+        #[pin]
+        inner: B,
+        meter: Meter,
+    }
+}
+
+impl<B: http_body::Body<Data = Bytes>> http_body::Body for MeteredBody<B> {
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, B::Error>>> {
+        let this = self.project();
+        let frame = ready!(this.inner.poll_frame(cx));
+
+        if let Some(Ok(ref frame)) = frame {
+            if let Some(data) = frame.data_ref() {
+                this.meter.charge(data.len() as u64);
+            }
+        }
+
+        Poll::Ready(frame)
+    }
+
+    #[inline]
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
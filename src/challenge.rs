@@ -0,0 +1,121 @@
+//! Challenges such as CAPTCHAs that can be offered to rate-limited clients instead of rejecting them outright.
+//!
+//! See [`RateLimitLayerBuilder::with_challenge`](crate::RateLimitLayerBuilder::with_challenge).
+
+use std::{
+    fmt,
+    hash::{BuildHasher, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use scc::HashMap;
+
+use crate::{clock::Instant, RandomState, RateLimitContext, RouteWithKey};
+
+/// How long an issued challenge can be solved for.
+pub const CHALLENGE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Number of issued challenges between cleanups of expired challenges.
+const CLEAN_EVERY: u64 = 256;
+
+/// Challenge issued for a rate-limited request, passed to the
+/// [challenge hook](crate::RateLimitLayerBuilder::with_challenge).
+#[derive(Debug, Clone)]
+pub struct Challenge {
+    token: Arc<str>,
+    context: RateLimitContext,
+}
+
+impl Challenge {
+    /// Get the unique token of this challenge, to be given back to
+    /// [`RateLimitLayer::solve_challenge`](crate::RateLimitLayer::solve_challenge) once the client has passed it.
+    ///
+    /// Tokens are 32 random hexadecimal characters, and expire after [`CHALLENGE_TTL`].
+    #[inline]
+    #[must_use]
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Get the context of the rate-limited request.
+    #[inline]
+    #[must_use]
+    pub fn context(&self) -> &RateLimitContext {
+        &self.context
+    }
+}
+
+/// What to do with the rate limit of a key once its client solved a challenge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChallengeReward {
+    /// Reset the rate limit of the key for the challenged route.
+    Reset,
+
+    /// Refund the given amount of time to the rate limit of the key for the challenged route,
+    /// see [`gcra::RateLimiter::refund`](crate::gcra::RateLimiter::refund).
+    Refund(Duration),
+}
+
+/// Table of issued challenges that have yet to be solved.
+pub(crate) struct Pending<K> {
+    entries: HashMap<Arc<str>, (RouteWithKey<K>, Instant), RandomState>,
+    issued: AtomicU64,
+}
+
+impl<K> fmt::Debug for Pending<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pending").finish_non_exhaustive()
+    }
+}
+
+impl<K> Default for Pending<K> {
+    fn default() -> Self {
+        Pending {
+            entries: HashMap::default(),
+            issued: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<K> Pending<K> {
+    /// Issue a new challenge for the given key.
+    pub fn issue(&self, key: RouteWithKey<K>, context: RateLimitContext) -> Challenge {
+        let now = Instant::now();
+
+        if self.issued.fetch_add(1, Ordering::Relaxed) % CLEAN_EVERY == CLEAN_EVERY - 1 {
+            self.entries.retain(|_, &mut (_, expires)| expires > now);
+        }
+
+        let token = new_token();
+
+        // 128-bit tokens will not collide in practice
+        _ = self.entries.insert(token.clone(), (key, now + CHALLENGE_TTL));
+
+        Challenge { token, context }
+    }
+
+    /// Take the key of the given challenge if it has not expired.
+    pub fn take(&self, token: &str) -> Option<RouteWithKey<K>> {
+        let (_, (key, expires)) = self.entries.remove(token)?;
+
+        (expires > Instant::now()).then_some(key)
+    }
+}
+
+/// Generate a new random token of 128 bits, hex-encoded.
+fn new_token() -> Arc<str> {
+    // each new `RandomState` is seeded differently, and SipHash is a keyed PRF
+    let mut bits = [0u64; 2];
+
+    for (i, bits) in bits.iter_mut().enumerate() {
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_usize(i);
+        *bits = hasher.finish();
+    }
+
+    format!("{:016x}{:016x}", bits[0], bits[1]).into()
+}
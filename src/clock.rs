@@ -0,0 +1,264 @@
+//! Time sources for the rate limiter.
+//!
+//! The rate limiter reads the current time from a [`Clock`], which defaults to [`SystemClock`].
+//! Other clocks can be plugged in with [`RateLimitLayerBuilder::with_clock`](crate::RateLimitLayerBuilder::with_clock),
+//! such as a [`ManualClock`] to drive time by hand in tests and assert exact throttle boundaries
+//! without sleeping.
+//!
+//! With the `web-time` cargo feature, [`Instant`] and system times come from the
+//! [`web-time`](https://docs.rs/web-time) crate, for targets where `std::time` is not available, such as
+//! `wasm32-unknown-unknown`. On all other targets these are the same types as in `std::time`.
+
+#[cfg(feature = "tokio")]
+use std::sync::Weak;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::Duration,
+};
+
+/// Monotonic timestamp used by the rate limiter, which is `std::time::Instant`
+/// unless the `web-time` cargo feature is enabled.
+#[cfg(not(feature = "web-time"))]
+pub use std::time::Instant;
+
+/// Monotonic timestamp used by the rate limiter, which is `std::time::Instant`
+/// unless the `web-time` cargo feature is enabled.
+#[cfg(feature = "web-time")]
+pub use web_time::Instant;
+
+#[cfg(not(feature = "web-time"))]
+pub(crate) use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "web-time")]
+pub(crate) use web_time::{SystemTime, UNIX_EPOCH};
+
+/// Source of the current time for the rate limiter.
+pub trait Clock: Send + Sync + 'static {
+    /// Get the current monotonic time, used to timestamp requests for the in-memory rate limiter.
+    fn now(&self) -> Instant;
+
+    /// Get the current time in nanoseconds since the UNIX epoch, used to timestamp requests
+    /// for custom [`Store`](crate::store::Store)s.
+    ///
+    /// Defaults to the system time, see [`store::now`](crate::store::now).
+    fn unix_now(&self) -> u64 {
+        crate::store::now()
+    }
+}
+
+/// Default [`Clock`] using [`Instant::now`] and the system time.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// [`Clock`] that only moves forward when [advanced](ManualClock::advance) by hand, for tests.
+///
+/// Clones share the same time, so one clone can be given to the rate limiter while another
+/// is kept to advance it.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use axum_gcra::{clock::{Clock, ManualClock}, gcra::{Quota, RateLimiter}};
+///
+/// // create the clock after the rate limiter, which ignores any earlier time
+/// let limiter = RateLimiter::<()>::default();
+/// let clock = ManualClock::new();
+/// let quota = Quota::simple(Duration::from_secs(1));
+///
+/// assert!(limiter.req_sync((), quota, clock.now()).is_ok());
+///
+/// clock.advance(Duration::from_millis(999));
+/// assert!(limiter.req_sync((), quota, clock.now()).is_err());
+///
+/// clock.advance(Duration::from_millis(1));
+/// assert!(limiter.req_sync((), quota, clock.now()).is_ok());
+/// ```
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    inner: Arc<ManualClockInner>,
+}
+
+#[derive(Debug)]
+struct ManualClockInner {
+    start: Instant,
+    unix_start: u64,
+
+    /// Nanoseconds the clock has been advanced since `start`.
+    elapsed: AtomicU64,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ManualClock {
+    /// Create a new manual clock, starting at the current time.
+    #[must_use]
+    pub fn new() -> Self {
+        ManualClock {
+            inner: Arc::new(ManualClockInner {
+                start: Instant::now(),
+                unix_start: crate::store::now(),
+                elapsed: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Move the clock forward by the given duration.
+    pub fn advance(&self, duration: Duration) {
+        let nanos = duration.as_nanos().min(u64::MAX as u128) as u64;
+        self.inner.elapsed.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    /// Get the total duration the clock has been advanced since it was created.
+    #[must_use]
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.inner.elapsed.load(Ordering::Relaxed))
+    }
+}
+
+impl Clock for ManualClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        self.inner.start + self.elapsed()
+    }
+
+    #[inline]
+    fn unix_now(&self) -> u64 {
+        self.inner.unix_start.saturating_add(self.inner.elapsed.load(Ordering::Relaxed))
+    }
+}
+
+/// Time source of the tables kept alongside the rate limiter, such as [bans](crate::ban::Bans),
+/// which follows the [`Clock`] of the layer they are attached to.
+///
+/// The clock is fixed on first use, so tables used before being attached to a layer with a
+/// custom clock keep using the [`SystemClock`].
+pub(crate) struct Timeline {
+    inner: OnceLock<(Arc<dyn Clock>, Instant)>,
+}
+
+impl Timeline {
+    pub const fn new() -> Self {
+        Timeline { inner: OnceLock::new() }
+    }
+
+    /// Use the given clock, unless this timeline has already been used or given another clock.
+    pub fn set_clock(&self, clock: Arc<dyn Clock>) {
+        let start = clock.now();
+        _ = self.inner.set((clock, start));
+    }
+
+    #[inline]
+    fn get(&self) -> &(Arc<dyn Clock>, Instant) {
+        self.inner.get_or_init(|| (Arc::new(SystemClock), Instant::now()))
+    }
+
+    /// Get the current time of the clock.
+    #[inline]
+    pub fn now(&self) -> Instant {
+        self.get().0.now()
+    }
+
+    /// Get the current time of the clock in nanoseconds since the UNIX epoch.
+    #[inline]
+    pub fn unix_now(&self) -> u64 {
+        self.get().0.unix_now()
+    }
+
+    /// Get the given time in nanoseconds since the start of this timeline.
+    #[inline]
+    pub fn relative(&self, ts: Instant) -> u64 {
+        ts.saturating_duration_since(self.get().1).as_nanos() as u64
+    }
+}
+
+/// Coarse time source that caches the current time, refreshed at a fixed resolution by a
+/// background task, so that reading the time is a single atomic load instead of a call to
+/// [`Instant::now`].
+///
+/// This trades precision for throughput, as timestamps may lag behind the real time by up to
+/// the resolution, and is only worthwhile at very high request rates. Quotas with emission
+/// intervals close to the resolution will be noticeably less accurate.
+///
+/// The background task stops once all clones of the clock have been dropped.
+///
+/// See [`RateLimitLayerBuilder::with_coarse_clock`](crate::RateLimitLayerBuilder::with_coarse_clock).
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone)]
+pub struct CoarseClock {
+    inner: Arc<CoarseClockInner>,
+}
+
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+struct CoarseClockInner {
+    start: Instant,
+
+    /// Nanoseconds since `start` as of the last refresh.
+    elapsed: AtomicU64,
+}
+
+#[cfg(feature = "tokio")]
+impl CoarseClock {
+    /// Create a new coarse clock refreshed at the given resolution, spawning the background task.
+    ///
+    /// This must be called from within a tokio runtime.
+    #[must_use]
+    pub fn new(resolution: Duration) -> Self {
+        let inner = Arc::new(CoarseClockInner {
+            start: Instant::now(),
+            elapsed: AtomicU64::new(0),
+        });
+
+        tokio::task::spawn(CoarseClockInner::refresh_task(Arc::downgrade(&inner), resolution));
+
+        CoarseClock { inner }
+    }
+
+    /// Get the cached current time.
+    #[inline]
+    #[must_use]
+    pub fn now(&self) -> Instant {
+        self.inner.start + Duration::from_nanos(self.inner.elapsed.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Clock for CoarseClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        CoarseClock::now(self)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl CoarseClockInner {
+    async fn refresh_task(clock: Weak<Self>, resolution: Duration) {
+        let mut interval = tokio::time::interval(resolution);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+        loop {
+            interval.tick().await;
+
+            // stop once all clocks have been dropped
+            let Some(clock) = clock.upgrade() else { break };
+
+            let elapsed = clock.start.elapsed().as_nanos() as u64;
+            clock.elapsed.fetch_max(elapsed, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,240 @@
+//! Plain snapshots of rate limiter configurations, such as to log the active configuration
+//! at startup or to detect configuration drift between instances.
+//!
+//! A [`RateLimitConfig`] is taken with [`RateLimitLayerBuilder::config`](crate::RateLimitLayerBuilder::config)
+//! or [`RateLimitLayer::config`](crate::RateLimitLayer::config). Secrets, such as the value of the
+//! [bypass header](crate::RateLimitLayerBuilder::with_bypass_header), are never included, and callbacks,
+//! stores and other opaque parts of the configuration are only reported as present or not.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::time::Duration;
+//! use axum_gcra::{gcra::Quota, RateLimitLayer, Route};
+//!
+//! let old = RateLimitLayer::<()>::builder()
+//!     .with_route(Route::post("/login"), Quota::simple(Duration::from_secs(5)))
+//!     .config();
+//!
+//! let new = RateLimitLayer::<()>::builder()
+//!     .with_route(Route::post("/login"), Quota::simple(Duration::from_secs(10)))
+//!     .with_global_fallback(true)
+//!     .config();
+//!
+//! let changes = old.diff(&new);
+//!
+//! assert_eq!(changes.len(), 2);
+//! assert_eq!(changes[0].field, "routes");
+//! assert_eq!(changes[1].field, "global_fallback");
+//! ```
+
+use std::{fmt, path::PathBuf, time::Duration};
+
+use http::StatusCode;
+
+use crate::{
+    gcra::{OverflowPolicy, Quota},
+    store::FailurePolicy,
+    GCInterval, Route,
+};
+
+/// Snapshot of the configuration of a rate limiter, see the [module documentation](self).
+///
+/// Routes and other collections are sorted, so snapshots of equivalent configurations compare equal.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct RateLimitConfig {
+    /// Registered routes and their quotas, sorted by path and method.
+    pub routes: Vec<(Route<'static>, Quota)>,
+
+    /// Quota of routes without a registered quota.
+    pub default_quota: Quota,
+
+    /// Default quota of [safe](crate::MethodClass::Safe) requests, if different from `default_quota`.
+    pub safe_default_quota: Option<Quota>,
+
+    /// Default quota of [unsafe](crate::MethodClass::Unsafe) requests, if different from `default_quota`.
+    pub unsafe_default_quota: Option<Quota>,
+
+    /// [Default quota](crate::RateLimitLayerBuilder::with_ipv4_default_quota) of IPv4 keys, if any.
+    pub ipv4_default_quota: Option<Quota>,
+
+    /// [Default quota](crate::RateLimitLayerBuilder::with_ipv6_default_quota) of IPv6 keys, if any.
+    pub ipv6_default_quota: Option<Quota>,
+
+    /// IPv4 and IPv6 prefix lengths IP keys are [masked](crate::RateLimitLayerBuilder::with_ip_masks) to, if any.
+    pub ip_masks: Option<(u8, u8)>,
+
+    /// Whether requests using default quotas share one bucket per method class,
+    /// see [`RateLimitLayerBuilder::with_read_write_quotas`](crate::RateLimitLayerBuilder::with_read_write_quotas).
+    pub class_buckets: bool,
+
+    /// Number of [pre-registered keys](crate::RateLimitLayerBuilder::add_key_quotas) with their own quotas.
+    pub key_quotas: usize,
+
+    /// Whether the [global fallback](crate::RateLimitLayerBuilder::with_global_fallback) is enabled.
+    pub global_fallback: bool,
+
+    /// Whether the [prefix fallback](crate::RateLimitLayerBuilder::with_prefix_fallback) is enabled.
+    pub prefix_fallback: bool,
+
+    /// Path prefixes [exempt](crate::RateLimitLayerBuilder::without_fallback_for) from the default quota, sorted.
+    pub fallback_exemptions: Vec<String>,
+
+    /// Prefix the router is [nested](crate::RateLimitLayerBuilder::with_nest_prefix) under, if any.
+    pub nest_prefix: Option<String>,
+
+    /// [Server-wide quota](crate::RateLimitLayerBuilder::with_server_quota), if any.
+    pub server_quota: Option<Quota>,
+
+    /// [Shared quotas](crate::RateLimitLayerBuilder::with_shared_quota) in the order they were added,
+    /// with their routes sorted by path and method.
+    pub shared_quotas: Vec<(Vec<Route<'static>>, Quota)>,
+
+    /// Quotas of [parent keys](crate::RateLimitLayerBuilder::with_parent_key), in the order they were added.
+    pub parent_keys: Vec<Quota>,
+
+    /// [Idle TTLs](crate::RateLimitLayerBuilder::with_route_ttl) of specific routes, sorted by path and method.
+    pub route_ttls: Vec<(Route<'static>, Duration)>,
+
+    /// [Idle timeout](crate::RateLimitLayerBuilder::with_idle_timeout) of all other entries, if any.
+    pub idle_timeout: Option<Duration>,
+
+    /// Whether [lifecycle callbacks](crate::RateLimitLayerBuilder::on_key_inserted) are set.
+    pub lifecycle_callbacks: bool,
+
+    /// [Garbage collection interval](crate::RateLimitLayerBuilder::with_gc_interval).
+    pub gc_interval: GCInterval,
+
+    /// Number of requests between garbage collection runs, if [overridden](crate::RateLimitLayerBuilder::with_gc_requests).
+    pub gc_requests: Option<u64>,
+
+    /// Number of shards of the rate limiter table, or `None` for the default.
+    pub shards: Option<usize>,
+
+    /// [Maximum number of entries](crate::RateLimitLayerBuilder::with_max_entries), if any.
+    pub max_entries: Option<usize>,
+
+    /// What happens to new keys when the rate limiter table is full.
+    pub overflow: OverflowPolicy,
+
+    /// Penalties charged for inner responses with specific status codes, sorted by status code.
+    pub status_penalties: Vec<(StatusCode, u64)>,
+
+    /// Whether [cancelled requests are refunded](crate::RateLimitLayerBuilder::with_cancel_refund).
+    pub cancel_refund: bool,
+
+    /// Maximum time requests may be delayed instead of rejected, if any.
+    pub max_delay: Option<Duration>,
+
+    /// Percentage of keys whose rejections are [enforced](crate::RateLimitLayerBuilder::with_enforcement).
+    pub enforcement: u8,
+
+    /// Multiplier of all quotas, see [`RateLimitLayer::set_scale`](crate::RateLimitLayer::set_scale).
+    pub scale: f32,
+
+    /// Name of the [bypass header](crate::RateLimitLayerBuilder::with_bypass_header), if any.
+    /// The secret value is never included.
+    pub bypass_header: Option<String>,
+
+    /// Number of [exemption predicates](crate::RateLimitLayerBuilder::exempt_if).
+    pub exemptions: usize,
+
+    /// Whether the [`RateLimiter`](crate::extensions::RateLimiter) extension is inserted into requests.
+    pub extension: bool,
+
+    /// Whether the [`RateLimitInfo`](crate::RateLimitInfo) extension is inserted into requests.
+    pub info_extension: bool,
+
+    /// Whether the [policy header](crate::RateLimitLayerBuilder::with_policy_header) is added to responses.
+    pub policy_header: bool,
+
+    /// Fraction of quotas at which clients are [warned](crate::RateLimitLayerBuilder::with_soft_limit), if any.
+    pub soft_limit: Option<f32>,
+
+    /// Name of the [correlation header](crate::RateLimitLayerBuilder::with_correlation_header), if any.
+    pub correlation_header: Option<String>,
+
+    /// Whether a custom [`Store`](crate::store::Store) is used instead of the in-memory table.
+    pub custom_store: bool,
+
+    /// What happens to requests when the custom store [fails](crate::RateLimitLayerBuilder::with_failure_policy).
+    pub failure_policy: FailurePolicy,
+
+    /// Whether a [quota resolver](crate::RateLimitLayerBuilder::with_quota_resolver) is consulted for
+    /// requests without a static quota.
+    pub quota_resolver: bool,
+
+    /// Path prefixes with their own [error handler](crate::RateLimitLayerBuilder::handle_error_for),
+    /// in the order they are tried.
+    pub route_error_handlers: Vec<String>,
+
+    /// Whether the rate limiter state is [shared](crate::RateLimitLayerBuilder::with_state) with other layers.
+    pub shared_state: bool,
+
+    /// File the rate limiter state is [persisted](crate::RateLimitLayerBuilder::with_persistence) to, if any.
+    pub persistence: Option<PathBuf>,
+
+    /// File the bans are [persisted](crate::RateLimitLayerBuilder::with_ban_persistence) to, if any.
+    pub ban_persistence: Option<PathBuf>,
+
+    /// Whether the bans are [shared](crate::RateLimitLayerBuilder::with_ban_store) through a
+    /// [`BanStore`](crate::ban::BanStore).
+    pub ban_store: bool,
+}
+
+/// A single difference between two [`RateLimitConfig`]s, as returned by [`RateLimitConfig::diff`].
+///
+/// Values are given as their [`Debug`](fmt::Debug) representations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigChange {
+    /// Name of the field that differs.
+    pub field: &'static str,
+
+    /// Value of the field in the configuration being compared.
+    pub old: String,
+
+    /// Value of the field in the other configuration.
+    pub new: String,
+}
+
+impl fmt::Display for ConfigChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.field, self.old, self.new)
+    }
+}
+
+macro_rules! diff_fields {
+    ($old:ident, $new:ident, $changes:ident, [$($field:ident),* $(,)?]) => {
+        $(
+            if $old.$field != $new.$field {
+                $changes.push(ConfigChange {
+                    field: stringify!($field),
+                    old: format!("{:?}", $old.$field),
+                    new: format!("{:?}", $new.$field),
+                });
+            }
+        )*
+    };
+}
+
+impl RateLimitConfig {
+    /// Compare this configuration to another, returning the fields that differ, in declaration order.
+    #[must_use]
+    pub fn diff(&self, other: &RateLimitConfig) -> Vec<ConfigChange> {
+        let mut changes = Vec::new();
+
+        #[rustfmt::skip]
+        diff_fields!(self, other, changes, [
+            routes, default_quota, safe_default_quota, unsafe_default_quota, ipv4_default_quota,
+            ipv6_default_quota, ip_masks, class_buckets, key_quotas, global_fallback, prefix_fallback,
+            fallback_exemptions, nest_prefix, server_quota, shared_quotas, parent_keys, route_ttls, idle_timeout,
+            lifecycle_callbacks, gc_interval, gc_requests, shards, max_entries, overflow, status_penalties,
+            cancel_refund, max_delay, enforcement, scale, bypass_header, exemptions, extension, info_extension,
+            policy_header, soft_limit, correlation_header, custom_store, failure_policy, quota_resolver,
+            route_error_handlers, shared_state, persistence, ban_persistence, ban_store,
+        ]);
+
+        changes
+    }
+}
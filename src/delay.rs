@@ -0,0 +1,113 @@
+//! Bounded queues of [delayed](crate::RateLimitLayerBuilder::with_delay) requests, per key and in total.
+//!
+//! See [`RateLimitLayerBuilder::with_delay_queue_depth`](crate::RateLimitLayerBuilder::with_delay_queue_depth)
+//! and [`RateLimitLayerBuilder::with_delay_queue_limit`](crate::RateLimitLayerBuilder::with_delay_queue_limit).
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use scc::HashMap;
+
+use crate::RandomState;
+
+/// Number of delayed requests waiting per key, by stable key hash, and in total.
+pub(crate) struct DelayQueues {
+    max_depth: usize,
+    max_total: usize,
+    pending: HashMap<u64, usize, RandomState>,
+    total: AtomicUsize,
+}
+
+/// Place of a request in the delay queues, which is freed when dropped.
+pub(crate) struct QueueSlot {
+    queues: Arc<DelayQueues>,
+    hash: u64,
+}
+
+impl fmt::Debug for DelayQueues {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DelayQueues")
+            .field("max_depth", &self.max_depth)
+            .field("max_total", &self.max_total)
+            .field("total", &self.total.load(Ordering::Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
+impl DelayQueues {
+    /// Create new queues with the given maximum depth per key and in total, where `usize::MAX` is unlimited.
+    pub fn new(max_depth: usize, max_total: usize) -> Arc<Self> {
+        Arc::new(DelayQueues {
+            max_depth,
+            max_total,
+            pending: HashMap::default(),
+            total: AtomicUsize::new(0),
+        })
+    }
+
+    /// Get the maximum depth per key.
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Get the maximum depth in total.
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub fn max_total(&self) -> usize {
+        self.max_total
+    }
+
+    /// Get the number of requests currently in the queues.
+    pub fn depth(&self) -> usize {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    /// Join the queue of the key with the given hash, returning `None` if it or the queues in total are full.
+    pub fn join(self: &Arc<Self>, hash: u64) -> Option<QueueSlot> {
+        if self.max_depth == 0 || self.max_total == 0 {
+            return None;
+        }
+
+        if self.total.fetch_add(1, Ordering::AcqRel) >= self.max_total {
+            self.total.fetch_sub(1, Ordering::AcqRel);
+            return None;
+        }
+
+        // only count per key if limited, as most keys never have more than one delayed request
+        if self.max_depth != usize::MAX {
+            let mut entry = self.pending.entry(hash).or_insert(0);
+
+            if *entry.get() >= self.max_depth {
+                self.total.fetch_sub(1, Ordering::AcqRel);
+                return None;
+            }
+
+            *entry.get_mut() += 1;
+        }
+
+        Some(QueueSlot {
+            queues: self.clone(),
+            hash,
+        })
+    }
+}
+
+impl Drop for QueueSlot {
+    fn drop(&mut self) {
+        let queues = &self.queues;
+
+        if queues.max_depth != usize::MAX {
+            queues.pending.remove_if(&self.hash, |pending| {
+                *pending -= 1;
+                *pending == 0
+            });
+        }
+
+        queues.total.fetch_sub(1, Ordering::AcqRel);
+    }
+}
@@ -0,0 +1,483 @@
+//! Shared deny lists of keys and networks that are rejected before reaching the rate limiter,
+//! and allow lists of those that bypass it.
+//!
+//! See [`RateLimitLayerBuilder::with_deny_list`](crate::RateLimitLayerBuilder::with_deny_list)
+//! and [`RateLimitLayerBuilder::with_allowed_keys`](crate::RateLimitLayerBuilder::with_allowed_keys).
+
+use std::{
+    fmt,
+    marker::PhantomData,
+    num::NonZeroU64,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{
+    extract::FromRequestParts,
+    response::{IntoResponse, Response},
+};
+use http::{request::Parts, HeaderName};
+use scc::{HashMap, HashSet};
+
+#[cfg(feature = "real_ip")]
+use std::sync::RwLock;
+
+#[cfg(feature = "real_ip")]
+use crate::real_ip::IpNetwork;
+
+use crate::{
+    clock::{Clock, Instant, Timeline},
+    gcra::{stable_hash, GCStats},
+    Key, RandomState, RateLimitError,
+};
+
+/// Shared list of denied keys and, with the `real_ip` feature, client networks, whose requests are
+/// rejected with `403 Forbidden` before reaching the rate limiter, without consuming any quota.
+///
+/// Entries can be added and removed at runtime through any clone of the list, and can either be permanent
+/// or expire after a given time-to-live. The same list can be given to multiple layers.
+///
+/// Keys are identified by a stable hash, so the list does not need to own copies of them. Networks are
+/// matched against the client IP of the request, as found by [`RealIp`](crate::real_ip::RealIp), regardless of the key type.
+///
+/// When configured, the list is also inserted into the extensions of allowed requests, so handlers can
+/// deny clients at runtime with [`Extension<DenyList<K>>`](axum::extract::Extension), or check
+/// a key with the [`DenyStatus`] extractor.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use axum_gcra::axum;
+/// use std::time::Duration;
+/// use axum::{routing::get, Router, extract::Extension};
+/// use axum_gcra::{RateLimitLayer, deny::DenyList, real_ip::RealIp};
+///
+/// let deny = DenyList::<RealIp>::new();
+///
+/// deny.deny_network("203.0.113.0/24".parse().unwrap(), None);
+///
+/// let app = Router::<()>::new()
+///     .route("/wp-login.php", get(|ip: RealIp, Extension(deny): Extension<DenyList<RealIp>>| async move {
+///         // honeypot
+///         deny.deny(&ip, Some(Duration::from_secs(24 * 60 * 60)));
+///     }))
+///     .route_layer(RateLimitLayer::<RealIp>::builder().with_deny_list(deny.clone()).default_handle_error());
+/// ```
+pub struct DenyList<K> {
+    inner: Arc<DenyListInner>,
+    _marker: PhantomData<fn(&K)>,
+}
+
+struct DenyListInner {
+    time: Timeline,
+
+    /// Expiration times of denied key hashes, where `u64::MAX` is permanent.
+    keys: HashMap<u64, u64, RandomState>,
+
+    /// Denied networks and their expiration times, which are expected to be few.
+    #[cfg(feature = "real_ip")]
+    networks: RwLock<Vec<(IpNetwork, u64)>>,
+
+    /// Fast check to skip looking up the client IP when no networks are denied.
+    #[cfg(feature = "real_ip")]
+    has_networks: AtomicBool,
+}
+
+impl<K> Clone for DenyList<K> {
+    fn clone(&self) -> Self {
+        DenyList {
+            inner: self.inner.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<K> fmt::Debug for DenyList<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DenyList").finish_non_exhaustive()
+    }
+}
+
+impl<K: Key> Default for DenyList<K> {
+    fn default() -> Self {
+        DenyList::new()
+    }
+}
+
+impl<K: Key> DenyList<K> {
+    /// Create a new empty deny list.
+    #[must_use]
+    pub fn new() -> Self {
+        DenyList {
+            inner: Arc::new(DenyListInner {
+                time: Timeline::new(),
+                keys: HashMap::default(),
+
+                #[cfg(feature = "real_ip")]
+                networks: RwLock::new(Vec::new()),
+
+                #[cfg(feature = "real_ip")]
+                has_networks: AtomicBool::new(false),
+            }),
+            _marker: PhantomData,
+        }
+    }
+
+    #[inline]
+    fn relative(&self, ts: Instant) -> u64 {
+        self.inner.time.relative(ts)
+    }
+
+    /// Set the clock of the layer this deny list is attached to, if not already used.
+    pub(crate) fn set_clock(&self, clock: Arc<dyn Clock>) {
+        self.inner.time.set_clock(clock);
+    }
+
+    fn expires(&self, ttl: Option<Duration>) -> u64 {
+        match ttl {
+            Some(ttl) => self.relative(self.inner.time.now()).saturating_add(ttl.as_nanos() as u64),
+            None => u64::MAX,
+        }
+    }
+
+    /// Deny the given key, permanently or for the given time-to-live, replacing any existing entry.
+    pub fn deny(&self, key: &K, ttl: Option<Duration>) {
+        _ = self.inner.keys.upsert(stable_hash(key), self.expires(ttl));
+    }
+
+    /// Remove the given key from the list, returning `true` if it was found.
+    pub fn allow(&self, key: &K) -> bool {
+        self.inner.keys.remove(&stable_hash(key)).is_some()
+    }
+
+    /// Returns `true` if the given key is currently denied.
+    ///
+    /// This does not check networks, as the key alone does not carry a client IP.
+    #[must_use]
+    pub fn is_denied(&self, key: &K) -> bool {
+        self.check_key(key, self.relative(self.inner.time.now())).is_some()
+    }
+
+    /// Remove any entries that have expired.
+    pub fn clean(&self) -> GCStats {
+        let now = self.relative(self.inner.time.now());
+        let mut stats = GCStats::default();
+
+        self.inner.keys.retain(|_, &mut expires| {
+            stats.scanned += 1;
+            stats.evicted += (expires <= now) as usize;
+            expires > now
+        });
+
+        #[cfg(feature = "real_ip")]
+        {
+            let mut networks = self.inner.networks.write().unwrap_or_else(|e| e.into_inner());
+
+            let len = networks.len();
+            networks.retain(|&(_, expires)| expires > now);
+
+            stats.scanned += len;
+            stats.evicted += len - networks.len();
+
+            self.inner.has_networks.store(!networks.is_empty(), Ordering::Relaxed);
+        }
+
+        stats
+    }
+
+    /// Check if a request is denied at the given time, returning the remaining time-to-live as an error.
+    #[cfg_attr(not(feature = "real_ip"), allow(unused_variables))]
+    pub(crate) fn check(&self, key: &K, parts: &Parts, now: Instant) -> Option<RateLimitError> {
+        let now = self.relative(now);
+
+        #[cfg(feature = "real_ip")]
+        if self.inner.has_networks.load(Ordering::Relaxed) {
+            let ip = parts.extensions.get().copied().or_else(|| crate::real_ip::get_ip_from_parts(parts));
+
+            if let Some(crate::real_ip::RealIp(ip)) = ip {
+                let networks = self.inner.networks.read().unwrap_or_else(|e| e.into_inner());
+
+                let remaining = (networks.iter())
+                    .filter(|(net, _)| net.contains(ip))
+                    .map(|&(_, expires)| expires.saturating_sub(now))
+                    .max();
+
+                if let Some(error) = remaining.and_then(NonZeroU64::new) {
+                    return Some(RateLimitError(error));
+                }
+            }
+        }
+
+        self.check_key(key, now)
+    }
+
+    fn check_key(&self, key: &K, now: u64) -> Option<RateLimitError> {
+        let remaining = self.inner.keys.read(&stable_hash(key), |_, expires| expires.saturating_sub(now));
+
+        remaining.and_then(NonZeroU64::new).map(RateLimitError)
+    }
+}
+
+#[cfg(feature = "real_ip")]
+impl<K: Key> DenyList<K> {
+    /// Deny all client IPs within the given network, permanently or for the given time-to-live,
+    /// replacing any existing entry for the same network.
+    pub fn deny_network(&self, network: IpNetwork, ttl: Option<Duration>) {
+        let expires = self.expires(ttl);
+        let mut networks = self.inner.networks.write().unwrap_or_else(|e| e.into_inner());
+
+        match networks.iter_mut().find(|(net, _)| *net == network) {
+            Some(entry) => entry.1 = expires,
+            None => networks.push((network, expires)),
+        }
+
+        self.inner.has_networks.store(true, Ordering::Relaxed);
+    }
+
+    /// Remove the given network from the list, returning `true` if it was found.
+    ///
+    /// This only removes the exact network, not any other networks overlapping it.
+    pub fn allow_network(&self, network: IpNetwork) -> bool {
+        let mut networks = self.inner.networks.write().unwrap_or_else(|e| e.into_inner());
+
+        let len = networks.len();
+        networks.retain(|(net, _)| *net != network);
+
+        self.inner.has_networks.store(!networks.is_empty(), Ordering::Relaxed);
+
+        networks.len() != len
+    }
+
+    /// List all denied networks, including expired entries not yet [cleaned](DenyList::clean).
+    #[must_use]
+    pub fn networks(&self) -> Vec<IpNetwork> {
+        let networks = self.inner.networks.read().unwrap_or_else(|e| e.into_inner());
+
+        networks.iter().map(|&(net, _)| net).collect()
+    }
+}
+
+/// Extractor that checks if the key of the request is on the [`DenyList`] of the rate limiter,
+/// including denied networks.
+///
+/// This requires the rate limiter to be configured [with a deny list](crate::RateLimitLayerBuilder::with_deny_list),
+/// or the list to be added as an [`Extension`](axum::Extension) otherwise, and the `K: Key` type
+/// must be the exact same as that of the list.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use axum_gcra::axum;
+/// use axum::{routing::get, Router};
+/// use axum_gcra::{RateLimitLayer, deny::{DenyList, DenyStatus}, real_ip::RealIp};
+///
+/// let deny = DenyList::<RealIp>::new();
+///
+/// let app = Router::<()>::new()
+///     // not rate limited, but still aware of the deny list
+///     .route("/status", get(|status: DenyStatus<RealIp>| async move {
+///         format!("denied: {}", status.is_denied())
+///     }))
+///     .layer(axum::Extension(deny));
+/// ```
+pub struct DenyStatus<K> {
+    result: Result<(), RateLimitError>,
+    _marker: PhantomData<fn(&K)>,
+}
+
+impl<K> DenyStatus<K> {
+    /// Returns `true` if the request is denied.
+    #[inline]
+    #[must_use]
+    pub fn is_denied(&self) -> bool {
+        self.result.is_err()
+    }
+
+    /// Returns the remaining time-to-live of the denial, if denied.
+    ///
+    /// Permanent denials have a practically infinite time-to-live.
+    #[must_use]
+    pub fn remaining(&self) -> Option<Duration> {
+        self.result.err().map(RateLimitError::as_duration)
+    }
+}
+
+impl<K> Clone for DenyStatus<K> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K> Copy for DenyStatus<K> {}
+
+impl<K> fmt::Debug for DenyStatus<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DenyStatus").field("result", &self.result).finish()
+    }
+}
+
+#[cfg_attr(not(feature = "axum-08"), async_trait::async_trait)]
+impl<K, S> FromRequestParts<S> for DenyStatus<K>
+where
+    K: Key + FromRequestParts<()>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        use axum::extract::Extension;
+
+        let Extension(list) = Extension::<DenyList<K>>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        let key = crate::get_user_key::<K>(parts, None).await.map_err(IntoResponse::into_response)?;
+
+        Ok(DenyStatus {
+            result: list.check(&key, parts, list.inner.time.now()).map_or(Ok(()), Err),
+            _marker: PhantomData,
+        })
+    }
+}
+
+/// Keys and networks that skip rate limiting entirely, configured on the builder
+/// and modifiable at runtime through the layer.
+#[derive(Default)]
+pub(crate) struct AllowList {
+    keys: HashSet<u64, RandomState>,
+
+    /// Fast check to skip hashing the key when no keys are allowed.
+    has_keys: AtomicBool,
+
+    #[cfg(feature = "real_ip")]
+    networks: RwLock<Vec<IpNetwork>>,
+
+    /// Fast check to skip looking up the client IP when no networks are allowed.
+    #[cfg(feature = "real_ip")]
+    has_networks: AtomicBool,
+
+    /// Header name and secret value for [`RateLimitLayerBuilder::with_bypass_header`](crate::RateLimitLayerBuilder::with_bypass_header).
+    bypass: Option<(HeaderName, Box<[u8]>)>,
+
+    /// Predicates for [`RateLimitLayerBuilder::exempt_if`](crate::RateLimitLayerBuilder::exempt_if).
+    exemptions: Vec<ExemptFn>,
+}
+
+type ExemptFn = Box<dyn Fn(&Parts) -> bool + Send + Sync>;
+
+impl AllowList {
+    pub fn add_key<K: Key>(&self, key: &K) {
+        _ = self.keys.insert(stable_hash(key));
+        self.has_keys.store(true, Ordering::Relaxed);
+    }
+
+    pub fn remove_key<K: Key>(&self, key: &K) -> bool {
+        let found = self.keys.remove(&stable_hash(key)).is_some();
+        self.has_keys.store(!self.keys.is_empty(), Ordering::Relaxed);
+        found
+    }
+
+    #[cfg(feature = "real_ip")]
+    pub fn add_network(&self, network: IpNetwork) {
+        let mut networks = self.networks.write().unwrap_or_else(|e| e.into_inner());
+
+        if !networks.contains(&network) {
+            networks.push(network);
+        }
+
+        self.has_networks.store(true, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "real_ip")]
+    pub fn remove_network(&self, network: IpNetwork) -> bool {
+        let mut networks = self.networks.write().unwrap_or_else(|e| e.into_inner());
+
+        let len = networks.len();
+        networks.retain(|net| *net != network);
+
+        self.has_networks.store(!networks.is_empty(), Ordering::Relaxed);
+
+        networks.len() != len
+    }
+
+    #[cfg(feature = "real_ip")]
+    pub fn networks(&self) -> Vec<IpNetwork> {
+        self.networks.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    #[inline]
+    pub fn allows_key<K: Key>(&self, key: &K) -> bool {
+        self.has_keys.load(Ordering::Relaxed) && self.keys.contains(&stable_hash(key))
+    }
+
+    pub fn set_bypass(&mut self, header: HeaderName, secret: &[u8]) {
+        self.bypass = Some((header, secret.into()));
+    }
+
+    /// Name of the bypass header, without the secret.
+    pub fn bypass_header(&self) -> Option<&HeaderName> {
+        self.bypass.as_ref().map(|(header, _)| header)
+    }
+
+    pub fn add_exemption(&mut self, pred: impl Fn(&Parts) -> bool + Send + Sync + 'static) {
+        self.exemptions.push(Box::new(pred));
+    }
+
+    /// Number of exemption predicates.
+    pub fn exemptions(&self) -> usize {
+        self.exemptions.len()
+    }
+
+    /// Check if the request carries the bypass secret, stripping the header in any case,
+    /// if it matches an exemption predicate, or if the client IP of the request is within an allowed network.
+    ///
+    /// Allowed requests skip the rate limiter entirely, and are forwarded without any of its extensions.
+    pub fn allows_client(&self, parts: &mut Parts) -> bool {
+        if let Some((ref header, ref secret)) = self.bypass {
+            // take all values so the secret never reaches the inner service
+            let mut found = false;
+
+            if let http::header::Entry::Occupied(entry) = parts.headers.entry(header) {
+                for value in entry.remove_entry_mult().1 {
+                    found |= constant_time_eq(value.as_bytes(), secret);
+                }
+            }
+
+            if found {
+                return true;
+            }
+        }
+
+        if self.exemptions.iter().any(|pred| pred(parts)) {
+            return true;
+        }
+
+        #[cfg(feature = "real_ip")]
+        if self.has_networks.load(Ordering::Relaxed) {
+            let ip = parts.extensions.get().copied().or_else(|| crate::real_ip::get_ip_from_parts(parts));
+
+            if let Some(crate::real_ip::RealIp(ip)) = ip {
+                let networks = self.networks.read().unwrap_or_else(|e| e.into_inner());
+
+                return networks.iter().any(|net| net.contains(ip));
+            }
+        }
+
+        false
+    }
+}
+
+/// Compare two byte strings in constant time with regard to their contents, to avoid leaking
+/// the secret through timing. Only the length may be leaked.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let diff = a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y));
+
+    std::hint::black_box(diff) == 0
+}
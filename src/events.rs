@@ -0,0 +1,83 @@
+//! Stream of rejection events, for alerting and fail2ban-style automation.
+//!
+//! See [`RateLimitLayerBuilder::with_event_stream`](crate::RateLimitLayerBuilder::with_event_stream).
+
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+
+use crate::{RateLimitContext, Route};
+
+/// Event emitted for every request rejected by a [`RateLimitLayer`](crate::RateLimitLayer),
+/// including those rejected by the [deny list](crate::RateLimitLayerBuilder::with_deny_list) or [bans](crate::RateLimitLayerBuilder::ban_after).
+#[derive(Debug, Clone)]
+pub struct RejectionEvent {
+    context: RateLimitContext,
+    violations: Option<u32>,
+}
+
+impl RejectionEvent {
+    /// Get the context of the rejection.
+    #[inline]
+    #[must_use]
+    pub fn context(&self) -> &RateLimitContext {
+        &self.context
+    }
+
+    /// Get the [`Debug`](std::fmt::Debug) representation of the rejected key.
+    #[inline]
+    #[must_use]
+    pub fn key(&self) -> &str {
+        self.context.key()
+    }
+
+    /// Get the route of the rejected request.
+    #[inline]
+    #[must_use]
+    pub fn route(&self) -> Route<'_> {
+        self.context.route()
+    }
+
+    /// Get the amount of time until the key can make another request.
+    #[inline]
+    #[must_use]
+    pub fn retry_after(&self) -> Duration {
+        self.context.retry_after()
+    }
+
+    /// Get the number of violations of the key within the current ban window, including this one,
+    /// if [bans](crate::RateLimitLayerBuilder::ban_after) are enabled and the request was rejected by the rate limiter.
+    #[inline]
+    #[must_use]
+    pub fn violations(&self) -> Option<u32> {
+        self.violations
+    }
+}
+
+/// Sender half of the event stream of a layer.
+#[derive(Debug, Clone)]
+pub(crate) struct Events {
+    tx: broadcast::Sender<RejectionEvent>,
+}
+
+impl Events {
+    pub fn new(capacity: usize) -> Self {
+        Events {
+            tx: broadcast::channel(capacity.max(1)).0,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<RejectionEvent> {
+        self.tx.subscribe()
+    }
+
+    /// Emit an event for the given rejection if anyone is listening.
+    pub fn emit(&self, context: &RateLimitContext, violations: Option<u32>) {
+        if self.tx.receiver_count() > 0 {
+            _ = self.tx.send(RejectionEvent {
+                context: context.clone(),
+                violations,
+            });
+        }
+    }
+}
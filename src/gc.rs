@@ -0,0 +1,181 @@
+//! Background garbage collection task for timed [GC intervals](crate::GCInterval::Time).
+
+use std::{fmt, hash::BuildHasher, sync::Arc, time::Duration};
+
+use tokio::{sync::watch, task::JoinHandle};
+
+use crate::{clock::Clock, gcra::RateLimiter, store, BuilderDropNotify, Key, RouteWithKey};
+
+/// Handle to the background garbage collection task of a rate limiter,
+/// as returned by [`RateLimitLayerBuilder::build_with_gc_handle`](crate::RateLimitLayerBuilder::build_with_gc_handle).
+///
+/// The task is aborted when this handle is dropped, unless [detached](GcHandle::detach).
+/// Use [`GcHandle::shutdown`] to stop it gracefully instead.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use axum_gcra::RateLimitLayer;
+///
+/// # async fn example() {
+/// let (layer, gc) = RateLimitLayer::<()>::builder()
+///     .with_gc_interval(Duration::from_secs(60))
+///     .build_with_gc_handle();
+///
+/// let gc = gc.expect("timed GC interval");
+///
+/// // clean more often under load
+/// gc.set_interval(Duration::from_secs(10));
+///
+/// // ... serve requests ...
+///
+/// gc.shutdown().await;
+/// # }
+/// ```
+pub struct GcHandle {
+    interval: watch::Sender<Option<Duration>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl GcHandle {
+    /// Get the current garbage collection interval.
+    #[must_use]
+    pub fn interval(&self) -> Duration {
+        self.interval.borrow().unwrap_or_default()
+    }
+
+    /// Change the garbage collection interval, restarting the timer from now.
+    ///
+    /// This has no effect if the task has been shut down.
+    pub fn set_interval(&self, interval: Duration) {
+        _ = self.interval.send_if_modified(|current| match current {
+            Some(current) => {
+                *current = interval;
+                true
+            }
+            None => false,
+        });
+    }
+
+    /// Returns `true` if the task has stopped, such as if the rate limiter was dropped.
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.task.as_ref().is_none_or(JoinHandle::is_finished)
+    }
+
+    /// Stop the task after any cleanup in progress, and wait for it to finish.
+    pub async fn shutdown(mut self) {
+        self.interval.send_replace(None);
+
+        if let Some(task) = self.task.take() {
+            _ = task.await;
+        }
+    }
+
+    /// Drop the handle without stopping the task, which then runs until the rate limiter is dropped.
+    pub fn detach(mut self) {
+        self.task = None;
+    }
+}
+
+impl Drop for GcHandle {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+impl fmt::Debug for GcHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GcHandle")
+            .field("interval", &self.interval())
+            .field("finished", &self.is_finished())
+            .finish()
+    }
+}
+
+/// Spawn the background garbage collection task.
+pub(crate) fn spawn<K: Key, H>(
+    limiter: Arc<RateLimiter<RouteWithKey<K>, H>>,
+    store: Option<Arc<dyn store::Store<K>>>,
+    clock: Arc<dyn Clock>,
+    signal: BuilderDropNotify,
+    interval: Duration,
+    incremental: bool,
+) -> GcHandle
+where
+    H: BuildHasher + Send + Sync + 'static,
+{
+    let (tx, mut rx) = watch::channel(Some(interval));
+
+    // one tick per shard, so every shard is still cleaned once per interval
+    let steps = if incremental { limiter.shard_count() } else { 1 };
+    let period = move |interval: Duration| (interval / steps as u32).max(Duration::from_nanos(1));
+
+    let task = tokio::task::spawn(async move {
+        let mut interval = tokio::time::interval(period(interval));
+        let mut step = 0;
+
+        // the handle may be detached, which closes the channel
+        let mut attached = true;
+
+        loop {
+            tokio::select! { biased;
+                _ = signal.notify.notified() => break,
+                res = rx.changed(), if attached => {
+                    let Ok(()) = res else {
+                        attached = false;
+                        continue;
+                    };
+
+                    let Some(new) = *rx.borrow_and_update() else { break };
+
+                    let new = period(new);
+                    interval = tokio::time::interval_at(tokio::time::Instant::now() + new, new);
+                    continue;
+                },
+                _ = interval.tick() => {},
+            }
+
+            #[cfg_attr(not(any(feature = "tracing", feature = "metrics")), allow(unused_variables))]
+            let stats =
+                if steps > 1 { limiter.clean_step(clock.now()).await } else { limiter.clean(clock.now()).await };
+
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                scanned = stats.scanned,
+                evicted = stats.evicted,
+                entries = limiter.len(),
+                "garbage collection",
+            );
+
+            #[cfg(feature = "metrics")]
+            crate::metrics::gc(stats, limiter.len());
+
+            step = (step + 1) % steps;
+
+            if let (Some(ref store), 0) = (&store, step) {
+                #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+                let res = store.gc(clock.unix_now()).await;
+
+                #[cfg(feature = "tracing")]
+                match res {
+                    Ok(s) => tracing::debug!(scanned = s.scanned, evicted = s.evicted, "store garbage collection"),
+                    Err(ref e) => tracing::warn!(error = %e, "store garbage collection failed"),
+                }
+            }
+
+            // also close task if no more references to the limiter
+            if Arc::strong_count(&limiter) == 1 {
+                break;
+            }
+        }
+    });
+
+    GcHandle {
+        interval: tx,
+        task: Some(task),
+    }
+}
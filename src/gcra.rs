@@ -5,74 +5,777 @@ use std::{
     borrow::Borrow,
     error::Error,
     fmt,
-    hash::{BuildHasher, Hash},
+    hash::{BuildHasher, Hash, Hasher},
+    io,
     num::NonZeroU64,
-    sync::atomic::{AtomicU64, Ordering},
-    time::{Duration, Instant},
+    path::Path,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
 };
 
-use scc::hash_map::{Entry, HashMap};
+use scc::hash_map::{Entry, HashMap, OccupiedEntry, VacantEntry};
+
+use crate::clock::Instant;
+
+mod window;
+
+pub mod simulate;
+
+#[cfg(feature = "gcra_interop")]
+pub mod interop;
 
 /// A rate limiter that uses the Generic Cell Rate Algorithm (GCRA) to limit the rate of requests.
 ///
 /// This rate limiter is designed to be used in a concurrent environment, and is thread-safe.
+///
+/// Requests for existing keys only take a shared read lock on the key's bucket in the table,
+/// and update the entry with a lock-free compare-and-swap loop on its [`Gcra`] timestamp,
+/// so concurrent requests for the same key never wait on each other. Exclusive locks are
+/// only taken when inserting or removing keys.
+///
+/// # Example
+///
+/// ```rust
+/// use std::{num::NonZeroU64, sync::atomic::{AtomicU64, Ordering}, time::{Duration, Instant}};
+/// use axum_gcra::gcra::{Quota, RateLimiter};
+///
+/// let limiter = RateLimiter::<&str>::default();
+/// let quota = Quota::new(Duration::from_secs(3600), NonZeroU64::new(100).unwrap());
+///
+/// let now = Instant::now();
+/// let admitted = AtomicU64::new(0);
+///
+/// // many threads racing on the same key admit exactly the burst size, no more, no less
+/// std::thread::scope(|s| {
+///     for _ in 0..8 {
+///         s.spawn(|| {
+///             for _ in 0..100 {
+///                 if limiter.req_sync("key", quota, now).is_ok() {
+///                     admitted.fetch_add(1, Ordering::Relaxed);
+///                 }
+///             }
+///         });
+///     }
+/// });
+///
+/// assert_eq!(admitted.into_inner(), quota.burst());
+/// ```
 pub struct RateLimiter<K: Eq + Hash, H: BuildHasher = std::collections::hash_map::RandomState> {
     start: Instant,
+
+    /// Relative timestamp of `start`, such that relative timestamps are aligned to UTC days,
+    /// and so are [window-based](Algorithm) quotas.
+    offset: u64,
+
+    /// UNIX timestamp of relative timestamp zero, in nanoseconds.
+    start_epoch: u64,
     gc_interval: u64,
-    last_gc: AtomicU64,
+    hasher: H,
+    shards: Box<[Shard<K, H>]>,
+
+    /// Maximum number of entries per shard, see [`RateLimiter::with_max_entries`].
+    shard_capacity: usize,
+    overflow: OverflowPolicy,
+
+    /// Next shard to clean, see [`RateLimiter::clean_step`].
+    gc_cursor: AtomicUsize,
+
+    /// Number of calls to [`RateLimiter::clean`] and [`RateLimiter::clean_step`], see [`RateLimiter::stats`].
+    gc_runs: AtomicU64,
+    last_gc_nanos: AtomicU64,
+
+    /// Entries restored from a [`HashedState`], by stable key hash, which are
+    /// moved into `limits` when their key is first seen.
+    restored: HashMap<u64, u64>,
+    has_restored: AtomicBool,
+
+    /// Per-key idle timeouts for garbage collection, see [`RateLimiter::with_idle_timeouts`].
+    idle_timeouts: Option<IdleTimeouts<K>>,
+
+    /// Callbacks for inserted and evicted keys, see [`RateLimiter::with_lifecycle`].
+    lifecycle: Option<Box<dyn Lifecycle<K>>>,
+}
+
+/// Callback giving the idle timeout of a key, see [`RateLimiter::with_idle_timeouts`].
+type IdleTimeouts<K> = Box<dyn Fn(&K) -> Option<Duration> + Send + Sync>;
+
+/// Callbacks notified when keys enter and leave a [`RateLimiter`], see [`RateLimiter::with_lifecycle`].
+///
+/// Both methods do nothing by default. They are called while the bucket of the key in the table is locked,
+/// so they should be quick, such as sending the key to a channel, and must not access the rate limiter.
+pub trait Lifecycle<K>: Send + Sync {
+    /// Called when an entry is inserted for a key that was not in the rate limiter.
+    fn inserted(&self, key: &K) {
+        _ = key;
+    }
+
+    /// Called when the entry of a key is removed by garbage collection, or evicted because the rate limiter
+    /// was [full](RateLimiter::with_max_entries). Entries removed explicitly, such as by
+    /// [`RateLimiter::remove_where`], are not reported.
+    fn evicted(&self, key: &K, entry: EvictedEntry) {
+        _ = (key, entry);
+    }
+}
+
+/// Final state of an entry removed from a [`RateLimiter`], as given to [`Lifecycle::evicted`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct EvictedEntry {
+    /// Theoretical arrival time of the entry, after which the key had its full quota back.
+    pub tat: Instant,
+
+    /// Time of the last request of the key, to within about a second.
+    pub last_seen: Instant,
+
+    /// Whether the entry was evicted to make room for new keys, rather than because it expired or was idle.
+    pub overflow: bool,
+}
+
+/// One independent shard of the rate limiter entries, see [`RateLimiter::with_shards`].
+struct Shard<K, H: BuildHasher> {
     limits: HashMap<K, Gcra, H>,
+    last_gc: AtomicU64,
+
+    /// Approximate number of entries, which is recounted on every full scan of the shard.
+    len: AtomicUsize,
+
+    /// Number of entries inserted and evicted over the lifetime of the shard, see [`RateLimiter::stats`].
+    inserts: AtomicU64,
+    evictions: AtomicU64,
+
+    /// Number of new keys that found the shard full, used to schedule sweeps for expired entries.
+    overflows: AtomicU64,
 }
 
-impl<K: Eq + Hash, H: BuildHasher> RateLimiter<K, H> {
+impl<K: Eq + Hash, H: BuildHasher> Shard<K, H> {
+    /// Retain only the entries matching the predicate, returning the number of entries removed.
+    async fn retain_async(&self, mut f: impl FnMut(&K, &mut Gcra) -> bool) -> usize {
+        let (mut len, mut removed) = (0, 0);
+        self.limits
+            .retain_async(|k, v| {
+                let keep = f(k, v);
+                len += keep as usize;
+                removed += !keep as usize;
+                keep
+            })
+            .await;
+        self.len.store(len, Ordering::Relaxed);
+        removed
+    }
+
+    /// Synchronous version of [`Shard::retain_async`].
+    fn retain_sync(&self, mut f: impl FnMut(&K, &mut Gcra) -> bool) -> usize {
+        let (mut len, mut removed) = (0, 0);
+        self.limits.retain(|k, v| {
+            let keep = f(k, v);
+            len += keep as usize;
+            removed += !keep as usize;
+            keep
+        });
+        self.len.store(len, Ordering::Relaxed);
+        removed
+    }
+
+    /// Like [`Shard::retain_async`], but counting the removed entries as evictions.
+    async fn evict_async(&self, f: impl FnMut(&K, &mut Gcra) -> bool) {
+        let removed = self.retain_async(f).await;
+        self.evictions.fetch_add(removed as u64, Ordering::Relaxed);
+    }
+
+    /// Synchronous version of [`Shard::evict_async`].
+    fn evict_sync(&self, f: impl FnMut(&K, &mut Gcra) -> bool) {
+        let removed = self.retain_sync(f);
+        self.evictions.fetch_add(removed as u64, Ordering::Relaxed);
+    }
+
+    fn inserted(&self) {
+        self.inserts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn removed(&self) {
+        _ = self.len.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |len| len.checked_sub(1));
+    }
+
+    /// Count a new key that found the shard full, returning `true` if the shard should be swept
+    /// for expired entries, which is only done once per batch of overflows of the given capacity.
+    fn overflowed(&self, capacity: usize) -> bool {
+        let batch = (capacity / 16).max(1) as u64;
+        self.overflows.fetch_add(1, Ordering::Relaxed).is_multiple_of(batch)
+    }
+}
+
+/// What to do with requests for new keys when the rate limiter is [full](RateLimiter::with_max_entries),
+/// after removing any expired entries.
+///
+/// Except with [`OverflowPolicy::EvictOldest`], a full shard is only swept for expired entries once per
+/// batch of new keys, a sixteenth of its capacity, so that floods of new keys are turned away without scanning
+/// the shard each time.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Evict the longest-idle entries to make room for the new key, which is the default.
+    ///
+    /// Evicted keys start over with a full quota.
+    #[default]
+    EvictOldest,
+
+    /// Reject requests for new keys, retrying after one emission interval of their quota,
+    /// while existing keys are unaffected.
+    RejectNew,
+
+    /// Allow requests for new keys without tracking them, so they are not rate limited
+    /// until there is room in the rate limiter again.
+    FailOpen,
+}
+
+impl OverflowPolicy {
+    /// Decide a request for a new key that could not be inserted, returning the TAT it would have.
+    fn decide(self, quota: Quota, n: u64, now: u64) -> Result<u64, RateLimitError> {
+        match self {
+            OverflowPolicy::RejectNew => Err(RateLimitError(quota.interval())),
+            _ => decide(None, now, quota, n),
+        }
+    }
+}
+
+/// Find the TAT at or below which entries must be evicted from a full shard to make room for new entries,
+/// freeing a small batch at once so that the cost of scanning the shard is amortized.
+fn eviction_threshold(mut tats: Vec<u64>, capacity: usize) -> Option<u64> {
+    let batch = (capacity / 16).max(1);
+    let excess = (tats.len() + batch).checked_sub(capacity)?.min(tats.len());
+
+    match excess {
+        0 => None,
+        _ => Some(*tats.select_nth_unstable(excess - 1).1),
+    }
+}
+
+/// One day in nanoseconds, which relative timestamps are aligned to.
+const DAY: u64 = 86_400_000_000_000;
+
+/// Returns the default number of shards, which is the number of available CPUs.
+pub(crate) fn default_shards() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+impl<K: Eq + Hash, H: BuildHasher + Clone> RateLimiter<K, H> {
     /// Constructs a new rate limiter with the given GCRA hasher and garbage collection interval, which is in number of requests
     /// (i.e. how many requests to process before cleaning up old entries), not time.
+    ///
+    /// The entries are split into one shard per available CPU, see [`RateLimiter::with_shards`].
     pub fn new(gc_interval: u64, hasher: H) -> Self {
+        Self::with_shards(gc_interval, default_shards(), hasher)
+    }
+
+    /// Constructs a new rate limiter like [`RateLimiter::new`], with the entries split into the given number
+    /// of independent shards selected by key hash, to reduce contention on machines with many cores.
+    ///
+    /// Each shard is garbage collected on its own, after `gc_interval` new entries have been
+    /// inserted into that shard. A shard count of zero is treated as one.
+    pub fn with_shards(gc_interval: u64, shards: usize, hasher: H) -> Self {
+        let shards = (0..shards.max(1))
+            .map(|_| Shard {
+                limits: HashMap::with_hasher(hasher.clone()),
+                last_gc: AtomicU64::new(1),
+                len: AtomicUsize::new(0),
+                inserts: AtomicU64::new(0),
+                evictions: AtomicU64::new(0),
+                overflows: AtomicU64::new(0),
+            })
+            .collect();
+
+        let epoch = crate::store::now();
+        let offset = epoch % DAY;
+
         RateLimiter {
             start: Instant::now(),
+            offset,
+            start_epoch: epoch - offset,
             gc_interval,
-            last_gc: AtomicU64::new(1),
-            limits: HashMap::with_hasher(hasher),
+            hasher,
+            shards,
+            shard_capacity: usize::MAX,
+            overflow: OverflowPolicy::default(),
+            gc_cursor: AtomicUsize::new(0),
+            gc_runs: AtomicU64::new(0),
+            last_gc_nanos: AtomicU64::new(0),
+            restored: HashMap::default(),
+            has_restored: AtomicBool::new(false),
+            idle_timeouts: None,
+            lifecycle: None,
+        }
+    }
+}
+
+impl<K: Eq + Hash, H: BuildHasher> RateLimiter<K, H> {
+    /// Limit the number of entries in the rate limiter to about the given maximum, to bound memory usage,
+    /// such as during floods of requests from spoofed IP addresses.
+    ///
+    /// When full, expired entries are removed first, and then the longest-idle entries, which are those with
+    /// the oldest theoretical arrival time and thus the closest to having their full quota back anyway.
+    /// Evicted keys start over with a full quota, so the maximum should comfortably exceed the number of
+    /// legitimate active keys.
+    ///
+    /// The maximum is split evenly between [shards](RateLimiter::with_shards), and may be exceeded
+    /// briefly by concurrent insertions.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::{collections::hash_map::RandomState, time::{Duration, Instant}};
+    /// use axum_gcra::gcra::{Quota, RateLimiter};
+    ///
+    /// let limiter = RateLimiter::<u32>::with_shards(8192, 1, RandomState::new()).with_max_entries(100);
+    /// let quota = Quota::simple(Duration::from_secs(60));
+    ///
+    /// for ip in 0..10_000 {
+    ///     _ = limiter.req_sync(ip, quota, Instant::now());
+    /// }
+    ///
+    /// assert!(limiter.len() <= 100);
+    /// ```
+    #[must_use]
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.shard_capacity = max_entries.div_ceil(self.shards.len()).max(1);
+        self
+    }
+
+    /// Set what happens to requests for new keys when the rate limiter is [full](RateLimiter::with_max_entries).
+    /// The default is [`OverflowPolicy::EvictOldest`].
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::{collections::hash_map::RandomState, time::{Duration, Instant}};
+    /// use axum_gcra::gcra::{OverflowPolicy, Quota, RateLimiter};
+    ///
+    /// let limiter = RateLimiter::<u32>::with_shards(8192, 1, RandomState::new())
+    ///     .with_max_entries(1)
+    ///     .with_overflow_policy(OverflowPolicy::RejectNew);
+    ///
+    /// let quota = Quota::simple(Duration::from_secs(60));
+    ///
+    /// assert!(limiter.req_sync(1, quota, Instant::now()).is_ok());
+    /// assert!(limiter.req_sync(2, quota, Instant::now()).is_err());
+    /// ```
+    #[must_use]
+    pub fn with_overflow_policy(mut self, overflow: OverflowPolicy) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Evict entries during garbage collection once their key has been idle for the duration given by the callback,
+    /// rather than once they have their full quota back, or keep them as usual if the callback returns `None`.
+    ///
+    /// Idle timeouts shorter than the replenish time of a quota forgive the rest of the used quota of idle keys,
+    /// such as to avoid keeping entries of long-period quotas for days, while longer idle timeouts keep entries
+    /// of keys that come back regularly. Any request counts as activity, including rejected ones, and idle time
+    /// is tracked to within about a second.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::{collections::hash_map::RandomState, time::{Duration, Instant}};
+    /// use axum_gcra::gcra::{Quota, RateLimiter};
+    ///
+    /// let limiter = RateLimiter::<u32>::with_shards(8192, 1, RandomState::new())
+    ///     .with_idle_timeouts(|&key| (key == 0).then_some(Duration::from_secs(60)));
+    ///
+    /// // 1000 requests per day
+    /// let quota = Quota::simple(Duration::from_secs(86400 / 1000));
+    ///
+    /// let now = Instant::now();
+    /// _ = limiter.req_sync(0, quota, now);
+    /// _ = limiter.req_sync(1, quota, now);
+    ///
+    /// // only key 0 is evicted after being idle for more than a minute
+    /// limiter.clean_sync(now + Duration::from_secs(62));
+    /// assert_eq!(limiter.len(), 1);
+    /// ```
+    #[must_use]
+    pub fn with_idle_timeouts(mut self, f: impl Fn(&K) -> Option<Duration> + Send + Sync + 'static) -> Self {
+        self.idle_timeouts = Some(Box::new(f));
+        self
+    }
+
+    /// Evict entries during garbage collection once their key has been idle for the given duration,
+    /// regardless of the period of their quota. See [`RateLimiter::with_idle_timeouts`] for more information.
+    #[must_use]
+    pub fn with_idle_timeout(self, timeout: Duration) -> Self {
+        self.with_idle_timeouts(move |_| Some(timeout))
+    }
+
+    /// Notify the given callbacks when keys are inserted into the rate limiter, and when they are removed by
+    /// garbage collection or evicted, such as to track the lifecycle of clients in an external system.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::{collections::hash_map::RandomState, sync::{Arc, Mutex}, time::{Duration, Instant}};
+    /// use axum_gcra::gcra::{EvictedEntry, Lifecycle, Quota, RateLimiter};
+    ///
+    /// #[derive(Default, Clone)]
+    /// struct Clients(Arc<Mutex<Vec<u32>>>);
+    ///
+    /// impl Lifecycle<u32> for Clients {
+    ///     fn inserted(&self, key: &u32) {
+    ///         self.0.lock().unwrap().push(*key);
+    ///     }
+    ///
+    ///     fn evicted(&self, key: &u32, _: EvictedEntry) {
+    ///         self.0.lock().unwrap().retain(|k| k != key);
+    ///     }
+    /// }
+    ///
+    /// let clients = Clients::default();
+    /// let limiter = RateLimiter::<u32>::with_shards(8192, 1, RandomState::new()).with_lifecycle(clients.clone());
+    /// let quota = Quota::simple(Duration::from_secs(1));
+    ///
+    /// let now = Instant::now();
+    /// _ = limiter.req_sync(1, quota, now);
+    /// assert_eq!(*clients.0.lock().unwrap(), [1]);
+    ///
+    /// limiter.clean_sync(now + Duration::from_secs(5));
+    /// assert!(clients.0.lock().unwrap().is_empty());
+    /// ```
+    #[must_use]
+    pub fn with_lifecycle(mut self, lifecycle: impl Lifecycle<K> + 'static) -> Self {
+        self.lifecycle = Some(Box::new(lifecycle));
+        self
+    }
+
+    /// Insert the entry of a new key, notifying the [lifecycle](RateLimiter::with_lifecycle) callbacks.
+    #[inline]
+    fn insert_new<'a>(&self, entry: VacantEntry<'a, K, Gcra, H>, gcra: Gcra) -> OccupiedEntry<'a, K, Gcra, H> {
+        let entry = entry.insert_entry(gcra);
+
+        if let Some(ref lifecycle) = self.lifecycle {
+            lifecycle.inserted(entry.key());
+        }
+
+        entry
+    }
+
+    /// Wrap a garbage collection predicate to notify the [lifecycle](RateLimiter::with_lifecycle) callbacks
+    /// of the entries it removes.
+    #[inline]
+    fn evicting<'a>(
+        &'a self,
+        overflow: bool,
+        mut keep: impl FnMut(&K, &mut Gcra) -> bool + 'a,
+    ) -> impl FnMut(&K, &mut Gcra) -> bool + 'a {
+        move |key, gcra| {
+            let kept = keep(key, gcra);
+
+            if let (false, Some(lifecycle)) = (kept, &self.lifecycle) {
+                let entry = EvictedEntry {
+                    tat: gcra.tat(self.start, self.offset),
+                    last_seen: self.start + Duration::from_nanos(gcra.seen().saturating_sub(self.offset)),
+                    overflow,
+                };
+
+                lifecycle.evicted(key, entry);
+            }
+
+            kept
+        }
+    }
+
+    /// Check if garbage collection should keep the given entry as of `before`, see [`RateLimiter::with_idle_timeouts`].
+    #[inline]
+    fn keep(&self, key: &K, gcra: &mut Gcra, before: u64) -> bool {
+        match self.idle_timeouts.as_ref().and_then(|f| f(key)) {
+            Some(timeout) => before.saturating_sub(gcra.seen()) < timeout.as_nanos() as u64,
+            None => *AtomicU64::get_mut(&mut gcra.0) >= before,
+        }
+    }
+
+    /// Start relative timestamps at the given time instead of when the rate limiter was created,
+    /// so that timestamps from a [`Clock`](crate::clock::Clock) that lags behind are not truncated.
+    #[must_use]
+    pub(crate) fn with_start(mut self, start: Instant) -> Self {
+        self.start = start;
+        self
+    }
+
+    /// Returns the approximate number of entries in the rate limiter.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.len.load(Ordering::Relaxed)).sum()
+    }
+
+    /// Returns `true` if the rate limiter has approximately no entries.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Prepare the shard for inserting a new entry, running garbage collection if due, and evicting
+    /// entries if the shard is full. Returns the overflow policy if the entry must not be inserted.
+    async fn prepare_insert(&self, shard: &Shard<K, H>, now: u64) -> Option<OverflowPolicy> {
+        if self.should_gc(shard) {
+            shard.evict_async(self.evicting(false, |k, v| self.keep(k, v, now))).await;
+        }
+
+        if shard.len.fetch_add(1, Ordering::Relaxed) < self.shard_capacity {
+            shard.inserted();
+            return None;
+        }
+
+        // without evicting live entries the shard stays full, so only sweep it every so often
+        if self.overflow != OverflowPolicy::EvictOldest && !shard.overflowed(self.shard_capacity) {
+            shard.removed(); // the new entry is not inserted after all
+            return Some(self.overflow);
+        }
+
+        shard.evict_async(self.evicting(false, |k, v| self.keep(k, v, now))).await;
+
+        if shard.len.load(Ordering::Relaxed) >= self.shard_capacity {
+            if self.overflow != OverflowPolicy::EvictOldest {
+                return Some(self.overflow);
+            }
+
+            let mut tats = Vec::new();
+            shard.limits.scan_async(|_, v| tats.push(v.0.load(Ordering::Relaxed))).await;
+
+            if let Some(threshold) = eviction_threshold(tats, self.shard_capacity) {
+                shard
+                    .evict_async(self.evicting(true, move |_, v| *AtomicU64::get_mut(&mut v.0) > threshold))
+                    .await;
+            }
+        }
+
+        // count the entry about to be inserted
+        shard.len.fetch_add(1, Ordering::Relaxed);
+        shard.inserted();
+
+        None
+    }
+
+    /// Synchronous version of [`RateLimiter::prepare_insert`].
+    fn prepare_insert_sync(&self, shard: &Shard<K, H>, now: u64) -> Option<OverflowPolicy> {
+        if self.should_gc(shard) {
+            shard.evict_sync(self.evicting(false, |k, v| self.keep(k, v, now)));
+        }
+
+        if shard.len.fetch_add(1, Ordering::Relaxed) < self.shard_capacity {
+            shard.inserted();
+            return None;
+        }
+
+        // without evicting live entries the shard stays full, so only sweep it every so often
+        if self.overflow != OverflowPolicy::EvictOldest && !shard.overflowed(self.shard_capacity) {
+            shard.removed(); // the new entry is not inserted after all
+            return Some(self.overflow);
+        }
+
+        shard.evict_sync(self.evicting(false, |k, v| self.keep(k, v, now)));
+
+        if shard.len.load(Ordering::Relaxed) >= self.shard_capacity {
+            if self.overflow != OverflowPolicy::EvictOldest {
+                return Some(self.overflow);
+            }
+
+            let mut tats = Vec::new();
+            shard.limits.scan(|_, v| tats.push(v.0.load(Ordering::Relaxed)));
+
+            if let Some(threshold) = eviction_threshold(tats, self.shard_capacity) {
+                shard.evict_sync(self.evicting(true, move |_, v| *AtomicU64::get_mut(&mut v.0) > threshold));
+            }
+        }
+
+        // count the entry about to be inserted
+        shard.len.fetch_add(1, Ordering::Relaxed);
+        shard.inserted();
+
+        None
+    }
+
+    /// Returns the number of shards the entries are split into, see [`RateLimiter::with_shards`].
+    #[must_use]
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    #[inline]
+    fn shard<Q: Hash + ?Sized>(&self, key: &Q) -> &Shard<K, H> {
+        if self.shards.len() == 1 {
+            return &self.shards[0];
+        }
+
+        // salted so keys within a shard don't all share the same hash bits in the map itself
+        let mut hasher = self.hasher.build_hasher();
+        hasher.write_u8(0xA5);
+        key.hash(&mut hasher);
+
+        &self.shards[(hasher.finish() % self.shards.len() as u64) as usize]
+    }
+
+    fn gc_finished(&self, started: Instant) {
+        self.gc_runs.fetch_add(1, Ordering::Relaxed);
+        self.last_gc_nanos.store(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Returns statistics about the entries and garbage collection of the rate limiter,
+    /// to export to whatever metrics system is in use.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::{collections::hash_map::RandomState, time::{Duration, Instant}};
+    /// use axum_gcra::gcra::{Quota, RateLimiter};
+    ///
+    /// let limiter = RateLimiter::<u32>::with_shards(8192, 1, RandomState::new());
+    /// let quota = Quota::simple(Duration::from_secs(1));
+    ///
+    /// for ip in 0..10 {
+    ///     _ = limiter.req_sync(ip, quota, Instant::now());
+    /// }
+    ///
+    /// limiter.clean_sync(Instant::now() + Duration::from_secs(2));
+    ///
+    /// let stats = limiter.stats();
+    /// assert_eq!(stats.inserts, 10);
+    /// assert_eq!(stats.evictions, 10);
+    /// assert_eq!(stats.entries, 0);
+    /// assert_eq!(stats.gc_runs, 1);
+    /// ```
+    #[must_use]
+    pub fn stats(&self) -> LimiterStats {
+        let mut stats = LimiterStats {
+            gc_runs: self.gc_runs.load(Ordering::Relaxed),
+            last_gc_duration: Duration::from_nanos(self.last_gc_nanos.load(Ordering::Relaxed)),
+            ..LimiterStats::default()
+        };
+
+        for shard in self.shards.iter() {
+            stats.entries += shard.len.load(Ordering::Relaxed);
+            stats.inserts += shard.inserts.load(Ordering::Relaxed);
+            stats.evictions += shard.evictions.load(Ordering::Relaxed);
+            stats.memory_estimate += shard.limits.capacity() * slot_size::<K, Gcra>();
         }
+
+        stats.memory_estimate += self.restored.capacity() * slot_size::<u64, u64>();
+        stats
     }
 
-    fn should_gc(&self) -> bool {
-        self.gc_interval != u64::MAX && 0 == self.last_gc.fetch_add(1, Ordering::Relaxed) % self.gc_interval
+    fn should_gc(&self, shard: &Shard<K, H>) -> bool {
+        self.gc_interval != u64::MAX
+            && shard.last_gc.fetch_add(1, Ordering::Relaxed).is_multiple_of(self.gc_interval)
     }
 
     #[inline]
     fn relative(&self, ts: Instant) -> u64 {
-        ts.saturating_duration_since(self.start).as_nanos() as u64
+        ts.saturating_duration_since(self.start).as_nanos() as u64 + self.offset
     }
 
-    /// Cleans up any entries that have not been accessed since the given time.
-    pub async fn clean(&self, before: Instant) {
+    /// Cleans up any entries that have expired before the given time,
+    /// returning statistics about the entries scanned and evicted.
+    pub async fn clean(&self, before: Instant) -> GCStats {
+        let started = Instant::now();
         let before = self.relative(before);
-        self.limits.retain_async(move |_, v| *AtomicU64::get_mut(&mut v.0) >= before).await;
-        self.last_gc.store(1, Ordering::Relaxed); // manual reset
+        let mut stats = GCStats::default();
+        for shard in self.shards.iter() {
+            shard.evict_async(self.evicting(false, |k, v| stats.retain(self.keep(k, v, before)))).await;
+            shard.last_gc.store(1, Ordering::Relaxed); // manual reset
+        }
+
+        if self.has_restored.load(Ordering::Relaxed) {
+            let before = before + self.start_epoch;
+            self.restored.retain_async(|_, tat| *tat >= before).await;
+            self.has_restored.store(!self.restored.is_empty(), Ordering::Relaxed);
+        }
+
+        self.gc_finished(started);
+        stats
     }
 
     /// Synchronous version of [`RateLimiter::clean`].
-    pub fn clean_sync(&self, before: Instant) {
+    pub fn clean_sync(&self, before: Instant) -> GCStats {
+        let started = Instant::now();
+        let before = self.relative(before);
+        let mut stats = GCStats::default();
+        for shard in self.shards.iter() {
+            shard.evict_sync(self.evicting(false, |k, v| stats.retain(self.keep(k, v, before))));
+            shard.last_gc.store(1, Ordering::Relaxed); // manual reset
+        }
+
+        if self.has_restored.load(Ordering::Relaxed) {
+            let before = before + self.start_epoch;
+            self.restored.retain(|_, tat| *tat >= before);
+            self.has_restored.store(!self.restored.is_empty(), Ordering::Relaxed);
+        }
+
+        self.gc_finished(started);
+        stats
+    }
+
+    /// Cleans up any entries that have expired before the given time in the next shard only,
+    /// cycling through all [shards](RateLimiter::with_shards) over successive calls.
+    ///
+    /// This bounds the work done per call to a single shard, so that cleaning a very large
+    /// table can be spread out over time without latency spikes.
+    pub async fn clean_step(&self, before: Instant) -> GCStats {
+        let started = Instant::now();
+        let before = self.relative(before);
+        let mut stats = GCStats::default();
+
+        let idx = self.gc_cursor.fetch_add(1, Ordering::Relaxed) % self.shards.len();
+        let shard = &self.shards[idx];
+
+        shard.evict_async(self.evicting(false, |k, v| stats.retain(self.keep(k, v, before)))).await;
+        shard.last_gc.store(1, Ordering::Relaxed); // manual reset
+
+        if idx == 0 && self.has_restored.load(Ordering::Relaxed) {
+            let before = before + self.start_epoch;
+            self.restored.retain_async(|_, tat| *tat >= before).await;
+            self.has_restored.store(!self.restored.is_empty(), Ordering::Relaxed);
+        }
+
+        self.gc_finished(started);
+        stats
+    }
+
+    /// Synchronous version of [`RateLimiter::clean_step`].
+    pub fn clean_step_sync(&self, before: Instant) -> GCStats {
+        let started = Instant::now();
         let before = self.relative(before);
-        self.limits.retain(move |_, v| *AtomicU64::get_mut(&mut v.0) >= before);
-        self.last_gc.store(1, Ordering::Relaxed); // manual reset
+        let mut stats = GCStats::default();
+
+        let idx = self.gc_cursor.fetch_add(1, Ordering::Relaxed) % self.shards.len();
+        let shard = &self.shards[idx];
+
+        shard.evict_sync(self.evicting(false, |k, v| stats.retain(self.keep(k, v, before))));
+        shard.last_gc.store(1, Ordering::Relaxed); // manual reset
+
+        if idx == 0 && self.has_restored.load(Ordering::Relaxed) {
+            let before = before + self.start_epoch;
+            self.restored.retain(|_, tat| *tat >= before);
+            self.has_restored.store(!self.restored.is_empty(), Ordering::Relaxed);
+        }
+
+        self.gc_finished(started);
+        stats
     }
 
     /// Perform a request, returning an error if the request is too soon.
     pub async fn req(&self, key: K, quota: Quota, now: Instant) -> Result<(), RateLimitError> {
         let now = self.relative(now);
+        let shard = self.shard(&key);
 
-        let Some(res) = self.limits.read_async(&key, |_, gcra| gcra.req(quota, now)).await else {
-            if self.should_gc() {
-                self.limits.retain_async(move |_, v| *AtomicU64::get_mut(&mut v.0) >= now).await;
+        let Some(res) = shard.limits.read_async(&key, |_, gcra| gcra.req(quota, now)).await else {
+            if let Some(overflow) = self.prepare_insert(shard, now).await {
+                return overflow.decide(quota, 1, now).map(|_| ());
             }
 
-            return match self.limits.entry_async(key).await {
+            return match shard.limits.entry_async(key).await {
                 Entry::Occupied(gcra) => gcra.get().req(quota, now),
-                Entry::Vacant(gcra) => {
-                    gcra.insert_entry(Gcra::first(quota, now));
-                    Ok(())
-                }
+                Entry::Vacant(gcra) => match self.take_restored(gcra.key(), now) {
+                    Some(restored) => self.insert_new(gcra, restored).get().req(quota, now),
+                    None => {
+                        self.insert_new(gcra, Gcra::first(quota, now));
+                        Ok(())
+                    }
+                },
             };
         };
 
@@ -82,17 +785,46 @@ impl<K: Eq + Hash, H: BuildHasher> RateLimiter<K, H> {
     /// Synchonous version of [`RateLimiter::req`].
     pub fn req_sync(&self, key: K, quota: Quota, now: Instant) -> Result<(), RateLimitError> {
         let now = self.relative(now);
+        let shard = self.shard(&key);
 
-        let Some(res) = self.limits.read(&key, |_, gcra| gcra.req(quota, now)) else {
-            if self.should_gc() {
-                self.limits.retain(move |_, v| *AtomicU64::get_mut(&mut v.0) >= now);
+        let Some(res) = shard.limits.read(&key, |_, gcra| gcra.req(quota, now)) else {
+            if let Some(overflow) = self.prepare_insert_sync(shard, now) {
+                return overflow.decide(quota, 1, now).map(|_| ());
             }
 
-            return match self.limits.entry(key) {
+            return match shard.limits.entry(key) {
                 Entry::Occupied(gcra) => gcra.get().req(quota, now),
+                Entry::Vacant(gcra) => match self.take_restored(gcra.key(), now) {
+                    Some(restored) => self.insert_new(gcra, restored).get().req(quota, now),
+                    None => {
+                        self.insert_new(gcra, Gcra::first(quota, now));
+                        Ok(())
+                    }
+                },
+            };
+        };
+
+        res
+    }
+
+    /// Perform a request costing `n` requests at once, returning an error if the request is too soon.
+    ///
+    /// Either all `n` requests are allowed, or none are. Note that if `n` exceeds the
+    /// [burst size](Quota::burst) of the quota, the request can never succeed.
+    pub async fn req_n(&self, key: K, quota: Quota, n: u64, now: Instant) -> Result<(), RateLimitError> {
+        let now = self.relative(now);
+        let shard = self.shard(&key);
+
+        let Some(res) = shard.limits.read_async(&key, |_, gcra| gcra.req_n(quota, n, now)).await else {
+            if let Some(overflow) = self.prepare_insert(shard, now).await {
+                return overflow.decide(quota, n, now).map(|_| ());
+            }
+
+            return match shard.limits.entry_async(key).await {
+                Entry::Occupied(gcra) => gcra.get().req_n(quota, n, now),
                 Entry::Vacant(gcra) => {
-                    gcra.insert_entry(Gcra::first(quota, now));
-                    Ok(())
+                    let initial = self.take_restored(gcra.key(), now).unwrap_or_else(|| Gcra::empty(quota, now));
+                    self.insert_new(gcra, initial).get().req_n(quota, n, now)
                 }
             };
         };
@@ -100,27 +832,217 @@ impl<K: Eq + Hash, H: BuildHasher> RateLimiter<K, H> {
         res
     }
 
-    /// Variant of [`RateLimiter::req`] that allows for a peek at the key after it's been inserted.
-    pub(crate) async fn req_peek_key<F>(
-        &self,
-        key: K,
-        quota: Quota,
-        now: Instant,
-        peek: F,
-    ) -> Result<(), RateLimitError>
-    where
-        F: FnOnce(&K),
-    {
+    /// Synchronous version of [`RateLimiter::req_n`].
+    pub fn req_n_sync(&self, key: K, quota: Quota, n: u64, now: Instant) -> Result<(), RateLimitError> {
         let now = self.relative(now);
-        let mut peek = Some(peek);
+        let shard = self.shard(&key);
 
-        let read = self
-            .limits
-            .read_async(&key, |_, gcra| {
-                gcra.req(quota, now)?;
-                let peek = unsafe { peek.take().unwrap_unchecked() }; // SAFETY: peek is Some
-                peek(&key);
-                Ok(())
+        let Some(res) = shard.limits.read(&key, |_, gcra| gcra.req_n(quota, n, now)) else {
+            if let Some(overflow) = self.prepare_insert_sync(shard, now) {
+                return overflow.decide(quota, n, now).map(|_| ());
+            }
+
+            return match shard.limits.entry(key) {
+                Entry::Occupied(gcra) => gcra.get().req_n(quota, n, now),
+                Entry::Vacant(gcra) => {
+                    let initial = self.take_restored(gcra.key(), now).unwrap_or_else(|| Gcra::empty(quota, now));
+                    self.insert_new(gcra, initial).get().req_n(quota, n, now)
+                }
+            };
+        };
+
+        res
+    }
+
+    /// Variant of [`RateLimiter::req`] taking a borrowed key, such as `&str` for `String` keys,
+    /// which is only converted into an owned key when a new entry has to be inserted.
+    ///
+    /// This avoids allocating an owned key on every request for keys that are already present.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::time::{Duration, Instant};
+    /// use axum_gcra::gcra::{Quota, RateLimiter};
+    ///
+    /// let limiter = RateLimiter::<String>::default();
+    /// let quota = Quota::simple(Duration::from_secs(1));
+    ///
+    /// let token: &str = "token-from-header";
+    ///
+    /// assert!(limiter.req_borrowed_sync(token, quota, Instant::now()).is_ok());
+    /// assert!(limiter.req_borrowed_sync(token, quota, Instant::now()).is_err());
+    /// ```
+    pub async fn req_borrowed<Q>(&self, key: &Q, quota: Quota, now: Instant) -> Result<(), RateLimitError>
+    where
+        K: Borrow<Q> + for<'q> From<&'q Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.req_n_borrowed(key, quota, 1, now).await
+    }
+
+    /// Synchronous version of [`RateLimiter::req_borrowed`].
+    pub fn req_borrowed_sync<Q>(&self, key: &Q, quota: Quota, now: Instant) -> Result<(), RateLimitError>
+    where
+        K: Borrow<Q> + for<'q> From<&'q Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.req_n_borrowed_sync(key, quota, 1, now)
+    }
+
+    /// Variant of [`RateLimiter::req_n`] taking a borrowed key, see [`RateLimiter::req_borrowed`].
+    pub async fn req_n_borrowed<Q>(
+        &self,
+        key: &Q,
+        quota: Quota,
+        n: u64,
+        now: Instant,
+    ) -> Result<(), RateLimitError>
+    where
+        K: Borrow<Q> + for<'q> From<&'q Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let now = self.relative(now);
+        let shard = self.shard(key);
+
+        if let Some(res) = shard.limits.read_async(key, |_, gcra| gcra.req_n(quota, n, now)).await {
+            return res;
+        }
+
+        if let Some(overflow) = self.prepare_insert(shard, now).await {
+            return overflow.decide(quota, n, now).map(|_| ());
+        }
+
+        // only allocate the owned key when inserting
+        match shard.limits.entry_async(K::from(key)).await {
+            Entry::Occupied(gcra) => gcra.get().req_n(quota, n, now),
+            Entry::Vacant(gcra) => {
+                let initial = self.take_restored(gcra.key(), now).unwrap_or_else(|| Gcra::empty(quota, now));
+                self.insert_new(gcra, initial).get().req_n(quota, n, now)
+            }
+        }
+    }
+
+    /// Synchronous version of [`RateLimiter::req_n_borrowed`].
+    pub fn req_n_borrowed_sync<Q>(&self, key: &Q, quota: Quota, n: u64, now: Instant) -> Result<(), RateLimitError>
+    where
+        K: Borrow<Q> + for<'q> From<&'q Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let now = self.relative(now);
+        let shard = self.shard(key);
+
+        if let Some(res) = shard.limits.read(key, |_, gcra| gcra.req_n(quota, n, now)) {
+            return res;
+        }
+
+        if let Some(overflow) = self.prepare_insert_sync(shard, now) {
+            return overflow.decide(quota, n, now).map(|_| ());
+        }
+
+        // only allocate the owned key when inserting
+        match shard.limits.entry(K::from(key)) {
+            Entry::Occupied(gcra) => gcra.get().req_n(quota, n, now),
+            Entry::Vacant(gcra) => {
+                let initial = self.take_restored(gcra.key(), now).unwrap_or_else(|| Gcra::empty(quota, now));
+                self.insert_new(gcra, initial).get().req_n(quota, n, now)
+            }
+        }
+    }
+
+    /// Returns the time at which the next request for the given key will be allowed,
+    /// or `None` if it would be allowed right now. This does not consume any quota.
+    pub async fn retry_at<Q>(&self, key: &Q, quota: Quota, now: Instant) -> Option<Instant>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.check(key, quota, now).await.err().map(|e| now + e.as_duration())
+    }
+
+    /// Synchronous version of [`RateLimiter::retry_at`].
+    pub fn retry_at_sync<Q>(&self, key: &Q, quota: Quota, now: Instant) -> Option<Instant>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.check_sync(key, quota, now).err().map(|e| now + e.as_duration())
+    }
+
+    /// Checks if a request would be allowed, without consuming any quota.
+    ///
+    /// Returns the error that [`RateLimiter::req`] would return if the request is too soon.
+    pub async fn check<Q>(&self, key: &Q, quota: Quota, now: Instant) -> Result<(), RateLimitError>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let now = self.relative(now);
+        self.shard(key).limits.read_async(key, |_, gcra| gcra.check(quota, now)).await.unwrap_or(Ok(()))
+    }
+
+    /// Synchronous version of [`RateLimiter::check`].
+    pub fn check_sync<Q>(&self, key: &Q, quota: Quota, now: Instant) -> Result<(), RateLimitError>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let now = self.relative(now);
+        self.shard(key).limits.read(key, |_, gcra| gcra.check(quota, now)).unwrap_or(Ok(()))
+    }
+
+    /// Returns the current [`Status`] of the given key for the given quota, without consuming any quota.
+    pub async fn status<Q>(&self, key: &Q, quota: Quota, now: Instant) -> Status
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let now = self.relative(now);
+        self.shard(key)
+            .limits
+            .read_async(key, |_, gcra| gcra.status(quota, now))
+            .await
+            .unwrap_or_else(|| Status::full(quota))
+    }
+
+    /// Synchronous version of [`RateLimiter::status`].
+    pub fn status_sync<Q>(&self, key: &Q, quota: Quota, now: Instant) -> Status
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let now = self.relative(now);
+        self.shard(key)
+            .limits
+            .read(key, |_, gcra| gcra.status(quota, now))
+            .unwrap_or_else(|| Status::full(quota))
+    }
+
+    /// Compute the status of an entry with the given theoretical arrival time (TAT), as given by
+    /// [`RateLimiter::scan`].
+    ///
+    /// Unlike [`Status::at`], this is also correct for window-based quotas, whose windows are
+    /// aligned to the epoch of the rate limiter.
+    #[must_use]
+    pub fn status_at(&self, tat: Instant, now: Instant, quota: Quota) -> Status {
+        Status::compute(self.relative(tat), self.relative(now), quota)
+    }
+
+    /// Variant of [`RateLimiter::req`] that allows for a peek at the key and the decision made for it,
+    /// returning whatever the `peek` callback returns.
+    pub(crate) async fn req_peek_key<F, R>(&self, key: K, quota: Quota, now: Instant, peek: F) -> R
+    where
+        F: FnOnce(&K, Result<Admitted, RateLimitError>) -> R,
+    {
+        let now = self.relative(now);
+        let shard = self.shard(&key);
+        let mut peek = Some(peek);
+
+        let read = shard
+            .limits
+            .read_async(&key, |_, gcra| {
+                let peek = unsafe { peek.take().unwrap_unchecked() }; // SAFETY: peek is Some
+                peek(&key, gcra.req_tat(quota, now).map(|tat| Admitted { tat, now }))
             })
             .await;
 
@@ -130,21 +1052,80 @@ impl<K: Eq + Hash, H: BuildHasher> RateLimiter<K, H> {
             let peek = unsafe { peek.unwrap_unchecked() };
 
             // since we hit the slow path, perform garbage collection
-            if self.should_gc() {
-                self.limits.retain_async(move |_, v| *AtomicU64::get_mut(&mut v.0) >= now).await;
+            if let Some(overflow) = self.prepare_insert(shard, now).await {
+                return peek(&key, overflow.decide(quota, 1, now).map(|tat| Admitted { tat, now }));
             }
 
-            return match self.limits.entry_async(key).await {
-                Entry::Occupied(gcra) => {
-                    gcra.get().req(quota, now)?;
-                    peek(gcra.key());
-                    Ok(())
-                }
-                Entry::Vacant(gcra) => {
-                    let gcra = gcra.insert_entry(Gcra::first(quota, now));
-                    peek(gcra.key());
-                    Ok(())
-                }
+            return match shard.limits.entry_async(key).await {
+                Entry::Occupied(gcra) => peek(
+                    gcra.key(),
+                    gcra.get().req_tat(quota, now).map(|tat| Admitted { tat, now }),
+                ),
+                Entry::Vacant(gcra) => match self.take_restored(gcra.key(), now) {
+                    Some(restored) => {
+                        let gcra = self.insert_new(gcra, restored);
+                        peek(
+                            gcra.key(),
+                            gcra.get().req_tat(quota, now).map(|tat| Admitted { tat, now }),
+                        )
+                    }
+                    None => {
+                        let first = Gcra::first(quota, now);
+                        let tat = first.0.load(Ordering::Relaxed);
+                        let gcra = self.insert_new(gcra, first);
+                        peek(gcra.key(), Ok(Admitted { tat, now }))
+                    }
+                },
+            };
+        };
+
+        res
+    }
+
+    /// Synchronous version of [`RateLimiter::req_peek_key`].
+    pub(crate) fn req_peek_key_sync<F, R>(&self, key: K, quota: Quota, now: Instant, peek: F) -> R
+    where
+        F: FnOnce(&K, Result<Admitted, RateLimitError>) -> R,
+    {
+        let now = self.relative(now);
+        let shard = self.shard(&key);
+        let mut peek = Some(peek);
+
+        let read = shard.limits.read(&key, |_, gcra| {
+            let peek = unsafe { peek.take().unwrap_unchecked() }; // SAFETY: peek is Some
+            peek(&key, gcra.req_tat(quota, now).map(|tat| Admitted { tat, now }))
+        });
+
+        // if read returns Some, then peek was consumed
+        let Some(res) = read else {
+            // otherwise we're free to unwrap it and use it here normally
+            let peek = unsafe { peek.unwrap_unchecked() };
+
+            // since we hit the slow path, perform garbage collection
+            if let Some(overflow) = self.prepare_insert_sync(shard, now) {
+                return peek(&key, overflow.decide(quota, 1, now).map(|tat| Admitted { tat, now }));
+            }
+
+            return match shard.limits.entry(key) {
+                Entry::Occupied(gcra) => peek(
+                    gcra.key(),
+                    gcra.get().req_tat(quota, now).map(|tat| Admitted { tat, now }),
+                ),
+                Entry::Vacant(gcra) => match self.take_restored(gcra.key(), now) {
+                    Some(restored) => {
+                        let gcra = self.insert_new(gcra, restored);
+                        peek(
+                            gcra.key(),
+                            gcra.get().req_tat(quota, now).map(|tat| Admitted { tat, now }),
+                        )
+                    }
+                    None => {
+                        let first = Gcra::first(quota, now);
+                        let tat = first.0.load(Ordering::Relaxed);
+                        let gcra = self.insert_new(gcra, first);
+                        peek(gcra.key(), Ok(Admitted { tat, now }))
+                    }
+                },
             };
         };
 
@@ -164,7 +1145,8 @@ impl<K: Eq + Hash, H: BuildHasher> RateLimiter<K, H> {
         K: Borrow<Q>,
         Q: Eq + Hash + ?Sized,
     {
-        self.limits
+        self.shard(key)
+            .limits
             .read_async(key, |_, grca| {
                 grca.0.fetch_add(penalty.as_nanos() as u64, Ordering::Relaxed)
             })
@@ -178,20 +1160,69 @@ impl<K: Eq + Hash, H: BuildHasher> RateLimiter<K, H> {
         K: Borrow<Q>,
         Q: Eq + Hash + ?Sized,
     {
-        self.limits
+        self.shard(key)
+            .limits
             .read(key, |_, grca| {
                 grca.0.fetch_add(penalty.as_nanos() as u64, Ordering::Relaxed)
             })
             .is_some()
     }
 
+    /// Charges the given amount of time to the given key as of `now`, like [`RateLimiter::penalize_sync`],
+    /// except that idle time before `now` is not credited. Returns `true` if the key was found.
+    pub(crate) fn charge_sync<Q>(&self, key: &Q, cost: Duration, now: Instant) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let (now, cost) = (self.relative(now), cost.as_nanos() as u64);
+
+        self.shard(key)
+            .limits
+            .read(key, |_, gcra| {
+                _ = gcra.0.fetch_update(Ordering::AcqRel, Ordering::Relaxed, |prev| {
+                    Some(prev.max(now).saturating_add(cost))
+                })
+            })
+            .is_some()
+    }
+
+    /// Refunds the given amount of time to the given key, returning `true` if the key was found.
+    ///
+    /// This is the inverse of [`RateLimiter::penalize`], and can be used to give back
+    /// quota for requests that turned out to be free, e.g. cache hits. A single request
+    /// costs the [emission interval](Quota::emission_interval) of its quota.
+    ///
+    /// Refunds cannot accumulate beyond the burst size of the quota.
+    pub async fn refund<Q>(&self, key: &Q, amount: Duration) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.shard(key).limits.read_async(key, |_, gcra| gcra.refund(amount)).await.is_some()
+    }
+
+    /// Synchronous version of [`RateLimiter::refund`].
+    pub fn refund_sync<Q>(&self, key: &Q, amount: Duration) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.shard(key).limits.read(key, |_, gcra| gcra.refund(amount)).is_some()
+    }
+
     /// Resets the rate limit for the given key, returning `true` if the key was found.
     pub async fn reset<Q>(&self, key: &Q) -> bool
     where
         K: Borrow<Q>,
         Q: Eq + Hash + ?Sized,
     {
-        self.limits.remove_async(key).await.is_some()
+        let shard = self.shard(key);
+        let found = shard.limits.remove_async(key).await.is_some();
+        if found {
+            shard.removed();
+        }
+        found
     }
 
     /// Synchronous version of [`RateLimiter::reset`].
@@ -200,182 +1231,1633 @@ impl<K: Eq + Hash, H: BuildHasher> RateLimiter<K, H> {
         K: Borrow<Q>,
         Q: Eq + Hash + ?Sized,
     {
-        self.limits.remove(key).is_some()
+        let shard = self.shard(key);
+        let found = shard.limits.remove(key).is_some();
+        if found {
+            shard.removed();
+        }
+        found
+    }
+
+    /// Calls the given function for every entry in the rate limiter, with the key and
+    /// its theoretical arrival time (TAT), which is the GCRA timestamp of the entry.
+    ///
+    /// Use [`RateLimiter::status_at`] to compute the status of an entry from its TAT.
+    pub async fn scan<F>(&self, mut f: F)
+    where
+        F: FnMut(&K, Instant),
+    {
+        let (start, offset) = (self.start, self.offset);
+        for shard in self.shards.iter() {
+            shard.limits.scan_async(|k, gcra| f(k, gcra.tat(start, offset))).await;
+        }
+    }
+
+    /// Synchronous version of [`RateLimiter::scan`].
+    pub fn scan_sync<F>(&self, mut f: F)
+    where
+        F: FnMut(&K, Instant),
+    {
+        let (start, offset) = (self.start, self.offset);
+        for shard in self.shards.iter() {
+            shard.limits.scan(|k, gcra| f(k, gcra.tat(start, offset)));
+        }
+    }
+
+    /// Export all unexpired entries as a [`MergeableState`], with timestamps relative to the UNIX epoch
+    /// so the state can be merged into rate limiters in other processes, such as after a restart.
+    pub async fn export_state(&self) -> MergeableState<K>
+    where
+        K: Clone,
+    {
+        let now = self.relative(Instant::now());
+        let mut state = MergeableState::default();
+        for shard in self.shards.iter() {
+            shard.limits.scan_async(|k, gcra| state.export(k, gcra, now, self.start_epoch)).await;
+        }
+        state
+    }
+
+    /// Synchronous version of [`RateLimiter::export_state`].
+    pub fn export_state_sync(&self) -> MergeableState<K>
+    where
+        K: Clone,
+    {
+        let now = self.relative(Instant::now());
+        let mut state = MergeableState::default();
+        for shard in self.shards.iter() {
+            shard.limits.scan(|k, gcra| state.export(k, gcra, now, self.start_epoch));
+        }
+        state
+    }
+
+    /// Merge the given state into this rate limiter, keeping the latest theoretical arrival time
+    /// of each entry, so that merging is commutative, associative and idempotent.
+    ///
+    /// Expired entries in the given state are skipped. Returns the number of entries merged.
+    pub async fn merge(&self, state: &MergeableState<K>) -> usize
+    where
+        K: Clone,
+    {
+        let now = self.relative(Instant::now());
+        let mut merged = 0;
+
+        for (key, &tat) in &state.entries {
+            let tat = tat.saturating_sub(self.start_epoch);
+
+            if tat >= now {
+                let shard = self.shard(key);
+                self.merge_entry(shard, shard.limits.entry_async(key.clone()).await, tat, now);
+                merged += 1;
+            }
+        }
+
+        merged
+    }
+
+    /// Synchronous version of [`RateLimiter::merge`].
+    pub fn merge_sync(&self, state: &MergeableState<K>) -> usize
+    where
+        K: Clone,
+    {
+        let now = self.relative(Instant::now());
+        let mut merged = 0;
+
+        for (key, &tat) in &state.entries {
+            let tat = tat.saturating_sub(self.start_epoch);
+
+            if tat >= now {
+                let shard = self.shard(key);
+                self.merge_entry(shard, shard.limits.entry(key.clone()), tat, now);
+                merged += 1;
+            }
+        }
+
+        merged
+    }
+
+    fn merge_entry(&self, shard: &Shard<K, H>, entry: Entry<'_, K, Gcra, H>, tat: u64, now: u64) {
+        match entry {
+            Entry::Occupied(gcra) => _ = gcra.get().0.fetch_max(tat, Ordering::AcqRel),
+            Entry::Vacant(gcra) => {
+                self.insert_new(gcra, Gcra::from_tat(tat, now));
+                shard.len.fetch_add(1, Ordering::Relaxed);
+                shard.inserted();
+            }
+        }
+    }
+
+    /// Take the restored state of a new entry, if any, see [`RateLimiter::restore`].
+    #[inline]
+    fn take_restored(&self, key: &K, now: u64) -> Option<Gcra> {
+        if !self.has_restored.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let (_, tat) = self.restored.remove(&stable_hash(key))?;
+        let tat = tat.saturating_sub(self.start_epoch);
+
+        (tat >= now).then(|| Gcra::from_tat(tat, now))
+    }
+
+    /// Export all unexpired entries as a [`HashedState`], with keys abstracted via a stable hash,
+    /// which does not require keys to be serializable.
+    pub async fn hashed_state(&self) -> HashedState {
+        let now = self.relative(Instant::now());
+        let mut state = HashedState::default();
+        for shard in self.shards.iter() {
+            shard.limits.scan_async(|k, gcra| state.export(k, gcra, now, self.start_epoch)).await;
+        }
+        self.restored
+            .scan_async(|&hash, &tat| state.export_restored(hash, tat, now, self.start_epoch))
+            .await;
+        state
+    }
+
+    /// Synchronous version of [`RateLimiter::hashed_state`].
+    pub fn hashed_state_sync(&self) -> HashedState {
+        let now = self.relative(Instant::now());
+        let mut state = HashedState::default();
+        for shard in self.shards.iter() {
+            shard.limits.scan(|k, gcra| state.export(k, gcra, now, self.start_epoch));
+        }
+        self.restored.scan(|&hash, &tat| state.export_restored(hash, tat, now, self.start_epoch));
+        state
+    }
+
+    /// Restore entries from a [`HashedState`], such as one saved before a restart.
+    ///
+    /// Since only the key hashes are known, restored entries are applied when their key is
+    /// first seen by [`RateLimiter::req`] or [`RateLimiter::req_n`], and take precedence over
+    /// fresh entries at that point. Existing entries are not affected. Returns the number of
+    /// unexpired entries restored.
+    pub fn restore(&self, state: &HashedState) -> usize {
+        let now = crate::store::now();
+        let mut restored = 0;
+
+        for &HashedEntry { key_hash, tat } in &state.entries {
+            if tat >= now {
+                self.restored.upsert(key_hash, tat);
+                restored += 1;
+            }
+        }
+
+        if restored > 0 {
+            self.has_restored.store(true, Ordering::Relaxed);
+        }
+
+        restored
+    }
+
+    /// Save all unexpired entries to the given file, see [`RateLimiter::hashed_state`].
+    ///
+    /// The file is written atomically by writing to a temporary file first and then renaming it.
+    /// This performs blocking file I/O.
+    pub fn save_to(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+
+        std::fs::write(&tmp, self.hashed_state_sync().encode())?;
+        std::fs::rename(&tmp, path)
+    }
+
+    /// Restore entries from a file previously written by [`RateLimiter::save_to`],
+    /// returning the number of unexpired entries restored. See [`RateLimiter::restore`].
+    ///
+    /// This performs blocking file I/O.
+    pub fn load_from(&self, path: impl AsRef<Path>) -> io::Result<usize> {
+        Ok(self.restore(&HashedState::decode(&std::fs::read(path)?)?))
+    }
+
+    /// Removes all entries with keys matching the given predicate, returning the number of entries removed.
+    pub async fn remove_where<F>(&self, mut pred: F) -> usize
+    where
+        F: FnMut(&K) -> bool,
+    {
+        let mut removed = 0;
+
+        for shard in self.shards.iter() {
+            shard
+                .retain_async(|k, _| {
+                    let remove = pred(k);
+                    removed += remove as usize;
+                    !remove
+                })
+                .await;
+        }
+
+        removed
+    }
+
+    /// Synchronous version of [`RateLimiter::remove_where`].
+    pub fn remove_where_sync<F>(&self, mut pred: F) -> usize
+    where
+        F: FnMut(&K) -> bool,
+    {
+        let mut removed = 0;
+
+        for shard in self.shards.iter() {
+            shard.retain_sync(|k, _| {
+                let remove = pred(k);
+                removed += remove as usize;
+                !remove
+            });
+        }
+
+        removed
+    }
+}
+
+/// A [`RateLimiter`] with a single fixed [`Quota`], for rate limiting things outside of HTTP requests,
+/// such as background jobs or WebSocket messages.
+///
+/// The lower-level [`RateLimiter`] methods are also available via [`Deref`](std::ops::Deref).
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use axum_gcra::gcra::{KeyedRateLimiter, Quota};
+///
+/// let limiter = KeyedRateLimiter::<&str>::new(Quota::simple(Duration::from_secs(1)));
+///
+/// assert!(limiter.req_sync("job").is_ok());
+/// assert!(limiter.req_sync("job").is_err());
+/// assert!(limiter.retry_at_sync("job").is_some());
+/// assert!(limiter.req_sync("other-job").is_ok());
+/// ```
+pub struct KeyedRateLimiter<K: Eq + Hash, H: BuildHasher = std::collections::hash_map::RandomState> {
+    limiter: RateLimiter<K, H>,
+    quota: Quota,
+}
+
+impl<K: Eq + Hash, H: BuildHasher + Default + Clone> KeyedRateLimiter<K, H> {
+    /// Constructs a new keyed rate limiter with the given quota.
+    #[must_use]
+    pub fn new(quota: Quota) -> Self {
+        Self::with_limiter(quota, RateLimiter::default())
+    }
+}
+
+impl<K: Eq + Hash, H: BuildHasher> KeyedRateLimiter<K, H> {
+    /// Constructs a new keyed rate limiter with the given quota and underlying [`RateLimiter`].
+    #[must_use]
+    pub fn with_limiter(quota: Quota, limiter: RateLimiter<K, H>) -> Self {
+        KeyedRateLimiter { limiter, quota }
+    }
+
+    /// Returns the quota of this rate limiter.
+    #[inline]
+    #[must_use]
+    pub fn quota(&self) -> Quota {
+        self.quota
+    }
+
+    /// Perform a request, returning an error if the request is too soon.
+    pub async fn req(&self, key: K) -> Result<(), RateLimitError> {
+        self.limiter.req(key, self.quota, Instant::now()).await
+    }
+
+    /// Synchronous version of [`KeyedRateLimiter::req`].
+    pub fn req_sync(&self, key: K) -> Result<(), RateLimitError> {
+        self.limiter.req_sync(key, self.quota, Instant::now())
+    }
+
+    /// Perform a request costing `n` requests at once. See [`RateLimiter::req_n`] for more information.
+    pub async fn req_n(&self, key: K, n: u64) -> Result<(), RateLimitError> {
+        self.limiter.req_n(key, self.quota, n, Instant::now()).await
+    }
+
+    /// Synchronous version of [`KeyedRateLimiter::req_n`].
+    pub fn req_n_sync(&self, key: K, n: u64) -> Result<(), RateLimitError> {
+        self.limiter.req_n_sync(key, self.quota, n, Instant::now())
+    }
+
+    /// Perform a request with a borrowed key. See [`RateLimiter::req_borrowed`] for more information.
+    pub async fn req_borrowed<Q>(&self, key: &Q) -> Result<(), RateLimitError>
+    where
+        K: Borrow<Q> + for<'q> From<&'q Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.limiter.req_borrowed(key, self.quota, Instant::now()).await
+    }
+
+    /// Synchronous version of [`KeyedRateLimiter::req_borrowed`].
+    pub fn req_borrowed_sync<Q>(&self, key: &Q) -> Result<(), RateLimitError>
+    where
+        K: Borrow<Q> + for<'q> From<&'q Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.limiter.req_borrowed_sync(key, self.quota, Instant::now())
+    }
+
+    /// Returns the time at which the next request for the given key will be allowed,
+    /// or `None` if it would be allowed right now.
+    pub async fn retry_at<Q>(&self, key: &Q) -> Option<Instant>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.limiter.retry_at(key, self.quota, Instant::now()).await
+    }
+
+    /// Synchronous version of [`KeyedRateLimiter::retry_at`].
+    pub fn retry_at_sync<Q>(&self, key: &Q) -> Option<Instant>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.limiter.retry_at_sync(key, self.quota, Instant::now())
+    }
+}
+
+impl<K: Eq + Hash, H: BuildHasher> std::ops::Deref for KeyedRateLimiter<K, H> {
+    type Target = RateLimiter<K, H>;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.limiter
+    }
+}
+
+impl<K: Eq + Hash, H: BuildHasher> Default for RateLimiter<K, H>
+where
+    H: Default + Clone,
+{
+    fn default() -> Self {
+        // default to 8192 unique requests before garbage collection
+        RateLimiter::new(8192, H::default())
+    }
+}
+
+/// Mergeable snapshot of rate limiter entries, mapping each key to its theoretical arrival time (TAT)
+/// in nanoseconds since the UNIX epoch.
+///
+/// Each entry is a max-register, so states form a CRDT: [merging](MergeableState::merge) keeps the
+/// latest TAT of each key, and states can be merged in any order, any number of times, with the same
+/// result. This allows eventually-consistent replication patterns, such as periodically uploading
+/// snapshots from each instance and merging them all on startup with [`RateLimiter::merge`].
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::{Duration, Instant};
+/// use axum_gcra::gcra::{Quota, RateLimiter};
+///
+/// let quota = Quota::simple(Duration::from_secs(60));
+///
+/// let a = RateLimiter::<&str>::default();
+/// let b = RateLimiter::<&str>::default();
+///
+/// assert!(a.req_sync("user", quota, Instant::now()).is_ok());
+///
+/// let mut state = b.export_state_sync();
+/// state.merge(a.export_state_sync());
+/// b.merge_sync(&state);
+///
+/// assert!(b.req_sync("user", quota, Instant::now()).is_err());
+/// ```
+#[derive(Debug, Clone)]
+pub struct MergeableState<K> {
+    entries: std::collections::HashMap<K, u64>,
+}
+
+impl<K> Default for MergeableState<K> {
+    fn default() -> Self {
+        MergeableState {
+            entries: Default::default(),
+        }
+    }
+}
+
+impl<K: Eq + Hash> MergeableState<K> {
+    /// Merge another state into this one, keeping the latest TAT of each key.
+    pub fn merge(&mut self, other: MergeableState<K>) {
+        for (key, tat) in other.entries {
+            self.insert(key, tat);
+        }
+    }
+
+    /// Insert a single entry, keeping the latest TAT if the key is already present.
+    pub fn insert(&mut self, key: K, tat: u64) {
+        let entry = self.entries.entry(key).or_insert(tat);
+        *entry = (*entry).max(tat);
+    }
+
+    /// Get the TAT of the given key, in nanoseconds since the UNIX epoch.
+    pub fn get<Q>(&self, key: &Q) -> Option<u64>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.entries.get(key).copied()
+    }
+
+    /// Returns the number of entries.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if there are no entries.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterate over all entries and their TATs, in nanoseconds since the UNIX epoch.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, u64)> + '_ {
+        self.entries.iter().map(|(k, &tat)| (k, tat))
+    }
+
+    /// Convert the keys of all entries, such as into a serializable form,
+    /// merging entries that map to the same key.
+    pub fn map_keys<T: Eq + Hash>(self, mut f: impl FnMut(K) -> T) -> MergeableState<T> {
+        self.entries.into_iter().map(|(k, tat)| (f(k), tat)).collect()
+    }
+
+    #[inline]
+    fn export(&mut self, key: &K, gcra: &Gcra, now: u64, epoch: u64)
+    where
+        K: Clone,
+    {
+        let tat = gcra.0.load(Ordering::Relaxed);
+
+        if tat >= now {
+            self.insert(key.clone(), tat + epoch);
+        }
+    }
+}
+
+impl<K: Eq + Hash> FromIterator<(K, u64)> for MergeableState<K> {
+    fn from_iter<I: IntoIterator<Item = (K, u64)>>(iter: I) -> Self {
+        let mut state = MergeableState::default();
+        for (key, tat) in iter {
+            state.insert(key, tat);
+        }
+        state
+    }
+}
+
+impl<K> IntoIterator for MergeableState<K> {
+    type Item = (K, u64);
+    type IntoIter = std::collections::hash_map::IntoIter<K, u64>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+/// Snapshot of rate limiter entries with keys abstracted via a stable hash, mapped to their theoretical
+/// arrival time (TAT) in nanoseconds since the UNIX epoch, as used by [`RateLimiter::save_to`].
+///
+/// Key hashes are computed from the key's [`Hash`] implementation with a fixed hash function,
+/// independent of the rate limiter's hasher, so they agree between processes running the same build.
+///
+/// With the `serde` cargo feature, this implements `Serialize` and `Deserialize` as a list of
+/// `{ "key_hash": u64, "tat": u64 }` objects, such as to dump it to JSON for debugging, or to ship it
+/// to another instance during a blue/green cutover.
+///
+/// # Example
+///
+/// ```rust
+/// # #[cfg(feature = "serde")] {
+/// use std::time::{Duration, Instant};
+/// use axum_gcra::gcra::{HashedState, Quota, RateLimiter};
+///
+/// let quota = Quota::simple(Duration::from_secs(60));
+///
+/// let old = RateLimiter::<&str>::default();
+/// assert!(old.req_sync("user", quota, Instant::now()).is_ok());
+///
+/// let json = serde_json::to_string(&old.hashed_state_sync()).unwrap();
+///
+/// let new = RateLimiter::<&str>::default();
+/// new.restore(&serde_json::from_str::<HashedState>(&json).unwrap());
+///
+/// assert!(new.req_sync("user", quota, Instant::now()).is_err());
+/// # }
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct HashedState {
+    entries: Vec<HashedEntry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct HashedEntry {
+    key_hash: u64,
+    tat: u64,
+}
+
+impl HashedState {
+    const MAGIC: &'static [u8; 5] = b"GCRA\x01";
+
+    /// Returns the number of entries.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if there are no entries.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterate over all entries as key hashes and their TATs, in nanoseconds since the UNIX epoch.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.entries.iter().map(|e| (e.key_hash, e.tat))
+    }
+
+    /// Encode the state into a compact binary format.
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::MAGIC.len() + 8 + self.entries.len() * 16);
+
+        buf.extend_from_slice(Self::MAGIC);
+        buf.extend_from_slice(&(self.entries.len() as u64).to_le_bytes());
+
+        for entry in &self.entries {
+            buf.extend_from_slice(&entry.key_hash.to_le_bytes());
+            buf.extend_from_slice(&entry.tat.to_le_bytes());
+        }
+
+        buf
+    }
+
+    /// Decode the state from the binary format produced by [`HashedState::encode`].
+    pub fn decode(buf: &[u8]) -> io::Result<HashedState> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid rate limiter state");
+
+        let buf = buf.strip_prefix(Self::MAGIC).ok_or_else(invalid)?;
+        let (len, buf) = buf.split_first_chunk::<8>().ok_or_else(invalid)?;
+
+        let len = u64::from_le_bytes(*len) as usize;
+        if buf.len() != len.checked_mul(16).ok_or_else(invalid)? {
+            return Err(invalid());
+        }
+
+        let entries = buf
+            .chunks_exact(16)
+            .map(|entry| {
+                let (hash, tat) = entry.split_at(8);
+
+                HashedEntry {
+                    key_hash: u64::from_le_bytes(hash.try_into().unwrap()),
+                    tat: u64::from_le_bytes(tat.try_into().unwrap()),
+                }
+            })
+            .collect();
+
+        Ok(HashedState { entries })
+    }
+
+    #[inline]
+    fn export<K: Hash>(&mut self, key: &K, gcra: &Gcra, now: u64, epoch: u64) {
+        let tat = gcra.0.load(Ordering::Relaxed);
+
+        if tat >= now {
+            self.entries.push(HashedEntry {
+                key_hash: stable_hash(key),
+                tat: tat + epoch,
+            });
+        }
+    }
+
+    /// Restored entries which have not been seen yet are kept as-is.
+    #[inline]
+    fn export_restored(&mut self, hash: u64, tat: u64, now: u64, epoch: u64) {
+        if tat >= now + epoch {
+            self.entries.push(HashedEntry { key_hash: hash, tat });
+        }
+    }
+}
+
+/// FNV-1a hasher, which is stable across processes unlike [`std::collections::hash_map::RandomState`].
+struct StableHasher(u64);
+
+impl std::hash::Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+pub(crate) fn stable_hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = StableHasher(0xcbf29ce484222325);
+    value.hash(&mut hasher);
+    std::hash::Hasher::finish(&hasher)
+}
+
+/// Approximate size of a hash table slot with the given key and value, plus per-slot metadata.
+const fn slot_size<K, V>() -> usize {
+    std::mem::size_of::<K>() + std::mem::size_of::<V>() + 2
+}
+
+/// Statistics about the entries and garbage collection of a [`RateLimiter`], as returned by [`RateLimiter::stats`].
+///
+/// Counters are cumulative over the lifetime of the rate limiter, so rates can be derived
+/// by the metrics system from successive samples.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LimiterStats {
+    /// Approximate number of entries, as returned by [`RateLimiter::len`].
+    pub entries: usize,
+
+    /// Number of entries inserted for new keys.
+    pub inserts: u64,
+
+    /// Number of entries evicted because they expired or the rate limiter was
+    /// [full](RateLimiter::with_max_entries). Entries removed explicitly,
+    /// such as by [`RateLimiter::remove_where`], are not counted.
+    pub evictions: u64,
+
+    /// Number of calls to [`RateLimiter::clean`] or [`RateLimiter::clean_step`], or their synchronous versions.
+    pub gc_runs: u64,
+
+    /// Duration of the last call to [`RateLimiter::clean`] or [`RateLimiter::clean_step`],
+    /// or their synchronous versions.
+    pub last_gc_duration: Duration,
+
+    /// Rough estimate of the memory used by the hash tables, in bytes, based on their capacity.
+    /// Memory owned by keys themselves, such as strings, is not included.
+    pub memory_estimate: usize,
+
+    /// Number of [delayed](crate::RateLimitLayerBuilder::with_delay) requests currently waiting,
+    /// as reported by [`RateLimitLayer::stats`](crate::RateLimitLayer::stats). Always zero for rate limiters on their own.
+    pub delayed: usize,
+}
+
+/// Statistics from a garbage collection run, as returned by [`RateLimiter::clean`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GCStats {
+    /// Number of entries scanned.
+    pub scanned: usize,
+
+    /// Number of expired entries evicted.
+    pub evicted: usize,
+}
+
+impl GCStats {
+    #[inline]
+    fn retain(&mut self, keep: bool) -> bool {
+        self.scanned += 1;
+        self.evicted += !keep as usize;
+        keep
+    }
+}
+
+/// An error that occurs when a rate limit is exceeded,
+/// with the amount of time until the next request can be made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct RateLimitError(pub NonZeroU64);
+
+impl fmt::Display for RateLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rate limit exceeded, retry in {:.3} seconds",
+            self.as_duration().as_secs_f32()
+        )
+    }
+}
+
+impl Error for RateLimitError {}
+
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+
+impl IntoResponse for RateLimitError {
+    fn into_response(self) -> Response {
+        use http::{HeaderName, HeaderValue};
+
+        // reuse as_duration value
+        let reset = self.as_duration();
+        let reset_secs = reset.as_secs().max(1);
+
+        #[cfg(not(feature = "problem_json"))]
+        let mut res = {
+            let mut res = Response::new(From::from(format!(
+                "rate limit exceeded, retry in {:.3} seconds",
+                reset.as_secs_f32()
+            )));
+
+            *res.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+            res
+        };
+
+        #[cfg(feature = "problem_json")]
+        let mut res = crate::problem::response(StatusCode::TOO_MANY_REQUESTS, &self.to_string(), Some(reset_secs));
+
+        // optimize for common values
+        let value = match reset_secs {
+            1 => HeaderValue::from_static("1"),
+            2 => HeaderValue::from_static("2"),
+            _ => {
+                #[cfg(feature = "itoa")]
+                let value = {
+                    let mut buffer = itoa::Buffer::new();
+                    HeaderValue::from_str(buffer.format(reset_secs)).unwrap()
+                };
+
+                #[cfg(not(feature = "itoa"))]
+                let value = HeaderValue::from_str(&reset_secs.to_string()).unwrap();
+
+                value
+            }
+        };
+
+        let headers = res.headers_mut();
+
+        headers.insert(HeaderName::from_static("ratelimit-reset"), value.clone());
+        headers.insert(HeaderName::from_static("x-ratelimit-reset"), value.clone());
+        headers.insert(HeaderName::from_static("retry-after"), value.clone());
+        headers.insert(
+            HeaderName::from_static("ratelimit-remaining"),
+            HeaderValue::from_static("0"),
+        );
+
+        res
+    }
+}
+
+impl RateLimitError {
+    /// Returns the amount of time until the next request can be made as a `Duration`.
+    #[inline]
+    #[must_use]
+    pub const fn as_duration(self) -> Duration {
+        Duration::from_nanos(self.0.get())
+    }
+}
+
+/// Algorithm used to enforce a [`Quota`].
+///
+/// All algorithms share the same per-key state of a single timestamp, so they can be mixed
+/// freely across routes of the same rate limiter and work with any [`Store`](crate::store::Store).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Algorithm {
+    /// Generic Cell Rate Algorithm, which spaces out requests evenly over time
+    /// with a tolerance for bursts, see [`Quota::new`]. This is the default.
+    ///
+    /// This is equivalent to a token bucket, see [`Quota::token_bucket`].
+    #[default]
+    Gcra,
+
+    /// Sliding window counter, which estimates the number of requests within the last window
+    /// from the counts of the current and previous fixed windows, see [`Quota::sliding_window`].
+    SlidingWindow,
+
+    /// Fixed window counter, which counts requests within fixed windows, such as calendar minutes or hours,
+    /// see [`Quota::fixed_window`].
+    FixedWindow,
+}
+
+#[cfg_attr(not(feature = "gossip"), allow(dead_code))]
+impl Algorithm {
+    #[inline]
+    pub(crate) const fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    #[inline]
+    pub(crate) const fn from_u8(value: u8) -> Option<Algorithm> {
+        match value {
+            0 => Some(Algorithm::Gcra),
+            1 => Some(Algorithm::SlidingWindow),
+            2 => Some(Algorithm::FixedWindow),
+            _ => None,
+        }
+    }
+}
+
+/// A rate limit quota, which defines the number of requests that can be made
+/// within a given time frame and with a given burst size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Quota {
+    /// Burst size/cells, in nanoseconds `(t * burst)`, or the window length of window-based algorithms
+    pub(crate) tau: u64,
+
+    /// Cell emission interval, in nanoseconds, or the cost of a request for window-based algorithms
+    pub(crate) t: u64,
+
+    pub(crate) algorithm: Algorithm,
+}
+
+#[cfg(feature = "governor")]
+impl From<governor::Quota> for Quota {
+    /// Converts a [`governor::Quota`] into an equivalent GCRA quota,
+    /// with the same replenish interval and burst size.
+    fn from(quota: governor::Quota) -> Quota {
+        Quota::new(quota.replenish_interval(), quota.burst_size().into())
+    }
+}
+
+impl Default for Quota {
+    /// Returns a default quota of 1000 requests per second.
+    fn default() -> Quota {
+        Quota::simple(Duration::from_millis(1))
+    }
+}
+
+impl fmt::Display for Quota {
+    /// Formats the quota as its burst and period, such as `20 requests per 2s`,
+    /// followed by the algorithm for window-based quotas, such as `100 requests per 60s (sliding window)`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let burst = self.burst();
+
+        write!(
+            f,
+            "{burst} {} per {:?}",
+            if burst == 1 { "request" } else { "requests" },
+            self.period()
+        )?;
+
+        match self.algorithm {
+            Algorithm::Gcra => Ok(()),
+            Algorithm::SlidingWindow => f.write_str(" (sliding window)"),
+            Algorithm::FixedWindow => f.write_str(" (fixed window)"),
+        }
+    }
+}
+
+impl Quota {
+    /// Constructs a new quota with the given number of burst requests and
+    /// an `emission_interval` parameter, which is the amount of time it takes
+    /// for the limit to release.
+    ///
+    /// For example, 100reqs/second would have an emission_interval of 10ms
+    ///
+    /// Burst requests ignore the individual emission interval in favor of
+    /// delivering all at once or in quick succession, up until the provided limit.
+    #[rustfmt::skip] #[must_use]
+    pub const fn new(emission_interval: Duration, burst: NonZeroU64) -> Quota {
+        let t = emission_interval.as_nanos() as u64;
+        Quota { t, tau: t * burst.get(), algorithm: Algorithm::Gcra }
+    }
+
+    /// Constructs a new quota with the given emission interval, but with a burst size of 1.
+    ///
+    /// See [`Quota::new`] for more information.
+    #[must_use]
+    pub const fn simple(emission_interval: Duration) -> Quota {
+        Self::new(emission_interval, NonZeroU64::MIN)
+    }
+
+    /// Constructs a new quota with the given emission interval and an explicit `tolerance`,
+    /// which is how far ahead of the emission schedule requests are allowed to arrive.
+    ///
+    /// [`Quota::new`] uses a tolerance of `emission_interval * burst`, but any tolerance can be given
+    /// here to tune between smoothing and burstiness precisely, such as a tolerance that is not a multiple
+    /// of the emission interval.
+    ///
+    /// Every request needs at least one emission interval of tolerance, so smaller tolerances are raised to
+    /// the emission interval, like for [`Quota::scaled`]. A tolerance of zero thus strictly spaces out
+    /// requests, with a burst of 1.
+    ///
+    /// ```rust
+    /// use std::time::{Duration, Instant};
+    /// use axum_gcra::gcra::{Quota, RateLimiter};
+    ///
+    /// let quota = Quota::with_tolerance(Duration::from_millis(100), Duration::from_millis(250));
+    ///
+    /// assert_eq!(quota.emission_interval(), Duration::from_millis(100));
+    /// assert_eq!(quota.tolerance(), Duration::from_millis(250));
+    /// assert_eq!(quota.burst(), 2);
+    ///
+    /// // one request per emission interval, with no burst
+    /// let strict = Quota::with_tolerance(Duration::from_millis(100), Duration::ZERO);
+    /// assert_eq!(strict.tolerance(), Duration::from_millis(100));
+    /// assert_eq!(strict.burst(), 1);
+    ///
+    /// let limiter = RateLimiter::<&str>::default();
+    /// let now = Instant::now();
+    ///
+    /// assert!(limiter.req_sync("key", strict, now).is_ok());
+    /// assert!(limiter.req_sync("key", strict, now).is_err());
+    /// assert!(limiter.req_sync("key", strict, now + Duration::from_millis(100)).is_ok());
+    /// ```
+    #[must_use]
+    pub const fn with_tolerance(emission_interval: Duration, tolerance: Duration) -> Quota {
+        let t = emission_interval.as_nanos() as u64;
+        let tau = tolerance.as_nanos() as u64;
+
+        Quota {
+            t,
+            tau: if tau < t { t } else { tau },
+            algorithm: Algorithm::Gcra,
+        }
+    }
+
+    /// Constructs a new quota for a classic token bucket holding up to `capacity` tokens, which is refilled
+    /// with `refill` tokens every `period`, and where every request takes one token.
+    ///
+    /// Token buckets are exactly equivalent to GCRA, so this is a [GCRA](Algorithm::Gcra) quota with a
+    /// [burst](Quota::burst) of `capacity` and an [emission interval](Quota::emission_interval) of
+    /// `period / refill`, for limits that are already documented in token bucket terms. Tokens are
+    /// refilled continuously rather than all at once after each period.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::{num::NonZeroU64, time::Duration};
+    /// use axum_gcra::gcra::Quota;
+    ///
+    /// // buckets of 50 tokens, refilled with 10 tokens per second
+    /// let quota = Quota::token_bucket(
+    ///     NonZeroU64::new(50).unwrap(),
+    ///     NonZeroU64::new(10).unwrap(),
+    ///     Duration::from_secs(1),
+    /// );
+    ///
+    /// assert_eq!(quota.burst(), 50);
+    /// assert_eq!(quota.emission_interval(), Duration::from_millis(100));
+    /// ```
+    #[must_use]
+    pub const fn token_bucket(capacity: NonZeroU64, refill: NonZeroU64, period: Duration) -> Quota {
+        let t = (period.as_nanos() / refill.get() as u128) as u64;
+        Quota::new(Duration::from_nanos(t), capacity)
+    }
+
+    /// Constructs a new quota allowing up to `limit` requests within any rolling `window`,
+    /// enforced with a [sliding window counter](Algorithm::SlidingWindow) instead of GCRA.
+    ///
+    /// Requests are counted in fixed windows of the given length, and the number of requests within the
+    /// last rolling window is estimated as the count of the current window plus the count of the previous
+    /// window weighted by how much of it still overlaps. Unlike GCRA, this allows the whole limit at once,
+    /// with no smoothing, but never more than the limit within any rolling window under uniform traffic.
+    ///
+    /// The [burst](Quota::burst) of the returned quota is the limit and its [tolerance](Quota::tolerance)
+    /// is the window length. Its [emission interval](Quota::emission_interval) is not a duration, but one
+    /// emission interval is still the cost of a single request for penalties and refunds. Sliding window
+    /// quotas are never [delayed](crate::RateLimitLayerBuilder::with_delay).
+    ///
+    /// Windows are aligned to UTC days, so windows that evenly divide a day start on the boundaries
+    /// of their length, such as every full minute or hour.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `(limit + 1)²` exceeds the window length in nanoseconds, such as more than about
+    /// 31 thousand requests per second or 245 thousand requests per minute.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::{num::NonZeroU64, time::{Duration, Instant}};
+    /// use axum_gcra::gcra::{Quota, RateLimiter};
+    ///
+    /// // no more than 100 requests per rolling minute
+    /// let quota = Quota::sliding_window(Duration::from_secs(60), NonZeroU64::new(100).unwrap());
+    /// assert_eq!(quota.burst(), 100);
+    ///
+    /// let limiter = RateLimiter::<&str>::default();
+    /// let now = Instant::now();
+    ///
+    /// assert!(limiter.req_n_sync("key", quota, 100, now).is_ok());
+    /// assert!(limiter.req_sync("key", quota, now).is_err());
+    /// ```
+    #[must_use]
+    pub const fn sliding_window(window: Duration, limit: NonZeroU64) -> Quota {
+        let window = window.as_nanos() as u64;
+        let unit = limit.get().saturating_add(1);
+
+        assert!(
+            (unit as u128) * (unit as u128) <= window as u128,
+            "sliding window limit too large for the window length"
+        );
+
+        Quota {
+            t: unit,
+            tau: window,
+            algorithm: Algorithm::SlidingWindow,
+        }
+    }
+
+    /// Constructs a new quota allowing up to `limit` requests within each fixed `window`,
+    /// enforced with a [fixed window counter](Algorithm::FixedWindow) instead of GCRA.
+    ///
+    /// Windows are aligned to UTC days, so windows that evenly divide a day start on the boundaries of
+    /// their length, such as every full minute or hour, and the count resets at the start of every window.
+    /// This is the cheapest algorithm to reason about, but allows up to twice the limit in quick succession
+    /// around window boundaries, with the whole limit at the end of one window and again at the start of
+    /// the next. Entries also expire as soon as their window ends.
+    ///
+    /// See [`Quota::sliding_window`] for the meaning of the other parameters of the returned quota,
+    /// which are the same.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit * (limit + 1)` exceeds the window length in nanoseconds.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::{num::NonZeroU64, time::Duration};
+    /// use axum_gcra::gcra::Quota;
+    ///
+    /// // up to 1000 requests per calendar hour
+    /// let quota = Quota::fixed_window(Duration::from_secs(3600), NonZeroU64::new(1000).unwrap());
+    ///
+    /// assert_eq!(quota.burst(), 1000);
+    /// assert_eq!(quota.window(), Some(Duration::from_secs(3600)));
+    /// ```
+    #[must_use]
+    pub const fn fixed_window(window: Duration, limit: NonZeroU64) -> Quota {
+        let window = window.as_nanos() as u64;
+        let unit = limit.get().saturating_add(1);
+
+        assert!(
+            (limit.get() as u128) * (unit as u128) < window as u128,
+            "fixed window limit too large for the window length"
+        );
+
+        Quota {
+            t: unit,
+            tau: window,
+            algorithm: Algorithm::FixedWindow,
+        }
+    }
+
+    /// Returns the burst size of the quota, which is the number of requests
+    /// that can be made at once before being rate limited.
+    ///
+    /// For quotas with an explicit [tolerance](Quota::with_tolerance), this is rounded down.
+    #[inline]
+    #[must_use]
+    pub const fn burst(&self) -> u64 {
+        if let Algorithm::SlidingWindow | Algorithm::FixedWindow = self.algorithm {
+            return self.t - 1;
+        }
+
+        match self.tau.checked_div(self.t) {
+            Some(burst) => burst,
+            None => 1,
+        }
     }
-}
 
-impl<K: Eq + Hash, H: BuildHasher> Default for RateLimiter<K, H>
-where
-    H: Default,
-{
-    fn default() -> Self {
-        // default to 8192 unique requests before garbage collection
-        RateLimiter::new(8192, H::default())
+    /// Returns the emission interval of the quota, which is the cost of a single request.
+    ///
+    /// For window-based quotas, this is not a duration, see [`Quota::sliding_window`].
+    #[inline]
+    #[must_use]
+    pub const fn emission_interval(&self) -> Duration {
+        Duration::from_nanos(self.t)
     }
-}
 
-/// An error that occurs when a rate limit is exceeded,
-/// with the amount of time until the next request can be made.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-#[repr(transparent)]
-pub struct RateLimitError(pub NonZeroU64);
+    /// Returns the tolerance of the quota, which is how far ahead of the emission schedule
+    /// requests are allowed to arrive, see [`Quota::with_tolerance`].
+    #[inline]
+    #[must_use]
+    pub const fn tolerance(&self) -> Duration {
+        Duration::from_nanos(self.tau)
+    }
 
-impl fmt::Display for RateLimitError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "rate limit exceeded, retry in {:.3} seconds",
-            self.as_duration().as_secs_f32()
-        )
+    /// Returns the algorithm used to enforce the quota.
+    #[inline]
+    #[must_use]
+    pub const fn algorithm(&self) -> Algorithm {
+        self.algorithm
     }
-}
 
-impl Error for RateLimitError {}
+    /// Returns the raw GCRA parameters of the quota, or `None` for window-based quotas.
+    #[inline]
+    #[must_use]
+    pub const fn policy(&self) -> Option<GcraPolicy> {
+        match self.algorithm {
+            Algorithm::Gcra => Some(GcraPolicy::new(self.emission_interval(), self.tolerance())),
+            _ => None,
+        }
+    }
 
-use axum::http::StatusCode;
-use axum::response::{IntoResponse, Response};
+    /// Returns the window length of window-based quotas, or `None` for GCRA.
+    #[inline]
+    #[must_use]
+    pub const fn window(&self) -> Option<Duration> {
+        match self.algorithm {
+            Algorithm::Gcra => None,
+            _ => Some(Duration::from_nanos(self.tau)),
+        }
+    }
 
-impl IntoResponse for RateLimitError {
-    fn into_response(self) -> Response {
-        use http::{HeaderName, HeaderValue};
+    /// Returns the period of the quota, within which up to [`burst`](Quota::burst) requests are allowed.
+    ///
+    /// For GCRA, this is the time it takes to recover the full burst after using it up,
+    /// and for window-based quotas, this is the window length.
+    ///
+    /// ```rust
+    /// use std::{num::NonZeroU64, time::Duration};
+    /// use axum_gcra::gcra::Quota;
+    ///
+    /// // 10 requests per second, with bursts of up to 20
+    /// let quota = Quota::new(Duration::from_millis(100), NonZeroU64::new(20).unwrap());
+    ///
+    /// assert_eq!(quota.period(), Duration::from_secs(2));
+    /// assert_eq!(quota.max_rate_per_second(), 10.0);
+    /// assert_eq!(quota.to_string(), "20 requests per 2s");
+    /// ```
+    #[inline]
+    #[must_use]
+    pub const fn period(&self) -> Duration {
+        match self.algorithm {
+            Algorithm::Gcra => Duration::from_nanos(self.burst().saturating_mul(self.t)),
+            _ => Duration::from_nanos(self.tau),
+        }
+    }
 
-        // reuse as_duration value
-        let reset = self.as_duration();
+    /// Returns the sustained rate of the quota, in requests per second, not counting bursts.
+    #[inline]
+    #[must_use]
+    pub fn max_rate_per_second(&self) -> f64 {
+        1e9 / self.interval().get() as f64
+    }
 
-        let mut res = Response::new(From::from(format!(
-            "rate limit exceeded, retry in {:.3} seconds",
-            reset.as_secs_f32()
-        )));
+    /// Returns this quota with its rate multiplied by the given factor, such as `0.5` to allow half
+    /// as many requests per [period](Quota::period) or `2.0` to allow twice as many.
+    ///
+    /// For GCRA, the emission interval is divided by the factor while the tolerance is kept, so both
+    /// the sustained rate and the burst scale with it, and the burst never drops below one request.
+    /// Existing rate limiter state remains valid across scales. Window-based quotas count requests
+    /// in their state, so they are returned unchanged, as are factors that are not positive.
+    ///
+    /// ```rust
+    /// use std::{num::NonZeroU64, time::Duration};
+    /// use axum_gcra::gcra::Quota;
+    ///
+    /// let quota = Quota::new(Duration::from_millis(100), NonZeroU64::new(10).unwrap());
+    ///
+    /// assert_eq!(quota.scaled(2.0).burst(), 20);
+    /// assert_eq!(quota.scaled(0.5).emission_interval(), Duration::from_millis(200));
+    /// ```
+    #[must_use]
+    pub fn scaled(self, factor: f32) -> Quota {
+        if self.algorithm != Algorithm::Gcra || factor.is_nan() || factor <= 0.0 {
+            return self;
+        }
 
-        *res.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+        let t = ((self.t as f64 / factor as f64).round() as u64).max(1);
 
-        let reset = reset.as_secs().max(1);
+        Quota {
+            t,
+            tau: self.tau.max(t),
+            algorithm: Algorithm::Gcra,
+        }
+    }
 
-        // optimize for common values
-        let value = match reset {
-            1 => HeaderValue::from_static("1"),
-            2 => HeaderValue::from_static("2"),
-            _ => {
-                #[cfg(feature = "itoa")]
-                let value = {
-                    let mut buffer = itoa::Buffer::new();
-                    HeaderValue::from_str(buffer.format(reset)).unwrap()
-                };
+    /// Returns the average time between requests at the sustained rate, in nanoseconds.
+    #[inline]
+    pub(crate) const fn interval(&self) -> NonZeroU64 {
+        let interval = match self.algorithm {
+            Algorithm::Gcra => self.t,
+            _ => self.tau / self.burst(),
+        };
 
-                #[cfg(not(feature = "itoa"))]
-                let value = HeaderValue::from_str(&reset.to_string()).unwrap();
+        match NonZeroU64::new(interval) {
+            Some(interval) => interval,
+            None => NonZeroU64::MIN,
+        }
+    }
 
-                value
-            }
-        };
+    /// Returns this quota with requests also allowed up to `delay` early, to be delayed
+    /// until they would have been allowed, see [`Admitted::delay`].
+    #[inline]
+    pub(crate) const fn tolerate(self, delay: Duration) -> Quota {
+        match self.algorithm {
+            Algorithm::Gcra => Quota {
+                tau: self.tau.saturating_add(delay.as_nanos() as u64),
+                ..self
+            },
+            _ => self,
+        }
+    }
+}
 
-        let headers = res.headers_mut();
+/// Raw parameters of a [GCRA](Algorithm::Gcra) quota, for policies that are not easily described by a rate
+/// and a burst size, such as a long tolerance that drains slowly, and to verify their behavior in tests.
+///
+/// Policies convert into quotas with [`From`], such as to
+/// [register routes](crate::RateLimitLayerBuilder::with_route), and back with [`Quota::policy`].
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::{Duration, Instant};
+/// use axum_gcra::gcra::{GcraPolicy, Quota, RateLimiter};
+///
+/// // bursts of up to 100 requests after a quiet day, but only one request per 15 minutes sustained
+/// let policy = GcraPolicy::new(Duration::from_secs(15 * 60), Duration::from_secs(86400));
+/// let quota = Quota::from(policy);
+///
+/// assert_eq!(policy.burst(), 96);
+/// assert_eq!(quota.policy(), Some(policy));
+///
+/// let limiter = RateLimiter::<&str>::default();
+/// let now = Instant::now();
+///
+/// assert!(limiter.req_n_sync("key", quota, 96, now).is_ok());
+///
+/// let mut tat = None;
+/// limiter.scan_sync(|_, t| tat = Some(t));
+/// let tat = tat.unwrap();
+///
+/// assert_eq!(policy.remaining(tat, now), 0);
+/// assert_eq!(policy.retry_after(tat, now, 1), Duration::from_secs(15 * 60));
+/// assert_eq!(limiter.req_sync("key", quota, now).unwrap_err().as_duration(), policy.retry_after(tat, now, 1));
+///
+/// // a quarter of the tolerance later, a quarter of the burst is available again
+/// let later = now + Duration::from_secs(86400 / 4);
+/// assert_eq!(policy.remaining(tat, later), 24);
+/// assert_eq!(policy.retry_after(tat, later, 24), Duration::ZERO);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GcraPolicy {
+    /// Time each request adds to the theoretical arrival time of the entry,
+    /// which is the inverse of the sustained rate.
+    pub emission_interval: Duration,
 
-        headers.insert(HeaderName::from_static("ratelimit-reset"), value.clone());
-        headers.insert(HeaderName::from_static("x-ratelimit-reset"), value.clone());
-        headers.insert(HeaderName::from_static("retry-after"), value.clone());
-        headers.insert(
-            HeaderName::from_static("ratelimit-remaining"),
-            HeaderValue::from_static("0"),
-        );
+    /// How far ahead of the emission schedule requests are allowed to arrive,
+    /// which is the size of the burst in units of time.
+    ///
+    /// Tolerances below the emission interval are raised to it, see [`Quota::with_tolerance`].
+    pub tolerance: Duration,
+}
 
-        res
+impl From<GcraPolicy> for Quota {
+    #[inline]
+    fn from(policy: GcraPolicy) -> Quota {
+        Quota::with_tolerance(policy.emission_interval, policy.tolerance)
     }
 }
 
-impl RateLimitError {
-    /// Returns the amount of time until the next request can be made as a `Duration`.
+impl GcraPolicy {
+    /// Constructs a new policy with the given emission interval and tolerance.
     #[inline]
     #[must_use]
-    pub const fn as_duration(self) -> Duration {
-        Duration::from_nanos(self.0.get())
+    pub const fn new(emission_interval: Duration, tolerance: Duration) -> GcraPolicy {
+        GcraPolicy {
+            emission_interval,
+            tolerance,
+        }
     }
-}
 
-/// A rate limit quota, which defines the number of requests that can be made
-/// within a given time frame and with a given burst size.
-#[derive(Debug, Clone, Copy)]
-pub struct Quota {
-    /// Burst size/cells, in nanoseconds `(t * burst)`
-    tau: u64,
+    /// Returns this policy with the given emission interval.
+    #[inline]
+    #[must_use]
+    pub const fn with_emission_interval(mut self, emission_interval: Duration) -> GcraPolicy {
+        self.emission_interval = emission_interval;
+        self
+    }
 
-    /// Cell emission interval, in nanoseconds
-    t: u64,
-}
+    /// Returns this policy with the given tolerance.
+    #[inline]
+    #[must_use]
+    pub const fn with_tolerance(mut self, tolerance: Duration) -> GcraPolicy {
+        self.tolerance = tolerance;
+        self
+    }
 
-impl Default for Quota {
-    /// Returns a default quota of 1000 requests per second.
-    fn default() -> Quota {
-        Quota::simple(Duration::from_millis(1))
+    /// Returns the number of requests that can be made at once by a fresh key, see [`Quota::burst`].
+    #[inline]
+    #[must_use]
+    pub const fn burst(&self) -> u64 {
+        Quota::with_tolerance(self.emission_interval, self.tolerance).burst()
     }
-}
 
-impl Quota {
-    /// Constructs a new quota with the given number of burst requests and
-    /// an `emission_interval` parameter, which is the amount of time it takes
-    /// for the limit to release.
-    ///
-    /// For example, 100reqs/second would have an emission_interval of 10ms
+    /// Returns how long a request costing `n` requests has to wait until it is allowed, for an entry with the
+    /// given theoretical arrival time (TAT), such as given by [`RateLimiter::scan`], or zero if it is allowed now.
     ///
-    /// Burst requests ignore the individual emission interval in favor of
-    /// delivering all at once or in quick succession, up until the provided limit.
-    #[rustfmt::skip] #[must_use]
-    pub const fn new(emission_interval: Duration, burst: NonZeroU64) -> Quota {
-        let t = emission_interval.as_nanos() as u64;
-        Quota { t, tau: t * burst.get() }
+    /// Requests costing more than the burst size are never allowed, see [`RateLimiter::req_n`].
+    #[must_use]
+    pub fn retry_after(&self, tat: Instant, now: Instant, n: u64) -> Duration {
+        let base = tat.min(now);
+        let (tat, now) = ((tat - base).as_nanos() as u64, (now - base).as_nanos() as u64);
+
+        match Gcra::decide_gcra(tat, now, Quota::from(*self), n) {
+            Ok(_) => Duration::ZERO,
+            Err(e) => e.as_duration(),
+        }
     }
 
-    /// Constructs a new quota with the given emission interval, but with a burst size of 1.
-    ///
-    /// See [`Quota::new`] for more information.
+    /// Returns the number of requests that can be made right now by an entry with the given
+    /// theoretical arrival time (TAT), such as given by [`RateLimiter::scan`], see [`Status::at`].
     #[must_use]
-    pub const fn simple(emission_interval: Duration) -> Quota {
-        Self::new(emission_interval, NonZeroU64::MIN)
+    pub fn remaining(&self, tat: Instant, now: Instant) -> u64 {
+        Status::at(tat, now, Quota::from(*self)).remaining
+    }
+}
+
+/// Snapshot of the remaining quota of a rate limiter entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Status {
+    /// Maximum number of requests that can be made at once, i.e. the [burst size](Quota::burst).
+    pub limit: u64,
+
+    /// Number of requests that can be made right now before being rate limited.
+    pub remaining: u64,
+
+    /// Amount of time until the quota is fully replenished, such that `remaining == limit`.
+    pub reset_after: Duration,
+}
+
+impl Status {
+    /// Status of an entry that has not been seen yet, or has fully replenished.
+    #[inline]
+    #[must_use]
+    pub const fn full(quota: Quota) -> Status {
+        let limit = quota.burst();
+
+        Status {
+            limit,
+            remaining: limit,
+            reset_after: Duration::ZERO,
+        }
+    }
+
+    /// Status of an entry that was just rejected, with `retry_after` until the next request is allowed.
+    #[inline]
+    pub(crate) fn exhausted(quota: Quota, retry_after: Duration) -> Status {
+        let limit = quota.burst();
+
+        Status {
+            limit,
+            remaining: 0,
+            reset_after: retry_after + Duration::from_nanos(quota.interval().get() * limit.saturating_sub(1)),
+        }
     }
 }
 
 /// Generic Cell Rate Algorithm (GCRA) implementation.
 ///
-/// Uses a single atomic value to store the next time a request can be made.
+/// Uses a single atomic value to store the next time a request can be made,
+/// which is updated with a lock-free compare-and-swap loop, and another with the coarse
+/// time of the last request, for [idle timeouts](RateLimiter::with_idle_timeouts).
 #[derive(Debug)]
-#[repr(transparent)]
-pub struct Gcra(AtomicU64);
+pub struct Gcra(AtomicU64, AtomicU32);
+
+/// Timestamps of the last request are stored in units of 2^30 nanoseconds, about a second.
+const SEEN_SHIFT: u32 = 30;
 
 impl Gcra {
     /// Constructs a new GCRA for the first request at the given time.
     ///
-    /// This is equivalent to `Gcra(now + t).req()`, but more efficient.
+    /// This is equivalent to `Gcra::empty(quota, now).req(quota, now)`, but more efficient.
+    #[inline]
+    #[must_use]
+    pub const fn first(quota: Quota, now: u64) -> Gcra {
+        Gcra::from_tat(
+            match quota.algorithm {
+                // Equivalent to `Gcra(now + t).req()` to calculate the first request
+                Algorithm::Gcra => now + quota.t + quota.t,
+                // a single request in the current window, see the `window` module
+                Algorithm::SlidingWindow => (now / quota.tau + 2) * quota.tau + quota.t,
+                Algorithm::FixedWindow => (now / quota.tau + 1) * quota.tau + quota.t,
+            },
+            now,
+        )
+    }
+
+    /// Constructs a new GCRA that has not seen any requests yet at the given time.
     #[inline]
     #[must_use]
-    pub const fn first(Quota { t, .. }: Quota, now: u64) -> Gcra {
-        // Equivalent to `Gcra(now + t).req()` to calculate the first request
-        Gcra(AtomicU64::new(now + t + t))
+    pub const fn empty(quota: Quota, now: u64) -> Gcra {
+        Gcra::from_tat(Self::initial(quota, now), now)
+    }
+
+    /// Constructs a GCRA with the given theoretical arrival time, last seen at the given time.
+    #[inline]
+    const fn from_tat(tat: u64, now: u64) -> Gcra {
+        Gcra(AtomicU64::new(tat), AtomicU32::new((now >> SEEN_SHIFT) as u32))
+    }
+
+    /// Get the time of the last request, rounded down to the storage resolution.
+    #[inline]
+    fn seen(&mut self) -> u64 {
+        (*AtomicU32::get_mut(&mut self.1) as u64) << SEEN_SHIFT
+    }
+
+    /// Record a request at the given time, only writing if the coarse timestamp changed.
+    #[inline]
+    fn touch(&self, now: u64) {
+        let seen = (now >> SEEN_SHIFT) as u32;
+
+        if self.1.load(Ordering::Relaxed) != seen {
+            self.1.store(seen, Ordering::Relaxed);
+        }
+    }
+
+    /// State of an entry that has not seen any requests yet, see [`Gcra::empty`].
+    #[inline]
+    const fn initial(quota: Quota, now: u64) -> u64 {
+        match quota.algorithm {
+            // see `Gcra::first`, this is the state before the first request
+            Algorithm::Gcra => now + quota.t,
+            // windows before the epoch are always expired
+            Algorithm::SlidingWindow | Algorithm::FixedWindow => 0,
+        }
     }
 
     /// Core GCRA logic. Returns the next time a request can be made, either as an error or a success.
-    fn decide(prev: u64, now: u64, Quota { tau, t }: Quota) -> Result<u64, RateLimitError> {
+    #[inline(always)]
+    fn decide(prev: u64, now: u64, quota: Quota) -> Result<u64, RateLimitError> {
+        Self::decide_n(prev, now, quota, 1)
+    }
+
+    /// Core logic for a request costing `n` requests at once,
+    /// equivalent to `n` requests made at the same time.
+    #[inline]
+    fn decide_n(prev: u64, now: u64, quota: Quota, n: u64) -> Result<u64, RateLimitError> {
+        match quota.algorithm {
+            Algorithm::Gcra => Self::decide_gcra(prev, now, quota, n),
+            Algorithm::SlidingWindow => window::decide_sliding(prev, now, quota, n),
+            Algorithm::FixedWindow => window::decide_fixed(prev, now, quota, n),
+        }
+    }
+
+    /// Core GCRA logic for a request costing `n` requests at once.
+    fn decide_gcra(prev: u64, now: u64, Quota { tau, t, .. }: Quota, n: u64) -> Result<u64, RateLimitError> {
+        // an entry that has caught up is equivalent to a fresh one, see `Gcra::initial`
+        let base = now.saturating_add(t).max(prev);
+
         // burst's act as an offset to allow more through at the start
-        let next = prev.saturating_sub(tau);
+        let next = base.saturating_add(t.saturating_mul(n.saturating_sub(1))).saturating_sub(tau);
 
         if now < next {
             // SAFETY: next > now, so next - now is non-zero by definition
             Err(RateLimitError(unsafe { NonZeroU64::new_unchecked(next - now) }))
         } else {
-            Ok(now.max(prev) + t)
+            Ok(base.saturating_add(t.saturating_mul(n)))
         }
     }
 
+    /// Returns the theoretical arrival time as an `Instant`, relative to the given start time.
+    fn tat(&self, start: Instant, offset: u64) -> Instant {
+        start + Duration::from_nanos(self.0.load(Ordering::Relaxed).saturating_sub(offset))
+    }
+
+    /// Move the next allowed request time back by the given amount.
+    fn refund(&self, amount: Duration) {
+        let amount = amount.as_nanos() as u64;
+        _ = self.0.fetch_update(Ordering::Release, Ordering::Relaxed, |prev| {
+            Some(prev.saturating_sub(amount))
+        });
+    }
+
+    /// Check if a request would be allowed, without updating the GCRA state.
+    pub fn check(&self, quota: Quota, now: u64) -> Result<(), RateLimitError> {
+        Self::decide(self.0.load(Ordering::Acquire), now, quota).map(|_| ())
+    }
+
+    /// Compute the current [`Status`] of the GCRA, without updating its state.
+    pub fn status(&self, quota: Quota, now: u64) -> Status {
+        Status::compute(self.0.load(Ordering::Acquire), now, quota)
+    }
+
     /// Perform a request, returning an error if the request is too soon.
     pub fn req(&self, quota: Quota, now: u64) -> Result<(), RateLimitError> {
+        self.req_tat(quota, now).map(|_| ())
+    }
+
+    /// Perform a request costing `n` requests at once, returning an error if the request is too soon.
+    pub fn req_n(&self, quota: Quota, n: u64, now: u64) -> Result<(), RateLimitError> {
+        self.req_n_tat(quota, n, now).map(|_| ())
+    }
+
+    /// Perform a request, returning the new theoretical arrival time on success.
+    #[inline]
+    fn req_tat(&self, quota: Quota, now: u64) -> Result<u64, RateLimitError> {
+        self.req_n_tat(quota, 1, now)
+    }
+
+    /// Perform a request costing `n` requests, returning the new theoretical arrival time on success.
+    ///
+    /// Rejections never write to the entry, and a failed swap only means another request
+    /// was admitted in the meantime, so the decision is simply retried with the newer value.
+    fn req_n_tat(&self, quota: Quota, n: u64, now: u64) -> Result<u64, RateLimitError> {
+        self.touch(now);
+
         let mut prev = self.0.load(Ordering::Acquire);
 
         loop {
-            let next = Self::decide(prev, now, quota)?;
+            let next = Self::decide_n(prev, now, quota, n)?;
 
-            match self.0.compare_exchange_weak(prev, next, Ordering::Release, Ordering::Relaxed) {
-                Ok(_) => return Ok(()),
+            match self.0.compare_exchange_weak(prev, next, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return Ok(next),
                 Err(next_prev) => prev = next_prev,
             }
         }
     }
 }
+
+/// Core GCRA decision for a request costing `n` requests at `now`, given the previous theoretical
+/// arrival time (TAT) of the entry, if any. Returns the new TAT if the request is allowed.
+///
+/// All timestamps are in nanoseconds relative to an arbitrary but consistent epoch. This is
+/// intended for [`Store`](crate::store::Store) implementations, which must apply the same logic.
+pub fn decide(prev: Option<u64>, now: u64, quota: Quota, n: u64) -> Result<u64, RateLimitError> {
+    // a missing entry is equivalent to `Gcra::empty`
+    Gcra::decide_n(prev.unwrap_or(Gcra::initial(quota, now)), now, quota, n)
+}
+
+/// Charge `n` requests to an entry with the given previous state, if any, even if they would exceed the quota,
+/// such as for requests that were already allowed elsewhere. Returns the new state.
+///
+/// Like [`decide`], this is intended for [`Store`](crate::store::Store) implementations,
+/// see [`Store::charge`](crate::store::Store::charge).
+#[must_use]
+pub fn charge(prev: Option<u64>, now: u64, quota: Quota, n: u64) -> u64 {
+    let prev = prev.unwrap_or(Gcra::initial(quota, now));
+
+    match quota.algorithm {
+        Algorithm::Gcra => prev.max(now.saturating_add(quota.t)).saturating_add(quota.t.saturating_mul(n)),
+        Algorithm::SlidingWindow => window::charge_sliding(prev, now, quota, n),
+        Algorithm::FixedWindow => window::charge_fixed(prev, now, quota, n),
+    }
+}
+
+/// An allowed request, used to compute the [`Status`] after the fact without reloading the GCRA.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Admitted {
+    tat: u64,
+    now: u64,
+}
+
+impl Admitted {
+    #[inline]
+    pub(crate) const fn new(tat: u64, now: u64) -> Admitted {
+        Admitted { tat, now }
+    }
+
+    #[inline]
+    pub(crate) fn status(self, quota: Quota) -> Status {
+        Status::compute(self.tat, self.now, quota)
+    }
+
+    /// Get how long a single request admitted with a [delay tolerance](Quota::tolerate)
+    /// must wait until it would have been allowed by the given quota.
+    #[inline]
+    pub(crate) fn delay(self, quota: Quota) -> Duration {
+        match quota.algorithm {
+            Algorithm::Gcra => Duration::from_nanos(self.tat.saturating_sub(self.now + quota.t + quota.tau)),
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+impl Status {
+    /// Compute the status of an entry with the given theoretical arrival time (TAT),
+    /// such as given by [`RateLimiter::scan`].
+    ///
+    /// This is only correct for GCRA quotas, see [`RateLimiter::status_at`] for window-based quotas.
+    #[must_use]
+    pub fn at(tat: Instant, now: Instant, quota: Quota) -> Status {
+        let base = tat.min(now);
+        Status::compute((tat - base).as_nanos() as u64, (now - base).as_nanos() as u64, quota)
+    }
+
+    /// Compute the status of an entry with the given theoretical arrival time (TAT) in nanoseconds,
+    /// relative to the same epoch as `now`. Missing entries have a [full](Status::full) status.
+    #[must_use]
+    pub fn from_nanos(tat: Option<u64>, now: u64, quota: Quota) -> Status {
+        match tat {
+            Some(tat) => Status::compute(tat, now, quota),
+            None => Status::full(quota),
+        }
+    }
+
+    /// Compute the status from the given theoretical arrival time of the next request.
+    fn compute(prev: u64, now: u64, quota: Quota) -> Status {
+        let Quota { tau, t, .. } = quota;
+
+        match quota.algorithm {
+            Algorithm::SlidingWindow => return window::status_sliding(prev, now, quota),
+            Algorithm::FixedWindow => return window::status_fixed(prev, now, quota),
+            Algorithm::Gcra => {}
+        }
+
+        let mut status = Status::full(quota);
+
+        // a fresh entry is equivalent to `now + t`, see `Gcra::first`
+        status.reset_after = Duration::from_nanos(prev.saturating_sub(now + t));
+
+        // number of requests `k` such that `max(now + t, prev) + k * t - tau <= now`
+        if let Some(available) = (now + tau).checked_sub((now + t).max(prev)) {
+            status.remaining = match available.checked_div(t) {
+                Some(k) => status.limit.min(k + 1),
+                None => status.limit,
+            };
+        } else {
+            status.remaining = 0;
+        }
+
+        status
+    }
+}
@@ -0,0 +1,103 @@
+//! Interoperability with the types of the [`gcra`](https://docs.rs/gcra) crate, enabled with the `gcra_interop`
+//! cargo feature, so that GCRA parameters and states computed elsewhere don't need duplicate definitions.
+//!
+//! [`RateLimit`]s convert into [`Quota`]s with [`From`], and GCRA quotas back with [`TryFrom`].
+//! [`GcraState`]s convert to and from the theoretical arrival times (TAT) in nanoseconds since the UNIX epoch
+//! used by [`MergeableState`](super::MergeableState) and [`Store`](crate::store::Store)s, such as to import
+//! existing states into a rate limiter with [`RateLimiter::merge`](super::RateLimiter::merge).
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::time::{Duration, Instant};
+//! use axum_gcra::gcra::{interop::{self, GcraState, RateLimit}, MergeableState, Quota, RateLimiter};
+//!
+//! let rate_limit = RateLimit::per_sec(10);
+//! let quota = Quota::from(rate_limit.clone());
+//!
+//! // a state that has used up its whole limit
+//! let mut state = GcraState::default();
+//! for _ in 0..10 {
+//!     state.check_and_modify(&rate_limit, 1).unwrap();
+//! }
+//!
+//! let mut imported = MergeableState::default();
+//! imported.insert("user", interop::unix_tat(&state, quota).unwrap());
+//!
+//! let limiter = RateLimiter::<&str>::default();
+//! limiter.merge_sync(&imported);
+//!
+//! assert!(limiter.req_sync("user", quota, Instant::now()).is_err());
+//! ```
+
+use std::time::Duration;
+
+pub use gcra_crate::{GcraState, RateLimit};
+
+use super::{Algorithm, Quota};
+use crate::clock::Instant;
+
+impl From<RateLimit> for Quota {
+    /// Converts a [`RateLimit`] into a GCRA quota with the same emission interval,
+    /// and its period as the [tolerance](Quota::tolerance), allowing the same bursts.
+    fn from(rate_limit: RateLimit) -> Quota {
+        Quota::from(&rate_limit)
+    }
+}
+
+impl From<&RateLimit> for Quota {
+    fn from(rate_limit: &RateLimit) -> Quota {
+        Quota::with_tolerance(rate_limit.emission_interval, rate_limit.period)
+    }
+}
+
+impl TryFrom<Quota> for RateLimit {
+    /// The algorithm of a quota that has no equivalent [`RateLimit`].
+    type Error = Algorithm;
+
+    /// Converts a [GCRA](Algorithm::Gcra) quota into a [`RateLimit`], with the [burst](Quota::burst)
+    /// as its resource limit and the [tolerance](Quota::tolerance) as its period.
+    fn try_from(quota: Quota) -> Result<RateLimit, Algorithm> {
+        match quota.algorithm {
+            Algorithm::Gcra => Ok(RateLimit {
+                resource_limit: quota.burst().min(u32::MAX as u64) as u32,
+                period: quota.tolerance(),
+                emission_interval: quota.emission_interval(),
+            }),
+            algorithm => Err(algorithm),
+        }
+    }
+}
+
+/// Convert a [`GcraState`] into the equivalent TAT for the given GCRA quota, in nanoseconds since the UNIX epoch,
+/// or `None` if the state is new.
+///
+/// States of the `gcra` crate lag one emission interval behind the TATs used here,
+/// as this crate counts the first request of a new state ahead of time.
+#[must_use]
+pub fn unix_tat(state: &GcraState, quota: Quota) -> Option<u64> {
+    let tat = state.tat?;
+    let (now, unix) = (Instant::now(), crate::store::now());
+
+    let unix = match tat.checked_duration_since(now) {
+        Some(ahead) => unix.saturating_add(ahead.as_nanos() as u64),
+        None => unix.saturating_sub(now.duration_since(tat).as_nanos() as u64),
+    };
+
+    Some(unix.saturating_add(quota.t))
+}
+
+/// Convert a TAT for the given GCRA quota, in nanoseconds since the UNIX epoch, into the equivalent
+/// [`GcraState`], see [`unix_tat`].
+#[must_use]
+pub fn gcra_state(tat: u64, quota: Quota) -> GcraState {
+    let (now, unix) = (Instant::now(), crate::store::now());
+    let tat = tat.saturating_sub(quota.t);
+
+    GcraState {
+        tat: match tat.checked_sub(unix) {
+            Some(ahead) => now.checked_add(Duration::from_nanos(ahead)),
+            None => now.checked_sub(Duration::from_nanos(unix - tat)),
+        },
+    }
+}
@@ -0,0 +1,135 @@
+//! Deterministic simulation of quotas against recorded traffic, such as to validate what a quota
+//! will do to real traffic traces before deploying it.
+//!
+//! Simulations use the exact same decisions as the rate limiter, for all [algorithms](super::Algorithm),
+//! but without any real time, so they are pure and reproducible. Arrivals are given as offsets since the start
+//! of the trace, in order of arrival, and windows of window-based algorithms are aligned to the start of the trace.
+//!
+//! Simulations model a single key, so traces with many clients should be split by key first.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::{num::NonZeroU64, time::Duration};
+//! use axum_gcra::gcra::{simulate::{simulate, Decision}, Quota};
+//!
+//! // 2 requests per second, with bursts of up to 2
+//! let quota = Quota::new(Duration::from_millis(500), NonZeroU64::new(2).unwrap());
+//!
+//! let trace = [0, 10, 20, 600, 1000].map(Duration::from_millis);
+//!
+//! let decisions = simulate(quota, &trace);
+//!
+//! assert_eq!(decisions[0], Decision::Allowed { remaining: 1 });
+//! assert_eq!(decisions[1], Decision::Allowed { remaining: 0 });
+//! assert_eq!(decisions[2], Decision::Throttled { retry_after: Duration::from_millis(480) });
+//! assert!(decisions[3].is_allowed());
+//! assert!(decisions[4].is_allowed());
+//! ```
+
+use std::time::Duration;
+
+use super::{decide, Quota, Status};
+
+/// Outcome of a simulated request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Decision {
+    /// The request was allowed.
+    Allowed {
+        /// Number of requests that could still be made at the same time.
+        remaining: u64,
+    },
+
+    /// The request was rate limited.
+    Throttled {
+        /// Time until the request would have been allowed.
+        retry_after: Duration,
+    },
+}
+
+impl Decision {
+    /// Returns `true` if the request was allowed.
+    #[inline]
+    #[must_use]
+    pub const fn is_allowed(&self) -> bool {
+        matches!(self, Decision::Allowed { .. })
+    }
+
+    /// Returns `true` if the request was rate limited.
+    #[inline]
+    #[must_use]
+    pub const fn is_throttled(&self) -> bool {
+        matches!(self, Decision::Throttled { .. })
+    }
+}
+
+/// Replays requests of a single key against a quota one at a time, see the [module documentation](self).
+///
+/// This is useful to simulate requests as they are read from a trace, or with differing costs.
+#[derive(Debug, Clone, Copy)]
+pub struct Simulator {
+    quota: Quota,
+
+    /// Theoretical arrival time, in nanoseconds since the start of the trace.
+    tat: Option<u64>,
+}
+
+impl Simulator {
+    /// Create a new simulator for the given quota, starting before any requests.
+    #[must_use]
+    pub const fn new(quota: Quota) -> Self {
+        Simulator { quota, tat: None }
+    }
+
+    /// Get the quota being simulated.
+    #[inline]
+    #[must_use]
+    pub const fn quota(&self) -> Quota {
+        self.quota
+    }
+
+    /// Simulate a request arriving at the given offset since the start of the trace.
+    ///
+    /// Offsets should not go backwards.
+    pub fn arrive(&mut self, at: Duration) -> Decision {
+        self.arrive_n(at, 1)
+    }
+
+    /// Simulate a request costing `n` requests at once arriving at the given offset since the start of the trace.
+    pub fn arrive_n(&mut self, at: Duration, n: u64) -> Decision {
+        let now = nanos(at);
+
+        match decide(self.tat, now, self.quota, n) {
+            Ok(tat) => {
+                self.tat = Some(tat);
+
+                Decision::Allowed {
+                    remaining: self.status(at).remaining,
+                }
+            }
+            Err(e) => Decision::Throttled {
+                retry_after: e.as_duration(),
+            },
+        }
+    }
+
+    /// Get the [`Status`] of the quota at the given offset since the start of the trace, without making a request.
+    #[must_use]
+    pub fn status(&self, at: Duration) -> Status {
+        Status::from_nanos(self.tat, nanos(at), self.quota)
+    }
+}
+
+#[inline]
+fn nanos(at: Duration) -> u64 {
+    at.as_nanos().min(u64::MAX as u128) as u64
+}
+
+/// Simulate requests arriving at the given offsets since the start of the trace, in order of arrival,
+/// returning the decision for each request. See the [module documentation](self) for more information.
+#[must_use]
+pub fn simulate(quota: Quota, arrivals: &[Duration]) -> Vec<Decision> {
+    let mut sim = Simulator::new(quota);
+
+    arrivals.iter().map(|&at| sim.arrive(at)).collect()
+}
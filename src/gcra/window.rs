@@ -0,0 +1,213 @@
+//! Window-based algorithms, see [`Algorithm`](super::Algorithm).
+//!
+//! Window states are packed into the same single timestamp as GCRA entries, so that garbage collection,
+//! eviction, merging, persistence and stores work on them unchanged. Windows are aligned to multiples
+//! of their length since the epoch of the timestamps, and a state is always at or after the end of the
+//! last window it is relevant for, so it expires exactly when it no longer affects any decisions.
+//!
+//! Window-based quotas store the window length as their tolerance `tau`, and the cost of
+//! a single request as their emission interval `t`.
+//!
+//! A sliding window state in window `k` is encoded as `(k + 2) * window + cur * (limit + 1) + prev`,
+//! where `cur` and `prev` are the counts of the current and previous windows. One request costs
+//! `limit + 1` (the [emission interval](super::Quota::emission_interval) of the quota), so penalties
+//! and refunds given in multiples of it add to or remove from the current count, while longer penalties
+//! carry over into later windows and block until then.
+//!
+//! A fixed window state in window `k` is encoded the same way as `(k + 1) * window + cur * (limit + 1)`,
+//! as the previous window does not matter.
+
+use std::{num::NonZeroU64, time::Duration};
+
+use super::{Quota, RateLimitError, Status};
+
+/// Counts of a sliding window at some time.
+struct Counts {
+    /// Index of the current window.
+    window: u64,
+
+    /// Time elapsed within the current window.
+    elapsed: u64,
+
+    prev: u64,
+    cur: u64,
+}
+
+#[inline]
+fn error(retry: u64) -> RateLimitError {
+    RateLimitError(NonZeroU64::new(retry).unwrap_or(NonZeroU64::MIN))
+}
+
+impl Counts {
+    /// Decode a state at the given time, or return the time until which it is blocked by a penalty.
+    fn decode(state: u64, now: u64, Quota { tau: w, t: unit, .. }: Quota) -> Result<Counts, u64> {
+        let limit = unit - 1;
+
+        let (window, elapsed) = (now / w, now % w);
+        let (state_window, payload) = (state / w, state % w);
+
+        let (prev, cur) = match state_window.checked_sub(window) {
+            Some(2) => (payload % unit, payload / unit),
+            Some(1) => ((payload / unit).min(limit), 0),
+            // penalized into a later window, blocked until it starts
+            Some(3..) => return Err((state_window - 2) * w),
+            Some(0) | None => (0, 0),
+        };
+
+        Ok(Counts {
+            window,
+            elapsed,
+            prev,
+            cur,
+        })
+    }
+
+    /// Estimated number of requests within the last window, scaled by the window length.
+    #[inline]
+    fn scaled_estimate(&self, w: u64) -> u128 {
+        self.prev as u128 * (w - self.elapsed) as u128 + self.cur as u128 * w as u128
+    }
+}
+
+/// Sliding window counter decision, see [`Gcra::decide_n`](super::Gcra::decide_n).
+pub(super) fn decide_sliding(prev: u64, now: u64, quota: Quota, n: u64) -> Result<u64, RateLimitError> {
+    let (w, unit) = (quota.tau, quota.t);
+    let limit = unit - 1;
+
+    let counts = match Counts::decode(prev, now, quota) {
+        Ok(counts) => counts,
+        Err(until) => return Err(error(until - now)),
+    };
+
+    let cur = counts.cur.saturating_add(n);
+
+    if counts.scaled_estimate(w) + n as u128 * w as u128 <= limit as u128 * w as u128 {
+        // cur <= limit and prev <= limit, so the payload is below `unit²`,
+        // which is at most the window length as checked by `Quota::sliding_window`
+        return Ok((counts.window + 2) * w + cur * unit + counts.prev);
+    }
+
+    let remaining = w - counts.elapsed;
+
+    let retry = if n > limit {
+        // never allowed, so just retry after the next window
+        remaining + w
+    } else if cur <= limit {
+        // wait for the previous window to decay enough within the current window
+        let elapsed = w - ((limit - cur) as u128 * w as u128 / counts.prev as u128) as u64;
+        elapsed - counts.elapsed
+    } else {
+        // wait for the current window to become the previous one and decay enough
+        let prev = counts.cur.min(limit);
+        let elapsed = match prev {
+            0 => 0,
+            _ => w.saturating_sub(((limit - n) as u128 * w as u128 / prev as u128) as u64),
+        };
+        remaining + elapsed
+    };
+
+    Err(error(retry))
+}
+
+/// Sliding window counter status, see [`Status::compute`].
+pub(super) fn status_sliding(prev: u64, now: u64, quota: Quota) -> Status {
+    let w = quota.tau;
+    let mut status = Status::full(quota);
+
+    match Counts::decode(prev, now, quota) {
+        Ok(counts) => {
+            let used = counts.scaled_estimate(w).div_ceil(w as u128) as u64;
+            status.remaining = status.limit.saturating_sub(used);
+
+            let remaining = w - counts.elapsed;
+            status.reset_after = Duration::from_nanos(match (counts.prev, counts.cur) {
+                (_, 1..) => remaining + w,
+                (1.., 0) => remaining,
+                (0, 0) => 0,
+            });
+        }
+        Err(_) => {
+            status.remaining = 0;
+            status.reset_after = Duration::from_nanos(prev.saturating_sub(now));
+        }
+    }
+
+    status
+}
+
+/// Force `n` requests onto a sliding window state, even if they exceed the limit,
+/// such as with requests that were already allowed elsewhere.
+#[cfg_attr(not(feature = "gossip"), allow(dead_code))]
+pub(super) fn charge_sliding(prev: u64, now: u64, quota: Quota, n: u64) -> u64 {
+    let (w, unit) = (quota.tau, quota.t);
+
+    match Counts::decode(prev, now, quota) {
+        Ok(counts) => (counts.window + 2) * w + counts.cur.saturating_add(n).min(unit - 1) * unit + counts.prev,
+        Err(_) => prev,
+    }
+}
+
+/// Decode a fixed window state into the current window, time elapsed within it and its count,
+/// or return the time until which it is blocked by a penalty.
+fn decode_fixed(state: u64, now: u64, Quota { tau: w, t: unit, .. }: Quota) -> Result<(u64, u64, u64), u64> {
+    let (window, elapsed) = (now / w, now % w);
+    let (state_window, payload) = (state / w, state % w);
+
+    match state_window.checked_sub(window) {
+        Some(1) => Ok((window, elapsed, payload / unit)),
+        // penalized into a later window, blocked until it starts
+        Some(2..) => Err((state_window - 1) * w),
+        Some(0) | None => Ok((window, elapsed, 0)),
+    }
+}
+
+/// Fixed window counter decision, see [`Gcra::decide_n`](super::Gcra::decide_n).
+pub(super) fn decide_fixed(prev: u64, now: u64, quota: Quota, n: u64) -> Result<u64, RateLimitError> {
+    let (w, unit) = (quota.tau, quota.t);
+
+    let (window, elapsed, cur) = match decode_fixed(prev, now, quota) {
+        Ok(counts) => counts,
+        Err(until) => return Err(error(until - now)),
+    };
+
+    let cur = cur.saturating_add(n);
+
+    if cur < unit {
+        return Ok((window + 1) * w + cur * unit);
+    }
+
+    // wait for the next window, although requests costing more than the limit are never allowed
+    Err(error(w - elapsed))
+}
+
+/// Fixed window counter status, see [`Status::compute`].
+pub(super) fn status_fixed(prev: u64, now: u64, quota: Quota) -> Status {
+    let mut status = Status::full(quota);
+
+    match decode_fixed(prev, now, quota) {
+        Ok((_, elapsed, cur)) => {
+            status.remaining = status.limit.saturating_sub(cur);
+
+            if cur > 0 {
+                status.reset_after = Duration::from_nanos(quota.tau - elapsed);
+            }
+        }
+        Err(_) => {
+            status.remaining = 0;
+            status.reset_after = Duration::from_nanos(prev.saturating_sub(now));
+        }
+    }
+
+    status
+}
+
+/// Force `n` requests onto a fixed window state, see [`charge_sliding`].
+#[cfg_attr(not(feature = "gossip"), allow(dead_code))]
+pub(super) fn charge_fixed(prev: u64, now: u64, quota: Quota, n: u64) -> u64 {
+    let (w, unit) = (quota.tau, quota.t);
+
+    match decode_fixed(prev, now, quota) {
+        Ok((window, _, cur)) => (window + 1) * w + cur.saturating_add(n).min(unit - 1) * unit,
+        Err(_) => prev,
+    }
+}
@@ -0,0 +1,154 @@
+//! Adapts the rate limiter to [`tonic`] gRPC services, so that gRPC and REST services can share
+//! the same quota configuration and limiter state.
+//!
+//! The [`RateLimitLayer`](crate::RateLimitLayer) works on gRPC requests as-is, as they are plain HTTP/2
+//! requests, but its rejections are regular HTTP responses that gRPC clients don't understand.
+//! [`RateLimitLayerBuilder::grpc_handle_error`](crate::RateLimitLayerBuilder::grpc_handle_error)
+//! instead converts them into gRPC [`Status`]es, with rate-limited requests returning `RESOURCE_EXHAUSTED`
+//! alongside a [`google.rpc.RetryInfo`](https://cloud.google.com/apis/design/errors#error_payloads) detail,
+//! as understood by standard gRPC clients and [`tonic-types`](https://docs.rs/tonic-types).
+//!
+//! Requests can be keyed by peer IP address with [`RealIp`](crate::real_ip::RealIp), which falls back
+//! to the peer address of `tonic` servers when the `real_ip` feature is enabled, or by any metadata key
+//! with [`RateLimitLayerBuilder::with_key_fn`](crate::RateLimitLayerBuilder::with_key_fn), as metadata
+//! is sent as request headers.
+//!
+//! Layers applied to a `tonic` server see requests before they are routed to a service, so all requests use
+//! the [default quota](crate::RateLimitLayerBuilder::with_default_quota). Per-method quotas can be given by
+//! including the method path, such as `/package.Service/Method`, in the key.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//! use axum_gcra::{gcra::Quota, PartsKey, RateLimitLayer};
+//!
+//! // key requests by their `x-api-key` metadata, rejecting requests without one
+//! let layer = RateLimitLayer::<PartsKey<String>>::builder()
+//!     .with_default_quota(Quota::simple(Duration::from_millis(100)))
+//!     .with_key_fn(|parts| Some(PartsKey(parts.headers.get("x-api-key")?.to_str().ok()?.to_owned())))
+//!     .grpc_handle_error();
+//!
+//! let server = tonic::transport::Server::builder().layer(layer);
+//! ```
+
+use axum::response::{IntoResponse, Response};
+use http::{header, StatusCode};
+use tonic::{metadata::MetadataMap, Code, Status};
+
+use crate::{Error, RateLimitContext};
+
+/// Type URL of the `google.rpc.RetryInfo` error detail.
+const RETRY_INFO_TYPE_URL: &str = "type.googleapis.com/google.rpc.RetryInfo";
+
+/// Convert the context of a rate-limited request into a gRPC [`Status`].
+///
+/// Rate-limited requests return `RESOURCE_EXHAUSTED`, and requests rejected by the
+/// [deny list](crate::RateLimitLayerBuilder::with_deny_list) return `PERMISSION_DENIED`.
+/// The status includes a `google.rpc.RetryInfo` detail with the [retry time](RateLimitContext::retry_after),
+/// and the rate limit headers of the rejection as metadata, such as `retry-after`.
+///
+/// This can be used to build gRPC responses in a custom [error handler](crate::RateLimitLayerBuilder::handle_error).
+#[must_use]
+pub fn status(ctx: &RateLimitContext) -> Status {
+    let code = match ctx.is_denied() {
+        true => Code::PermissionDenied,
+        false => Code::ResourceExhausted,
+    };
+
+    let message = ctx.to_string();
+    let details = encode_status_details(code, &message, ctx.retry_after());
+
+    let mut headers = ctx.clone().into_response().into_parts().0.headers;
+
+    // the body is replaced by the gRPC response
+    headers.remove(header::CONTENT_TYPE);
+    headers.remove(header::CONTENT_LENGTH);
+
+    Status::with_details_and_metadata(code, message, details.into(), MetadataMap::from_headers(headers))
+}
+
+/// Convert a rate limiter error into a gRPC response, see
+/// [`RateLimitLayerBuilder::grpc_handle_error`](crate::RateLimitLayerBuilder::grpc_handle_error).
+pub(crate) fn into_response<Inner, Rejection>(e: Error<Inner, Rejection>) -> Response
+where
+    Inner: IntoResponse,
+    Rejection: IntoResponse,
+{
+    let status = match e {
+        Error::RateLimit(ctx) => status(&ctx),
+        Error::Store(_) => Status::unavailable("rate limiter store unavailable"),
+        Error::KeyRejection(rejection) => {
+            let res = rejection.into_response();
+            Status::new(code_for(res.status()), "rate limiter key rejected")
+        }
+        // already handled by the inner service, challenge hook or another error handler
+        Error::Inner(e) => return e.into_response(),
+        Error::Challenge(res) | Error::KeyResponse(res) | Error::Handled(res) => return res,
+    };
+
+    status.into_http().map(axum::body::Body::new)
+}
+
+/// Map the status of an HTTP rejection to the closest gRPC status code.
+fn code_for(status: StatusCode) -> Code {
+    match status {
+        StatusCode::BAD_REQUEST => Code::InvalidArgument,
+        StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+        StatusCode::FORBIDDEN => Code::PermissionDenied,
+        StatusCode::NOT_FOUND => Code::NotFound,
+        StatusCode::TOO_MANY_REQUESTS => Code::ResourceExhausted,
+        StatusCode::SERVICE_UNAVAILABLE => Code::Unavailable,
+        _ => Code::Internal,
+    }
+}
+
+/// Encode a `google.rpc.Status` message with a single `google.rpc.RetryInfo` detail, as used for the
+/// `grpc-status-details-bin` header. The messages are simple enough to encode by hand.
+fn encode_status_details(code: Code, message: &str, retry_after: std::time::Duration) -> Vec<u8> {
+    // google.protobuf.Duration { int64 seconds = 1; int32 nanos = 2; }
+    let mut duration = Vec::new();
+    encode_varint_field(&mut duration, 1, retry_after.as_secs());
+    encode_varint_field(&mut duration, 2, retry_after.subsec_nanos() as u64);
+
+    // google.rpc.RetryInfo { google.protobuf.Duration retry_delay = 1; }
+    let mut retry_info = Vec::new();
+    encode_bytes_field(&mut retry_info, 1, &duration);
+
+    // google.protobuf.Any { string type_url = 1; bytes value = 2; }
+    let mut any = Vec::new();
+    encode_bytes_field(&mut any, 1, RETRY_INFO_TYPE_URL.as_bytes());
+    encode_bytes_field(&mut any, 2, &retry_info);
+
+    // google.rpc.Status { int32 code = 1; string message = 2; repeated google.protobuf.Any details = 3; }
+    let mut status = Vec::new();
+    encode_varint_field(&mut status, 1, code as i32 as u64);
+    encode_bytes_field(&mut status, 2, message.as_bytes());
+    encode_bytes_field(&mut status, 3, &any);
+
+    status
+}
+
+fn encode_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+
+    buf.push(value as u8);
+}
+
+/// Encode a varint field, omitting default zero values as proto3 does.
+fn encode_varint_field(buf: &mut Vec<u8>, field: u64, value: u64) {
+    if value != 0 {
+        encode_varint(buf, field << 3);
+        encode_varint(buf, value);
+    }
+}
+
+/// Encode a length-delimited field.
+fn encode_bytes_field(buf: &mut Vec<u8>, field: u64, value: &[u8]) {
+    encode_varint(buf, field << 3 | 2);
+    encode_varint(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
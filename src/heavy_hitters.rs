@@ -0,0 +1,215 @@
+//! Space-bounded tracking of the keys that consumed the most quota recently.
+//!
+//! See [`RateLimitLayerBuilder::with_heavy_hitters`](crate::RateLimitLayerBuilder::with_heavy_hitters).
+
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    clock::{Clock, Instant, Timeline},
+    gcra::stable_hash,
+};
+
+/// Minimum number of counters per shard.
+const MIN_SHARD_CAPACITY: usize = 64;
+
+/// Key that consumed a lot of quota, as returned by [`RateLimitLayer::heavy_hitters`](crate::RateLimitLayer::heavy_hitters).
+#[derive(Debug, Clone)]
+pub struct HeavyHitter {
+    key: Arc<str>,
+    count: u64,
+    error: u64,
+}
+
+impl HeavyHitter {
+    /// Get the [`Debug`](fmt::Debug) representation of the key.
+    #[inline]
+    #[must_use]
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Get the estimated number of allowed requests by this key, which may overestimate
+    /// the true number by up to [`HeavyHitter::error`], but never underestimates it.
+    #[inline]
+    #[must_use]
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Get the maximum overestimation of [`HeavyHitter::count`].
+    #[inline]
+    #[must_use]
+    pub fn error(&self) -> u64 {
+        self.error
+    }
+}
+
+/// Sharded [SpaceSaving](https://doi.org/10.1007/978-3-540-30570-5_27) sketches over two rotating time windows.
+pub(crate) struct HeavyHitters {
+    time: Timeline,
+    window: u64,
+
+    /// Number of counters per shard.
+    capacity: usize,
+
+    /// Keys are assigned to shards by hash, so shards never share keys.
+    shards: Box<[Mutex<Windows>]>,
+}
+
+#[derive(Default)]
+struct Windows {
+    epoch: u64,
+    current: Sketch,
+    previous: Sketch,
+}
+
+#[derive(Default)]
+struct Sketch {
+    counters: HashMap<u64, Counter, crate::RandomState>,
+}
+
+struct Counter {
+    key: Arc<str>,
+    count: u64,
+    error: u64,
+}
+
+impl fmt::Debug for HeavyHitters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HeavyHitters")
+            .field("window", &Duration::from_nanos(self.window))
+            .field("capacity", &(self.capacity * self.shards.len()))
+            .finish_non_exhaustive()
+    }
+}
+
+impl HeavyHitters {
+    pub fn new(capacity: usize, window: Duration) -> Self {
+        // SpaceSaving needs enough counters per shard to be accurate
+        let shards = (capacity / MIN_SHARD_CAPACITY).clamp(1, crate::gcra::default_shards());
+
+        HeavyHitters {
+            time: Timeline::new(),
+            window: (window.as_nanos() as u64).max(1),
+            capacity: capacity.div_ceil(shards).max(1),
+            shards: (0..shards).map(|_| Mutex::default()).collect(),
+        }
+    }
+
+    /// Set the clock of the layer, if not already used.
+    pub fn set_clock(&self, clock: Arc<dyn Clock>) {
+        self.time.set_clock(clock);
+    }
+
+    #[inline]
+    fn epoch(&self, now: Instant) -> u64 {
+        self.time.relative(now) / self.window
+    }
+
+    /// Count `n` requests for the given key.
+    pub fn record<K: Hash + fmt::Debug>(&self, key: &K, n: u64, now: Instant) {
+        let hash = stable_hash(key);
+        let epoch = self.epoch(now);
+
+        let shard = &self.shards[(hash % self.shards.len() as u64) as usize];
+        let mut shard = shard.lock().unwrap_or_else(|e| e.into_inner());
+
+        shard.rotate(epoch);
+        shard.current.add(hash, n, self.capacity, || format!("{key:?}").into());
+    }
+
+    /// Get up to `k` keys with the highest counts over the current and previous windows, sorted by count.
+    pub fn top(&self, k: usize, now: Instant) -> Vec<HeavyHitter> {
+        let epoch = self.epoch(now);
+        let mut top = Vec::new();
+
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap_or_else(|e| e.into_inner());
+
+            shard.rotate(epoch);
+
+            let mut merged: HashMap<u64, HeavyHitter, crate::RandomState> = HashMap::default();
+
+            for sketch in [&shard.previous, &shard.current] {
+                for (&hash, counter) in &sketch.counters {
+                    let hitter = merged.entry(hash).or_insert_with(|| HeavyHitter {
+                        key: counter.key.clone(),
+                        count: 0,
+                        error: 0,
+                    });
+
+                    hitter.count += counter.count;
+                    hitter.error += counter.error;
+                }
+            }
+
+            top.extend(merged.into_values());
+        }
+
+        top.sort_unstable_by_key(|hitter| std::cmp::Reverse(hitter.count));
+        top.truncate(k);
+        top
+    }
+}
+
+impl Windows {
+    fn rotate(&mut self, epoch: u64) {
+        if epoch == self.epoch {
+            return;
+        }
+
+        if epoch == self.epoch + 1 {
+            self.previous = std::mem::take(&mut self.current);
+        } else {
+            self.previous.counters.clear();
+            self.current.counters.clear();
+        }
+
+        self.epoch = epoch;
+    }
+}
+
+impl Sketch {
+    fn add(&mut self, hash: u64, n: u64, capacity: usize, key: impl FnOnce() -> Arc<str>) {
+        if let Some(counter) = self.counters.get_mut(&hash) {
+            counter.count += n;
+            return;
+        }
+
+        if self.counters.len() < capacity {
+            self.counters.insert(
+                hash,
+                Counter {
+                    key: key(),
+                    count: n,
+                    error: 0,
+                },
+            );
+            return;
+        }
+
+        // replace the smallest counter, inheriting its count as the error
+        let Some((&min, _)) = self.counters.iter().min_by_key(|(_, counter)| counter.count) else {
+            return;
+        };
+
+        let Some(Counter { count, .. }) = self.counters.remove(&min) else {
+            return;
+        };
+
+        self.counters.insert(
+            hash,
+            Counter {
+                key: key(),
+                count: count + n,
+                error: count,
+            },
+        );
+    }
+}
@@ -2,18 +2,48 @@
 #![cfg_attr(docsrs, feature(doc_auto_cfg, doc_cfg))]
 #![warn(clippy::perf, clippy::style)]
 
+#[cfg(not(any(feature = "axum-07", feature = "axum-08")))]
+compile_error!("one of the `axum-07` or `axum-08` features must be enabled");
+
+// `axum-08` takes precedence so that `--all-features` builds still work
+/// The version of `axum` this crate is built against, which is axum 0.8 if the `axum-08` feature is enabled,
+/// even if `axum-07` is enabled as well, such as with `--all-features`, and axum 0.7 otherwise.
+///
+/// Routers, extractors and responses must come from the same version, so code that supports both features
+/// can import `axum` through this re-export.
+///
+/// ```rust
+/// // with both features enabled, axum 0.8 wins
+/// # #[cfg(feature = "axum-08")]
+/// let _: axum_gcra::axum::Router = axum08::Router::new();
+///
+/// # #[cfg(not(feature = "axum-08"))]
+/// let _: axum_gcra::axum::Router = axum::Router::new();
+/// ```
+#[cfg(feature = "axum-08")]
+pub extern crate axum08 as axum;
+
+/// The version of `axum` this crate is built against, see the `axum-08` feature.
+#[cfg(not(feature = "axum-08"))]
+pub use ::axum;
+
 use std::{
     any::TypeId,
     borrow::Cow,
     collections::HashMap,
     convert::Infallible,
+    fmt,
     future::{Future, Ready},
     hash::{BuildHasher, Hash},
     ops::Deref,
+    path::PathBuf,
     pin::Pin,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering},
+        Arc,
+    },
     task::{ready, Context, Poll},
-    time::{Duration, Instant},
+    time::Duration,
 };
 
 use axum::{
@@ -24,6 +54,8 @@ use axum::{
 use http::{request::Parts, Extensions, Method};
 use tower::{Layer, Service};
 
+use crate::clock::Instant;
+
 #[cfg(feature = "ahash")]
 type RandomState = ahash::RandomState;
 
@@ -33,25 +65,159 @@ type RandomState = std::collections::hash_map::RandomState;
 #[cfg(feature = "real_ip")]
 pub mod real_ip;
 
+#[cfg(feature = "real_ip")]
+pub mod asn;
+
 #[cfg(all(doc, feature = "real_ip"))]
 use real_ip::RealIp; // needed for the doc link in the README
 
+#[cfg(feature = "real_ip")]
+mod router;
+
+#[cfg(feature = "real_ip")]
+pub use router::{RouterRateLimitExt, RouterRealIpExt};
+
 /// Trait for user-provided keys used to identify rate limiter entries.
 ///
 /// Keys should be uniquely identifiable to avoid rate limiting other users,
 /// e.g. using a user ID or IP address.
 ///
 /// Keys must also implement [`FromRequestParts`] to extract the key from the request
-/// within the rate limiter layer/service.
-pub trait Key: Hash + Eq + Send + Sync + 'static {}
+/// within the rate limiter layer/service, unless wrapped in a [`PartsKey`].
+///
+/// The [`Debug`](fmt::Debug) representation of a key is used to identify it
+/// in [`RateLimitContext`] and other diagnostics.
+pub trait Key: Hash + Eq + fmt::Debug + Send + Sync + 'static {}
+
+impl<K> Key for K where K: Hash + Eq + fmt::Debug + Send + Sync + 'static {}
+
+/// Key extracted by a [key function](RateLimitLayerBuilder::with_key_fn), or otherwise cloned from the
+/// request extensions, such as to use the rate limiter in front of plain `hyper` or `tower` services
+/// without implementing axum's [`FromRequestParts`] for the key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PartsKey<K>(pub K);
+
+/// Rejection of a [`PartsKey`] that was neither returned by the [key function](RateLimitLayerBuilder::with_key_fn)
+/// nor found in the request extensions, which responds with `500 Internal Server Error`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MissingKey;
+
+impl fmt::Display for MissingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("missing rate limiter key")
+    }
+}
+
+impl std::error::Error for MissingKey {}
+
+impl IntoResponse for MissingKey {
+    fn into_response(self) -> Response {
+        (http::StatusCode::INTERNAL_SERVER_ERROR, "Missing rate limiter key").into_response()
+    }
+}
+
+/// What to do with a request whose key could not be extracted, as returned by the
+/// [key rejection handler](RateLimitLayerBuilder::on_key_rejection).
+pub enum KeyFallback<K> {
+    /// Reject the request with the rejection of the key extractor, as [`Error::KeyRejection`].
+    Reject,
+
+    /// Reject the request with the given response instead, such as with a custom status or JSON body,
+    /// as [`Error::KeyResponse`].
+    Respond(Response),
+
+    /// Rate limit the request with the given key instead, such as an anonymous key whose bucket is
+    /// shared by all requests without a key.
+    Key(K),
+}
+
+impl<K: fmt::Debug> fmt::Debug for KeyFallback<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyFallback::Reject => f.write_str("Reject"),
+            KeyFallback::Respond(res) => f.debug_tuple("Respond").field(&res.status()).finish(),
+            KeyFallback::Key(key) => f.debug_tuple("Key").field(key).finish(),
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "axum-08"), async_trait::async_trait)]
+impl<K, S> FromRequestParts<S> for PartsKey<K>
+where
+    K: Clone + Send + Sync + 'static,
+    S: Send + Sync,
+{
+    type Rejection = MissingKey;
 
-impl<K> Key for K where K: Hash + Eq + Send + Sync + 'static {}
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<K>().cloned().map(PartsKey).ok_or(MissingKey)
+    }
+}
 
 pub mod gcra;
+
+pub mod clock;
+
+#[cfg(feature = "tokio")]
+mod gc;
+
+#[cfg(feature = "tokio")]
+pub use gc::GcHandle;
 pub use gcra::RateLimitError;
 
+#[cfg(feature = "problem_json")]
+mod problem;
+
+mod delay;
+mod rejection;
+mod warmup;
+
+pub mod store;
+
+pub mod ban;
+pub mod bandwidth;
+pub mod challenge;
+pub mod config;
+pub mod deny;
+pub mod heavy_hitters;
+pub mod query_key;
+pub mod resolver;
+pub mod route_key;
+pub mod violations;
+pub mod ws;
+
+#[cfg(feature = "tokio")]
+pub mod events;
+
+#[cfg(feature = "metrics")]
+pub mod metrics;
+
+#[cfg(feature = "opentelemetry")]
+pub mod otel;
+
+#[cfg(feature = "load")]
+pub mod load;
+
+#[cfg(feature = "admin")]
+mod admin;
+
+#[cfg(feature = "tonic")]
+pub mod grpc;
+
+#[cfg(feature = "tower_sessions")]
+pub mod session;
+
+#[cfg(feature = "axum-extra")]
+pub mod typed_headers;
+
+#[cfg(feature = "testing")]
+pub mod testing;
+
+#[cfg(feature = "accept")]
+pub mod accept;
+
 /// Interval for garbage collection of the rate limiter, which can be either
-/// a number of requests or a time duration.
+/// a number of requests or a time duration, or manual.
 ///
 /// The default is 8192 requests.
 ///
@@ -61,7 +227,8 @@ pub enum GCInterval {
     /// Run garbage collection after a number of requests.
     ///
     /// This may temporarily block a request if the rate limiter is being cleaned,
-    /// as that single request needs to wait on all parts of the table to be cleaned.
+    /// as that single request needs to wait on its [shard](RateLimitLayerBuilder::with_shards)
+    /// of the table to be cleaned. Each shard counts its requests separately.
     ///
     /// Setting this to `u64::MAX` will disable garbage collection entirely.
     Requests(u64),
@@ -71,6 +238,13 @@ pub enum GCInterval {
     /// This does not block the request, since it runs externally to the request.
     #[cfg(feature = "tokio")]
     Time(Duration),
+
+    /// Never run garbage collection automatically, such as on runtimes without tokio or background tasks.
+    ///
+    /// Expired entries must then be cleaned by calling [`RateLimitLayer::clean_now`]
+    /// or [`RateLimitLayer::clean_now_sync`] periodically,
+    /// such as from a timer of the runtime in use.
+    Manual,
 }
 
 impl Default for GCInterval {
@@ -86,6 +260,8 @@ impl GCInterval {
 
             #[cfg(feature = "tokio")]
             GCInterval::Time(_) => u64::MAX,
+
+            GCInterval::Manual => u64::MAX,
         }
     }
 }
@@ -143,6 +319,88 @@ macro_rules! decl_route_methods {
     };
 }
 
+/// Class of HTTP methods, by whether they are [safe](https://www.rfc-editor.org/rfc/rfc9110#section-9.2.1),
+/// meaning read-only, or may modify state on the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MethodClass {
+    /// `GET`, `HEAD`, `OPTIONS` and `TRACE` requests.
+    Safe,
+
+    /// All other requests, such as `POST`, `PUT`, `PATCH` and `DELETE`.
+    Unsafe,
+}
+
+impl MethodClass {
+    /// Get the class of the given method.
+    #[must_use]
+    pub fn of(method: &Method) -> Self {
+        match *method {
+            Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE => MethodClass::Safe,
+            _ => MethodClass::Unsafe,
+        }
+    }
+}
+
+/// Typed identifier of a route, such as a user-defined enum of all routes of an app,
+/// to register quotas without repeating route strings in the builder.
+///
+/// Using the same identifiers to define the router and the quotas turns typos into compile errors,
+/// rather than quotas that silently never apply.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use axum_gcra::axum;
+/// use std::time::Duration;
+/// use axum::{routing::{get, post}, Router};
+/// use axum_gcra::{gcra::Quota, RateLimitLayer, RouteId};
+/// use http::Method;
+///
+/// #[derive(Clone, Copy)]
+/// enum ApiRoute {
+///     Login,
+///     Messages,
+/// }
+///
+/// impl RouteId for ApiRoute {
+///     fn method(&self) -> Method {
+///         match self {
+///             ApiRoute::Login => Method::POST,
+///             ApiRoute::Messages => Method::GET,
+///         }
+///     }
+///
+///     fn path(&self) -> &'static str {
+///         match self {
+///             ApiRoute::Login => "/login",
+///             ApiRoute::Messages => "/messages",
+///         }
+///     }
+/// }
+///
+/// let app = Router::<()>::new()
+///     .route(ApiRoute::Login.path(), post(|| async { "Logged in" }))
+///     .route(ApiRoute::Messages.path(), get(|| async { "Messages" }))
+///     .route_layer(
+///         RateLimitLayer::<()>::builder()
+///             .with_route_id(ApiRoute::Login, Quota::simple(Duration::from_secs(5)))
+///             .with_route_id(ApiRoute::Messages, Quota::simple(Duration::from_millis(100)))
+///             .default_handle_error(),
+///     );
+/// ```
+pub trait RouteId {
+    /// Method of the route.
+    fn method(&self) -> Method;
+
+    /// Path of the route, as given to [`axum::Router::route`].
+    fn path(&self) -> &'static str;
+
+    /// Get the [`Route`] for this identifier.
+    fn route(&self) -> Route<'static> {
+        Route::new(self.method(), self.path())
+    }
+}
+
 decl_route_methods! {
     get     => GET,
     post    => POST,
@@ -155,30 +413,132 @@ decl_route_methods! {
     connect => CONNECT
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 struct RouteWithKey<T> {
     path: MatchedPath,
     method: Method,
     key: T,
 }
 
+// interned routes are hashed by their precomputed ID instead of the method and path
+impl<T: Hash> Hash for RouteWithKey<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        match self.path {
+            MatchedPath::Interned(id, _) => state.write_u64(id),
+            ref path => {
+                path.hash(state);
+                self.method.hash(state);
+            }
+        }
+
+        self.key.hash(state);
+    }
+}
+
+impl<T: PartialEq> PartialEq for RouteWithKey<T> {
+    fn eq(&self, other: &Self) -> bool {
+        if let (MatchedPath::Interned(a, _), MatchedPath::Interned(b, _)) = (&self.path, &other.path) {
+            if a != b {
+                return false;
+            }
+        }
+
+        self.path == other.path && self.method == other.method && self.key == other.key
+    }
+}
+
+impl<T: Eq> Eq for RouteWithKey<T> {}
+
 impl<T> RouteWithKey<T> {
     #[inline]
-    fn as_route(&self) -> Route {
+    fn as_route(&self) -> Route<'_> {
         Route {
             path: Cow::Borrowed(&*self.path),
             method: Cow::Borrowed(&self.method),
         }
     }
+    fn into_route_key(self) -> (Route<'static>, T) {
+        let path = match self.path {
+            MatchedPath::Static(path) => path,
+            path => Cow::Owned(String::from(&*path)),
+        };
+
+        let route = Route {
+            path,
+            method: Cow::Owned(self.method),
+        };
+
+        (route, self.key)
+    }
 }
 
 /// Hashmap of quotas for rate limiting, mapping a path as passed to [`Router`](axum::Router) to a [`gcra::Quota`].
 type Quotas = HashMap<Route<'static>, gcra::Quota, RandomState>;
 
+/// Route from the quota table, interned when the layer is built so that rate limiter keys
+/// can be hashed by a precomputed ID rather than the route path on every request.
+#[derive(Debug, Clone)]
+struct InternedRoute {
+    id: u64,
+    path: Arc<str>,
+    quota: gcra::Quota,
+}
+
+/// Hashmap of interned routes, see [`InternedRoute`].
+type InternedRoutes = HashMap<Route<'static>, InternedRoute, RandomState>;
+
+/// Bucket of a [shared quota](RateLimitLayerBuilder::with_shared_quota), by the index of the quota
+/// and the stable hash of the key.
+type SharedBucket = (usize, u64);
+
+/// Quotas shared by groups of routes, see [`RateLimitLayerBuilder::with_shared_quota`].
+struct SharedQuotas {
+    quotas: Vec<gcra::Quota>,
+    routes: HashMap<Route<'static>, usize, RandomState>,
+    limiter: gcra::RateLimiter<SharedBucket>,
+}
+
+/// Bucket of a [parent key](RateLimitLayerBuilder::with_parent_key), by the index of the parent
+/// and the stable hash of the parent key.
+type ParentBucket = (usize, u64);
+
+/// Function deriving the stable hash of a [parent key](RateLimitLayerBuilder::with_parent_key) from a key.
+type ParentFn<K> = Box<dyn Fn(&K) -> Option<u64> + Send + Sync>;
+
+/// Quotas of keys derived from the key of requests, see [`RateLimitLayerBuilder::with_parent_key`].
+struct ParentKeys<K> {
+    parents: Vec<(gcra::Quota, ParentFn<K>)>,
+    limiter: gcra::RateLimiter<ParentBucket>,
+}
+
+impl SharedQuotas {
+    fn config(&self) -> Vec<(Vec<Route<'static>>, gcra::Quota)> {
+        let mut groups: Vec<_> = self.quotas.iter().map(|&quota| (Vec::new(), quota)).collect();
+
+        for (route, &group) in &self.routes {
+            groups[group].0.push(route.clone());
+        }
+
+        for (routes, _) in &mut groups {
+            routes.sort_unstable_by(|a, b| (&a.path, a.method.as_str()).cmp(&(&b.path, b.method.as_str())));
+        }
+
+        // groups whose routes were all moved to later groups never apply
+        groups.retain(|(routes, _)| !routes.is_empty());
+        groups
+    }
+}
+
 #[derive(Debug, Clone)]
 enum MatchedPath {
     Fallback,
     Axum(AxumMatchedPath),
+
+    /// User-provided path, such as when resetting a route manually.
+    Static(Cow<'static, str>),
+
+    /// Path of a route in the quota table, with the ID of the route, see [`InternedRoute`].
+    Interned(u64, Arc<str>),
 }
 
 impl Deref for MatchedPath {
@@ -189,6 +549,8 @@ impl Deref for MatchedPath {
         match self {
             MatchedPath::Fallback => "",
             MatchedPath::Axum(path) => path.as_str(),
+            MatchedPath::Static(path) => path,
+            MatchedPath::Interned(_, path) => path,
         }
     }
 }
@@ -230,24 +592,260 @@ struct BuilderDropNotify {
 /// Builder for the rate limiter layer.
 ///
 /// This struct is used to configure the rate limiter before building it.
-pub struct RateLimitLayerBuilder<K = (), H: BuildHasher = RandomState> {
+pub struct RateLimitLayerBuilder<K: Key = (), H: BuildHasher = RandomState> {
     quotas: Quotas,
+    routes: InternedRoutes,
     default_quota: gcra::Quota,
+
+    /// Default quotas overriding `default_quota` for each [`MethodClass`], see [`RateLimitLayerBuilder::with_default_quota_for`].
+    class_quotas: [Option<gcra::Quota>; 2],
+
+    /// Whether requests using default quotas share one bucket per [`MethodClass`] rather than per method,
+    /// see [`RateLimitLayerBuilder::with_read_write_quotas`].
+    class_buckets: bool,
+
+    /// Default quotas of IPv4 and IPv6 keys, see [`RateLimitLayerBuilder::with_ipv4_default_quota`].
+    ip_quotas: [Option<gcra::Quota>; 2],
+
+    /// Prefix lengths IPv4 and IPv6 keys are masked to, see [`RateLimitLayerBuilder::with_ip_masks`].
+    ip_masks: Option<(u8, u8)>,
+
+    /// Quotas of [pre-registered keys](RateLimitLayerBuilder::add_key_quotas), by the stable hash of the key.
+    key_quotas: HashMap<u64, gcra::Quota, RandomState>,
     set_ext: Option<Box<dyn SetExtension<K, H>>>,
+    track_response: Option<Box<dyn TrackResponse<K, H>>>,
+    status_penalties: Vec<(http::StatusCode, u64)>,
+    cancel_refund: Option<Box<dyn GuardRefund<K, H>>>,
+    challenge: Option<Box<dyn IssueChallenge<K>>>,
+    route_errors: Vec<(Cow<'static, str>, RouteErrorFn)>,
+    challenges: Option<Arc<challenge::Pending<K>>>,
+
+    #[cfg(feature = "tokio")]
+    events: Option<events::Events>,
     global_fallback: bool,
+    prefix_fallback: bool,
+    fallback_exemptions: Vec<Cow<'static, str>>,
+
+    /// Prefix the router is nested under, stripped from matched paths, see [`RateLimitLayerBuilder::with_nest_prefix`].
+    nest_prefix: Option<Cow<'static, str>>,
+
+    /// Idle timeouts of the entries of specific routes, see [`RateLimitLayerBuilder::with_route_ttl`].
+    route_ttls: HashMap<Route<'static>, Duration, RandomState>,
+    idle_timeout: Option<Duration>,
+    lifecycle: KeyLifecycle,
     gc_interval: GCInterval,
+    gc_requests: Option<u64>,
+    shards: Option<usize>,
+    max_entries: Option<usize>,
+    overflow: gcra::OverflowPolicy,
+    incremental_gc: bool,
+    rejection: Arc<rejection::RejectionConfig>,
+    bans: Option<ban::Bans<K>>,
+    violations: Option<violations::Violations<K>>,
+    deny_list: Option<deny::DenyList<K>>,
+    allow_list: deny::AllowList,
+    heavy_hitters: Option<heavy_hitters::HeavyHitters>,
+
+    clock: Option<Arc<dyn clock::Clock>>,
+    set_info: bool,
+    policy_header: bool,
+    soft_limit: Option<f32>,
+    correlation_header: Option<http::HeaderName>,
+    decision_hook: Option<DecisionHook>,
+    key_fn: Option<KeyFn<K>>,
+    key_fallback: Option<KeyFallbackFn<K>>,
+    key_normalizer: Option<Box<dyn Fn(K) -> K + Send + Sync>>,
+    route_key: Option<Box<dyn route_key::RouteKey>>,
+    quota_resolver: Option<Box<dyn resolver::QuotaResolver<K>>>,
+
+    /// Percentage of keys whose rate limit rejections are enforced, see [`RateLimitLayerBuilder::with_enforcement`].
+    enforcement: AtomicU8,
+    /// Bits of the `f32` multiplier of all quotas, see [`RateLimitLayer::set_scale`].
+    scale: AtomicU32,
+
+    /// Whether rejections are enforced at all, see [`RateLimitLayerBuilder::with_enabled_flag`].
+    enabled: Arc<AtomicBool>,
+    max_delay: Option<Duration>,
+    delay_queues: Arc<delay::DelayQueues>,
+    bandwidth: Option<bandwidth::Bandwidth>,
+    server_quota: Option<(gcra::Quota, gcra::RateLimiter<()>)>,
+    shared_quotas: Option<SharedQuotas>,
+    parent_keys: Option<ParentKeys<K>>,
+    warmup: Option<warmup::Warmup>,
+
+    #[cfg(feature = "load")]
+    load: load::LoadTracker,
+    state: Option<RateLimitState<K, H>>,
+    store: Option<Arc<dyn store::Store<K>>>,
+    failure_policy: store::FailurePolicy,
+    persist: Option<PathBuf>,
+    persist_on_drop: Option<PersistOnDrop<K, H>>,
+    ban_persist: Option<PathBuf>,
+    ban_persist_on_drop: Option<PersistBansOnDrop<K>>,
+    ban_store: Option<(Arc<dyn ban::BanStore>, Duration)>,
 
     #[cfg(feature = "tokio")]
     shutdown: BuilderDropNotify,
 }
 
-impl<K, H: BuildHasher> Drop for RateLimitLayerBuilder<K, H> {
+/// Saves the rate limiter state when the builder is dropped, see [`RateLimitLayerBuilder::with_persistence`].
+struct PersistOnDrop<K: Key, H: BuildHasher> {
+    path: PathBuf,
+    limiter: Arc<gcra::RateLimiter<RouteWithKey<K>, H>>,
+}
+
+impl<K: Key, H: BuildHasher> Drop for PersistOnDrop<K, H> {
+    fn drop(&mut self) {
+        _ = self.limiter.save_to(&self.path);
+    }
+}
+
+/// Saves the bans when the builder is dropped, see [`RateLimitLayerBuilder::with_ban_persistence`].
+struct PersistBansOnDrop<K: Key> {
+    path: PathBuf,
+    bans: ban::Bans<K>,
+}
+
+impl<K: Key> Drop for PersistBansOnDrop<K> {
+    fn drop(&mut self) {
+        _ = self.bans.save_to(&self.path);
+    }
+}
+
+type KeyInsertedFn = Box<dyn Fn(&Route<'_>, &str) + Send + Sync>;
+type KeyEvictedFn = Box<dyn Fn(&Route<'_>, &str, gcra::EvictedEntry) + Send + Sync>;
+
+/// Callbacks for keys entering and leaving the rate limiter, see [`RateLimitLayerBuilder::on_key_inserted`].
+#[derive(Default)]
+struct KeyLifecycle {
+    inserted: Option<KeyInsertedFn>,
+    evicted: Option<KeyEvictedFn>,
+}
+
+impl KeyLifecycle {
+    fn is_set(&self) -> bool {
+        self.inserted.is_some() || self.evicted.is_some()
+    }
+}
+
+impl<K: Key> gcra::Lifecycle<RouteWithKey<K>> for KeyLifecycle {
+    fn inserted(&self, key: &RouteWithKey<K>) {
+        if let Some(ref f) = self.inserted {
+            f(&key.as_route(), &format!("{:?}", key.key));
+        }
+    }
+
+    fn evicted(&self, key: &RouteWithKey<K>, entry: gcra::EvictedEntry) {
+        if let Some(ref f) = self.evicted {
+            f(&key.as_route(), &format!("{:?}", key.key), entry);
+        }
+    }
+}
+
+impl<K: Key, H: BuildHasher> Drop for RateLimitLayerBuilder<K, H> {
     fn drop(&mut self) {
         #[cfg(feature = "tokio")]
         self.shutdown.notify.notify_waiters();
     }
 }
 
+/// Shared rate limiter state, containing the table of rate limiter entries.
+///
+/// By default, each [`RateLimitLayer`] built from a [`RateLimitLayerBuilder`] creates its own state,
+/// which is shared by all clones of that layer. To share the same entries between layers with
+/// different configurations, such as the same API mounted under multiple routers, the state can be
+/// created separately or taken from an existing layer with [`RateLimitLayer::state`],
+/// then given to other builders with [`RateLimitLayerBuilder::with_state`].
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use axum_gcra::axum;
+/// use axum::Router;
+/// use axum_gcra::{RateLimitLayer, RateLimitState, real_ip::RealIp};
+///
+/// let state = RateLimitState::<RealIp>::default();
+///
+/// let internal = Router::<()>::new().route_layer(
+///     RateLimitLayer::builder().with_state(state.clone()).default_handle_error());
+///
+/// let external = Router::<()>::new().route_layer(
+///     RateLimitLayer::builder().with_state(state).with_global_fallback(true).default_handle_error());
+/// ```
+pub struct RateLimitState<K: Key = (), H: BuildHasher = RandomState> {
+    limiter: Arc<gcra::RateLimiter<RouteWithKey<K>, H>>,
+}
+
+impl<K: Key, H: BuildHasher> Clone for RateLimitState<K, H> {
+    fn clone(&self) -> Self {
+        Self {
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+impl<K: Key, H: BuildHasher + Clone> RateLimitState<K, H> {
+    /// Create new empty rate limiter state with the given hasher and garbage collection interval,
+    /// in number of requests. See [`gcra::RateLimiter::new`] for more information.
+    ///
+    /// Time-based garbage collection is configured on each builder with
+    /// [`RateLimitLayerBuilder::with_gc_interval`] instead.
+    #[must_use]
+    pub fn new(gc_interval: u64, hasher: H) -> Self {
+        RateLimitState {
+            limiter: Arc::new(gcra::RateLimiter::new(gc_interval, hasher)),
+        }
+    }
+
+    /// Create new empty rate limiter state split into the given number of shards.
+    /// See [`gcra::RateLimiter::with_shards`] for more information.
+    #[must_use]
+    pub fn with_shards(gc_interval: u64, shards: usize, hasher: H) -> Self {
+        RateLimitState {
+            limiter: Arc::new(gcra::RateLimiter::with_shards(gc_interval, shards, hasher)),
+        }
+    }
+}
+
+impl<K: Key, H: BuildHasher> RateLimitState<K, H> {
+    /// Returns `true` if both states refer to the same rate limiter entries.
+    #[must_use]
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.limiter, &other.limiter)
+    }
+
+    /// Returns the approximate number of entries in the state.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.limiter.len()
+    }
+
+    /// Returns `true` if the state has no entries.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Pre-populate the state from a [`gcra::HashedState`], such as a snapshot taken with
+    /// [`RateLimitLayer::hashed_state`], before building layers with it.
+    /// See [`gcra::RateLimiter::restore`] for more information.
+    pub fn restore(&self, state: &gcra::HashedState) -> usize {
+        self.limiter.restore(state)
+    }
+
+    /// Pre-populate the state from a file written by [`RateLimitLayer::save_to`].
+    /// See [`gcra::RateLimiter::load_from`] for more information.
+    pub fn load_from(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<usize> {
+        self.limiter.load_from(path)
+    }
+}
+
+impl<K: Key, H: BuildHasher + Default + Clone> Default for RateLimitState<K, H> {
+    fn default() -> Self {
+        RateLimitState::new(GCInterval::default().to_requests(), H::default())
+    }
+}
+
 /// Rate limiter [`Layer`] for axum.
 ///
 /// This struct is not meant to be used directly, but rather through the [`RateLimitLayerBuilder`].
@@ -263,7 +861,13 @@ pub struct RateLimitLayer<K: Key = (), H: BuildHasher = RandomState> {
 /// Used to insert the rate limiter into the request's extensions,
 /// without knowing the type of the key except when the handler is defined and not further.
 trait SetExtension<K: Key, H: BuildHasher>: Send + Sync + 'static {
-    fn set_extension(&self, req: &mut Extensions, key: &RouteWithKey<K>, layer: RateLimitLayer<K, H>);
+    fn set_extension(
+        &self,
+        req: &mut Extensions,
+        key: &RouteWithKey<K>,
+        quota: gcra::Quota,
+        layer: RateLimitLayer<K, H>,
+    );
 }
 
 struct DoSetExtension;
@@ -273,49 +877,276 @@ where
     K: Clone,
     H: Send + Sync + 'static,
 {
-    fn set_extension(&self, req: &mut Extensions, key: &RouteWithKey<K>, layer: RateLimitLayer<K, H>) {
+    fn set_extension(
+        &self,
+        req: &mut Extensions,
+        key: &RouteWithKey<K>,
+        quota: gcra::Quota,
+        layer: RateLimitLayer<K, H>,
+    ) {
         req.insert(extensions::RateLimiter::<K, H> {
             key: key.clone(),
+            quota,
             layer,
         });
     }
 }
 
-impl<K: Key, H: BuildHasher> Clone for RateLimitLayer<K, H> {
-    fn clone(&self) -> Self {
-        Self {
-            limiter: self.limiter.clone(),
-            builder: self.builder.clone(),
-        }
+/// Callback run with the status of the inner response of an allowed request,
+/// returning a future to drive before the response is returned, if any.
+type ResponseHook = Box<dyn FnOnce(http::StatusCode) -> Option<BoxFuture<'static, ()>> + Send>;
+
+/// Object-safe trait for tracking the response of an allowed request.
+///
+/// Used to apply [status penalties](RateLimitLayerBuilder::with_status_penalty), which need to keep
+/// a copy of the key until the response is ready, similar to [`SetExtension`].
+trait TrackResponse<K: Key, H: BuildHasher>: Send + Sync + 'static {
+    fn track_response(
+        &self,
+        key: &RouteWithKey<K>,
+        quota: gcra::Quota,
+        layer: &RateLimitLayer<K, H>,
+    ) -> ResponseHook;
+}
+
+struct DoTrackResponse;
+
+impl<K: Key, H: BuildHasher> TrackResponse<K, H> for DoTrackResponse
+where
+    K: Clone,
+    H: Send + Sync + 'static,
+{
+    fn track_response(
+        &self,
+        key: &RouteWithKey<K>,
+        quota: gcra::Quota,
+        layer: &RateLimitLayer<K, H>,
+    ) -> ResponseHook {
+        let key = key.clone();
+        let layer = layer.clone();
+
+        Box::new(move |status| layer.penalize_status(key, quota, status))
     }
 }
 
-impl<I: Clone, K: Key, H: BuildHasher> Clone for RateLimitService<I, K, H> {
-    fn clone(&self) -> Self {
-        Self {
-            inner: self.inner.clone(),
-            layer: self.layer.clone(),
-        }
+/// Object-safe trait for [refunding cancelled requests](RateLimitLayerBuilder::with_cancel_refund),
+/// which wraps the response hook of an allowed request in a [`RefundGuard`].
+trait GuardRefund<K: Key, H: BuildHasher>: Send + Sync + 'static {
+    fn guard_refund(
+        &self,
+        key: &RouteWithKey<K>,
+        quota: gcra::Quota,
+        layer: &RateLimitLayer<K, H>,
+        hook: Option<ResponseHook>,
+    ) -> ResponseHook;
+}
+
+struct DoGuardRefund;
+
+impl<K: Key, H: BuildHasher> GuardRefund<K, H> for DoGuardRefund
+where
+    K: Clone,
+    H: Send + Sync + 'static,
+{
+    fn guard_refund(
+        &self,
+        key: &RouteWithKey<K>,
+        quota: gcra::Quota,
+        layer: &RateLimitLayer<K, H>,
+        hook: Option<ResponseHook>,
+    ) -> ResponseHook {
+        let guard = RefundGuard {
+            key: Some(key.clone()),
+            quota,
+            layer: layer.clone(),
+        };
+
+        Box::new(move |status| {
+            guard.disarm();
+            hook.and_then(|hook| hook(status))
+        })
     }
 }
 
-impl<K: Key, H: BuildHasher> RateLimitLayer<K, H> {
-    /// Begin building a new rate limiter layer starting with the default configuration.
-    #[must_use]
-    pub fn builder() -> RateLimitLayerBuilder<K, H> {
-        RateLimitLayerBuilder::new()
+/// Refunds an allowed request when dropped before the response is produced,
+/// such as when the client disconnects, unless [disarmed](RefundGuard::disarm).
+struct RefundGuard<K: Key, H: BuildHasher> {
+    key: Option<RouteWithKey<K>>,
+    quota: gcra::Quota,
+    layer: RateLimitLayer<K, H>,
+}
+
+impl<K: Key, H: BuildHasher> RefundGuard<K, H> {
+    /// Drop the guard without refunding, as the response was produced.
+    fn disarm(mut self) {
+        self.key = None;
     }
 }
 
-impl<K: Key, H: BuildHasher> RateLimitLayerBuilder<K, H> {
-    #[must_use]
-    pub fn new() -> Self {
+impl<K: Key, H: BuildHasher> Drop for RefundGuard<K, H> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.layer.refund_cancelled(key, self.quota);
+        }
+    }
+}
+
+/// Future returned by the [challenge hook](RateLimitLayerBuilder::with_challenge).
+type ChallengeFuture = BoxFuture<'static, Option<Response>>;
+
+/// Object-safe trait for issuing a challenge to a rate-limited request,
+/// which needs to keep a copy of the key until the challenge is solved.
+trait IssueChallenge<K: Key>: Send + Sync + 'static {
+    fn issue_challenge(&self, key: &RouteWithKey<K>, ctx: &RateLimitContext) -> ChallengeFuture;
+}
+
+struct DoIssueChallenge<K, F> {
+    pending: Arc<challenge::Pending<K>>,
+    hook: F,
+}
+
+impl<K, F, R> IssueChallenge<K> for DoIssueChallenge<K, F>
+where
+    K: Key + Clone,
+    F: Fn(challenge::Challenge) -> R + Send + Sync + 'static,
+    R: Future<Output = Option<Response>> + Send + 'static,
+{
+    fn issue_challenge(&self, key: &RouteWithKey<K>, ctx: &RateLimitContext) -> ChallengeFuture {
+        Box::pin((self.hook)(self.pending.issue(key.clone(), ctx.clone())))
+    }
+}
+
+/// Rejection of a request by the rate limiter, with a pending [challenge](RateLimitLayerBuilder::with_challenge), if any.
+struct Rejected {
+    ctx: RateLimitContext,
+    challenge: Option<ChallengeFuture>,
+}
+
+impl Rejected {
+    /// Wait for the challenge, if any, to decide the error.
+    async fn into_error<K: Key, H: BuildHasher, Inner, Rejection>(
+        self: Box<Self>,
+        layer: &RateLimitLayer<K, H>,
+    ) -> Error<Inner, Rejection> {
+        if let Some(challenge) = self.challenge {
+            if let Some(res) = challenge.await {
+                return Error::Challenge(res);
+            }
+        }
+
+        layer.rate_limited(self.ctx)
+    }
+}
+
+type RouteErrorFn = Box<dyn Fn(RateLimitContext) -> Response + Send + Sync>;
+
+/// Check if the given path is within the given prefix, either exactly or at a segment boundary.
+fn within_prefix(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/'),
+        None => false,
+    }
+}
+
+impl<K: Key, H: BuildHasher> Clone for RateLimitLayer<K, H> {
+    fn clone(&self) -> Self {
+        Self {
+            limiter: self.limiter.clone(),
+            builder: self.builder.clone(),
+        }
+    }
+}
+
+impl<I: Clone, K: Key, H: BuildHasher> Clone for RateLimitService<I, K, H> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+impl<K: Key, H: BuildHasher> RateLimitLayer<K, H> {
+    /// Begin building a new rate limiter layer starting with the default configuration.
+    #[must_use]
+    pub fn builder() -> RateLimitLayerBuilder<K, H> {
+        RateLimitLayerBuilder::new()
+    }
+}
+
+impl<K: Key, H: BuildHasher> RateLimitLayerBuilder<K, H> {
+    #[must_use]
+    pub fn new() -> Self {
         RateLimitLayerBuilder {
             quotas: Default::default(),
+            routes: Default::default(),
             default_quota: Default::default(),
+            class_quotas: [None; 2],
+            ip_quotas: [None; 2],
+            ip_masks: None,
+            class_buckets: false,
+            key_quotas: HashMap::default(),
             set_ext: None,
+            track_response: None,
+            status_penalties: Vec::new(),
+            cancel_refund: None,
+            challenge: None,
+            route_errors: Vec::new(),
+            challenges: None,
+
+            #[cfg(feature = "tokio")]
+            events: None,
             global_fallback: false,
+            prefix_fallback: false,
+            fallback_exemptions: Vec::new(),
+            nest_prefix: None,
+            route_ttls: HashMap::default(),
+            idle_timeout: None,
+            lifecycle: KeyLifecycle::default(),
             gc_interval: GCInterval::default(),
+            gc_requests: None,
+            shards: None,
+            max_entries: None,
+            overflow: gcra::OverflowPolicy::EvictOldest,
+            incremental_gc: false,
+            rejection: Default::default(),
+            bans: None,
+            violations: None,
+            deny_list: None,
+            allow_list: Default::default(),
+            heavy_hitters: None,
+            clock: None,
+            set_info: false,
+            policy_header: false,
+            soft_limit: None,
+            correlation_header: None,
+            decision_hook: None,
+            key_fn: None,
+            key_fallback: None,
+            key_normalizer: None,
+            route_key: None,
+            quota_resolver: None,
+            enforcement: AtomicU8::new(100),
+            scale: AtomicU32::new(1f32.to_bits()),
+            enabled: Arc::new(AtomicBool::new(true)),
+            max_delay: None,
+            delay_queues: delay::DelayQueues::new(usize::MAX, usize::MAX),
+            bandwidth: None,
+            server_quota: None,
+            shared_quotas: None,
+            parent_keys: None,
+            warmup: None,
+
+            #[cfg(feature = "load")]
+            load: Default::default(),
+            state: None,
+            store: None,
+            failure_policy: store::FailurePolicy::default(),
+            persist: None,
+            persist_on_drop: None,
+            ban_persist: None,
+            ban_persist_on_drop: None,
+            ban_store: None,
 
             #[cfg(feature = "tokio")]
             shutdown: BuilderDropNotify::default(),
@@ -334,6 +1165,25 @@ impl<K: Key, H: BuildHasher> RateLimitLayerBuilder<K, H> {
         self
     }
 
+    /// Insert a route entry for a [typed route identifier](RouteId) into the quota table for the rate limiter.
+    pub fn add_route_id(&mut self, route: impl RouteId, quota: gcra::Quota) {
+        self.add_route(route.route(), quota);
+    }
+
+    /// Insert a route entry for a [typed route identifier](RouteId) into the quota table for the rate limiter.
+    #[must_use]
+    pub fn with_route_id(mut self, route: impl RouteId, quota: gcra::Quota) -> Self {
+        self.add_route_id(route, quota);
+        self
+    }
+
+    /// Insert many route entries for [typed route identifiers](RouteId) into the quota table for the rate limiter.
+    #[must_use]
+    pub fn with_route_ids(mut self, quotas: impl IntoIterator<Item = (impl RouteId, gcra::Quota)>) -> Self {
+        self.add_routes(quotas.into_iter().map(|(route, quota)| (route.route(), quota)));
+        self
+    }
+
     /// Insert many route entries into the quota table for the rate limiter.
     pub fn add_routes(&mut self, quotas: impl IntoIterator<Item = (impl Into<Route<'static>>, gcra::Quota)>) {
         self.quotas.extend(quotas.into_iter().map(|(route, quota)| (route.into(), quota)));
@@ -356,6 +1206,117 @@ impl<K: Key, H: BuildHasher> RateLimitLayerBuilder<K, H> {
         self
     }
 
+    /// Fallback quota for rate limiting requests of the given [`MethodClass`] if no specific quota is found
+    /// for the path, overriding the [default quota](RateLimitLayerBuilder::with_default_quota) for that class.
+    ///
+    /// With the [global fallback](RateLimitLayerBuilder::with_global_fallback), each method still has
+    /// its own shared bucket, so this only changes their quotas.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    /// use axum_gcra::{gcra::Quota, MethodClass, RateLimitLayer};
+    ///
+    /// let layer = RateLimitLayer::<()>::builder()
+    ///     .with_global_fallback(true)
+    ///     // 60 reads per minute, but only 10 writes per minute
+    ///     .with_default_quota(Quota::simple(Duration::from_secs(1)))
+    ///     .with_default_quota_for(MethodClass::Unsafe, Quota::simple(Duration::from_secs(6)))
+    ///     .default_handle_error();
+    /// ```
+    #[must_use]
+    pub fn with_default_quota_for(mut self, class: MethodClass, quota: gcra::Quota) -> Self {
+        self.class_quotas[class as usize] = Some(quota);
+        self
+    }
+
+    /// Split rate limiting into reads and writes, with separate default quotas for [safe](MethodClass::Safe)
+    /// and [unsafe](MethodClass::Unsafe) requests, as most APIs limit writes much tighter than reads.
+    ///
+    /// Unlike [`RateLimitLayerBuilder::with_default_quota_for`], requests using the default quotas also share
+    /// one bucket per method class rather than per method, so all writes of a key to a route, such as
+    /// `PUT /users/:id` and `DELETE /users/:id`, draw from the same write bucket, while `GET` and `HEAD` requests
+    /// draw from the read bucket. With the [global fallback](RateLimitLayerBuilder::with_global_fallback),
+    /// each key has exactly one read and one write bucket for all such routes.
+    ///
+    /// Routes with [registered quotas](RateLimitLayerBuilder::with_route) are still limited per method as usual.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    /// use axum_gcra::{gcra::Quota, RateLimitLayer, real_ip::RealIp};
+    ///
+    /// // 100 reads per minute, but only 10 writes per minute
+    /// let layer = RateLimitLayer::<RealIp>::builder()
+    ///     .with_global_fallback(true)
+    ///     .with_read_write_quotas(
+    ///         Quota::simple(Duration::from_millis(600)),
+    ///         Quota::simple(Duration::from_secs(6)),
+    ///     )
+    ///     .default_handle_error();
+    /// ```
+    #[must_use]
+    pub fn with_read_write_quotas(mut self, read: gcra::Quota, write: gcra::Quota) -> Self {
+        self.class_quotas = [Some(read), Some(write)];
+        self.class_buckets = true;
+        self
+    }
+
+    /// Fallback quota for rate limiting requests of IPv4 keys if no specific quota is found for the path,
+    /// overriding the [default quota](RateLimitLayerBuilder::with_default_quota) and any
+    /// [method class quotas](RateLimitLayerBuilder::with_default_quota_for) for such keys.
+    ///
+    /// Many users often share one IPv4 address behind NATs, so they may warrant a looser quota than IPv6 clients,
+    /// see also [`RateLimitLayerBuilder::with_ipv6_default_quota`]. This only applies to [`RealIp`] and
+    /// [`RealIpPrivacyMask`](real_ip::RealIpPrivacyMask) keys, and IPv4-mapped IPv6 addresses count as IPv4.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    /// use axum_gcra::{gcra::Quota, RateLimitLayer, real_ip::RealIp};
+    ///
+    /// let layer = RateLimitLayer::<RealIp>::builder()
+    ///     .with_default_quota(Quota::simple(Duration::from_secs(1)))
+    ///     // shared NAT addresses get 10 times the quota of single clients
+    ///     .with_ipv4_default_quota(Quota::simple(Duration::from_millis(100)))
+    ///     // every IPv6 client gets a whole /64, so rotating addresses doesn't help
+    ///     .with_ip_masks(32, 64)
+    ///     .default_handle_error();
+    /// ```
+    #[cfg(feature = "real_ip")]
+    #[must_use]
+    pub fn with_ipv4_default_quota(mut self, quota: gcra::Quota) -> Self {
+        self.ip_quotas[0] = Some(quota);
+        self
+    }
+
+    /// Fallback quota for rate limiting requests of IPv6 keys if no specific quota is found for the path,
+    /// such as a tighter quota for clients that can rotate through many addresses.
+    /// See [`RateLimitLayerBuilder::with_ipv4_default_quota`] for more information.
+    #[cfg(feature = "real_ip")]
+    #[must_use]
+    pub fn with_ipv6_default_quota(mut self, quota: gcra::Quota) -> Self {
+        self.ip_quotas[1] = Some(quota);
+        self
+    }
+
+    /// Mask the addresses of [`RealIp`] and [`RealIpPrivacyMask`](real_ip::RealIpPrivacyMask)
+    /// keys to the given IPv4 and IPv6 prefix lengths, so that all clients within each network share the same key,
+    /// such as `/64` for IPv6 clients that rotate through the addresses of their subnet.
+    ///
+    /// Prefix lengths longer than the address are clamped to its length, so `32` and `128` keep addresses
+    /// as they are.
+    /// Keys are masked before the [key normalizer](RateLimitLayerBuilder::with_key_normalizer), if any.
+    #[cfg(feature = "real_ip")]
+    #[must_use]
+    pub fn with_ip_masks(mut self, v4_prefix: u8, v6_prefix: u8) -> Self {
+        self.ip_masks = Some((v4_prefix, v6_prefix));
+        self
+    }
+
     /// Set whether to use a global fallback shared rate-limiter for all paths not explicitly defined.
     #[must_use]
     pub fn with_global_fallback(mut self, global_fallback: bool) -> Self {
@@ -363,166 +1324,4221 @@ impl<K: Key, H: BuildHasher> RateLimitLayerBuilder<K, H> {
         self
     }
 
-    /// Set the interval for which garbage collection for the rate limiter will occur.
-    /// Garbage collection in this case is defined as removing old expired requests
-    /// from the rate limiter table to avoid it growing indefinitely.
+    /// Set whether to resolve the quota of paths not explicitly defined by walking up their path prefixes,
+    /// taking the quota of the most specific registered route with the same method, before falling back
+    /// to the default quota. This allows setting defaults per API section instead of per endpoint.
     ///
-    /// The default is 8192 requests.
+    /// For example, `GET /api/v1/users/:id` falls back to `GET /api/v1/users`, then `GET /api/v1`, then
+    /// `GET /api`, and finally the default quota. The root path `/` is never used as a prefix.
     ///
-    /// If the `tokio` feature is enabled, this can also be a time [`Duration`],
-    /// and a background task will be spawned to clean the rate limiter at the
-    /// given time interval. Cleanup is asynchronous and will not block the request
-    /// in this case.
+    /// Each endpoint still has its own bucket with the quota of its section, unless the
+    /// [global fallback](RateLimitLayerBuilder::with_global_fallback) is enabled,
+    /// in which case all endpoints of a section share its bucket.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    /// use axum_gcra::{gcra::Quota, RateLimitLayer, Route};
+    ///
+    /// let layer = RateLimitLayer::<()>::builder()
+    ///     .with_prefix_fallback(true)
+    ///     // applies to all `GET /api/v1/...` routes without their own quota
+    ///     .with_route(Route::get("/api/v1"), Quota::simple(Duration::from_millis(100)))
+    ///     .with_route(Route::get("/api/v1/search"), Quota::simple(Duration::from_secs(1)))
+    ///     .default_handle_error();
+    /// ```
     #[must_use]
-    pub fn with_gc_interval(mut self, gc_interval: impl Into<GCInterval>) -> Self {
-        self.gc_interval = gc_interval.into();
+    pub fn with_prefix_fallback(mut self, prefix_fallback: bool) -> Self {
+        self.prefix_fallback = prefix_fallback;
         self
     }
 
-    /// Set whether to insert the [`RateLimiter`](extensions::RateLimiter) extension into the request
-    /// to allow for manual rate limiting control downstream.
+    /// Exempt paths under the given prefix from the default quota and the
+    /// [global fallback](RateLimitLayerBuilder::with_global_fallback), such that requests to them
+    /// are not rate limited at all unless they have their own registered quota, such as for static assets.
+    ///
+    /// Prefixes match whole path segments, so `/assets` matches `/assets` and `/assets/*path`,
+    /// but not `/assets2`. Paths are those of the matched route, or of the [route key](RateLimitLayerBuilder::with_route_key).
+    /// Exempt requests do not receive any rate limiting [extensions](RateLimitLayerBuilder::with_extension).
     ///
     /// # Example
     ///
     /// ```rust,no_run
-    /// # use std::time::Duration;
-    /// use axum::{extract::Extension, routing::get, Router};
-    /// use axum_gcra::{RateLimitLayer, extensions::RateLimiter};
+    /// use std::time::Duration;
+    /// use axum_gcra::{gcra::Quota, RateLimitLayer};
     ///
-    /// // Note this must be identical to the key used in the rate limiter layer
-    /// type Key = ();
+    /// let layer = RateLimitLayer::<()>::builder()
+    ///     .with_global_fallback(true)
+    ///     .with_default_quota(Quota::simple(Duration::from_millis(100)))
+    ///     // static assets and the favicon are not rate limited
+    ///     .without_fallback_for("/assets")
+    ///     .without_fallback_for("/favicon.ico")
+    ///     .default_handle_error();
+    /// ```
+    #[must_use]
+    pub fn without_fallback_for(mut self, prefix: impl Into<Cow<'static, str>>) -> Self {
+        let prefix = prefix.into();
+
+        // a trailing slash would otherwise never match the prefix itself
+        self.fallback_exemptions.push(match prefix.strip_suffix('/') {
+            Some(trimmed) if !trimmed.is_empty() => Cow::Owned(trimmed.to_owned()),
+            _ => prefix,
+        });
+
+        self
+    }
+
+    /// Set the prefix the rate-limited router is [nested](axum::Router::nest) under, so that routes,
+    /// [TTLs](RateLimitLayerBuilder::with_route_ttl) and [exemptions](RateLimitLayerBuilder::without_fallback_for)
+    /// can be given as paths of the inner router.
     ///
-    /// let app = Router::<()>::new()
-    ///     // access the rate limiter for this request
-    ///     .route("/", get(|rl: Extension<RateLimiter<Key>>| async move {
-    ///         rl.penalize(Duration::from_secs(50)).await;
-    ///     }))
-    ///     .route_layer(RateLimitLayer::<Key>::builder().with_extension(true).default_handle_error());
+    /// Routes of nested routers are matched by their full path, such as `/api/users/:id` for the route
+    /// `/users/:id` of a router nested under `/api`, so quotas registered for the inner paths would otherwise
+    /// never apply. With a nest prefix, the prefix is stripped from matched paths before looking up their
+    /// quotas, while paths outside of the prefix are used as-is, so the same layer keeps working if the router
+    /// is also served without nesting. Paths derived by a custom [`RouteKey`](route_key::RouteKey) are never stripped.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use axum_gcra::axum;
+    /// use std::time::Duration;
+    /// use axum::{routing::post, Router};
+    /// use axum_gcra::{gcra::Quota, RateLimitLayer, Route};
+    ///
+    /// let api = Router::<()>::new()
+    ///     .route("/login", post(|| async { "Hello, World!" }))
+    ///     .route_layer(
+    ///         RateLimitLayer::<()>::builder()
+    ///             .with_route(Route::post("/login"), Quota::simple(Duration::from_secs(5)))
+    ///             .with_nest_prefix("/api")
+    ///             .default_handle_error(),
+    ///     );
+    ///
+    /// // `POST /api/login` uses the quota of `POST /login`
+    /// let app = Router::new().nest("/api", api);
+    /// ```
     #[must_use]
-    pub fn with_extension(mut self, extend: bool) -> Self
-    where
-        K: Clone,
-        H: Send + Sync + 'static,
-    {
-        self.set_ext = match extend {
-            true => Some(Box::new(DoSetExtension) as Box<dyn SetExtension<K, H>>),
-            false => None,
+    pub fn with_nest_prefix(mut self, prefix: impl Into<Cow<'static, str>>) -> Self {
+        let prefix = prefix.into();
+
+        self.nest_prefix = match prefix.strip_suffix('/') {
+            Some("") => None,
+            Some(trimmed) => Some(Cow::Owned(trimmed.to_owned())),
+            None if prefix.is_empty() => None,
+            None => Some(prefix),
         };
+
         self
     }
-}
 
-impl Default for RateLimitLayerBuilder<()> {
-    fn default() -> Self {
-        RateLimitLayerBuilder::new()
-    }
-}
+    /// Set how long garbage collection keeps the entries of the given route after their last request,
+    /// instead of until they have their full quota back, which is the default.
+    ///
+    /// This allows entries of hot public routes to be evicted quickly, forgiving the rest of their used quota
+    /// once idle, while keeping entries of cold routes, such as admin routes, for hours.
+    /// See [`gcra::RateLimiter::with_idle_timeouts`] for more information.
+    ///
+    /// This overrides the [idle timeout](RateLimitLayerBuilder::with_idle_timeout) for the route, if any.
+    /// The route must be registered with its own quota. TTLs only apply to rate limiter state created
+    /// by this builder, not to [shared state](RateLimitLayerBuilder::with_state) or custom [stores](store::Store).
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    /// use axum_gcra::{gcra::Quota, RateLimitLayer, Route};
+    ///
+    /// let layer = RateLimitLayer::<()>::builder()
+    ///     // 1000 searches per day, but forgotten after an hour of inactivity
+    ///     .with_route(Route::get("/search"), Quota::simple(Duration::from_secs(86400 / 1000)))
+    ///     .with_route_ttl(Route::get("/search"), Duration::from_secs(3600))
+    ///     .default_handle_error();
+    /// ```
+    #[must_use]
+    pub fn with_route_ttl(mut self, route: impl Into<Route<'static>>, ttl: Duration) -> Self {
+        self.route_ttls.insert(route.into(), ttl);
+        self
+    }
+
+    /// Set how long garbage collection keeps entries after their last request, regardless of the period
+    /// of their quota, such that long-period quotas like 1000 requests per day do not keep the entries
+    /// of every client seen that day in memory. Idle clients start over with a full quota.
+    ///
+    /// [Route TTLs](RateLimitLayerBuilder::with_route_ttl) take precedence over this, and the same limitations apply.
+    /// The default is to keep entries until they have their full quota back.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    /// use axum_gcra::{gcra::Quota, RateLimitLayer};
+    ///
+    /// let layer = RateLimitLayer::<()>::builder()
+    ///     .with_default_quota(Quota::simple(Duration::from_secs(86400 / 1000)))
+    ///     .with_idle_timeout(Duration::from_secs(15 * 60))
+    ///     .default_handle_error();
+    /// ```
+    #[must_use]
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Call the given callback when a key is first seen on a route, that is, when an entry is inserted
+    /// into the rate limiter for it, with the route and the [`Debug`](fmt::Debug) form of the key,
+    /// such as to track active clients in an external system without polling [snapshots](RateLimitLayer::snapshot).
+    ///
+    /// Callbacks are called while the entry is locked, so they should be quick, such as sending to a channel.
+    /// Like [route TTLs](RateLimitLayerBuilder::with_route_ttl), they only apply to rate limiter state created
+    /// by this builder. See also [`RateLimitLayerBuilder::on_key_evicted`].
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use axum_gcra::{RateLimitLayer, real_ip::RealIp};
+    ///
+    /// let layer = RateLimitLayer::<RealIp>::builder()
+    ///     .on_key_inserted(|route, key| println!("{key} started using {route:?}"))
+    ///     .on_key_evicted(|route, key, entry| {
+    ///         println!("{key} stopped using {route:?}, last seen {:?}", entry.last_seen)
+    ///     })
+    ///     .default_handle_error();
+    /// ```
+    #[must_use]
+    pub fn on_key_inserted(mut self, f: impl Fn(&Route<'_>, &str) + Send + Sync + 'static) -> Self {
+        self.lifecycle.inserted = Some(Box::new(f));
+        self
+    }
+
+    /// Call the given callback when the entry of a key on a route is removed from the rate limiter, because it
+    /// expired, was [idle](RateLimitLayerBuilder::with_idle_timeout), or was evicted to make room for new keys,
+    /// with the route, the [`Debug`](fmt::Debug) form of the key and the final state of the entry.
+    ///
+    /// Entries removed explicitly, such as by [`RateLimitLayer::reset`], are not reported.
+    /// See [`RateLimitLayerBuilder::on_key_inserted`] for more information.
+    #[must_use]
+    pub fn on_key_evicted(
+        mut self,
+        f: impl Fn(&Route<'_>, &str, gcra::EvictedEntry) + Send + Sync + 'static,
+    ) -> Self {
+        self.lifecycle.evicted = Some(Box::new(f));
+        self
+    }
+
+    /// Enforce a single server-wide quota shared by all requests of all keys, such as 5000 requests per second
+    /// in total, to protect downstream dependencies regardless of how traffic is distributed between clients.
+    ///
+    /// The server-wide quota is checked before the per-key quotas, and requests rejected by it do not count
+    /// towards the per-key quotas, [bans](RateLimitLayerBuilder::ban_after) or
+    /// [violations](RateLimitLayerBuilder::with_violation_counters) of their key. Likewise, only requests
+    /// allowed by all other quotas count towards the server-wide quota.
+    /// Such rejections are marked by [`RateLimitContext::is_server_limited`].
+    ///
+    /// The server-wide quota is always tracked in memory, even with a custom [`Store`](store::Store),
+    /// so it applies per instance. The default is no server-wide quota.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::{num::NonZeroU64, time::Duration};
+    /// use axum_gcra::{gcra::Quota, RateLimitLayer, real_ip::RealIp};
+    ///
+    /// // at most 5000 requests per second in total, in bursts of up to 500
+    /// let layer = RateLimitLayer::<RealIp>::builder()
+    ///     .with_server_quota(Quota::new(Duration::from_micros(200), NonZeroU64::new(500).unwrap()))
+    ///     .build();
+    /// ```
+    #[must_use]
+    pub fn with_server_quota(mut self, quota: gcra::Quota) -> Self {
+        let limiter = gcra::RateLimiter::with_shards(u64::MAX, 1, Default::default());
+        self.server_quota = Some((quota, limiter));
+        self
+    }
+
+    /// Make the given routes also draw from a single bucket per key with the given quota, in addition to
+    /// their own quotas, such as to limit `/search` and `/autocomplete` separately, but to no more than
+    /// 30 requests per minute per client in total.
+    ///
+    /// Requests must be allowed by both the quota of their route and the shared quota, and only allowed
+    /// requests count towards the shared quota. Routes are matched exactly, as with [`RateLimitLayerBuilder::with_route`],
+    /// and each route can only be part of one shared quota, so adding a route again moves it to the new one.
+    ///
+    /// Like the [server-wide quota](RateLimitLayerBuilder::with_server_quota), shared quotas are always tracked in memory,
+    /// even with a custom [`Store`](store::Store), so they apply per instance.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    /// use axum_gcra::{gcra::Quota, RateLimitLayer, real_ip::RealIp, Route};
+    ///
+    /// let layer = RateLimitLayer::<RealIp>::builder()
+    ///     .with_route(Route::get("/search"), Quota::simple(Duration::from_secs(3)))
+    ///     .with_route(Route::get("/autocomplete"), Quota::simple(Duration::from_millis(500)))
+    ///     // 30 requests per minute in total, in bursts of up to 10
+    ///     .with_shared_quota(
+    ///         [Route::get("/search"), Route::get("/autocomplete")],
+    ///         Quota::new(Duration::from_secs(2), 10.try_into().unwrap()),
+    ///     )
+    ///     .default_handle_error();
+    /// ```
+    #[must_use]
+    pub fn with_shared_quota(
+        mut self,
+        routes: impl IntoIterator<Item = impl Into<Route<'static>>>,
+        quota: gcra::Quota,
+    ) -> Self {
+        let shared = self.shared_quotas.get_or_insert_with(|| SharedQuotas {
+            quotas: Vec::new(),
+            routes: HashMap::default(),
+            limiter: gcra::RateLimiter::new(GCInterval::default().to_requests(), Default::default()),
+        });
+
+        let group = shared.quotas.len();
+        shared.quotas.push(quota);
+        shared.routes.extend(routes.into_iter().map(|route| (route.into(), group)));
+
+        self
+    }
+
+    /// Also limit requests by a parent key derived from their key with the given function, such as the `/24` subnet
+    /// of a [`RealIp`], with the given quota, to catch distributed abuse from one network while
+    /// keeping the limits of individual keys unchanged.
+    ///
+    /// Requests must be allowed by both the quota of their own key and of every parent key, and only allowed
+    /// requests count towards the quotas of their parents. Parent keys are limited across all routes, and requests
+    /// for which the function returns `None` are not limited by that parent. Parents can be added repeatedly,
+    /// such as for both subnets and autonomous systems.
+    ///
+    /// Like the [server-wide quota](RateLimitLayerBuilder::with_server_quota), parent keys are always tracked in memory,
+    /// even with a custom [`Store`](store::Store), so they apply per instance.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::{net::IpAddr, time::Duration};
+    /// use axum_gcra::{gcra::Quota, real_ip::{IpNetwork, RealIp}, RateLimitLayer};
+    ///
+    /// let layer = RateLimitLayer::<RealIp>::builder()
+    ///     // 10 requests per second per address
+    ///     .with_default_quota(Quota::simple(Duration::from_millis(100)))
+    ///     // and 100 requests per second per subnet
+    ///     .with_parent_key(Quota::simple(Duration::from_millis(10)), |ip: &RealIp| match ip.0 {
+    ///         IpAddr::V4(_) => IpNetwork::new(ip.0, 24),
+    ///         IpAddr::V6(_) => IpNetwork::new(ip.0, 48),
+    ///     })
+    ///     .default_handle_error();
+    /// ```
+    #[must_use]
+    pub fn with_parent_key<P: Hash>(
+        mut self,
+        quota: gcra::Quota,
+        parent: impl Fn(&K) -> Option<P> + Send + Sync + 'static,
+    ) -> Self {
+        let parents = self.parent_keys.get_or_insert_with(|| ParentKeys {
+            parents: Vec::new(),
+            limiter: gcra::RateLimiter::new(GCInterval::default().to_requests(), Default::default()),
+        });
+
+        parents.parents.push((quota, Box::new(move |key| parent(key).map(|p| gcra::stable_hash(&p)))));
+
+        self
+    }
+
+    /// Never throttle the first `requests` requests of a new key within `period` of its first request,
+    /// such as to avoid false positives for clients that legitimately burst on first page load
+    /// with many parallel asset and API calls.
+    ///
+    /// Requests are counted per key across all routes, whether they would have been throttled or not,
+    /// and requests that would have been throttled are forwarded without consuming quota, reported as
+    /// [not enforced](Decision::is_enforced), with the same [extensions](RateLimitLayerBuilder::with_extension)
+    /// as allowed requests but no requests remaining. Keys idle for a whole `period` are forgotten and
+    /// start over as new keys. [Bans](RateLimitLayerBuilder::ban_after), the [deny list](RateLimitLayerBuilder::with_deny_list)
+    /// and the [server-wide quota](RateLimitLayerBuilder::with_server_quota) still apply.
+    ///
+    /// Note that this requires remembering every key seen within the last `period`. The default is no grace period.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    /// use axum_gcra::{RateLimitLayer, real_ip::RealIp};
+    ///
+    /// // let new clients make up to 50 requests in their first 10 seconds
+    /// let layer = RateLimitLayer::<RealIp>::builder()
+    ///     .with_warmup(50, Duration::from_secs(10))
+    ///     .build();
+    /// ```
+    ///
+    /// Handlers can rely on the extensions during warm-up:
+    ///
+    /// ```rust
+    /// # use axum_gcra::axum;
+    /// use std::time::Duration;
+    /// use axum::{body::Body, extract::Extension, routing::get, Router};
+    /// use axum_gcra::{extensions::RateLimiter, gcra::Quota, RateLimitLayer};
+    /// use http::{Request, StatusCode};
+    /// use tower::ServiceExt;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")] async fn main() {
+    /// let layer = RateLimitLayer::<()>::builder()
+    ///     .with_default_quota(Quota::simple(Duration::from_secs(3600)))
+    ///     .with_warmup(3, Duration::from_secs(10))
+    ///     .with_extension(true)
+    ///     .build();
+    ///
+    /// let app = Router::new()
+    ///     .route("/", get(|_: Extension<RateLimiter<()>>| async {}))
+    ///     .route_layer(layer.default_handle_error());
+    ///
+    /// let status = || async {
+    ///     app.clone().oneshot(Request::get("/").body(Body::empty()).unwrap()).await.unwrap().status()
+    /// };
+    ///
+    /// // the quota only allows one request, but the first three are let through
+    /// for _ in 0..3 {
+    ///     assert_eq!(status().await, StatusCode::OK);
+    /// }
+    ///
+    /// assert_eq!(status().await, StatusCode::TOO_MANY_REQUESTS);
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn with_warmup(mut self, requests: u64, period: Duration) -> Self {
+        self.warmup = Some(warmup::Warmup::new(requests, period));
+        self
+    }
+
+    /// Set the interval for which garbage collection for the rate limiter will occur.
+    /// Garbage collection in this case is defined as removing old expired requests
+    /// from the rate limiter table to avoid it growing indefinitely.
+    ///
+    /// The default is 8192 requests.
+    ///
+    /// If the `tokio` feature is enabled, this can also be a time [`Duration`],
+    /// and a background task will be spawned to clean the rate limiter at the
+    /// given time interval. Cleanup is asynchronous and will not block the request
+    /// in this case.
+    #[must_use]
+    pub fn with_gc_interval(mut self, gc_interval: impl Into<GCInterval>) -> Self {
+        self.gc_interval = gc_interval.into();
+        self
+    }
+
+    /// Also run garbage collection on a [shard](RateLimitLayerBuilder::with_shards) of the table after
+    /// the given number of requests to it, whichever comes first with a timed [GC interval](RateLimitLayerBuilder::with_gc_interval).
+    ///
+    /// This overrides the number of requests given by [`GCInterval::Requests`], and timed cleanups reset the count.
+    /// The default is to only use the GC interval.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    /// use axum_gcra::RateLimitLayer;
+    ///
+    /// // clean every minute, or every 100k requests during bursts
+    /// let builder = RateLimitLayer::<()>::builder()
+    ///     .with_gc_interval(Duration::from_secs(60))
+    ///     .with_gc_requests(100_000);
+    /// ```
+    #[must_use]
+    pub fn with_gc_requests(mut self, requests: u64) -> Self {
+        self.gc_requests = Some(requests);
+        self
+    }
+
+    /// Set whether timed garbage collection cleans one [shard](RateLimitLayerBuilder::with_shards)
+    /// of the table per tick, spread evenly over the [GC interval](RateLimitLayerBuilder::with_gc_interval),
+    /// rather than sweeping the entire table at once. This keeps tail latency flat for very large tables.
+    ///
+    /// The default is `false`. See [`gcra::RateLimiter::clean_step`] for more information.
+    #[must_use]
+    pub fn with_incremental_gc(mut self, incremental_gc: bool) -> Self {
+        self.incremental_gc = incremental_gc;
+        self
+    }
+
+    /// Temporarily ban keys that are rejected `violations` times `within` the given window, starting from
+    /// the first rejection. Requests from banned keys are rejected `ban_for` the given duration without
+    /// consuming any quota, with the remaining ban as the retry time, see [`RateLimitContext::is_banned`].
+    ///
+    /// Bans can be queried and lifted at runtime with [`RateLimitLayer::bans`]. The default is to never ban.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use axum_gcra::axum;
+    /// use std::time::Duration;
+    /// use axum::http::StatusCode;
+    /// use axum_gcra::{RateLimitLayer, Error, real_ip::RealIp};
+    ///
+    /// // ban for an hour after 20 rejections within a minute
+    /// let layer = RateLimitLayer::<RealIp>::builder()
+    ///     .ban_after(20, Duration::from_secs(60), Duration::from_secs(60 * 60))
+    ///     .handle_error(|e| async move {
+    ///         match e {
+    ///             Error::RateLimit(ctx) if ctx.is_banned() => StatusCode::FORBIDDEN,
+    ///             Error::RateLimit(_) => StatusCode::TOO_MANY_REQUESTS,
+    ///             _ => StatusCode::BAD_REQUEST,
+    ///         }
+    ///     });
+    /// ```
+    #[must_use]
+    pub fn ban_after(mut self, violations: u32, within: Duration, ban_for: Duration) -> Self {
+        self.bans = Some(ban::Bans::new(ban::BanPolicy {
+            violations: violations.max(1),
+            within,
+            ban_for,
+        }));
+        self
+    }
+
+    /// Restore the [bans](RateLimitLayerBuilder::ban_after) from the given file when the layer is
+    /// [built](RateLimitLayerBuilder::build), and save them back to the file once the layer and all of its
+    /// clones have been dropped, so that bans survive restarts.
+    ///
+    /// A missing or invalid file is ignored. See [`Bans::save_to`](ban::Bans::save_to) for more information.
+    #[must_use]
+    pub fn with_ban_persistence(mut self, path: impl Into<PathBuf>) -> Self {
+        self.ban_persist = Some(path.into());
+        self
+    }
+
+    /// Share the [bans](RateLimitLayerBuilder::ban_after) with other replicas through the given [`BanStore`](ban::BanStore),
+    /// such as the `RedisBanStore` of the `redis` cargo feature, even if the rate limiter itself is local-only.
+    ///
+    /// New bans and lifted bans are written to the store in the background, and bans from other replicas
+    /// are [loaded](ban::Bans::sync) from it once when the layer is built and then every `sync_interval`,
+    /// in a background task that stops once the layer and all of its clones have been dropped.
+    /// With [`with_ban_persistence`](RateLimitLayerBuilder::with_ban_persistence), bans survive restarts
+    /// of all replicas and the store alike.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # #[cfg(feature = "redis")]
+    /// # async fn example() -> redis::RedisResult<()> {
+    /// use std::time::Duration;
+    /// use axum_gcra::{RateLimitLayer, real_ip::RealIp, store::redis::RedisBanStore};
+    ///
+    /// let client = redis::Client::open("redis://127.0.0.1/")?;
+    /// let conn = redis::aio::ConnectionManager::new(client).await?;
+    ///
+    /// let layer = RateLimitLayer::<RealIp>::builder()
+    ///     .ban_after(20, Duration::from_secs(60), Duration::from_secs(60 * 60))
+    ///     .with_ban_store(RedisBanStore::new(conn), Duration::from_secs(10))
+    ///     .build();
+    /// # Ok(()) }
+    /// ```
+    #[cfg(feature = "tokio")]
+    #[must_use]
+    pub fn with_ban_store(mut self, store: impl ban::BanStore, sync_interval: Duration) -> Self {
+        self.ban_store = Some((Arc::new(store), sync_interval));
+        self
+    }
+
+    /// Count how many times each key has been rejected by the rate limiter, with counts halving
+    /// every `half_life`, to distinguish a first-time limit hit from a chronic abuser.
+    ///
+    /// The count of the key is available from [`RateLimitContext::violations`], [`RateLimitInfo::violations`]
+    /// and [`EntrySnapshot::violations`], and the table can be queried at runtime with [`RateLimitLayer::violations`].
+    /// The default is no violation counters.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use axum_gcra::axum;
+    /// use std::time::Duration;
+    /// use axum::http::StatusCode;
+    /// use axum_gcra::{RateLimitLayer, Error, real_ip::RealIp};
+    ///
+    /// let layer = RateLimitLayer::<RealIp>::builder()
+    ///     .with_violation_counters(Duration::from_secs(60 * 60))
+    ///     .handle_error(|e| async move {
+    ///         match e {
+    ///             Error::RateLimit(ctx) if ctx.violations() > 100.0 => StatusCode::FORBIDDEN,
+    ///             Error::RateLimit(_) => StatusCode::TOO_MANY_REQUESTS,
+    ///             _ => StatusCode::BAD_REQUEST,
+    ///         }
+    ///     });
+    /// ```
+    #[must_use]
+    pub fn with_violation_counters(mut self, half_life: Duration) -> Self {
+        self.violations = Some(violations::Violations::new(half_life));
+        self
+    }
+
+    /// Reject requests whose key or client network is on the given [`DenyList`](deny::DenyList)
+    /// with `403 Forbidden`, before reaching the rate limiter, see [`RateLimitContext::is_denied`].
+    ///
+    /// The list can be shared with other layers and modified at runtime through any of its clones.
+    /// It is also inserted into the extensions of allowed requests. The default is no deny list.
+    #[must_use]
+    pub fn with_deny_list(mut self, deny_list: deny::DenyList<K>) -> Self {
+        self.deny_list = Some(deny_list);
+        self
+    }
+
+    /// Allow the given keys to skip rate limiting entirely, such as for internal services, without consuming any quota.
+    ///
+    /// Allowed requests bypass the [deny list](RateLimitLayerBuilder::with_deny_list) and [bans](RateLimitLayerBuilder::ban_after),
+    /// and do not receive any rate limiting [extensions](RateLimitLayerBuilder::with_extension).
+    /// Keys are identified by a stable hash, so the builder does not keep copies of them.
+    pub fn add_allowed_keys<'a>(&mut self, keys: impl IntoIterator<Item = &'a K>) {
+        for key in keys {
+            self.allow_list.add_key(key);
+        }
+    }
+
+    /// Allow the given keys to skip rate limiting entirely, see [`RateLimitLayerBuilder::add_allowed_keys`].
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use axum_gcra::{RateLimitLayer, real_ip::RealIp};
+    ///
+    /// let monitoring = RealIp("198.51.100.7".parse().unwrap());
+    ///
+    /// let builder = RateLimitLayer::<RealIp>::builder().with_allowed_keys([&monitoring]);
+    /// ```
+    #[must_use]
+    pub fn with_allowed_keys<'a>(mut self, keys: impl IntoIterator<Item = &'a K>) -> Self {
+        self.add_allowed_keys(keys);
+        self
+    }
+
+    /// Pre-register the given keys with their own quotas, such as partner API tokens with elevated limits
+    /// loaded from a database at startup, so that their first requests do not hit the default limits.
+    ///
+    /// The quota of a registered key applies to all of its requests, in place of the route and default quotas,
+    /// though each route still has its own bucket. Keys are identified by a stable hash, so the builder does
+    /// not keep copies of them.
+    pub fn add_key_quotas<'a>(&mut self, quotas: impl IntoIterator<Item = (&'a K, gcra::Quota)>) {
+        for (key, quota) in quotas {
+            self.key_quotas.insert(gcra::stable_hash(key), quota);
+        }
+    }
+
+    /// Pre-register the given key with its own quota, see [`RateLimitLayerBuilder::add_key_quotas`].
+    #[must_use]
+    pub fn with_key_quota(mut self, key: &K, quota: gcra::Quota) -> Self {
+        self.add_key_quotas([(key, quota)]);
+        self
+    }
+
+    /// Pre-register the given keys with their own quotas, see [`RateLimitLayerBuilder::add_key_quotas`].
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    /// use axum_gcra::{gcra::Quota, RateLimitLayer};
+    ///
+    /// # fn load_partners() -> Vec<(String, u64)> { Vec::new() }
+    /// // (token, requests per second)
+    /// let partners: Vec<(String, u64)> = load_partners();
+    ///
+    /// let builder = RateLimitLayer::<String>::builder()
+    ///     .with_default_quota(Quota::simple(Duration::from_secs(1)))
+    ///     .with_key_quotas(partners.iter().map(|(token, rps)| {
+    ///         (token, Quota::simple(Duration::from_secs(1) / *rps as u32))
+    ///     }));
+    /// ```
+    #[must_use]
+    pub fn with_key_quotas<'a>(mut self, quotas: impl IntoIterator<Item = (&'a K, gcra::Quota)>) -> Self {
+        self.add_key_quotas(quotas);
+        self
+    }
+
+    /// Allow requests from client IPs within the given networks to skip rate limiting entirely,
+    /// such as for monitoring probes and office networks, regardless of the key type.
+    ///
+    /// This is checked before extracting the key, and otherwise behaves like [`RateLimitLayerBuilder::add_allowed_keys`].
+    /// Allowed requests do not receive any rate limiting [extensions](RateLimitLayerBuilder::with_extension).
+    #[cfg(feature = "real_ip")]
+    pub fn add_allowed_networks(&mut self, networks: impl IntoIterator<Item = real_ip::IpNetwork>) {
+        for network in networks {
+            self.allow_list.add_network(network);
+        }
+    }
+
+    /// Allow requests from client IPs within the given networks to skip rate limiting entirely,
+    /// see [`RateLimitLayerBuilder::add_allowed_networks`].
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use axum_gcra::{RateLimitLayer, real_ip::IpNetwork};
+    ///
+    /// let builder = RateLimitLayer::<()>::builder().with_allowed_networks([
+    ///     "10.0.0.0/8".parse::<IpNetwork>().unwrap(),
+    ///     "2001:db8::/32".parse().unwrap(),
+    /// ]);
+    /// ```
+    #[cfg(feature = "real_ip")]
+    #[must_use]
+    pub fn with_allowed_networks(mut self, networks: impl IntoIterator<Item = real_ip::IpNetwork>) -> Self {
+        self.add_allowed_networks(networks);
+        self
+    }
+
+    /// Allow requests carrying the given secret in the given header to skip rate limiting entirely,
+    /// such as for load tests and trusted internal callers that can't be identified by IP.
+    ///
+    /// The secret is compared in constant time, and the header is always removed from the request
+    /// so that it never reaches the inner service. Otherwise this behaves like [`RateLimitLayerBuilder::add_allowed_keys`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the secret is shorter than 16 bytes, as short secrets are easily guessed,
+    /// and an empty secret would let any request with an empty header bypass all limits.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use axum_gcra::axum;
+    /// use axum::http::HeaderName;
+    /// use axum_gcra::RateLimitLayer;
+    ///
+    /// let secret = std::env::var("RATELIMIT_BYPASS_TOKEN").unwrap();
+    ///
+    /// let builder = RateLimitLayer::<()>::builder()
+    ///     .with_bypass_header(HeaderName::from_static("x-ratelimit-bypass"), secret);
+    /// ```
+    #[must_use]
+    pub fn with_bypass_header(mut self, header: http::HeaderName, secret: impl AsRef<[u8]>) -> Self {
+        let secret = secret.as_ref();
+
+        assert!(secret.len() >= 16, "bypass secret must be at least 16 bytes long");
+
+        self.allow_list.set_bypass(header, secret);
+        self
+    }
+
+    /// Allow requests matching the given predicate to skip rate limiting entirely, such as requests
+    /// already authenticated as internal or admin by earlier middleware, without maintaining IP allow lists.
+    ///
+    /// The predicate is checked before the key is extracted. Multiple predicates can be added, and requests
+    /// matching any of them are exempt. Otherwise this behaves like [`RateLimitLayerBuilder::add_allowed_keys`],
+    /// so exempt requests do not receive any rate limiting [extensions](RateLimitLayerBuilder::with_extension).
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use axum_gcra::RateLimitLayer;
+    ///
+    /// #[derive(Clone, PartialEq)]
+    /// enum Role {
+    ///     User,
+    ///     Admin,
+    /// }
+    ///
+    /// let builder = RateLimitLayer::<()>::builder()
+    ///     .exempt_if(|parts| parts.extensions.get::<Role>() == Some(&Role::Admin));
+    /// ```
+    #[must_use]
+    pub fn exempt_if(mut self, pred: impl Fn(&Parts) -> bool + Send + Sync + 'static) -> Self {
+        self.allow_list.add_exemption(pred);
+        self
+    }
+
+    /// Emit a [`RejectionEvent`](events::RejectionEvent) for every rejected request on a broadcast channel,
+    /// which can be subscribed to with [`RateLimitLayer::subscribe`], such as for alerting and fail2ban-style automation.
+    ///
+    /// The channel holds up to `capacity` events, after which slow receivers miss the oldest events.
+    /// Events are only built while there are receivers. The default is no event stream.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use axum_gcra::{RateLimitLayer, real_ip::RealIp};
+    ///
+    /// # async fn example() {
+    /// let layer = RateLimitLayer::<RealIp>::builder().with_event_stream(1024).build();
+    ///
+    /// let mut events = layer.subscribe().unwrap();
+    ///
+    /// tokio::spawn(async move {
+    ///     while let Ok(event) = events.recv().await {
+    ///         eprintln!("{} throttled on {:?} for {:?}", event.key(), event.route(), event.retry_after());
+    ///     }
+    /// });
+    /// # }
+    /// ```
+    #[cfg(feature = "tokio")]
+    #[must_use]
+    pub fn with_event_stream(mut self, capacity: usize) -> Self {
+        self.events = Some(events::Events::new(capacity));
+        self
+    }
+
+    /// Track the keys that consumed the most quota, which can be queried with [`RateLimitLayer::heavy_hitters`],
+    /// such as for dashboards and abuse triage.
+    ///
+    /// Allowed requests and [status penalties](RateLimitLayerBuilder::with_status_penalty) are counted
+    /// per key across all routes in a [SpaceSaving](https://doi.org/10.1007/978-3-540-30570-5_27) sketch of
+    /// `capacity` counters, over the current and previous `window`. Keys are identified by their
+    /// [`Debug`](fmt::Debug) representation. The heaviest keys are always tracked, and counts are
+    /// estimated within [`HeavyHitter::error`](heavy_hitters::HeavyHitter::error) of their true value.
+    ///
+    /// The capacity should be several times the number of keys to be queried. The default is no tracking.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    /// use axum_gcra::{RateLimitLayer, real_ip::RealIp};
+    ///
+    /// let layer = RateLimitLayer::<RealIp>::builder()
+    ///     .with_heavy_hitters(200, Duration::from_secs(5 * 60))
+    ///     .build();
+    ///
+    /// for hitter in layer.heavy_hitters(20) {
+    ///     println!("{}: {} requests", hitter.key(), hitter.count());
+    /// }
+    /// ```
+    #[must_use]
+    pub fn with_heavy_hitters(mut self, capacity: usize, window: Duration) -> Self {
+        self.heavy_hitters = Some(heavy_hitters::HeavyHitters::new(capacity, window));
+        self
+    }
+
+    /// Set the number of independent shards the rate limiter table is split into, to reduce
+    /// lock contention under high concurrency. See [`gcra::RateLimiter::with_shards`] for more information.
+    ///
+    /// The default is the number of available CPUs. This has no effect when using [shared state](RateLimitLayerBuilder::with_state).
+    #[must_use]
+    pub fn with_shards(mut self, shards: usize) -> Self {
+        self.shards = Some(shards);
+        self
+    }
+
+    /// Limit the number of entries in the rate limiter table to bound memory usage, evicting the longest-idle
+    /// entries when full. See [`gcra::RateLimiter::with_max_entries`] for more information.
+    ///
+    /// The default is unlimited. This has no effect when using [shared state](RateLimitLayerBuilder::with_state).
+    #[must_use]
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Set what happens to requests for new keys when the rate limiter table is [full](RateLimitLayerBuilder::with_max_entries),
+    /// such as rejecting them with `429 Too Many Requests` rather than evicting existing entries.
+    ///
+    /// The default is [`OverflowPolicy::EvictOldest`](gcra::OverflowPolicy::EvictOldest).
+    /// This has no effect when using [shared state](RateLimitLayerBuilder::with_state).
+    #[must_use]
+    pub fn with_overflow_policy(mut self, overflow: gcra::OverflowPolicy) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Use a [coarse clock](clock::CoarseClock) refreshed at the given resolution, such as 1 millisecond,
+    /// to timestamp requests instead of calling [`Instant::now`] for every request.
+    ///
+    /// This is only worthwhile for very high request rates, and trades up to `resolution` of precision
+    /// for throughput.
+    ///
+    /// This must be called from within a tokio runtime, as it spawns the task refreshing the clock.
+    #[cfg(feature = "tokio")]
+    #[must_use]
+    pub fn with_coarse_clock(self, resolution: Duration) -> Self {
+        self.with_clock(clock::CoarseClock::new(resolution))
+    }
+
+    /// Use the given [`Clock`](clock::Clock) as the time source of the rate limiter,
+    /// instead of [`Instant::now`] and the system time.
+    ///
+    /// This is mostly useful for tests, where a [`ManualClock`](clock::ManualClock) can be advanced by hand
+    /// to assert exact throttle boundaries without sleeping, or to plug in alternative time sources.
+    ///
+    /// [Bans](RateLimitLayerBuilder::ban_after),
+    /// [violation counters](RateLimitLayerBuilder::with_violation_counters),
+    /// the [deny list](RateLimitLayerBuilder::with_deny_list), [warm-up](RateLimitLayerBuilder::with_warmup) and
+    /// [heavy hitters](RateLimitLayerBuilder::with_heavy_hitters) follow the same clock, unless they were
+    /// already used before the layer was built, such as when shared with another layer.
+    ///
+    /// Note that background tasks such as [garbage collection](RateLimitLayerBuilder::with_gc_interval)
+    /// use the clock for timestamps, but are still scheduled by the tokio timer.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use axum_gcra::axum;
+    /// use axum::{routing::get, Router};
+    /// use axum_gcra::{clock::ManualClock, RateLimitLayer};
+    ///
+    /// let clock = ManualClock::new();
+    ///
+    /// let app = Router::<()>::new()
+    ///     .route("/", get(|| async { "Hello, World!" }))
+    ///     .route_layer(RateLimitLayer::<()>::builder().with_clock(clock.clone()).default_handle_error());
+    ///
+    /// // later, in the test
+    /// clock.advance(std::time::Duration::from_secs(1));
+    /// ```
+    ///
+    /// Bans expire on the same clock:
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use axum_gcra::{clock::ManualClock, RateLimitLayer, real_ip::RealIp};
+    ///
+    /// let clock = ManualClock::new();
+    /// let layer = RateLimitLayer::<RealIp>::builder()
+    ///     .with_clock(clock.clone())
+    ///     .ban_after(10, Duration::from_secs(60), Duration::from_secs(300))
+    ///     .build();
+    ///
+    /// let bans = layer.bans().unwrap();
+    /// let ip = RealIp([203, 0, 113, 7].into());
+    /// bans.ban(&ip, Duration::from_secs(300));
+    ///
+    /// clock.advance(Duration::from_secs(299));
+    /// assert_eq!(bans.ban_remaining(&ip), Some(Duration::from_secs(1)));
+    ///
+    /// clock.advance(Duration::from_secs(1));
+    /// assert!(!bans.is_banned(&ip));
+    /// ```
+    #[must_use]
+    pub fn with_clock(mut self, clock: impl clock::Clock) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Set a static HTML template to be used for the body of rate limit rejections when the client's
+    /// `Accept` header prefers `text/html`, such as when browsing with a web browser. Other clients
+    /// will receive the usual plain text (or JSON) body.
+    ///
+    /// The following placeholders will be replaced in the template:
+    /// - `{{retry_after}}`: seconds until the next request can be made, as in the `retry-after` header
+    /// - `{{retry_after_ms}}`: milliseconds until the next request can be made
+    /// - `{{path}}`: the matched route path, which is empty if the global fallback was used
+    ///
+    /// This only applies to the rejection's default response, so custom
+    /// [error handlers](RateLimitLayerBuilder::handle_error) must convert
+    /// the [`RateLimitContext`] into a response to make use of it.
+    #[must_use]
+    pub fn with_html_template(mut self, template: impl Into<Cow<'static, str>>) -> Self {
+        Arc::make_mut(&mut self.rejection).html_template = Some(template.into());
+        self
+    }
+
+    /// Set whether to emit the `retry-after` header of rate limit rejections as an absolute HTTP-date
+    /// (e.g. `Sun, 06 Nov 1994 08:49:37 GMT`) rather than as a number of seconds, which some CDNs and
+    /// edge caches require. The other rate limit headers are unaffected.
+    ///
+    /// The default is `false`.
+    #[must_use]
+    pub fn with_retry_after_http_date(mut self, http_date: bool) -> Self {
+        Arc::make_mut(&mut self.rejection).retry_after_http_date = http_date;
+        self
+    }
+
+    /// Set the maximum amount of random jitter to add to the retry time advertised by rate limit rejections,
+    /// to avoid many clients throttled at the same time from retrying in a synchronized stampede.
+    ///
+    /// This only affects the response headers and body, not the rate limiter state itself,
+    /// nor [`RateLimitContext::retry_after`].
+    ///
+    /// The default is no jitter.
+    #[must_use]
+    pub fn with_retry_after_jitter(mut self, max_jitter: Duration) -> Self {
+        Arc::make_mut(&mut self.rejection).retry_after_jitter = max_jitter;
+        self
+    }
+
+    /// Set whether to include a `policy` member describing the exceeded quota in the `application/problem+json`
+    /// body of rate limit rejections, so clients can pace themselves automatically, such as:
+    ///
+    /// ```json
+    /// {"type":"about:blank","title":"Too Many Requests","status":429,"detail":"...","retry_after":2,
+    ///  "policy":{"limit":100,"window":60,"burst":100,"scope":"route"}}
+    /// ```
+    ///
+    /// The `limit` is the number of requests allowed per `window` in seconds, which is the [period](gcra::Quota::period)
+    /// of the quota, and the `burst` is how many of them can be made at once. The `scope` is `route` for the quota of
+    /// the route or key, `global` for the [global fallback](RateLimitLayerBuilder::with_global_fallback), `shared` for
+    /// a [shared quota](RateLimitLayerBuilder::with_shared_quota), `server` for the [server-wide quota](RateLimitLayerBuilder::with_server_quota),
+    /// or `ban` for [banned](RateLimitLayerBuilder::ban_after) keys.
+    ///
+    /// This only applies to the rejection's default response, like [`with_html_template`](RateLimitLayerBuilder::with_html_template).
+    /// The default is `false`.
+    #[cfg(feature = "problem_json")]
+    #[must_use]
+    pub fn with_policy_body(mut self, enabled: bool) -> Self {
+        Arc::make_mut(&mut self.rejection).policy_body = enabled;
+        self
+    }
+
+    /// Use the given shared [`RateLimitState`] for the rate limiter entries, rather than
+    /// creating new state when building the layer.
+    ///
+    /// When using shared state, the number of requests between garbage collection runs
+    /// is determined by the state itself, but time-based [GC intervals](RateLimitLayerBuilder::with_gc_interval)
+    /// still apply to the built layer.
+    #[must_use]
+    pub fn with_state(mut self, state: RateLimitState<K, H>) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    /// Use the given [`Store`](store::Store) for rate limiter entries instead of the built-in in-memory table,
+    /// such as to share rate limits between multiple server instances.
+    ///
+    /// Note that the `_sync` methods of [`RateLimitLayer`] and the [`RateLimiter`](extensions::RateLimiter)
+    /// extension, as well as [`RateLimitLayer::reset`] and [`RateLimitLayer::snapshot`], only operate
+    /// on the in-memory table and will not see entries in a custom store.
+    ///
+    /// Store failures are returned as [`Error::Store`], unless configured otherwise
+    /// with [`with_failure_policy`](RateLimitLayerBuilder::with_failure_policy).
+    #[must_use]
+    pub fn with_store(mut self, store: impl store::Store<K>) -> Self {
+        self.store = Some(Arc::new(store));
+        self
+    }
+
+    /// Set what happens to requests when the custom [`Store`](store::Store) fails, such as on network errors:
+    /// forward them without rate limiting, reject them with a given status, or rate limit them with the
+    /// in-memory table of this instance instead, see [`FailurePolicy`](store::FailurePolicy).
+    ///
+    /// The default is to reject them as [`Error::Store`] with `503 Service Unavailable`. Each failure is
+    /// logged and counted in the `axum_gcra_store_failures_total` metric with the `metrics` feature.
+    /// Unless failing closed, errors from [`Store::poll_ready`](store::Store::poll_ready) are ignored as well.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use axum_gcra::{RateLimitLayer, real_ip::RealIp, store::{FailurePolicy, Store}};
+    ///
+    /// # fn example(redis: impl Store<RealIp>) {
+    /// // keep limiting per instance while Redis is down
+    /// let layer = RateLimitLayer::<RealIp>::builder()
+    ///     .with_store(redis)
+    ///     .with_failure_policy(FailurePolicy::Local)
+    ///     .default_handle_error();
+    /// # }
+    /// ```
+    ///
+    /// Requests forwarded when failing open carry the same extensions as other allowed requests:
+    ///
+    /// ```rust
+    /// # use axum_gcra::axum;
+    /// use axum::{body::Body, extract::Extension, routing::get, Router};
+    /// use futures_util::future::BoxFuture;
+    /// use http::{Request, StatusCode};
+    /// use tower::ServiceExt;
+    /// use axum_gcra::{
+    ///     extensions::RateLimiter,
+    ///     gcra::{GCStats, Quota},
+    ///     store::{FailurePolicy, Store, StoreError, StoreKey, StoreResult},
+    ///     RateLimitError, RateLimitLayer,
+    /// };
+    ///
+    /// /// Store that is always down.
+    /// struct DownStore;
+    ///
+    /// impl Store<()> for DownStore {
+    ///     fn get_update<'a>(&'a self, _: StoreKey<'a, ()>, _: Quota, _: u64, _: u64)
+    ///         -> BoxFuture<'a, StoreResult<Result<u64, RateLimitError>>>
+    ///     {
+    ///         Box::pin(async { Err(StoreError::new("connection refused")) })
+    ///     }
+    ///
+    ///     fn remove<'a>(&'a self, _: StoreKey<'a, ()>) -> BoxFuture<'a, StoreResult<bool>> {
+    ///         Box::pin(async { Err(StoreError::new("connection refused")) })
+    ///     }
+    ///
+    ///     fn gc(&self, _: u64) -> BoxFuture<'_, StoreResult<GCStats>> {
+    ///         Box::pin(async { Err(StoreError::new("connection refused")) })
+    ///     }
+    /// }
+    ///
+    /// # #[tokio::main(flavor = "current_thread")] async fn main() {
+    /// let layer = RateLimitLayer::<()>::builder()
+    ///     .with_store(DownStore)
+    ///     .with_failure_policy(FailurePolicy::Open)
+    ///     .with_extension(true)
+    ///     .default_handle_error();
+    ///
+    /// let app = Router::new().route("/", get(|_: Extension<RateLimiter<()>>| async {})).route_layer(layer);
+    ///
+    /// let res = app.oneshot(Request::get("/").body(Body::empty()).unwrap()).await.unwrap();
+    /// assert_eq!(res.status(), StatusCode::OK);
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn with_failure_policy(mut self, policy: store::FailurePolicy) -> Self {
+        self.failure_policy = policy;
+        self
+    }
+
+    /// Restore the rate limiter state from the given file when the layer is [built](RateLimitLayerBuilder::build),
+    /// and save it back to the file once the layer and all of its clones have been dropped, such as after
+    /// a graceful shutdown, so that restarts do not reset every entry.
+    ///
+    /// A missing or invalid file is ignored. See [`gcra::RateLimiter::save_to`] for more information.
+    #[must_use]
+    pub fn with_persistence(mut self, path: impl Into<PathBuf>) -> Self {
+        self.persist = Some(path.into());
+        self
+    }
+
+    /// Set whether to insert the [`RateLimitInfo`] extension into allowed requests,
+    /// such as to include the remaining quota and charged key in request logs.
+    ///
+    /// The default is `false`, as it requires formatting the key for every request.
+    #[must_use]
+    pub fn with_info_extension(mut self, info: bool) -> Self {
+        self.set_info = info;
+        self
+    }
+
+    /// Set whether to add an `x-ratelimit-policy` header to responses, describing which quota rule
+    /// matched the request, such as `route=/api/users; method=GET; quota=100/60s; source=exact`.
+    ///
+    /// The quota is given as its [burst](gcra::Quota::burst) per [period](gcra::Quota::period), and the source
+    /// is one of `exact` or `prefix` for [registered routes](RateLimitLayerBuilder::with_route), `default` or
+    /// `fallback` for the default quota without and with the [global fallback](RateLimitLayerBuilder::with_global_fallback),
+    /// `key` for [pre-registered keys](RateLimitLayerBuilder::add_key_quotas), or `resolver` for
+    /// [resolved quotas](RateLimitLayerBuilder::with_quota_resolver).
+    ///
+    /// This is meant to verify route patterns and fallback configuration in staging, and should not be
+    /// enabled in production, as it reveals the configuration to clients. The default is `false`.
+    #[must_use]
+    pub fn with_policy_header(mut self, enabled: bool) -> Self {
+        self.policy_header = enabled;
+        self
+    }
+
+    /// Warn clients that used up the given fraction of the burst of their quota, such as `0.8` for 80%,
+    /// with an `x-ratelimit-warning: approaching-limit` header on allowed responses, so well-behaved clients
+    /// can back off before being rejected. Such requests are also reported as [warned](Decision::is_warned)
+    /// to the [decision hook](RateLimitLayerBuilder::with_decision_hook).
+    ///
+    /// The threshold is clamped to between 0 and 1, where a threshold of 1 only warns requests using up the
+    /// last of the quota. The default is no soft limit.
+    #[must_use]
+    pub fn with_soft_limit(mut self, threshold: f32) -> Self {
+        self.soft_limit = Some(if threshold.is_nan() { 1.0 } else { threshold.clamp(0.0, 1.0) });
+        self
+    }
+
+    /// Pass the value of the given request header, such as `x-request-id` or `traceparent`, through to the
+    /// [decision hook](RateLimitLayerBuilder::with_decision_hook) and the [`RateLimitContext`] of rejections,
+    /// so throttle events can be joined with access logs and traces.
+    ///
+    /// The value is available from [`Decision::correlation_id`] and [`RateLimitContext::correlation_id`],
+    /// and is `None` for requests without the header or with a value that is not visible ASCII.
+    /// The default is no correlation header.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use axum_gcra::{RateLimitLayer, real_ip::RealIp};
+    ///
+    /// let layer = RateLimitLayer::<RealIp>::builder()
+    ///     .with_correlation_header(http::HeaderName::from_static("x-request-id"))
+    ///     .with_decision_hook(|decision| {
+    ///         if !decision.is_allowed() {
+    ///             eprintln!("request {:?} throttled", decision.correlation_id());
+    ///         }
+    ///     })
+    ///     .build();
+    /// ```
+    #[must_use]
+    pub fn with_correlation_header(mut self, header: http::HeaderName) -> Self {
+        self.correlation_header = Some(header);
+        self
+    }
+
+    /// Call the given hook with the [`Decision`] for every request that reaches the rate limiter,
+    /// whether allowed or rejected, such as to wire up structured logging or sampling of decisions
+    /// with any logging framework.
+    ///
+    /// The hook is called synchronously on the request path, so it should be cheap. Only one hook
+    /// can be set, replacing any previous one. The default is no hook.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use axum_gcra::{RateLimitLayer, real_ip::RealIp};
+    ///
+    /// let layer = RateLimitLayer::<RealIp>::builder()
+    ///     .with_decision_hook(|decision| {
+    ///         if !decision.is_allowed() {
+    ///             eprintln!("{:?} throttled on {:?} for {:?}", decision.key(), decision.route(), decision.retry_after());
+    ///         }
+    ///     })
+    ///     .build();
+    /// ```
+    #[must_use]
+    pub fn with_decision_hook(mut self, hook: impl Fn(&Decision<'_>) + Send + Sync + 'static) -> Self {
+        self.decision_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Extract keys from the request parts with the given function, falling back to the
+    /// [`FromRequestParts`] implementation of the key when it returns `None`.
+    ///
+    /// Combined with [`PartsKey`], this allows using the rate limiter as a plain [`tower::Layer`]
+    /// in front of any `hyper` or `tower` service of [`http::Request`]s, such as in a proxy without an
+    /// axum router. Note that such requests have no axum [`MatchedPath`](AxumMatchedPath), so they are
+    /// only limited by the default quota, unless the key function includes the route in the key.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use axum_gcra::axum;
+    /// use axum_gcra::{PartsKey, RateLimitLayer};
+    /// use tower::Layer;
+    /// # use axum::handler::HandlerWithoutStateExt;
+    /// # let proxy = (|| async { "hello" }).into_service();
+    ///
+    /// // key by the API key header in a non-axum service
+    /// let layer = RateLimitLayer::<PartsKey<String>>::builder()
+    ///     .with_key_fn(|parts| {
+    ///         let key = parts.headers.get("x-api-key")?.to_str().ok()?;
+    ///         Some(PartsKey(key.to_owned()))
+    ///     })
+    ///     .default_handle_error();
+    ///
+    /// // any `tower::Service<http::Request<B>>`, such as a hyper proxy
+    /// let service = layer.layer(proxy);
+    /// ```
+    #[must_use]
+    pub fn with_key_fn(mut self, key_fn: impl Fn(&Parts) -> Option<K> + Send + Sync + 'static) -> Self {
+        self.key_fn = Some(Box::new(key_fn));
+        self
+    }
+
+    /// Handle requests whose key could not be extracted with the given callback, instead of always
+    /// rejecting them with the rejection of the key extractor, such as the bare `400 Bad Request`
+    /// of [`RealIp`] when the client address is unknown.
+    ///
+    /// The callback can reject the request with a custom response, which bypasses the
+    /// [error handler](RateLimitLayerBuilder::handle_error) as [`Error::KeyResponse`], or rate limit it
+    /// under a substitute key, such as a single anonymous bucket, see [`KeyFallback`].
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use axum_gcra::axum;
+    /// use axum::{http::StatusCode, response::IntoResponse};
+    /// use axum_gcra::{KeyFallback, PartsKey, RateLimitLayer};
+    ///
+    /// let layer = RateLimitLayer::<PartsKey<String>>::builder()
+    ///     .on_key_rejection(|parts| match parts.uri.path().starts_with("/public") {
+    ///         // all anonymous requests to public routes share one bucket
+    ///         true => KeyFallback::Key(PartsKey("anonymous".to_owned())),
+    ///         false => KeyFallback::Respond(
+    ///             (StatusCode::UNAUTHORIZED, r#"{"error":"missing account"}"#).into_response(),
+    ///         ),
+    ///     })
+    ///     .default_handle_error();
+    /// ```
+    #[must_use]
+    pub fn on_key_rejection(mut self, f: impl Fn(&Parts) -> KeyFallback<K> + Send + Sync + 'static) -> Self {
+        self.key_fallback = Some(Box::new(f));
+        self
+    }
+
+    /// Normalize extracted keys with the given function before they are rate limited, such as to lowercase
+    /// tokens, trim whitespace or strip port numbers, so that trivially different representations of the
+    /// same client share one bucket.
+    ///
+    /// The normalizer also applies to keys given to the methods of [`RateLimitLayer`] that take a key
+    /// and route, such as [`RateLimitLayer::reset_route`], but not to
+    /// [allowed keys](RateLimitLayerBuilder::with_allowed_keys), [bans](RateLimitLayer::bans) or
+    /// pre-registered [key quotas](RateLimitLayerBuilder::add_key_quotas), whose keys should be given
+    /// in normalized form.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use axum_gcra::{PartsKey, RateLimitLayer};
+    ///
+    /// let layer = RateLimitLayer::<PartsKey<String>>::builder()
+    ///     .with_key_fn(|parts| {
+    ///         let token = parts.headers.get("x-api-token")?.to_str().ok()?;
+    ///         Some(PartsKey(token.to_owned()))
+    ///     })
+    ///     .with_key_normalizer(|PartsKey(token)| PartsKey(token.trim().to_ascii_lowercase()))
+    ///     .default_handle_error();
+    /// ```
+    #[must_use]
+    pub fn with_key_normalizer(mut self, normalizer: impl Fn(K) -> K + Send + Sync + 'static) -> Self {
+        self.key_normalizer = Some(Box::new(normalizer));
+        self
+    }
+
+    /// Derive the route identity of requests with the given [`RouteKey`](route_key::RouteKey), which selects
+    /// their quota and bucket, instead of their method and axum [`MatchedPath`](AxumMatchedPath).
+    ///
+    /// This allows mapping unusual routing schemes onto quota buckets, such as raw paths behind
+    /// rewrites, localized or case-insensitive paths, or ignoring the method.
+    /// See the [`route_key`] module for more information.
+    #[must_use]
+    pub fn with_route_key(mut self, route_key: impl route_key::RouteKey) -> Self {
+        self.route_key = Some(Box::new(route_key));
+        self
+    }
+
+    /// Look up the quotas of requests without a static quota with the given [`QuotaResolver`](resolver::QuotaResolver),
+    /// such as from a configuration service or database, instead of using the default quota.
+    ///
+    /// The resolver is awaited before each such request, which also disables the allocation-free fast path
+    /// for built-in keys, so slow lookups should be [cached](resolver::CachedQuotaResolver). With the
+    /// [global fallback](RateLimitLayerBuilder::with_global_fallback), all such requests of a key still share
+    /// the same bucket. See the [`resolver`] module for more information.
+    #[must_use]
+    pub fn with_quota_resolver(mut self, resolver: impl resolver::QuotaResolver<K>) -> Self {
+        self.quota_resolver = Some(Box::new(resolver));
+        self
+    }
+
+    /// Enforce rate limit rejections for only the given percentage of keys, to ramp up gradually from
+    /// shadow mode, where rate limited requests are only observed, to full enforcement at `100`, which is the default.
+    ///
+    /// Keys are selected deterministically by their stable hash, so a given client sees consistent behavior,
+    /// and raising the percentage only adds keys to those already enforced. Requests of other keys that would
+    /// have been rejected are forwarded to the inner service instead, without consuming quota, and are still
+    /// reported to tracing, metrics and the [decision hook](RateLimitLayerBuilder::with_decision_hook)
+    /// as [not enforced](Decision::is_enforced). They do not count towards [bans](RateLimitLayerBuilder::ban_after)
+    /// or [violations](RateLimitLayerBuilder::with_violation_counters), but carry the same
+    /// [extensions](RateLimitLayerBuilder::with_extension) as allowed requests, with no requests remaining.
+    ///
+    /// The [deny list](RateLimitLayerBuilder::with_deny_list) and existing bans are always enforced, unless
+    /// [disabled](RateLimitLayerBuilder::with_enabled_flag) entirely. The percentage can be changed at runtime with [`RateLimitLayer::set_enforcement`],
+    /// and values above `100` are treated as `100`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use axum_gcra::{RateLimitLayer, real_ip::RealIp};
+    ///
+    /// // shadow mode, only observe what would be rate limited
+    /// let layer = RateLimitLayer::<RealIp>::builder()
+    ///     .with_enforcement(0)
+    ///     .with_decision_hook(|decision| {
+    ///         if !decision.is_enforced() {
+    ///             eprintln!("would have throttled {:?}", decision.key());
+    ///         }
+    ///     })
+    ///     .build();
+    ///
+    /// // later, enforce for a quarter of clients
+    /// layer.set_enforcement(25);
+    /// ```
+    ///
+    /// Handlers see that requests of keys outside the rollout are over their quota:
+    ///
+    /// ```rust
+    /// # use axum_gcra::axum;
+    /// use std::time::Duration;
+    /// use axum::{body::Body, extract::Extension, routing::get, Router};
+    /// use axum_gcra::{gcra::Quota, RateLimitInfo, RateLimitLayer};
+    /// use http::Request;
+    /// use tower::ServiceExt;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")] async fn main() {
+    /// let layer = RateLimitLayer::<()>::builder()
+    ///     .with_default_quota(Quota::simple(Duration::from_secs(3600)))
+    ///     .with_enforcement(0)
+    ///     .with_info_extension(true)
+    ///     .build();
+    ///
+    /// let handler = |Extension(info): Extension<RateLimitInfo>| async move { info.remaining().to_string() };
+    /// let app = Router::new().route("/", get(handler)).route_layer(layer.default_handle_error());
+    ///
+    /// let remaining = || async {
+    ///     let res = app.clone().oneshot(Request::get("/").body(Body::empty()).unwrap()).await.unwrap();
+    ///     axum::body::to_bytes(res.into_body(), 16).await.unwrap()
+    /// };
+    ///
+    /// assert_eq!(remaining().await, "0");
+    ///
+    /// // would have been rejected, but is let through in shadow mode
+    /// assert_eq!(remaining().await, "0");
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn with_enforcement(self, percent: u8) -> Self {
+        self.enforcement.store(percent.min(100), Ordering::Relaxed);
+        self
+    }
+
+    /// Use the given flag to enable or disable enforcement instantly at runtime, such as from a
+    /// feature flag system, for incident response when the rate limiter itself is misbehaving.
+    ///
+    /// While the flag is `false`, rate limit rejections and [bans](RateLimitLayerBuilder::ban_after)
+    /// are not enforced for any key, as if the [enforcement percentage](RateLimitLayerBuilder::with_enforcement)
+    /// were zero, so requests are still counted and reported as [not enforced](Decision::is_enforced).
+    /// The [deny list](RateLimitLayerBuilder::with_deny_list) is always enforced.
+    ///
+    /// Without a flag, enforcement can still be toggled with [`RateLimitLayer::set_enabled`].
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+    /// use axum_gcra::{RateLimitLayer, real_ip::RealIp};
+    ///
+    /// let enabled = Arc::new(AtomicBool::new(true));
+    ///
+    /// let layer = RateLimitLayer::<RealIp>::builder()
+    ///     .with_enabled_flag(enabled.clone())
+    ///     .build();
+    ///
+    /// // during an incident
+    /// enabled.store(false, Ordering::Relaxed);
+    /// assert!(!layer.is_enabled());
+    /// ```
+    #[must_use]
+    pub fn with_enabled_flag(mut self, enabled: Arc<AtomicBool>) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    /// Delay requests instead of rejecting them when they would be allowed within `max_delay`,
+    /// smoothing bursts rather than responding with `429 Too Many Requests`.
+    ///
+    /// Such requests are admitted right away, reserving their place in the quota, and then wait until
+    /// the quota would have allowed them before being forwarded to the inner service. Only requests
+    /// that would have to wait longer than `max_delay` are rejected, with a retry-after duration
+    /// after which they would be delayed again rather than rejected.
+    ///
+    /// Delayed requests are reported as allowed to the [decision hook](RateLimitLayerBuilder::with_decision_hook),
+    /// with no remaining quota. The default is to never delay requests.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    /// use axum_gcra::{RateLimitLayer, real_ip::RealIp};
+    ///
+    /// let layer = RateLimitLayer::<RealIp>::builder()
+    ///     .with_delay(Duration::from_millis(500))
+    ///     .build();
+    /// ```
+    #[cfg(feature = "tokio")]
+    #[must_use]
+    pub fn with_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = Some(max_delay).filter(|d| !d.is_zero());
+        self
+    }
+
+    /// Limit the number of [delayed](RateLimitLayerBuilder::with_delay) requests waiting per key,
+    /// across all routes, for requests beyond it to be rejected instead of delayed.
+    ///
+    /// Delayed requests of a key are always forwarded in FIFO order, one emission interval apart, as each
+    /// reserves its own place in the quota on arrival, so they never wake at once and race each other.
+    /// This bounds how many such requests a single client can keep pending. The default is unlimited,
+    /// apart from the limit implied by the maximum delay. See also [`RateLimitLayerBuilder::with_delay_queue_limit`].
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    /// use axum_gcra::{RateLimitLayer, real_ip::RealIp};
+    ///
+    /// let layer = RateLimitLayer::<RealIp>::builder()
+    ///     .with_delay(Duration::from_secs(2))
+    ///     .with_delay_queue_depth(4)
+    ///     .build();
+    /// ```
+    #[cfg(feature = "tokio")]
+    #[must_use]
+    pub fn with_delay_queue_depth(mut self, max_depth: usize) -> Self {
+        self.delay_queues = delay::DelayQueues::new(max_depth, self.delay_queues.max_total());
+        self
+    }
+
+    /// Limit the total number of [delayed](RateLimitLayerBuilder::with_delay) requests waiting across all keys,
+    /// for requests beyond it to be rejected with `429 Too Many Requests` instead of delayed.
+    ///
+    /// Without a limit, many clients at once, such as during an attack, can accumulate an unbounded number of
+    /// pending requests, each holding on to its connection and request. The number of requests currently waiting
+    /// is available from [`LimiterStats::delayed`](gcra::LimiterStats::delayed) of [`RateLimitLayer::stats`].
+    /// The default is unlimited.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    /// use axum_gcra::{RateLimitLayer, real_ip::RealIp};
+    ///
+    /// let layer = RateLimitLayer::<RealIp>::builder()
+    ///     .with_delay(Duration::from_secs(2))
+    ///     .with_delay_queue_depth(4)
+    ///     .with_delay_queue_limit(10_000)
+    ///     .build();
+    /// ```
+    #[cfg(feature = "tokio")]
+    #[must_use]
+    pub fn with_delay_queue_limit(mut self, max_total: usize) -> Self {
+        self.delay_queues = delay::DelayQueues::new(self.delay_queues.max_depth(), max_total);
+        self
+    }
+
+    /// Limit the bytes of response bodies streamed to each key, across all routes, to about `bytes` per `period`,
+    /// such as to cap large downloads at some number of megabytes per minute per client.
+    ///
+    /// Bytes are charged in units of one KiB as they flow through a [`BandwidthLayer`](bandwidth::BandwidthLayer),
+    /// which must be applied inside of this layer. Requests of keys that have used up their bandwidth are
+    /// rate limited like any other request, without counting towards their per-request quotas, while
+    /// responses already streaming are never cut off, so a key may overshoot its limit by up to the size
+    /// of its in-flight responses.
+    ///
+    /// This is in addition to the per-request quotas. The default is no bandwidth limit.
+    /// Bandwidth is always tracked in memory, even with a custom [`Store`](store::Store).
+    ///
+    /// See [`BandwidthLayer`](bandwidth::BandwidthLayer) for an example.
+    #[must_use]
+    pub fn with_bandwidth_limit(mut self, bytes: u64, period: Duration) -> Self {
+        self.bandwidth = Some(bandwidth::Bandwidth::new(bytes, period));
+        self
+    }
+
+    /// Set whether to insert the [`RateLimiter`](extensions::RateLimiter) extension into the request
+    /// to allow for manual rate limiting control downstream.
+    ///
+    /// Requests that would have been rejected, but are let through because enforcement is
+    /// [disabled](RateLimitLayer::set_enabled) or during [warm-up](RateLimitLayerBuilder::with_warmup),
+    /// carry the extension as well. Requests that skip rate limiting entirely, such as those of
+    /// [allowed keys](RateLimitLayerBuilder::add_allowed_keys) and networks,
+    /// [exempt](RateLimitLayerBuilder::exempt_if) requests and [fallback exemptions](RateLimitLayerBuilder::without_fallback_for),
+    /// carry no extensions at all, so handlers on routes that may see them should take an `Option<Extension<_>>`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use axum_gcra::axum;
+    /// # use std::time::Duration;
+    /// use axum::{extract::Extension, routing::get, Router};
+    /// use axum_gcra::{RateLimitLayer, extensions::RateLimiter};
+    ///
+    /// // Note this must be identical to the key used in the rate limiter layer
+    /// type Key = ();
+    ///
+    /// let app = Router::<()>::new()
+    ///     // access the rate limiter for this request, unless it was allowed to skip rate limiting
+    ///     .route("/", get(|rl: Option<Extension<RateLimiter<Key>>>| async move {
+    ///         if let Some(rl) = rl {
+    ///             rl.penalize(Duration::from_secs(50)).await;
+    ///         }
+    ///     }))
+    ///     .route_layer(RateLimitLayer::<Key>::builder().with_extension(true).default_handle_error());
+    /// ```
+    ///
+    /// Handlers can rely on the extension while enforcement is disabled:
+    ///
+    /// ```rust
+    /// # use axum_gcra::axum;
+    /// use std::time::Duration;
+    /// use axum::{body::Body, extract::Extension, routing::get, Router};
+    /// use axum_gcra::{extensions::RateLimiter, gcra::Quota, RateLimitLayer};
+    /// use http::{Request, StatusCode};
+    /// use tower::ServiceExt;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")] async fn main() {
+    /// let layer = RateLimitLayer::<()>::builder()
+    ///     .with_default_quota(Quota::simple(Duration::from_secs(3600)))
+    ///     .with_extension(true)
+    ///     .build();
+    ///
+    /// layer.set_enabled(false);
+    ///
+    /// let app = Router::new()
+    ///     .route("/", get(|_: Extension<RateLimiter<()>>| async {}))
+    ///     .route_layer(layer.default_handle_error());
+    ///
+    /// // the second request would have been rejected
+    /// for _ in 0..2 {
+    ///     let res = app.clone().oneshot(Request::get("/").body(Body::empty()).unwrap()).await.unwrap();
+    ///     assert_eq!(res.status(), StatusCode::OK);
+    /// }
+    /// # }
+    #[must_use]
+    pub fn with_extension(mut self, extend: bool) -> Self
+    where
+        K: Clone,
+        H: Send + Sync + 'static,
+    {
+        self.set_ext = match extend {
+            true => Some(Box::new(DoSetExtension) as Box<dyn SetExtension<K, H>>),
+            false => None,
+        };
+        self
+    }
+
+    /// Charge the given number of extra requests against the quota of a key when the inner service
+    /// responds to one of its allowed requests with the given status, such as `401 Unauthorized`
+    /// or `404 Not Found`, so that credential-stuffing and enumeration attacks are throttled much
+    /// faster than legitimate traffic.
+    ///
+    /// The penalty applies to the same route and key as the request. Calling this again for the same status
+    /// replaces its cost. When using a custom [`Store`](store::Store), the penalty is applied with
+    /// [`Store::adjust`](store::Store::adjust) before the response is returned.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use axum::http::StatusCode;
+    /// use axum_gcra::{RateLimitLayer, real_ip::RealIp};
+    ///
+    /// let builder = RateLimitLayer::<RealIp>::builder()
+    ///     .with_status_penalty(StatusCode::UNAUTHORIZED, 5)
+    ///     .with_status_penalty(StatusCode::NOT_FOUND, 2);
+    /// ```
+    #[must_use]
+    pub fn with_status_penalty(mut self, status: http::StatusCode, cost: u64) -> Self
+    where
+        K: Clone,
+        H: Send + Sync + 'static,
+    {
+        self.status_penalties.retain(|&(s, _)| s != status);
+        self.status_penalties.push((status, cost));
+
+        self.track_response = Some(Box::new(DoTrackResponse) as Box<dyn TrackResponse<K, H>>);
+        self
+    }
+
+    /// Set whether to refund the cost of allowed requests that are cancelled before the inner service
+    /// produces a response, such as when the client disconnects and the response future is dropped,
+    /// or when the inner service fails with an error.
+    ///
+    /// Without this, cancelled requests still count against their key, which skews the limits of
+    /// proxies multiplexing many users over the same key. Only the quota of the key is refunded, not the
+    /// [server-wide](RateLimitLayerBuilder::with_server_quota) or [shared](RateLimitLayerBuilder::with_shared_quota)
+    /// quotas. When using a custom [`Store`](store::Store), the refund is applied with [`Store::adjust`](store::Store::adjust)
+    /// in a new task, which requires the `tokio` feature and a running tokio runtime.
+    ///
+    /// The default is `false`.
+    #[must_use]
+    pub fn with_cancel_refund(mut self, enabled: bool) -> Self
+    where
+        K: Clone,
+        H: Send + Sync + 'static,
+    {
+        self.cancel_refund = match enabled {
+            true => Some(Box::new(DoGuardRefund) as Box<dyn GuardRefund<K, H>>),
+            false => None,
+        };
+        self
+    }
+
+    /// Offer a challenge, such as a CAPTCHA, to rate-limited requests instead of rejecting them outright.
+    ///
+    /// The hook is called with a [`Challenge`](challenge::Challenge) for every request rejected by the rate limiter,
+    /// and can return an alternative response, such as a CAPTCHA page embedding the challenge token,
+    /// which is then returned as [`Error::Challenge`]. If it returns `None`, the request is rejected as usual.
+    /// Requests rejected by the [deny list](RateLimitLayerBuilder::with_deny_list) or [bans](RateLimitLayerBuilder::ban_after)
+    /// are never challenged.
+    ///
+    /// Once the client passes the challenge, give its token to [`RateLimitLayer::solve_challenge`]
+    /// to reset or loosen the rate limit of the key for the challenged route.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use axum_gcra::axum;
+    /// use axum::{routing::post, Router, response::{Html, IntoResponse}};
+    /// use axum_gcra::{RateLimitLayer, challenge::ChallengeReward, real_ip::RealIp};
+    ///
+    /// let layer = RateLimitLayer::<RealIp>::builder()
+    ///     .with_challenge(|challenge| async move {
+    ///         let page = format!("<form action='/verify' method='post'><input name='token' value='{}'>...", challenge.token());
+    ///         Some(Html(page).into_response())
+    ///     })
+    ///     .build();
+    ///
+    /// let verify = layer.clone();
+    ///
+    /// let app = Router::<()>::new()
+    ///     .route("/verify", post(move |token: String| async move {
+    ///         // ... verify the CAPTCHA response ...
+    ///         verify.solve_challenge(&token, ChallengeReward::Reset).await;
+    ///     }))
+    ///     .route_layer(layer.clone().default_handle_error());
+    /// ```
+    #[must_use]
+    pub fn with_challenge<F, R>(mut self, hook: F) -> Self
+    where
+        K: Clone,
+        F: Fn(challenge::Challenge) -> R + Send + Sync + 'static,
+        R: Future<Output = Option<Response>> + Send + 'static,
+    {
+        let pending = Arc::new(challenge::Pending::default());
+
+        self.challenges = Some(pending.clone());
+        self.challenge = Some(Box::new(DoIssueChallenge { pending, hook }));
+        self
+    }
+
+    /// Handle rate-limited requests to routes under the given path prefix with the given callback,
+    /// overriding the global [error handler](RateLimitLayerBuilder::handle_error), such as to render
+    /// an HTML error page for pages while returning JSON for `/api`.
+    ///
+    /// The prefix is matched against the route the request is limited by, such as `/api/users/{id}`,
+    /// at segment boundaries. Handlers are tried in the order they were added, and the response of the
+    /// first matching one is returned as [`Error::Handled`], which the global handler should return as-is,
+    /// as the [`IntoResponse`] implementation of [`Error`] does.
+    /// The [challenge hook](RateLimitLayerBuilder::with_challenge) takes precedence.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use axum_gcra::axum;
+    /// use axum::{http::StatusCode, response::{Html, IntoResponse}, Json};
+    /// use axum_gcra::{Error, RateLimitLayer};
+    ///
+    /// let layer = RateLimitLayer::<()>::builder()
+    ///     .handle_error_for("/api", |ctx| {
+    ///         let body = serde_json::json!({ "retry_after": ctx.retry_after().as_secs() });
+    ///         (StatusCode::TOO_MANY_REQUESTS, Json(body))
+    ///     })
+    ///     .handle_error(|e| async move {
+    ///         match e {
+    ///             Error::RateLimit(_) => {
+    ///                 (StatusCode::TOO_MANY_REQUESTS, Html("<h1>Slow down</h1>")).into_response()
+    ///             }
+    ///             e => e.into_response(),
+    ///         }
+    ///     });
+    /// ```
+    #[must_use]
+    pub fn handle_error_for<F, R>(mut self, prefix: impl Into<Cow<'static, str>>, handler: F) -> Self
+    where
+        F: Fn(RateLimitContext) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route_errors.push((prefix.into(), Box::new(move |ctx| handler(ctx).into_response())));
+        self
+    }
+
+    /// Take a [snapshot](config::RateLimitConfig) of the configuration of this builder, without any secrets,
+    /// such as to log it or to compare it to the configuration of other instances.
+    #[must_use]
+    pub fn config(&self) -> config::RateLimitConfig {
+        fn sorted<T>(mut routes: Vec<(Route<'static>, T)>) -> Vec<(Route<'static>, T)> {
+            routes.sort_unstable_by(|(a, _), (b, _)| {
+                (&a.path, a.method.as_str()).cmp(&(&b.path, b.method.as_str()))
+            });
+            routes
+        }
+
+        let mut fallback_exemptions: Vec<_> = self.fallback_exemptions.iter().map(|p| p.to_string()).collect();
+        fallback_exemptions.sort_unstable();
+
+        let mut status_penalties = self.status_penalties.clone();
+        status_penalties.sort_unstable();
+
+        config::RateLimitConfig {
+            routes: sorted(self.quotas.iter().map(|(route, &quota)| (route.clone(), quota)).collect()),
+            default_quota: self.default_quota,
+            safe_default_quota: self.class_quotas[MethodClass::Safe as usize],
+            unsafe_default_quota: self.class_quotas[MethodClass::Unsafe as usize],
+            ipv4_default_quota: self.ip_quotas[0],
+            ipv6_default_quota: self.ip_quotas[1],
+            ip_masks: self.ip_masks,
+            class_buckets: self.class_buckets,
+            key_quotas: self.key_quotas.len(),
+            global_fallback: self.global_fallback,
+            prefix_fallback: self.prefix_fallback,
+            fallback_exemptions,
+            nest_prefix: self.nest_prefix.as_deref().map(str::to_owned),
+            server_quota: self.server_quota.as_ref().map(|(quota, _)| *quota),
+            shared_quotas: self.shared_quotas.as_ref().map(SharedQuotas::config).unwrap_or_default(),
+            parent_keys: self
+                .parent_keys
+                .as_ref()
+                .map_or_else(Vec::new, |p| p.parents.iter().map(|p| p.0).collect()),
+            route_ttls: sorted(self.route_ttls.iter().map(|(route, &ttl)| (route.clone(), ttl)).collect()),
+            idle_timeout: self.idle_timeout,
+            lifecycle_callbacks: self.lifecycle.is_set(),
+            gc_interval: self.gc_interval,
+            gc_requests: self.gc_requests,
+            shards: self.shards,
+            max_entries: self.max_entries,
+            overflow: self.overflow,
+            status_penalties,
+            cancel_refund: self.cancel_refund.is_some(),
+            max_delay: self.max_delay,
+            enforcement: self.enforcement.load(Ordering::Relaxed),
+            scale: f32::from_bits(self.scale.load(Ordering::Relaxed)),
+            bypass_header: self.allow_list.bypass_header().map(|h| h.as_str().to_owned()),
+            exemptions: self.allow_list.exemptions(),
+            extension: self.set_ext.is_some(),
+            info_extension: self.set_info,
+            policy_header: self.policy_header,
+            soft_limit: self.soft_limit,
+            correlation_header: self.correlation_header.as_ref().map(|h| h.as_str().to_owned()),
+            custom_store: self.store.is_some(),
+            failure_policy: self.failure_policy,
+            quota_resolver: self.quota_resolver.is_some(),
+            route_error_handlers: self.route_errors.iter().map(|(prefix, _)| prefix.to_string()).collect(),
+            shared_state: self.state.is_some(),
+            persistence: self.persist.clone().or_else(|| self.persist_on_drop.as_ref().map(|p| p.path.clone())),
+            ban_persistence: (self.ban_persist.clone())
+                .or_else(|| self.ban_persist_on_drop.as_ref().map(|p| p.path.clone())),
+            ban_store: self.ban_store.is_some() || self.bans.as_ref().is_some_and(ban::Bans::has_store),
+        }
+    }
+}
+
+impl<K: Key, H: BuildHasher> fmt::Debug for RateLimitLayerBuilder<K, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("RateLimitLayerBuilder").field(&self.config()).finish()
+    }
+}
+
+impl<K: Key, H: BuildHasher> fmt::Debug for RateLimitLayer<K, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimitLayer")
+            .field("config", &self.builder.config())
+            .field("entries", &self.limiter.len())
+            .finish()
+    }
+}
+
+impl Default for RateLimitLayerBuilder<()> {
+    fn default() -> Self {
+        RateLimitLayerBuilder::new()
+    }
+}
+
+/// Error wrapper for rate limiting errors or inner service errors.
+///
+/// Implements [`IntoResponse`], and [`std::error::Error`] when the inner error does and the
+/// key rejection implements [`Display`](fmt::Display), so it can be used with error-reporting
+/// crates or converted into a [`tower::BoxError`].
+#[derive(Debug)]
+pub enum Error<Inner, Rejection> {
+    /// Inner service error.
+    ///
+    /// For most axum services, this will be a [`Infallible`].
+    Inner(Inner),
+
+    /// Rate limiting error.
+    ///
+    /// This error is returned when the rate limiter has blocked the request,
+    /// and will be passed to the [error handler](RateLimitLayerBuilder::handle_error)
+    /// along with the context of the rejection.
+    RateLimit(RateLimitContext),
+
+    /// Key extraction rejection.
+    KeyRejection(Rejection),
+
+    /// Response returned by the [key rejection handler](RateLimitLayerBuilder::on_key_rejection)
+    /// for a request whose key could not be extracted.
+    KeyResponse(Response),
+
+    /// Error from a custom [`Store`](store::Store), such as a network error.
+    ///
+    /// Converts into a `503 Service Unavailable` response.
+    Store(store::StoreError),
+
+    /// Alternative response returned by the [challenge hook](RateLimitLayerBuilder::with_challenge)
+    /// for a rate-limited request.
+    Challenge(Response),
+
+    /// Response of the [route error handler](RateLimitLayerBuilder::handle_error_for)
+    /// for a rate-limited request, which overrides the global error handler.
+    Handled(Response),
+}
+
+/// Context of a rate-limited request, passed to the [error handler](RateLimitLayerBuilder::handle_error)
+/// via [`Error::RateLimit`] so it can log or build informative responses without recomputing anything.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use axum_gcra::axum;
+/// use axum::{Router, http::StatusCode};
+/// use axum_gcra::{RateLimitLayer, Error, real_ip::RealIp};
+///
+/// let app = Router::<()>::new().route_layer(
+///     RateLimitLayer::<RealIp>::builder().handle_error(|e| async move {
+///         match e {
+///             Error::RateLimit(ctx) => {
+///                 eprintln!("{} rate limited on {:?}, retry in {:?}", ctx.key(), ctx.route(), ctx.retry_after());
+///                 StatusCode::TOO_MANY_REQUESTS
+///             }
+///             _ => StatusCode::BAD_REQUEST,
+///         }
+///     }));
+/// ```
+///
+/// When converted into a [`Response`], the context is also inserted into the response's extensions,
+/// so outer middleware (logging, metrics, etc.) can observe throttled requests by checking for it.
+#[derive(Clone)]
+pub struct RateLimitContext {
+    error: RateLimitError,
+    quota: gcra::Quota,
+    method: Method,
+    path: MatchedPath,
+    key: Arc<str>,
+    prefers_html: bool,
+    banned: bool,
+    denied: bool,
+    server_limited: bool,
+    shared_limited: bool,
+    violations: f64,
+    policy: Option<http::HeaderValue>,
+    correlation_id: Option<Arc<str>>,
+    config: Arc<rejection::RejectionConfig>,
+}
+
+impl RateLimitContext {
+    fn new<K: Key>(
+        error: RateLimitError,
+        key: &RouteWithKey<K>,
+        quota: gcra::Quota,
+        parts: &Parts,
+        correlation_id: Option<&str>,
+        config: &Arc<rejection::RejectionConfig>,
+    ) -> Self {
+        RateLimitContext {
+            error,
+            quota,
+            method: key.method.clone(),
+            path: key.path.clone(),
+            key: format!("{:?}", key.key).into(),
+            prefers_html: config.html_template.is_some() && rejection::prefers_html(&parts.headers),
+            banned: false,
+            denied: false,
+            server_limited: false,
+            shared_limited: false,
+            violations: 0.0,
+            policy: None,
+            correlation_id: correlation_id.map(Arc::from),
+            config: config.clone(),
+        }
+    }
+
+    /// Get the underlying [`RateLimitError`].
+    #[inline]
+    #[must_use]
+    pub fn error(&self) -> RateLimitError {
+        self.error
+    }
+
+    /// Get the amount of time until the next request can be made.
+    #[inline]
+    #[must_use]
+    pub fn retry_after(&self) -> Duration {
+        self.error.as_duration()
+    }
+
+    /// Get the [`Debug`](fmt::Debug) representation of the key that was rate limited.
+    #[inline]
+    #[must_use]
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Get the route that was rate limited.
+    ///
+    /// If the request fell back to the [global fallback](RateLimitLayerBuilder::with_global_fallback)
+    /// rate limiter, the path will be empty.
+    #[must_use]
+    pub fn route(&self) -> Route<'_> {
+        Route {
+            method: Cow::Borrowed(&self.method),
+            path: Cow::Borrowed(&self.path),
+        }
+    }
+
+    /// Get the quota that was applied to the request.
+    #[inline]
+    #[must_use]
+    pub fn quota(&self) -> gcra::Quota {
+        self.quota
+    }
+
+    /// Returns `true` if the request was rejected because the key is [banned](RateLimitLayerBuilder::ban_after),
+    /// in which case the [retry time](RateLimitContext::retry_after) is the remaining duration of the ban.
+    #[inline]
+    #[must_use]
+    pub fn is_banned(&self) -> bool {
+        self.banned
+    }
+
+    /// Returns `true` if the request was rejected by the [deny list](RateLimitLayerBuilder::with_deny_list),
+    /// in which case it converts into a `403 Forbidden` response. Denied requests are also [banned](RateLimitContext::is_banned).
+    #[inline]
+    #[must_use]
+    pub fn is_denied(&self) -> bool {
+        self.denied
+    }
+
+    /// Returns `true` if the request was rejected by the [server-wide quota](RateLimitLayerBuilder::with_server_quota)
+    /// rather than the quota of its key, in which case the [quota](RateLimitContext::quota) is the server-wide quota.
+    #[inline]
+    #[must_use]
+    pub fn is_server_limited(&self) -> bool {
+        self.server_limited
+    }
+
+    /// Get the decayed number of rate limit violations of the key, including this one
+    /// unless [banned](RateLimitContext::is_banned), if [violation counters](RateLimitLayerBuilder::with_violation_counters)
+    /// are enabled, otherwise zero.
+    #[inline]
+    #[must_use]
+    pub fn violations(&self) -> f64 {
+        self.violations
+    }
+
+    /// Get the value of the [correlation header](RateLimitLayerBuilder::with_correlation_header)
+    /// of the rejected request, if any.
+    #[inline]
+    #[must_use]
+    pub fn correlation_id(&self) -> Option<&str> {
+        self.correlation_id.as_deref()
+    }
+}
+
+impl fmt::Debug for RateLimitContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimitContext")
+            .field("error", &self.error)
+            .field("quota", &self.quota)
+            .field("method", &self.method)
+            .field("path", &&*self.path)
+            .field("key", &self.key)
+            .field("banned", &self.banned)
+            .field("denied", &self.denied)
+            .field("violations", &self.violations)
+            .field("correlation_id", &self.correlation_id)
+            .finish()
+    }
+}
+
+impl IntoResponse for RateLimitContext {
+    fn into_response(self) -> Response {
+        let mut res = match self.denied {
+            true => http::StatusCode::FORBIDDEN.into_response(),
+            false => {
+                let scope = match self.path {
+                    _ if self.banned => "ban",
+                    _ if self.server_limited => "server",
+                    _ if self.shared_limited => "shared",
+                    MatchedPath::Fallback => "global",
+                    _ => "route",
+                };
+
+                self.config.render(self.error, &self.path, self.prefers_html, self.quota, scope)
+            }
+        };
+
+        if let Some(ref policy) = self.policy {
+            res.headers_mut().insert(X_RATELIMIT_POLICY.clone(), policy.clone());
+        }
+
+        res.extensions_mut().insert(self);
+        res
+    }
+}
+
+impl fmt::Display for RateLimitContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl std::error::Error for RateLimitContext {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Information about an allowed request, inserted into the request's extensions
+/// if [enabled](RateLimitLayerBuilder::with_info_extension).
+///
+/// Requests that would have been rejected, but are [not enforced](Decision::is_enforced),
+/// are given the same information with no requests remaining. Requests that skip rate limiting
+/// entirely, such as those of [allowed keys](RateLimitLayerBuilder::add_allowed_keys), are not.
+///
+/// Unlike the [`RateLimiter`](extensions::RateLimiter) extension, this is not generic over
+/// the key type, so it can be easily accessed by logging middleware and the like.
+#[derive(Debug, Clone)]
+pub struct RateLimitInfo {
+    quota: gcra::Quota,
+    status: gcra::Status,
+    method: Method,
+    path: MatchedPath,
+    key: Arc<str>,
+    violations: f64,
+}
+
+impl RateLimitInfo {
+    fn new<K: Key>(key: &RouteWithKey<K>, quota: gcra::Quota, status: gcra::Status, violations: f64) -> Self {
+        RateLimitInfo {
+            quota,
+            status,
+            method: key.method.clone(),
+            path: key.path.clone(),
+            key: format!("{:?}", key.key).into(),
+            violations,
+        }
+    }
+
+    /// Get the [`Debug`](fmt::Debug) representation of the key that was charged.
+    #[inline]
+    #[must_use]
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Get the route that was charged.
+    ///
+    /// If the request fell back to the [global fallback](RateLimitLayerBuilder::with_global_fallback)
+    /// rate limiter, the path will be empty.
+    #[must_use]
+    pub fn route(&self) -> Route<'_> {
+        Route {
+            method: Cow::Borrowed(&self.method),
+            path: Cow::Borrowed(&self.path),
+        }
+    }
+
+    /// Get the quota that was applied to the request.
+    #[inline]
+    #[must_use]
+    pub fn quota(&self) -> gcra::Quota {
+        self.quota
+    }
+
+    /// Get the [`Status`](gcra::Status) of the quota right after the request was allowed,
+    /// or of the exhausted quota if it was not enforced.
+    #[inline]
+    #[must_use]
+    pub fn status(&self) -> gcra::Status {
+        self.status
+    }
+
+    /// Get the number of requests remaining right after the request was allowed.
+    #[inline]
+    #[must_use]
+    pub fn remaining(&self) -> u64 {
+        self.status.remaining
+    }
+
+    /// Get the decayed number of rate limit violations of the key, if
+    /// [violation counters](RateLimitLayerBuilder::with_violation_counters) are enabled, otherwise zero.
+    #[inline]
+    #[must_use]
+    pub fn violations(&self) -> f64 {
+        self.violations
+    }
+}
+
+type DecisionHook = Box<dyn Fn(&Decision<'_>) + Send + Sync>;
+
+type KeyFn<K> = Box<dyn Fn(&Parts) -> Option<K> + Send + Sync>;
+
+type KeyFallbackFn<K> = Box<dyn Fn(&Parts) -> KeyFallback<K> + Send + Sync>;
+
+/// Rate limiting decision for a single request, passed to the
+/// [decision hook](RateLimitLayerBuilder::with_decision_hook).
+pub struct Decision<'a> {
+    key: &'a dyn fmt::Debug,
+    method: &'a Method,
+    path: &'a str,
+    quota: gcra::Quota,
+    result: Result<gcra::Status, RateLimitError>,
+    correlation_id: Option<&'a str>,
+    banned: bool,
+    denied: bool,
+    server_limited: bool,
+    enforced: bool,
+    warned: bool,
+}
+
+impl<'a> Decision<'a> {
+    fn new<K: Key>(
+        key: &'a RouteWithKey<K>,
+        quota: gcra::Quota,
+        result: Result<gcra::Status, RateLimitError>,
+        correlation_id: Option<&'a str>,
+    ) -> Self {
+        Decision {
+            key: &key.key,
+            method: &key.method,
+            path: &key.path,
+            quota,
+            result,
+            correlation_id,
+            banned: false,
+            denied: false,
+            server_limited: false,
+            enforced: true,
+            warned: false,
+        }
+    }
+
+    /// Get the key of the request, which can be formatted with its [`Debug`](fmt::Debug) representation.
+    #[inline]
+    #[must_use]
+    pub fn key(&self) -> &'a dyn fmt::Debug {
+        self.key
+    }
+
+    /// Get the route of the request.
+    ///
+    /// If the request fell back to the [global fallback](RateLimitLayerBuilder::with_global_fallback)
+    /// rate limiter, the path will be empty.
+    #[must_use]
+    pub fn route(&self) -> Route<'a> {
+        Route {
+            method: Cow::Borrowed(self.method),
+            path: Cow::Borrowed(self.path),
+        }
+    }
+
+    /// Get the quota that was applied to the request.
+    #[inline]
+    #[must_use]
+    pub fn quota(&self) -> gcra::Quota {
+        self.quota
+    }
+
+    /// Get the value of the [correlation header](RateLimitLayerBuilder::with_correlation_header)
+    /// of the request, if any.
+    #[inline]
+    #[must_use]
+    pub fn correlation_id(&self) -> Option<&'a str> {
+        self.correlation_id
+    }
+
+    /// Get the number of requests the request was charged, which is zero if it was rejected.
+    #[inline]
+    #[must_use]
+    pub fn cost(&self) -> u64 {
+        self.result.is_ok() as u64
+    }
+
+    /// Returns `true` if the request was allowed.
+    #[inline]
+    #[must_use]
+    pub fn is_allowed(&self) -> bool {
+        self.result.is_ok()
+    }
+
+    /// Get the number of requests remaining right after the request was allowed, or zero if it was rejected.
+    #[inline]
+    #[must_use]
+    pub fn remaining(&self) -> u64 {
+        self.result.as_ref().map_or(0, |status| status.remaining)
+    }
+
+    /// Get the [`Status`](gcra::Status) of the quota right after the request was allowed, if it was.
+    #[inline]
+    #[must_use]
+    pub fn status(&self) -> Option<gcra::Status> {
+        self.result.as_ref().ok().copied()
+    }
+
+    /// Get the amount of time until the next request can be made, if the request was rejected.
+    #[inline]
+    #[must_use]
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.result.as_ref().err().map(|e| e.as_duration())
+    }
+
+    /// Returns `true` if the request was rejected because the key is [banned](RateLimitLayerBuilder::ban_after),
+    /// see [`RateLimitContext::is_banned`].
+    #[inline]
+    #[must_use]
+    pub fn is_banned(&self) -> bool {
+        self.banned
+    }
+
+    /// Returns `true` if the request was rejected by the [deny list](RateLimitLayerBuilder::with_deny_list),
+    /// see [`RateLimitContext::is_denied`].
+    #[inline]
+    #[must_use]
+    pub fn is_denied(&self) -> bool {
+        self.denied
+    }
+
+    /// Returns `true` if the request was rejected by the [server-wide quota](RateLimitLayerBuilder::with_server_quota),
+    /// see [`RateLimitContext::is_server_limited`].
+    #[inline]
+    #[must_use]
+    pub fn is_server_limited(&self) -> bool {
+        self.server_limited
+    }
+
+    /// Returns `false` if the request was rate limited but forwarded anyway, because its key is not
+    /// within the [enforced percentage](RateLimitLayerBuilder::with_enforcement) or still within its
+    /// [grace period](RateLimitLayerBuilder::with_warmup). Always `true` otherwise.
+    #[inline]
+    #[must_use]
+    pub fn is_enforced(&self) -> bool {
+        self.enforced
+    }
+
+    /// Returns `true` if the request was allowed, but crossed the [soft limit](RateLimitLayerBuilder::with_soft_limit)
+    /// of its quota, so the client was warned that it is approaching the limit.
+    #[inline]
+    #[must_use]
+    pub fn is_warned(&self) -> bool {
+        self.warned
+    }
+}
+
+impl fmt::Debug for Decision<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Decision")
+            .field("key", &self.key)
+            .field("method", &self.method)
+            .field("path", &self.path)
+            .field("quota", &self.quota)
+            .field("result", &self.result)
+            .field("banned", &self.banned)
+            .field("denied", &self.denied)
+            .field("server_limited", &self.server_limited)
+            .field("enforced", &self.enforced)
+            .finish()
+    }
+}
+
+/// Snapshot of a single rate limiter entry, as returned by [`RateLimitLayer::snapshot`].
+#[derive(Debug, Clone)]
+pub struct EntrySnapshot {
+    quota: gcra::Quota,
+    status: gcra::Status,
+    tat: Instant,
+    method: Method,
+    path: MatchedPath,
+    key: Arc<str>,
+    violations: f64,
+}
+
+impl EntrySnapshot {
+    fn new<K: Key>(
+        key: &RouteWithKey<K>,
+        tat: Instant,
+        quota: gcra::Quota,
+        status: gcra::Status,
+        violations: f64,
+    ) -> Self {
+        EntrySnapshot {
+            quota,
+            status,
+            tat,
+            method: key.method.clone(),
+            path: key.path.clone(),
+            key: format!("{:?}", key.key).into(),
+            violations,
+        }
+    }
+
+    /// Get the [`Debug`](fmt::Debug) representation of the entry's key.
+    #[inline]
+    #[must_use]
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Get the route of the entry.
+    ///
+    /// If the entry is for the [global fallback](RateLimitLayerBuilder::with_global_fallback)
+    /// rate limiter, the path will be empty.
+    #[must_use]
+    pub fn route(&self) -> Route<'_> {
+        Route {
+            method: Cow::Borrowed(&self.method),
+            path: Cow::Borrowed(&self.path),
+        }
+    }
+
+    /// Get the effective quota of the entry.
+    #[inline]
+    #[must_use]
+    pub fn quota(&self) -> gcra::Quota {
+        self.quota
+    }
+
+    /// Get the [`Status`](gcra::Status) of the entry at the time of the snapshot.
+    #[inline]
+    #[must_use]
+    pub fn status(&self) -> gcra::Status {
+        self.status
+    }
+
+    /// Get the theoretical arrival time (TAT) of the entry, which is the raw GCRA timestamp.
+    #[inline]
+    #[must_use]
+    pub fn tat(&self) -> Instant {
+        self.tat
+    }
+
+    /// Get the decayed number of rate limit violations of the entry's key across all routes, if
+    /// [violation counters](RateLimitLayerBuilder::with_violation_counters) are enabled, otherwise zero.
+    #[inline]
+    #[must_use]
+    pub fn violations(&self) -> f64 {
+        self.violations
+    }
+}
+
+/// Effective route table of a rate limiter, as returned by [`RateLimitLayer::route_table`].
+///
+/// The [`Display`](fmt::Display) implementation prints one line per quota, such as for a startup log.
+#[derive(Debug, Clone)]
+pub struct RouteTable {
+    routes: Vec<(Route<'static>, gcra::Quota)>,
+    default_quota: gcra::Quota,
+    class_quotas: [Option<gcra::Quota>; 2],
+    global_fallback: bool,
+    prefix_fallback: bool,
+    fallback_exemptions: Vec<Cow<'static, str>>,
+}
+
+impl RouteTable {
+    /// Get the registered routes and their quotas, sorted by path and method.
+    #[inline]
+    #[must_use]
+    pub fn routes(&self) -> &[(Route<'static>, gcra::Quota)] {
+        &self.routes
+    }
+
+    /// Get the quota for routes without a registered quota, see [`RateLimitLayerBuilder::with_default_quota`].
+    #[inline]
+    #[must_use]
+    pub fn default_quota(&self) -> gcra::Quota {
+        self.default_quota
+    }
+
+    /// Get the quota for routes without a registered quota for requests of the given method class,
+    /// see [`RateLimitLayerBuilder::with_default_quota_for`].
+    #[inline]
+    #[must_use]
+    pub fn default_quota_for(&self, class: MethodClass) -> gcra::Quota {
+        self.class_quotas[class as usize].unwrap_or(self.default_quota)
+    }
+
+    /// Check if the [global fallback](RateLimitLayerBuilder::with_global_fallback) is enabled.
+    #[inline]
+    #[must_use]
+    pub fn global_fallback(&self) -> bool {
+        self.global_fallback
+    }
+
+    /// Check if the [prefix fallback](RateLimitLayerBuilder::with_prefix_fallback) is enabled.
+    #[inline]
+    #[must_use]
+    pub fn prefix_fallback(&self) -> bool {
+        self.prefix_fallback
+    }
+
+    /// Get the path prefixes [exempt](RateLimitLayerBuilder::without_fallback_for) from the default quota.
+    pub fn fallback_exemptions(&self) -> impl Iterator<Item = &str> {
+        self.fallback_exemptions.iter().map(|prefix| &**prefix)
+    }
+}
+
+impl fmt::Display for RouteTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn quota(f: &mut fmt::Formatter<'_>, quota: gcra::Quota) -> fmt::Result {
+            write!(
+                f,
+                "burst {}, emission interval {:?}",
+                quota.burst(),
+                quota.emission_interval()
+            )
+        }
+
+        f.write_str("default: ")?;
+        quota(f, self.default_quota)?;
+
+        if self.global_fallback {
+            f.write_str(" (global)")?;
+        }
+
+        for (class, name) in [(MethodClass::Safe, "safe"), (MethodClass::Unsafe, "unsafe")] {
+            if let Some(q) = self.class_quotas[class as usize] {
+                write!(f, "\ndefault {name}: ")?;
+                quota(f, q)?;
+            }
+        }
+
+        for (route, q) in &self.routes {
+            write!(f, "\n{} {}: ", route.method, route.path)?;
+            quota(f, *q)?;
+        }
+
+        if self.prefix_fallback {
+            f.write_str("\nprefix fallback enabled")?;
+        }
+
+        for prefix in &self.fallback_exemptions {
+            write!(f, "\nexempt: {prefix}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<Inner, Rejection> fmt::Display for Error<Inner, Rejection>
+where
+    Inner: fmt::Display,
+    Rejection: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Inner(e) => fmt::Display::fmt(e, f),
+            Error::RateLimit(e) => fmt::Display::fmt(e, f),
+            Error::KeyRejection(e) => write!(f, "key rejected: {e}"),
+            Error::KeyResponse(_) => f.write_str("key rejected"),
+            Error::Store(e) => fmt::Display::fmt(e, f),
+            Error::Challenge(_) => f.write_str("rate limit challenge issued"),
+            Error::Handled(_) => f.write_str("rate limited"),
+        }
+    }
+}
+
+impl<Inner, Rejection> std::error::Error for Error<Inner, Rejection>
+where
+    Inner: std::error::Error + 'static,
+    Rejection: fmt::Debug + fmt::Display,
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Inner(e) => Some(e),
+            Error::RateLimit(e) => Some(e),
+            Error::KeyRejection(_) => None,
+            Error::KeyResponse(_) => None,
+            Error::Store(e) => Some(e),
+            Error::Challenge(_) => None,
+            Error::Handled(_) => None,
+        }
+    }
+}
+
+impl<Inner, Rejection> IntoResponse for Error<Inner, Rejection>
+where
+    Inner: IntoResponse,
+    Rejection: IntoResponse,
+{
+    fn into_response(self) -> Response {
+        match self {
+            Error::RateLimit(e) => e.into_response(),
+            Error::KeyRejection(e) => e.into_response(),
+            Error::KeyResponse(res) => res,
+            Error::Inner(e) => e.into_response(),
+            Error::Store(e) => e.status().into_response(),
+            Error::Challenge(res) => res,
+            Error::Handled(res) => res,
+        }
+    }
+}
+
+use futures_util::{future::BoxFuture, TryFuture};
+
+pin_project_lite::pin_project! {
+    #[doc(hidden)]
+    #[project = RateLimitedResponseProj]
+    pub enum RateLimitedResponse<B, I: Service<Request<B>>, K: FromRequestParts<()>> {
+        RateLimiting {
+            #[pin] f: BoxFuture<'static, Result<(Parts, Option<ResponseHook>), Error<I::Error, K::Rejection>>>,
+
+            inner: I, // storing `I` separately helps avoid an `I: Sync` bound
+            body: Option<B>, // similar story, helps avoid `B: Send + 'static` bound
+        },
+
+        Inner { #[pin] f: I::Future, hook: Option<ResponseHook>, headers: Option<http::HeaderMap> },
+
+        Hooked { #[pin] f: BoxFuture<'static, ()>, res: Option<I::Response> },
+
+        Rejected { error: Option<Error<I::Error, K::Rejection>> },
+    }
+}
+
+impl<B, I, K, ResBody> Future for RateLimitedResponse<B, I, K>
+where
+    I: Service<
+        Request<B>,
+        Response = http::Response<ResBody>,
+        Future: TryFuture<Ok = I::Response, Error = I::Error>,
+    >,
+    K: FromRequestParts<()>,
+{
+    type Output = Result<I::Response, Error<I::Error, K::Rejection>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        loop {
+            match self.as_mut().project() {
+                RateLimitedResponseProj::RateLimiting { inner, body, f } => match ready!(f.try_poll(cx)) {
+                    Ok((mut req, hook)) => {
+                        let headers = req.extensions.remove::<ResponseHeaders>().map(|h| h.0);
+                        let req = Request::from_parts(req, body.take().expect("body is Some"));
+                        let f = inner.call(req);
+                        self.set(RateLimitedResponse::Inner { f, hook, headers })
+                    }
+                    Err(e) => return Poll::Ready(Err(e)),
+                },
+                RateLimitedResponseProj::Inner { f, hook, headers } => match ready!(f.try_poll(cx)) {
+                    Ok(mut res) => {
+                        if let Some(headers) = headers.take() {
+                            res.headers_mut().extend(headers);
+                        }
+
+                        match hook.take().and_then(|hook| hook(res.status())) {
+                            Some(f) => self.set(RateLimitedResponse::Hooked { f, res: Some(res) }),
+                            None => return Poll::Ready(Ok(res)),
+                        }
+                    }
+                    Err(e) => return Poll::Ready(Err(Error::Inner(e))),
+                },
+                RateLimitedResponseProj::Hooked { f, res } => {
+                    ready!(f.poll(cx));
+                    return Poll::Ready(Ok(res.take().expect("polled after completion")));
+                }
+                RateLimitedResponseProj::Rejected { error } => {
+                    return Poll::Ready(Err(error.take().expect("polled after completion")))
+                }
+            }
+        }
+    }
+}
+
+impl<K: Key, H: BuildHasher> RateLimitLayer<K, H> {
+    /// Get the current time for timestamping requests, see [`RateLimitLayerBuilder::with_clock`].
+    #[inline]
+    fn now(&self) -> Instant {
+        match self.builder.clock {
+            Some(ref clock) => clock.now(),
+            None => Instant::now(),
+        }
+    }
+
+    /// Get the current time for timestamping requests to custom stores, in nanoseconds since the UNIX epoch.
+    #[inline]
+    fn unix_now(&self) -> u64 {
+        match self.builder.clock {
+            Some(ref clock) => clock.unix_now(),
+            None => store::now(),
+        }
+    }
+
+    /// Get the path and method identifying the route of a request, see [`RateLimitLayerBuilder::with_route_key`].
+    fn request_route(&self, parts: &Parts) -> (MatchedPath, Method) {
+        if let Some(route) = self.builder.route_key.as_ref().and_then(|r| r.route_key(parts)) {
+            return (MatchedPath::Static(route.path), route.method.into_owned());
+        }
+
+        let path = match parts.extensions.get::<AxumMatchedPath>() {
+            Some(path) => match self.builder.nest_prefix.as_deref().and_then(|p| path.as_str().strip_prefix(p)) {
+                // the root of the nested router is matched as the prefix itself
+                Some("") => MatchedPath::Static(Cow::Borrowed("/")),
+                Some(rest) if rest.starts_with('/') => MatchedPath::Static(Cow::Owned(rest.to_owned())),
+                _ => MatchedPath::Axum(path.clone()),
+            },
+            None => MatchedPath::Fallback,
+        };
+
+        (path, parts.method.clone())
+    }
+
+    /// Apply the [key normalizer](RateLimitLayerBuilder::with_key_normalizer), if any.
+    #[inline]
+    fn normalize(&self, #[cfg_attr(not(feature = "real_ip"), allow(unused_mut))] mut key: K) -> K {
+        #[cfg(feature = "real_ip")]
+        if let Some((v4, v6)) = self.builder.ip_masks {
+            real_ip::mask_key_ip(&mut key, v4, v6);
+        }
+
+        match self.builder.key_normalizer {
+            Some(ref normalize) => normalize(key),
+            None => key,
+        }
+    }
+
+    /// Build the internal key for the given key and route, taking the global fallback into account.
+    fn route_key(&self, key: K, route: Route<'static>) -> RouteWithKey<K> {
+        let mut key = RouteWithKey {
+            path: MatchedPath::Static(route.path),
+            method: route.method.into_owned(),
+            key: self.normalize(key),
+        };
+
+        self.resolve_quota(&mut key);
+        key
+    }
+
+    /// Build the internal keys of an exported state, see [`RateLimitLayer::route_key`].
+    fn route_keys(
+        &self,
+        state: &gcra::MergeableState<(Route<'static>, K)>,
+    ) -> gcra::MergeableState<RouteWithKey<K>>
+    where
+        K: Clone,
+    {
+        state.iter().map(|((route, key), tat)| (self.route_key(key.clone(), route.clone()), tat)).collect()
+    }
+
+    /// Get the effective route table of this layer, with the registered routes, their quotas and
+    /// the fallback settings, such as to log the configuration at startup or show it from a debug endpoint.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    /// use axum_gcra::{gcra::Quota, RateLimitLayer, Route};
+    ///
+    /// let layer = RateLimitLayer::<()>::builder()
+    ///     .with_route(Route::post("/login"), Quota::simple(Duration::from_secs(5)))
+    ///     .build();
+    ///
+    /// println!("rate limits:\n{}", layer.route_table());
+    /// ```
+    #[must_use]
+    pub fn route_table(&self) -> RouteTable {
+        let builder = &self.builder;
+
+        let mut routes: Vec<_> = builder.routes.iter().map(|(route, r)| (route.clone(), r.quota)).collect();
+        routes.sort_unstable_by(|(a, _), (b, _)| (&a.path, a.method.as_str()).cmp(&(&b.path, b.method.as_str())));
+
+        RouteTable {
+            routes,
+            default_quota: builder.default_quota,
+            class_quotas: builder.class_quotas,
+            global_fallback: builder.global_fallback,
+            prefix_fallback: builder.prefix_fallback,
+            fallback_exemptions: builder.fallback_exemptions.clone(),
+        }
+    }
+
+    /// Take a [snapshot](config::RateLimitConfig) of the configuration of this layer, see
+    /// [`RateLimitLayerBuilder::config`].
+    ///
+    /// Unlike [`RateLimitLayer::route_table`], this is the configuration as given to the builder,
+    /// such as to detect configuration drift between instances with [`config::RateLimitConfig::diff`].
+    #[must_use]
+    pub fn config(&self) -> config::RateLimitConfig {
+        self.builder.config()
+    }
+
+    /// Get the shared [`RateLimitState`] used by this layer, which can be given to other
+    /// builders with [`RateLimitLayerBuilder::with_state`] to share the same entries.
+    #[must_use]
+    pub fn state(&self) -> RateLimitState<K, H> {
+        RateLimitState {
+            limiter: self.limiter.clone(),
+        }
+    }
+
+    /// Get the table of banned keys, if [enabled](RateLimitLayerBuilder::ban_after).
+    #[inline]
+    #[must_use]
+    pub fn bans(&self) -> Option<&ban::Bans<K>> {
+        self.builder.bans.as_ref()
+    }
+
+    /// Get the table of [violation counters](RateLimitLayerBuilder::with_violation_counters), if enabled.
+    #[inline]
+    #[must_use]
+    pub fn violations(&self) -> Option<&violations::Violations<K>> {
+        self.builder.violations.as_ref()
+    }
+
+    /// Subscribe to the [event stream](RateLimitLayerBuilder::with_event_stream) of rejected requests, if enabled.
+    #[cfg(feature = "tokio")]
+    #[must_use]
+    pub fn subscribe(&self) -> Option<tokio::sync::broadcast::Receiver<events::RejectionEvent>> {
+        self.builder.events.as_ref().map(events::Events::subscribe)
+    }
+
+    /// Get up to `k` keys that consumed the most quota over roughly the last one to two
+    /// [windows](RateLimitLayerBuilder::with_heavy_hitters), sorted by decreasing count.
+    ///
+    /// Returns an empty list if heavy hitter tracking is not enabled.
+    #[must_use]
+    pub fn heavy_hitters(&self, k: usize) -> Vec<heavy_hitters::HeavyHitter> {
+        match self.builder.heavy_hitters {
+            Some(ref hitters) => hitters.top(k, self.now()),
+            None => Vec::new(),
+        }
+    }
+
+    /// Get the [deny list](RateLimitLayerBuilder::with_deny_list), if any.
+    #[inline]
+    #[must_use]
+    pub fn deny_list(&self) -> Option<&deny::DenyList<K>> {
+        self.builder.deny_list.as_ref()
+    }
+
+    /// Allow the given key to skip rate limiting entirely from now on,
+    /// see [`RateLimitLayerBuilder::add_allowed_keys`].
+    pub fn allow_key(&self, key: &K) {
+        self.builder.allow_list.add_key(key);
+    }
+
+    /// Stop allowing the given key to skip rate limiting, returning `true` if it was allowed.
+    pub fn remove_allowed_key(&self, key: &K) -> bool {
+        self.builder.allow_list.remove_key(key)
+    }
+
+    /// Allow all client IPs within the given network to skip rate limiting entirely from now on,
+    /// see [`RateLimitLayerBuilder::add_allowed_networks`].
+    #[cfg(feature = "real_ip")]
+    pub fn allow_network(&self, network: real_ip::IpNetwork) {
+        self.builder.allow_list.add_network(network);
+    }
+
+    /// Stop allowing the given network to skip rate limiting, returning `true` if it was allowed.
+    ///
+    /// This only removes the exact network, not any other networks overlapping it.
+    #[cfg(feature = "real_ip")]
+    pub fn remove_allowed_network(&self, network: real_ip::IpNetwork) -> bool {
+        self.builder.allow_list.remove_network(network)
+    }
+
+    /// List all networks allowed to skip rate limiting.
+    #[cfg(feature = "real_ip")]
+    #[must_use]
+    pub fn allowed_networks(&self) -> Vec<real_ip::IpNetwork> {
+        self.builder.allow_list.networks()
+    }
+
+    /// Get the percentage of keys whose rate limit rejections are enforced,
+    /// see [`RateLimitLayerBuilder::with_enforcement`].
+    #[must_use]
+    pub fn enforcement(&self) -> u8 {
+        self.builder.enforcement.load(Ordering::Relaxed)
+    }
+
+    /// Change the percentage of keys whose rate limit rejections are enforced from now on,
+    /// affecting all clones of this layer, see [`RateLimitLayerBuilder::with_enforcement`].
+    pub fn set_enforcement(&self, percent: u8) {
+        self.builder.enforcement.store(percent.min(100), Ordering::Relaxed);
+    }
+
+    /// Get the multiplier of all quotas, see [`RateLimitLayer::set_scale`].
+    #[must_use]
+    pub fn scale(&self) -> f32 {
+        f32::from_bits(self.builder.scale.load(Ordering::Relaxed))
+    }
+
+    /// Multiply all quotas by the given factor from now on, affecting all clones of this layer,
+    /// such as `0.5` during a brownout of a dependency or `2.0` during a sale. The default is `1.0`.
+    ///
+    /// This applies to the quotas of routes, keys, the default quota, [resolved quotas](RateLimitLayerBuilder::with_quota_resolver),
+    /// and the [server-wide](RateLimitLayerBuilder::with_server_quota) and [shared](RateLimitLayerBuilder::with_shared_quota)
+    /// quotas alike, without rebuilding the layer or resetting any buckets. See [`gcra::Quota::scaled`] for how
+    /// quotas are scaled. Factors that are not positive and finite are ignored.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use axum_gcra::{RateLimitLayer, real_ip::RealIp};
+    ///
+    /// let layer = RateLimitLayer::<RealIp>::builder().build();
+    ///
+    /// // the database is struggling, so halve all limits until it recovers
+    /// layer.set_scale(0.5);
+    /// ```
+    pub fn set_scale(&self, factor: f32) {
+        if factor.is_finite() && factor > 0.0 {
+            self.builder.scale.store(factor.to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    /// Apply the [scale](RateLimitLayer::set_scale) to the given quota.
+    #[inline]
+    fn scaled(&self, quota: gcra::Quota) -> gcra::Quota {
+        match self.builder.scale.load(Ordering::Relaxed) {
+            bits if bits == 1f32.to_bits() => quota,
+            bits => quota.scaled(f32::from_bits(bits)),
+        }
+    }
+
+    /// Returns `false` if enforcement is disabled, see [`RateLimitLayerBuilder::with_enabled_flag`].
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.builder.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Enable or disable enforcement instantly for all clones of this layer,
+    /// see [`RateLimitLayerBuilder::with_enabled_flag`].
+    ///
+    /// Disabling turns off rate limits and [bans](RateLimitLayerBuilder::ban_after), but not
+    /// the [deny list](RateLimitLayerBuilder::with_deny_list), whose entries are always rejected.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use axum_gcra::axum;
+    /// use std::time::Duration;
+    /// use axum::{body::Body, routing::get, Router};
+    /// use axum_gcra::{deny::DenyList, real_ip::RealIp, RateLimitLayer};
+    /// use http::{Request, StatusCode};
+    /// use tower::ServiceExt;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")] async fn main() {
+    /// let deny = DenyList::<RealIp>::new();
+    /// deny.deny(&RealIp([203, 0, 113, 7].into()), None);
+    ///
+    /// let layer = RateLimitLayer::<RealIp>::builder()
+    ///     .ban_after(1, Duration::from_secs(60), Duration::from_secs(3600))
+    ///     .with_deny_list(deny)
+    ///     .build();
+    ///
+    /// layer.bans().unwrap().ban(&RealIp([198, 51, 100, 1].into()), Duration::from_secs(3600));
+    /// layer.set_enabled(false);
+    ///
+    /// let app = Router::new().route("/", get(|| async {})).route_layer(layer.clone().default_handle_error());
+    ///
+    /// let status = |ip: &'static str| {
+    ///     let req = Request::get("/").header("x-real-ip", ip).body(Body::empty()).unwrap();
+    ///     let app = app.clone();
+    ///     async move { app.oneshot(req).await.unwrap().status() }
+    /// };
+    ///
+    /// // banned keys are let through while disabled, denied keys are not
+    /// assert_eq!(status("198.51.100.1").await, StatusCode::OK);
+    /// assert_ne!(status("203.0.113.7").await, StatusCode::OK);
+    ///
+    /// layer.set_enabled(true);
+    /// assert_ne!(status("198.51.100.1").await, StatusCode::OK);
+    /// # }
+    /// ```
+    pub fn set_enabled(&self, enabled: bool) {
+        self.builder.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Check if rate limit rejections of the given key are enforced.
+    fn is_enforced(&self, key: &K) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+
+        let percent = self.builder.enforcement.load(Ordering::Relaxed);
+
+        percent >= 100 || gcra::stable_hash(key) % 100 < percent as u64
+    }
+
+    /// Report a rejection that is not enforced, see [`RateLimitLayerBuilder::with_enforcement`].
+    ///
+    /// The reason is `shadow` for enforcement rollout, or `warmup` for the [grace period](RateLimitLayerBuilder::with_warmup).
+    #[cfg_attr(
+        not(any(feature = "tracing", feature = "metrics", feature = "opentelemetry")),
+        allow(unused_variables)
+    )]
+    fn not_enforced(
+        &self,
+        parts: &Parts,
+        key: &RouteWithKey<K>,
+        quota: gcra::Quota,
+        error: RateLimitError,
+        banned: bool,
+        reason: &'static str,
+    ) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            method = %key.method,
+            route = &*key.path,
+            key = ?key.key,
+            retry_after_ms = error.as_duration().as_millis() as u64,
+            banned,
+            reason,
+            "request rate limited, but not enforced",
+        );
+
+        #[cfg(feature = "metrics")]
+        metrics::throttled(key, error.as_duration(), reason);
+
+        #[cfg(feature = "opentelemetry")]
+        otel::rejected(key, error.as_duration(), reason);
+
+        if let Some(ref hook) = self.builder.decision_hook {
+            let mut decision = Decision::new(key, quota, Err(error), self.correlation_id(parts));
+            decision.banned = banned;
+            decision.enforced = false;
+            hook(&decision);
+        }
+    }
+
+    /// Get the value of the [correlation header](RateLimitLayerBuilder::with_correlation_header) of a request.
+    fn correlation_id<'p>(&self, parts: &'p Parts) -> Option<&'p str> {
+        let header = self.builder.correlation_header.as_ref()?;
+
+        parts.headers.get(header).and_then(|value| value.to_str().ok())
+    }
+
+    /// Get the decayed violation count of the given key, or zero if not counted.
+    fn violations_at(&self, key: &K, now: Instant) -> f64 {
+        self.builder.violations.as_ref().map_or(0.0, |v| v.count_at(key, now))
+    }
+
+    /// Get the quota that applies to the given internal key.
+    fn quota_for(&self, key: &RouteWithKey<K>) -> gcra::Quota {
+        let quota = match self.find_route(&key.as_route()) {
+            Some((route, _)) => route.quota,
+            None => self.default_quota(&key.method, &key.key),
+        };
+
+        self.scaled(self.key_quota(&key.key).unwrap_or(quota))
+    }
+
+    /// Get the quota of a [pre-registered key](RateLimitLayerBuilder::add_key_quotas), if any.
+    #[inline]
+    fn key_quota(&self, key: &K) -> Option<gcra::Quota> {
+        if self.builder.key_quotas.is_empty() {
+            return None;
+        }
+
+        self.builder.key_quotas.get(&gcra::stable_hash(key)).copied()
+    }
+
+    /// Get the default quota for requests of the given key with the given method,
+    /// see [`RateLimitLayerBuilder::with_default_quota_for`] and
+    /// [`RateLimitLayerBuilder::with_ipv4_default_quota`].
+    fn default_quota(&self, method: &Method, key: &K) -> gcra::Quota {
+        (self.ip_quota(key))
+            .or(self.builder.class_quotas[MethodClass::of(method) as usize])
+            .unwrap_or(self.builder.default_quota)
+    }
+
+    /// Get the default quota of IPv4 or IPv6 keys, see [`RateLimitLayerBuilder::with_ipv4_default_quota`].
+    #[cfg(feature = "real_ip")]
+    fn ip_quota(&self, key: &K) -> Option<gcra::Quota> {
+        if self.builder.ip_quotas == [None; 2] {
+            return None;
+        }
+
+        let ip = real_ip::key_ip(key)?;
+        self.builder.ip_quotas[ip.to_canonical().is_ipv6() as usize]
+    }
+
+    #[cfg(not(feature = "real_ip"))]
+    #[inline(always)]
+    fn ip_quota(&self, _key: &K) -> Option<gcra::Quota> {
+        None
+    }
+
+    /// Build the error of a rate-limited request, using the
+    /// [route error handler](RateLimitLayerBuilder::handle_error_for) of its route, if any.
+    fn rate_limited<Inner, Rejection>(&self, ctx: RateLimitContext) -> Error<Inner, Rejection> {
+        let handler = self.builder.route_errors.iter().find(|(prefix, _)| within_prefix(&ctx.path, prefix));
+
+        match handler {
+            Some((_, handler)) => Error::Handled(handler(ctx)),
+            None => Error::RateLimit(ctx),
+        }
+    }
+
+    /// Check if the given route is [exempt](RateLimitLayerBuilder::without_fallback_for) from the default quota.
+    fn is_fallback_exempt(&self, path: &str, method: &Method) -> bool {
+        let exempt = self.builder.fallback_exemptions.iter().any(|prefix| within_prefix(path, prefix));
+
+        exempt
+            && self
+                .find_route(&Route {
+                    method: Cow::Borrowed(method),
+                    path: Cow::Borrowed(path),
+                })
+                .is_none()
+    }
+
+    /// Find the registered route for the given route, walking up its path prefixes if
+    /// [prefix fallback](RateLimitLayerBuilder::with_prefix_fallback) is enabled.
+    /// Also returns whether the route matched exactly.
+    fn find_route(&self, route: &Route<'_>) -> Option<(InternedRoute, bool)> {
+        let routes = &self.builder.routes;
+
+        if let Some(found) = routes.get(route) {
+            return Some((found.clone(), true));
+        }
+
+        if !self.builder.prefix_fallback || routes.is_empty() {
+            return None;
+        }
+
+        let mut path = &*route.path;
+
+        // stop before the root, which would otherwise be a prefix of everything
+        while let Some(idx) = path.trim_end_matches('/').rfind('/').filter(|&idx| idx > 0) {
+            path = &path[..idx];
+
+            let prefix = Route {
+                method: Cow::Borrowed(&*route.method),
+                path: Cow::Borrowed(path),
+            };
+
+            if let Some(found) = routes.get(&prefix) {
+                return Some((found.clone(), false));
+            }
+        }
+
+        None
+    }
+
+    /// Take a snapshot of all entries currently in the rate limiter, such as to inspect
+    /// which keys are close to their limits from an admin endpoint.
+    ///
+    /// Note that this requires scanning the entire rate limiter table.
+    pub async fn snapshot(&self) -> Vec<EntrySnapshot> {
+        let now = self.now();
+        let mut entries = Vec::new();
+        self.limiter
+            .scan(|key, tat| {
+                let quota = self.quota_for(key);
+
+                entries.push(EntrySnapshot::new(
+                    key,
+                    tat,
+                    quota,
+                    self.limiter.status_at(tat, now, quota),
+                    self.violations_at(&key.key, now),
+                ))
+            })
+            .await;
+        entries
+    }
+
+    /// Synchronous version of [`RateLimitLayer::snapshot`].
+    pub fn snapshot_sync(&self) -> Vec<EntrySnapshot> {
+        let now = self.now();
+        let mut entries = Vec::new();
+        self.limiter.scan_sync(|key, tat| {
+            let quota = self.quota_for(key);
+
+            entries.push(EntrySnapshot::new(
+                key,
+                tat,
+                quota,
+                self.limiter.status_at(tat, now, quota),
+                self.violations_at(&key.key, now),
+            ))
+        });
+        entries
+    }
+
+    /// Get statistics about the entries and garbage collection of the in-memory rate limiter,
+    /// such as total keys, inserts, evictions and the duration of the last garbage collection run,
+    /// to export to whatever metrics system the application already uses.
+    ///
+    /// Entries held by a custom [`Store`](store::Store) are not included.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use axum_gcra::{RateLimitLayer, real_ip::RealIp};
+    ///
+    /// # fn example(layer: RateLimitLayer<RealIp>) {
+    /// let stats = layer.stats();
+    ///
+    /// println!(
+    ///     "{} keys, {} evicted, last GC took {:?}, ~{} bytes",
+    ///     stats.entries, stats.evictions, stats.last_gc_duration, stats.memory_estimate,
+    /// );
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn stats(&self) -> gcra::LimiterStats {
+        let mut stats = self.limiter.stats();
+        stats.delayed = self.builder.delay_queues.depth();
+        stats
+    }
+
+    /// Run garbage collection on the rate limiter right now, removing all expired entries,
+    /// and return statistics about how many entries were scanned and evicted.
+    ///
+    /// This can be used in addition to the regular [GC interval](RateLimitLayerBuilder::with_gc_interval),
+    /// such as to reclaim memory from an admin endpoint after an attack.
+    ///
+    /// If a custom [`Store`](store::Store) is used, it is also cleaned and its statistics are included.
+    pub async fn clean_now(&self) -> gcra::GCStats {
+        let mut stats = self.limiter.clean(self.now()).await;
+
+        if let Some(ref store) = self.builder.store {
+            if let Ok(s) = store.gc(self.unix_now()).await {
+                stats.scanned += s.scanned;
+                stats.evicted += s.evicted;
+            }
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            scanned = stats.scanned,
+            evicted = stats.evicted,
+            "manual garbage collection"
+        );
+
+        #[cfg(feature = "metrics")]
+        metrics::gc(stats, self.limiter.len());
+
+        stats
+    }
+
+    /// Synchronous version of [`RateLimitLayer::clean_now`].
+    pub fn clean_now_sync(&self) -> gcra::GCStats {
+        let stats = self.limiter.clean_sync(self.now());
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            scanned = stats.scanned,
+            evicted = stats.evicted,
+            "manual garbage collection"
+        );
+
+        #[cfg(feature = "metrics")]
+        metrics::gc(stats, self.limiter.len());
+
+        stats
+    }
+
+    /// Charge several requests at once, each of the given cost for the given key and route, only if all of them
+    /// are allowed, such as for handlers fanning out to several internal quotas of a user, their organization
+    /// and a global key.
+    ///
+    /// Each request is charged against the quota of its route and key, as for requests to the layer. If any is
+    /// rejected, the requests already charged are refunded, so either all or none of them are charged, and the
+    /// first rate limit error is returned. Concurrent requests may briefly observe the charges of a rejected batch.
+    ///
+    /// The outer result is the error of the custom [`Store`](store::Store), if any.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use axum_gcra::{RateLimitLayer, Route};
+    ///
+    /// # async fn example(layer: RateLimitLayer<String>) {
+    /// let batch = [
+    ///     ("user:42".to_owned(), Route::post("/export"), 1),
+    ///     ("org:7".to_owned(), Route::post("/export"), 1),
+    ///     ("global".to_owned(), Route::post("/export"), 5),
+    /// ];
+    ///
+    /// if let Ok(Err(e)) = layer.check_many(&batch).await {
+    ///     println!("export rate limited, retry in {:?}", e.as_duration());
+    /// }
+    /// # }
+    /// ```
+    pub async fn check_many(
+        &self,
+        requests: &[(K, Route<'_>, u64)],
+    ) -> Result<Result<(), RateLimitError>, store::StoreError>
+    where
+        K: Clone,
+    {
+        let batch = self.batch_keys(requests);
+
+        let Some(ref store) = self.builder.store else {
+            let now = self.now();
+
+            for (i, &(ref key, quota, n)) in batch.iter().enumerate() {
+                if let Err(e) = self.limiter.req_n(key.clone(), quota, n, now).await {
+                    for &(ref key, quota, n) in &batch[..i] {
+                        self.limiter.refund(key, batch_cost(quota, n)).await;
+                    }
+
+                    return Ok(Err(e));
+                }
+            }
+
+            return Ok(Ok(()));
+        };
+
+        let now = self.unix_now();
+
+        for (i, &(ref key, quota, n)) in batch.iter().enumerate() {
+            let res = store.get_update(store::key_of(key), quota, n, now).await;
+
+            if !matches!(res, Ok(Ok(_))) {
+                for &(ref key, quota, n) in &batch[..i] {
+                    let delta = batch_cost(quota, n).as_nanos().min(i64::MAX as u128) as i64;
+                    _ = store.adjust(store::key_of(key), -delta).await;
+                }
+
+                return res.map(|res| res.map(|_| ()));
+            }
+        }
+
+        Ok(Ok(()))
+    }
+
+    /// Synchronous version of [`RateLimitLayer::check_many`] for the in-memory rate limiter,
+    /// which ignores any custom [`Store`](store::Store).
+    pub fn check_many_sync(&self, requests: &[(K, Route<'_>, u64)]) -> Result<(), RateLimitError>
+    where
+        K: Clone,
+    {
+        let batch = self.batch_keys(requests);
+        let now = self.now();
+
+        for (i, &(ref key, quota, n)) in batch.iter().enumerate() {
+            if let Err(e) = self.limiter.req_n_sync(key.clone(), quota, n, now) {
+                for &(ref key, quota, n) in &batch[..i] {
+                    self.limiter.refund_sync(key, batch_cost(quota, n));
+                }
+
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Build the internal keys and quotas of a batch for [`RateLimitLayer::check_many`], skipping free requests.
+    fn batch_keys(&self, requests: &[(K, Route<'_>, u64)]) -> Vec<(RouteWithKey<K>, gcra::Quota, u64)>
+    where
+        K: Clone,
+    {
+        let mut batch = Vec::with_capacity(requests.len());
+
+        for (key, route, n) in requests.iter().filter(|&&(_, _, n)| n > 0) {
+            let mut key = RouteWithKey {
+                path: MatchedPath::Static(Cow::Owned(route.path.as_ref().to_owned())),
+                method: route.method.as_ref().clone(),
+                key: self.normalize(key.clone()),
+            };
+
+            let quota = self.resolve_quota(&mut key);
+            batch.push((key, quota, *n));
+        }
+
+        batch
+    }
+
+    /// Export all unexpired entries of the in-memory rate limiter as a [`MergeableState`](gcra::MergeableState),
+    /// keyed by route and key, such as to persist them or replicate them to other instances.
+    ///
+    /// Entries of the [global fallback](RateLimitLayerBuilder::with_global_fallback) rate limiter have an empty path.
+    pub async fn export_state(&self) -> gcra::MergeableState<(Route<'static>, K)>
+    where
+        K: Clone,
+    {
+        self.limiter.export_state().await.map_keys(RouteWithKey::into_route_key)
+    }
+
+    /// Synchronous version of [`RateLimitLayer::export_state`].
+    pub fn export_state_sync(&self) -> gcra::MergeableState<(Route<'static>, K)>
+    where
+        K: Clone,
+    {
+        self.limiter.export_state_sync().map_keys(RouteWithKey::into_route_key)
+    }
+
+    /// Merge the given state, such as one previously [exported](RateLimitLayer::export_state) by this
+    /// or another instance, into the in-memory rate limiter. See [`gcra::RateLimiter::merge`] for more information.
+    pub async fn merge_state(&self, state: &gcra::MergeableState<(Route<'static>, K)>) -> usize
+    where
+        K: Clone,
+    {
+        self.limiter.merge(&self.route_keys(state)).await
+    }
+
+    /// Synchronous version of [`RateLimitLayer::merge_state`].
+    pub fn merge_state_sync(&self, state: &gcra::MergeableState<(Route<'static>, K)>) -> usize
+    where
+        K: Clone,
+    {
+        self.limiter.merge_sync(&self.route_keys(state))
+    }
+
+    /// Export the in-memory rate limiter state with keys abstracted via their hash,
+    /// such as to ship it to another instance. See [`gcra::HashedState`] for more information.
+    pub async fn hashed_state(&self) -> gcra::HashedState {
+        self.limiter.hashed_state().await
+    }
+
+    /// Synchronous version of [`RateLimitLayer::hashed_state`].
+    pub fn hashed_state_sync(&self) -> gcra::HashedState {
+        self.limiter.hashed_state_sync()
+    }
+
+    /// Restore the in-memory rate limiter state from a [`gcra::HashedState`], such as one exported by
+    /// another instance. See [`gcra::RateLimiter::restore`] for more information.
+    pub fn restore(&self, state: &gcra::HashedState) -> usize {
+        self.limiter.restore(state)
+    }
+
+    /// Save the in-memory rate limiter state to the given file right now.
+    /// See [`gcra::RateLimiter::save_to`] for more information.
+    pub fn save_to(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        self.limiter.save_to(path)
+    }
+
+    /// Restore the in-memory rate limiter state from the given file.
+    /// See [`gcra::RateLimiter::load_from`] for more information.
+    pub fn load_from(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<usize> {
+        self.limiter.load_from(path)
+    }
+
+    /// Reset the rate limit for the given key on all routes, returning the number of entries removed.
+    ///
+    /// This requires scanning the entire rate limiter table, so prefer
+    /// [`RateLimitLayer::reset_route`] if the route is known.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use axum_gcra::axum;
+    /// use axum::Router;
+    /// use axum_gcra::{RateLimitLayer, real_ip::RealIp};
+    ///
+    /// let layer = RateLimitLayer::<RealIp>::builder().build();
+    ///
+    /// // keep a handle to the shared limiter
+    /// let handle = layer.clone();
+    ///
+    /// let app = Router::<()>::new().route_layer(layer.clone().default_handle_error());
+    ///
+    /// # async {
+    /// // later, e.g. from an admin endpoint
+    /// handle.reset(&RealIp("127.0.0.1".parse().unwrap())).await;
+    /// # };
+    /// ```
+    pub async fn reset(&self, key: &K) -> usize {
+        self.limiter.remove_where(|k| k.key == *key).await
+    }
+
+    /// Synchronous version of [`RateLimitLayer::reset`].
+    pub fn reset_sync(&self, key: &K) -> usize {
+        self.limiter.remove_where_sync(|k| k.key == *key)
+    }
+
+    /// Reset the rate limit for the given key on the given route, returning `true` if the key was found.
+    ///
+    /// If the route has no specific quota and the [global fallback](RateLimitLayerBuilder::with_global_fallback)
+    /// is enabled, this resets the key's shared fallback entry.
+    pub async fn reset_route(&self, key: K, route: impl Into<Route<'static>>) -> bool {
+        let key = self.route_key(key, route.into());
+
+        if let Some(ref store) = self.builder.store {
+            return store.remove(store::key_of(&key)).await.unwrap_or(false);
+        }
+
+        self.limiter.reset(&key).await
+    }
+
+    /// Synchronous version of [`RateLimitLayer::reset_route`].
+    pub fn reset_route_sync(&self, key: K, route: impl Into<Route<'static>>) -> bool {
+        self.limiter.reset_sync(&self.route_key(key, route.into()))
+    }
+
+    async fn req_peek_key<F, R>(
+        &self,
+        mut key: RouteWithKey<K>,
+        dynamic: Option<gcra::Quota>,
+        now: Instant,
+        delayable: bool,
+        peek: F,
+    ) -> Result<R, store::StoreError>
+    where
+        F: FnOnce(&RouteWithKey<K>, gcra::Quota, Result<gcra::Admitted, RateLimitError>) -> R,
+    {
+        let quota = self.resolve_quota(&mut key);
+        let quota = dynamic.map_or(quota, |dynamic| self.scaled(dynamic));
+        let check = if delayable { self.delay_quota(quota) } else { quota };
+
+        if let Err(error) = self.check_bandwidth(&key.key, now) {
+            return Ok(peek(&key, quota, Err(error)));
+        }
+
+        if let Some(ref store) = self.builder.store {
+            let unix_now = self.unix_now();
+
+            match store.get_update(store::key_of(&key), check, 1, unix_now).await {
+                Ok(res) => return Ok(peek(&key, quota, res.map(|tat| gcra::Admitted::new(tat, unix_now)))),
+                Err(e) => {
+                    let policy = self.builder.failure_policy;
+
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(
+                        error = %e,
+                        key = ?key.key,
+                        policy = policy.as_str(),
+                        "rate limiter store failed",
+                    );
+
+                    #[cfg(feature = "metrics")]
+                    metrics::store_failure(&key, policy.as_str());
+
+                    match policy {
+                        store::FailurePolicy::Local => {}
+                        store::FailurePolicy::Closed(status) => return Err(e.with_status(status)),
+                        store::FailurePolicy::Open => {
+                            // the state of the key is unknown, so allow it as if it were its first request
+                            let res = gcra::decide(None, unix_now, check, 1)
+                                .map(|tat| gcra::Admitted::new(tat, unix_now));
+                            return Ok(peek(&key, quota, res));
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(self.limiter.req_peek_key(key, check, now, |key, res| peek(key, quota, res)).await)
+    }
+
+    /// Synchronous version of [`RateLimitLayer::req_peek_key`] for the in-memory rate limiter,
+    /// which must not be used when a custom store is configured.
+    fn req_peek_key_sync<F, R>(&self, mut key: RouteWithKey<K>, now: Instant, delayable: bool, peek: F) -> R
+    where
+        F: FnOnce(&RouteWithKey<K>, gcra::Quota, Result<gcra::Admitted, RateLimitError>) -> R,
+    {
+        let quota = self.resolve_quota(&mut key);
+        let check = if delayable { self.delay_quota(quota) } else { quota };
+
+        if let Err(error) = self.check_bandwidth(&key.key, now) {
+            return peek(&key, quota, Err(error));
+        }
+
+        self.limiter.req_peek_key_sync(key, check, now, |key, res| peek(key, quota, res))
+    }
+
+    /// Check the [bandwidth limit](RateLimitLayerBuilder::with_bandwidth_limit) of the given key, if any,
+    /// before its request is charged, so that requests rejected by it do not count towards their own quota.
+    #[inline]
+    fn check_bandwidth(&self, key: &K, now: Instant) -> Result<(), RateLimitError> {
+        match self.builder.bandwidth {
+            Some(ref bandwidth) => bandwidth.check(key, now),
+            None => Ok(()),
+        }
+    }
+
+    /// Wait out the delay of a [delayed](RateLimitLayerBuilder::with_delay) request,
+    /// which is always zero without the `tokio` feature.
+    #[cfg_attr(not(feature = "tokio"), allow(unused_variables))]
+    async fn wait(&self, delay: Duration) {
+        #[cfg(feature = "load")]
+        let _pending = self.builder.load.pending();
+
+        #[cfg(feature = "tokio")]
+        tokio::time::sleep(delay).await;
+    }
+
+    /// Join the [delay queue](RateLimitLayerBuilder::with_delay_queue_depth) of the given key,
+    /// returning `None` if requests of the key cannot be delayed right now.
+    fn join_delay_queue(&self, key: &K) -> Option<delay::QueueSlot> {
+        self.builder.max_delay?;
+        self.builder.delay_queues.join(gcra::stable_hash(key))
+    }
+
+    /// Get the quota actually checked for requests, which tolerates the [delay](RateLimitLayerBuilder::with_delay), if any.
+    #[inline]
+    fn delay_quota(&self, quota: gcra::Quota) -> gcra::Quota {
+        match self.builder.max_delay {
+            Some(max_delay) => quota.tolerate(max_delay),
+            None => quota,
+        }
+    }
+
+    /// Get how long an allowed request must be [delayed](RateLimitLayerBuilder::with_delay) before being forwarded.
+    #[inline]
+    fn delay_for(&self, quota: gcra::Quota, res: &Result<gcra::Admitted, RateLimitError>) -> Duration {
+        match (self.builder.max_delay, res) {
+            (Some(_), Ok(admitted)) => admitted.delay(quota),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Consult the [quota resolver](RateLimitLayerBuilder::with_quota_resolver), if any,
+    /// for requests of the given key without a static quota.
+    async fn dynamic_quota(&self, parts: &Parts, key: &RouteWithKey<K>) -> Option<gcra::Quota> {
+        let resolver = self.builder.quota_resolver.as_ref()?;
+        let route = key.as_route();
+
+        if self.find_route(&route).is_some() || self.key_quota(&key.key).is_some() {
+            return None;
+        }
+
+        resolver.resolve(&route, parts, &key.key).await
+    }
+
+    /// Describe the quota rule matching the given key, for the [policy header](RateLimitLayerBuilder::with_policy_header).
+    fn policy_header(&self, key: &RouteWithKey<K>, dynamic: Option<gcra::Quota>) -> Option<http::HeaderValue> {
+        if !self.builder.policy_header {
+            return None;
+        }
+
+        let (path, quota, source) = match self.find_route(&key.as_route()) {
+            Some((route, exact)) => (route.path, route.quota, if exact { "exact" } else { "prefix" }),
+            None if self.builder.global_fallback => {
+                (Arc::from("*"), self.default_quota(&key.method, &key.key), "fallback")
+            }
+            None => (
+                Arc::from(&*key.path),
+                self.default_quota(&key.method, &key.key),
+                "default",
+            ),
+        };
+
+        let (quota, source) = match (self.key_quota(&key.key), dynamic) {
+            (Some(quota), _) => (quota, "key"),
+            (None, Some(quota)) => (quota, "resolver"),
+            (None, None) => (quota, source),
+        };
+
+        let quota = self.scaled(quota);
+
+        let policy = format!(
+            "route={path}; method={}; quota={}/{:?}; source={source}",
+            key.method,
+            quota.burst(),
+            quota.period()
+        );
+
+        http::HeaderValue::try_from(policy).ok()
+    }
+
+    /// Get the quota for the given key, switching it to the interned route or the global fallback.
+    fn resolve_quota(&self, key: &mut RouteWithKey<K>) -> gcra::Quota {
+        let quota = match self.find_route(&key.as_route()) {
+            Some((route, exact)) => {
+                // sections only share their bucket with the global fallback
+                if exact || self.builder.global_fallback {
+                    key.path = MatchedPath::Interned(route.id, route.path);
+                }
+
+                route.quota
+            }
+            None => {
+                if self.builder.global_fallback {
+                    key.path = MatchedPath::Fallback;
+                }
+
+                let quota = self.default_quota(&key.method, &key.key);
+
+                // count all methods of the same class as one, keeping the class of the method
+                if self.builder.class_buckets {
+                    key.method = match MethodClass::of(&key.method) {
+                        MethodClass::Safe => Method::GET,
+                        MethodClass::Unsafe => Method::POST,
+                    };
+                }
+
+                quota
+            }
+        };
+
+        self.scaled(self.key_quota(&key.key).unwrap_or(quota))
+    }
+
+    /// Reward the client that passed the given [challenge](RateLimitLayerBuilder::with_challenge)
+    /// by resetting or loosening the rate limit of its key for the challenged route.
+    ///
+    /// Returns `false` if the token is unknown, already used, or expired.
+    pub async fn solve_challenge(&self, token: &str, reward: challenge::ChallengeReward) -> bool {
+        let Some(key) = self.builder.challenges.as_ref().and_then(|pending| pending.take(token)) else {
+            return false;
+        };
+
+        let store = self.builder.store.as_ref();
+
+        match reward {
+            challenge::ChallengeReward::Reset => match store {
+                Some(store) => _ = store.remove(store::key_of(&key)).await,
+                None => _ = self.limiter.reset(&key).await,
+            },
+            challenge::ChallengeReward::Refund(amount) => match store {
+                Some(store) => {
+                    let delta = amount.as_nanos().min(i64::MAX as u128) as i64;
+                    _ = store.adjust(store::key_of(&key), -delta).await;
+                }
+                None => _ = self.limiter.refund(&key, amount).await,
+            },
+        }
+
+        true
+    }
+
+    /// Apply the [status penalty](RateLimitLayerBuilder::with_status_penalty) for the response to an allowed request, if any.
+    fn penalize_status(
+        &self,
+        key: RouteWithKey<K>,
+        quota: gcra::Quota,
+        status: http::StatusCode,
+    ) -> Option<BoxFuture<'static, ()>> {
+        let &(_, cost) = self.builder.status_penalties.iter().find(|&&(s, _)| s == status)?;
+
+        let penalty = quota.emission_interval().saturating_mul(cost.min(u32::MAX as u64) as u32);
+
+        if let Some(ref hitters) = self.builder.heavy_hitters {
+            hitters.record(&key.key, cost, self.now());
+        }
+
+        if let Some(ref store) = self.builder.store {
+            let store = store.clone();
+            let delta = penalty.as_nanos().min(i64::MAX as u128) as i64;
+
+            return Some(Box::pin(async move {
+                _ = store.adjust(store::key_of(&key), delta).await;
+            }));
+        }
+
+        self.limiter.penalize_sync(&key, penalty);
+
+        None
+    }
+
+    /// Refund an allowed request that was [cancelled](RateLimitLayerBuilder::with_cancel_refund) before its response.
+    fn refund_cancelled(&self, key: RouteWithKey<K>, quota: gcra::Quota) {
+        let amount = quota.emission_interval();
+
+        let Some(ref store) = self.builder.store else {
+            self.limiter.refund_sync(&key, amount);
+            return;
+        };
+
+        #[cfg(feature = "tokio")]
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let store = store.clone();
+            let delta = amount.as_nanos().min(i64::MAX as u128) as i64;
+
+            runtime.spawn(async move {
+                _ = store.adjust(store::key_of(&key), -delta).await;
+            });
+        }
+
+        #[cfg(not(feature = "tokio"))]
+        let _ = store;
+    }
+
+    /// Check if the key of a request is [denied](RateLimitLayerBuilder::with_deny_list) or
+    /// [banned](RateLimitLayerBuilder::ban_after), returning the rejection context if so.
+    #[allow(clippy::result_large_err)]
+    fn check_ban(&self, parts: &Parts, key: &mut RouteWithKey<K>, now: Instant) -> Result<(), RateLimitContext> {
+        let denied = self.builder.deny_list.as_ref().and_then(|list| list.check(&key.key, parts, now));
+
+        let (error, denied) = match denied {
+            Some(error) => (error, true),
+            None => match self.builder.bans.as_ref().and_then(|bans| bans.check(&key.key, now)) {
+                Some(error) => (error, false),
+                None => return Ok(()),
+            },
+        };
+
+        let quota = self.resolve_quota(key);
+
+        if !denied && !self.is_enabled() {
+            self.not_enforced(parts, key, quota, error, true, "shadow");
+            return Ok(());
+        }
+
+        #[cfg(feature = "load")]
+        self.builder.load.record(true, now);
+
+        let mut ctx = RateLimitContext::new(
+            error,
+            key,
+            quota,
+            parts,
+            self.correlation_id(parts),
+            &self.builder.rejection,
+        );
+        ctx.banned = true;
+        ctx.violations = self.violations_at(&key.key, now);
+        ctx.denied = denied;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            method = %key.method,
+            route = &*key.path,
+            key = ?key.key,
+            retry_after_ms = error.as_duration().as_millis() as u64,
+            denied,
+            "request rejected by ban",
+        );
+
+        #[cfg(feature = "metrics")]
+        metrics::throttled(key, error.as_duration(), if denied { "denied" } else { "banned" });
+
+        #[cfg(feature = "opentelemetry")]
+        otel::rejected(key, error.as_duration(), if denied { "denied" } else { "banned" });
+
+        if let Some(ref hook) = self.builder.decision_hook {
+            let mut decision = Decision::new(key, quota, Err(error), self.correlation_id(parts));
+            decision.banned = true;
+            decision.denied = denied;
+            hook(&decision);
+        }
+
+        #[cfg(feature = "tokio")]
+        if let Some(ref events) = self.builder.events {
+            events.emit(&ctx, None);
+        }
+
+        Err(ctx)
+    }
+
+    /// Check the [shared quota](RateLimitLayerBuilder::with_shared_quota) of the route of the given key, if any,
+    /// returning the bucket to charge once the request is allowed, or the rejection context if exceeded.
+    #[allow(clippy::result_large_err)]
+    fn check_shared_quota(
+        &self,
+        parts: &Parts,
+        key: &RouteWithKey<K>,
+        now: Instant,
+    ) -> Result<Option<SharedBucket>, RateLimitContext> {
+        let Some(ref shared) = self.builder.shared_quotas else {
+            return Ok(None);
+        };
+
+        let Some(&group) = shared.routes.get(&key.as_route()) else {
+            return Ok(None);
+        };
+
+        let quota = self.scaled(shared.quotas[group]);
+        let bucket = (group, gcra::stable_hash(&key.key));
+
+        // only peek for now, as the request may still be rejected by its own quota
+        let Err(error) = shared.limiter.check_sync(&bucket, quota, now) else {
+            return Ok(Some(bucket));
+        };
+
+        if !self.is_enabled() {
+            self.not_enforced(parts, key, quota, error, false, "shadow");
+            return Ok(None);
+        }
+
+        #[cfg(feature = "load")]
+        self.builder.load.record(true, now);
+
+        let mut ctx = RateLimitContext::new(
+            error,
+            key,
+            quota,
+            parts,
+            self.correlation_id(parts),
+            &self.builder.rejection,
+        );
+        ctx.shared_limited = true;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            method = %key.method,
+            route = &*key.path,
+            key = ?key.key,
+            retry_after_ms = error.as_duration().as_millis() as u64,
+            "request rejected by shared quota",
+        );
+
+        #[cfg(feature = "metrics")]
+        metrics::throttled(key, error.as_duration(), "shared");
+
+        #[cfg(feature = "opentelemetry")]
+        otel::rejected(key, error.as_duration(), "shared");
+
+        if let Some(ref hook) = self.builder.decision_hook {
+            hook(&Decision::new(key, quota, Err(error), self.correlation_id(parts)));
+        }
+
+        #[cfg(feature = "tokio")]
+        if let Some(ref events) = self.builder.events {
+            events.emit(&ctx, None);
+        }
+
+        Err(ctx)
+    }
+
+    /// Count an allowed request towards its [shared quota](RateLimitLayerBuilder::with_shared_quota), if any.
+    fn charge_shared_quota(&self, bucket: Option<SharedBucket>, now: Instant) {
+        if let (Some(shared), Some(bucket)) = (&self.builder.shared_quotas, bucket) {
+            // may fail if concurrent requests used up the quota since it was checked, which is tolerated
+            _ = shared.limiter.req_sync(bucket, self.scaled(shared.quotas[bucket.0]), now);
+        }
+    }
+
+    /// Check the quotas of the [parent keys](RateLimitLayerBuilder::with_parent_key) of the given key, if any,
+    /// returning the rejection context if any is exceeded. Allowed requests are charged with [`RateLimitLayer::charge_parent_keys`].
+    #[allow(clippy::result_large_err)]
+    fn check_parent_keys(
+        &self,
+        parts: &Parts,
+        key: &RouteWithKey<K>,
+        now: Instant,
+    ) -> Result<(), RateLimitContext> {
+        let Some(ref parents) = self.builder.parent_keys else {
+            return Ok(());
+        };
+
+        let exceeded = parents.parents.iter().enumerate().find_map(|(idx, (quota, parent))| {
+            let quota = self.scaled(*quota);
+
+            // only peek for now, as the request may still be rejected by its own quota
+            match parents.limiter.check_sync(&(idx, parent(&key.key)?), quota, now) {
+                Ok(()) => None,
+                Err(error) => Some((quota, error)),
+            }
+        });
+
+        let Some((quota, error)) = exceeded else {
+            return Ok(());
+        };
+
+        if !self.is_enabled() {
+            self.not_enforced(parts, key, quota, error, false, "shadow");
+            return Ok(());
+        }
+
+        #[cfg(feature = "load")]
+        self.builder.load.record(true, now);
+
+        let ctx = RateLimitContext::new(
+            error,
+            key,
+            quota,
+            parts,
+            self.correlation_id(parts),
+            &self.builder.rejection,
+        );
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            method = %key.method,
+            route = &*key.path,
+            key = ?key.key,
+            retry_after_ms = error.as_duration().as_millis() as u64,
+            "request rejected by parent key",
+        );
+
+        #[cfg(feature = "metrics")]
+        metrics::throttled(key, error.as_duration(), "parent");
+
+        #[cfg(feature = "opentelemetry")]
+        otel::rejected(key, error.as_duration(), "parent");
+
+        if let Some(ref hook) = self.builder.decision_hook {
+            hook(&Decision::new(key, quota, Err(error), self.correlation_id(parts)));
+        }
+
+        #[cfg(feature = "tokio")]
+        if let Some(ref events) = self.builder.events {
+            events.emit(&ctx, None);
+        }
+
+        Err(ctx)
+    }
+
+    /// Count an allowed request towards the quotas of the [parent keys](RateLimitLayerBuilder::with_parent_key) of its key.
+    fn charge_parent_keys(&self, key: &K, now: Instant) {
+        let Some(ref parents) = self.builder.parent_keys else {
+            return;
+        };
+
+        for (idx, (quota, parent)) in parents.parents.iter().enumerate() {
+            if let Some(hash) = parent(key) {
+                // may fail if concurrent requests used up the quota since it was checked, which is tolerated
+                _ = parents.limiter.req_sync((idx, hash), self.scaled(*quota), now);
+            }
+        }
+    }
+
+    /// Check the [server-wide quota](RateLimitLayerBuilder::with_server_quota), if any, returning the
+    /// rejection context if exceeded. Allowed requests are charged with [`RateLimitLayer::charge_server_quota`].
+    #[allow(clippy::result_large_err)]
+    fn check_server_quota(
+        &self,
+        parts: &Parts,
+        key: &RouteWithKey<K>,
+        now: Instant,
+    ) -> Result<(), RateLimitContext> {
+        let Some((quota, ref limiter)) = self.builder.server_quota else {
+            return Ok(());
+        };
+
+        let quota = self.scaled(quota);
+
+        // only peek for now, as the request may still be rejected by its own quota
+        let Err(error) = limiter.check_sync(&(), quota, now) else {
+            return Ok(());
+        };
+
+        if !self.is_enabled() {
+            self.not_enforced(parts, key, quota, error, false, "shadow");
+            return Ok(());
+        }
+
+        #[cfg(feature = "load")]
+        self.builder.load.record(true, now);
+
+        let mut ctx = RateLimitContext::new(
+            error,
+            key,
+            quota,
+            parts,
+            self.correlation_id(parts),
+            &self.builder.rejection,
+        );
+        ctx.server_limited = true;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            method = %key.method,
+            route = &*key.path,
+            key = ?key.key,
+            retry_after_ms = error.as_duration().as_millis() as u64,
+            "request rejected by server-wide quota",
+        );
+
+        #[cfg(feature = "metrics")]
+        metrics::throttled(key, error.as_duration(), "server");
+
+        #[cfg(feature = "opentelemetry")]
+        otel::rejected(key, error.as_duration(), "server");
+
+        if let Some(ref hook) = self.builder.decision_hook {
+            let mut decision = Decision::new(key, quota, Err(error), self.correlation_id(parts));
+            decision.server_limited = true;
+            hook(&decision);
+        }
+
+        #[cfg(feature = "tokio")]
+        if let Some(ref events) = self.builder.events {
+            events.emit(&ctx, None);
+        }
+
+        Err(ctx)
+    }
+
+    /// Count an allowed request towards the [server-wide quota](RateLimitLayerBuilder::with_server_quota), if any.
+    fn charge_server_quota(&self, now: Instant) {
+        if let Some((quota, ref limiter)) = self.builder.server_quota {
+            // may fail if concurrent requests used up the quota since it was checked, which is tolerated
+            _ = limiter.req_sync((), self.scaled(quota), now);
+        }
+    }
+
+    /// Insert the extensions of a request forwarded to the inner service, with the given status of its quota.
+    ///
+    /// Requests that would have been rejected, but are not [enforced](Decision::is_enforced),
+    /// get the same extensions as allowed requests, with no requests remaining.
+    fn insert_extensions(
+        &self,
+        parts: &mut Parts,
+        key: &RouteWithKey<K>,
+        quota: gcra::Quota,
+        status: gcra::Status,
+        now: Instant,
+    ) where
+        H: 'static,
+    {
+        if let Some(ref bandwidth) = self.builder.bandwidth {
+            parts.extensions.insert(bandwidth.meter(&key.key));
+        }
+
+        if let Some(ref set_ext) = self.builder.set_ext {
+            // set_extension will clone the key internally
+            set_ext.set_extension(&mut parts.extensions, key, quota, self.clone());
+        }
+
+        if self.builder.set_info {
+            parts.extensions.insert(RateLimitInfo::new(
+                key,
+                quota,
+                status,
+                self.violations_at(&key.key, now),
+            ));
+        }
+
+        if let Some(ref deny_list) = self.builder.deny_list {
+            parts.extensions.insert(deny_list.clone());
+        }
+    }
+
+    /// Apply the rate limiting decision for a request to its parts, returning the rejection context if rejected.
+    fn apply_decision(
+        &self,
+        parts: &mut Parts,
+        key: &RouteWithKey<K>,
+        quota: gcra::Quota,
+        now: Instant,
+        res: Result<gcra::Admitted, RateLimitError>,
+    ) -> Result<Option<ResponseHook>, Box<Rejected>>
+    where
+        H: 'static,
+    {
+        let warming_up = self.builder.warmup.as_ref().is_some_and(|warmup| warmup.record(&key.key, now));
+
+        match res {
+            Ok(admitted) => {
+                self.insert_extensions(parts, key, quota, admitted.status(quota), now);
+
+                if let Some(ref hitters) = self.builder.heavy_hitters {
+                    hitters.record(&key.key, 1, now);
+                }
+
+                #[cfg(feature = "tracing")]
+                tracing::trace!(
+                    method = %key.method,
+                    route = &*key.path,
+                    key = ?key.key,
+                    remaining = admitted.status(quota).remaining,
+                    "request allowed",
+                );
+
+                #[cfg(feature = "metrics")]
+                metrics::allowed(key);
+
+                #[cfg(feature = "load")]
+                self.builder.load.record(false, now);
+
+                #[cfg(feature = "opentelemetry")]
+                otel::allowed(key, admitted.status(quota).remaining);
+
+                let warned = self.builder.soft_limit.is_some_and(|threshold| {
+                    let status = admitted.status(quota);
+                    (status.limit - status.remaining) as f32 >= threshold * status.limit as f32
+                });
+
+                if warned {
+                    let warning = http::HeaderValue::from_static("approaching-limit");
+                    ResponseHeaders::insert(parts, X_RATELIMIT_WARNING.clone(), warning);
+                }
+
+                if let Some(ref hook) = self.builder.decision_hook {
+                    let mut decision =
+                        Decision::new(key, quota, Ok(admitted.status(quota)), self.correlation_id(parts));
+                    decision.warned = warned;
+                    hook(&decision);
+                }
 
-/// Error wrapper for rate limiting errors or inner service errors.
-#[derive(Debug)]
-pub enum Error<Inner, Rejection> {
-    /// Inner service error.
-    ///
-    /// For most axum services, this will be a [`Infallible`].
-    Inner(Inner),
+                let hook =
+                    self.builder.track_response.as_ref().map(|track| track.track_response(key, quota, self));
 
-    /// Rate limiting error.
-    ///
-    /// This error is returned when the rate limiter has blocked the request,
-    /// and will be passed to the [error handler](RateLimitLayerBuilder::handle_error).
-    RateLimit(RateLimitError),
+                Ok(match self.builder.cancel_refund {
+                    Some(ref guard) => Some(guard.guard_refund(key, quota, self, hook)),
+                    None => hook,
+                })
+            }
+            Err(e) if warming_up => {
+                self.not_enforced(parts, key, quota, e, false, "warmup");
+                self.insert_extensions(parts, key, quota, gcra::Status::exhausted(quota, e.as_duration()), now);
 
-    /// Key extraction rejection.
-    KeyRejection(Rejection),
-}
+                #[cfg(feature = "load")]
+                self.builder.load.record(false, now);
 
-impl<Inner, Rejection> IntoResponse for Error<Inner, Rejection>
-where
-    Inner: IntoResponse,
-    Rejection: IntoResponse,
-{
-    fn into_response(self) -> Response {
-        match self {
-            Error::RateLimit(e) => e.into_response(),
-            Error::KeyRejection(e) => e.into_response(),
-            Error::Inner(e) => e.into_response(),
-        }
-    }
-}
+                Ok(None)
+            }
+            Err(e) if !self.is_enforced(&key.key) => {
+                self.not_enforced(parts, key, quota, e, false, "shadow");
+                self.insert_extensions(parts, key, quota, gcra::Status::exhausted(quota, e.as_duration()), now);
 
-use futures_util::{future::BoxFuture, TryFuture};
+                #[cfg(feature = "load")]
+                self.builder.load.record(false, now);
 
-pin_project_lite::pin_project! {
-    #[doc(hidden)]
-    #[project = RateLimitedResponseProj]
-    pub enum RateLimitedResponse<B, I: Service<Request<B>>, K: FromRequestParts<()>> {
-        RateLimiting {
-            #[pin] f: BoxFuture<'static, Result<Parts, Error<I::Error, K::Rejection>>>,
+                Ok(None)
+            }
+            Err(e) => {
+                #[cfg_attr(not(feature = "tokio"), allow(unused_variables))]
+                let violations = self.builder.bans.as_ref().map(|bans| bans.violation(&key.key, now));
 
-            inner: I, // storing `I` separately helps avoid an `I: Sync` bound
-            body: Option<B>, // similar story, helps avoid `B: Send + 'static` bound
-        },
+                let mut ctx = RateLimitContext::new(
+                    e,
+                    key,
+                    quota,
+                    parts,
+                    self.correlation_id(parts),
+                    &self.builder.rejection,
+                );
 
-        Inner { #[pin] f: I::Future },
-    }
-}
+                if let Some(ref counters) = self.builder.violations {
+                    ctx.violations = counters.record(&key.key, now);
+                }
 
-impl<B, I, K> Future for RateLimitedResponse<B, I, K>
-where
-    I: Service<Request<B>, Future: TryFuture<Ok = I::Response, Error = I::Error>>,
-    K: FromRequestParts<()>,
-{
-    type Output = Result<I::Response, Error<I::Error, K::Rejection>>;
+                #[cfg(feature = "tracing")]
+                tracing::debug!(
+                    method = %key.method,
+                    route = &*key.path,
+                    key = ?key.key,
+                    retry_after_ms = e.as_duration().as_millis() as u64,
+                    violations = ctx.violations,
+                    "request rate limited",
+                );
 
-    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        loop {
-            match self.as_mut().project() {
-                RateLimitedResponseProj::RateLimiting { inner, body, f } => match ready!(f.try_poll(cx)) {
-                    Ok(req) => {
-                        let req = Request::from_parts(req, body.take().expect("body is Some"));
-                        let f = inner.call(req);
-                        self.set(RateLimitedResponse::Inner { f })
-                    }
-                    Err(e) => return Poll::Ready(Err(e)),
-                },
-                RateLimitedResponseProj::Inner { f } => match ready!(f.try_poll(cx)) {
-                    Ok(ok) => return Poll::Ready(Ok(ok)),
-                    Err(e) => return Poll::Ready(Err(Error::Inner(e))),
-                },
+                #[cfg(feature = "metrics")]
+                metrics::throttled(key, e.as_duration(), "rate_limited");
+
+                #[cfg(feature = "load")]
+                self.builder.load.record(true, now);
+
+                #[cfg(feature = "opentelemetry")]
+                otel::rejected(key, e.as_duration(), "rate_limited");
+
+                if let Some(ref hook) = self.builder.decision_hook {
+                    hook(&Decision::new(key, quota, Err(e), self.correlation_id(parts)));
+                }
+
+                #[cfg(feature = "tokio")]
+                if let Some(ref events) = self.builder.events {
+                    events.emit(&ctx, violations);
+                }
+
+                let challenge = self.builder.challenge.as_ref().map(|c| c.issue_challenge(key, &ctx));
+
+                Err(Box::new(Rejected { ctx, challenge }))
             }
         }
     }
 }
 
-impl<K: Key, H: BuildHasher> RateLimitLayer<K, H> {
-    async fn req_peek_key<F>(
-        &self,
-        mut key: RouteWithKey<K>,
-        now: std::time::Instant,
-        peek: F,
-    ) -> Result<(), RateLimitError>
-    where
-        F: FnOnce(&RouteWithKey<K>),
-    {
-        let quota = match self.builder.quotas.get(&key.as_route()).copied() {
-            Some(quota) => quota,
-            None => {
-                if self.builder.global_fallback {
-                    key.path = MatchedPath::Fallback;
-                }
+/// Get the amount to refund for `n` requests of the given quota charged by [`RateLimitLayer::check_many`].
+#[inline]
+fn batch_cost(quota: gcra::Quota, n: u64) -> Duration {
+    quota.emission_interval().saturating_mul(n.min(u32::MAX as u64) as u32)
+}
 
-                self.builder.default_quota
-            }
-        };
+/// Headers to add to the response of an allowed request, such as the [policy header](RateLimitLayerBuilder::with_policy_header),
+/// carried in its extensions until the response.
+#[derive(Default, Clone)]
+struct ResponseHeaders(http::HeaderMap);
+
+impl ResponseHeaders {
+    /// Add the given header to the response of the request with the given parts.
+    fn insert(parts: &mut Parts, name: http::HeaderName, value: http::HeaderValue) {
+        parts.extensions.get_or_insert_default::<ResponseHeaders>().0.insert(name, value);
+    }
+}
+
+static X_RATELIMIT_POLICY: http::HeaderName = http::HeaderName::from_static("x-ratelimit-policy");
+static X_RATELIMIT_WARNING: http::HeaderName = http::HeaderName::from_static("x-ratelimit-warning");
 
-        self.limiter.req_peek_key(key, quota, now, peek).await
+/// Attach the [policy header](RateLimitLayerBuilder::with_policy_header), if any, to the decision for a request.
+fn attach_policy(
+    parts: &mut Parts,
+    mut decision: Result<Option<ResponseHook>, Box<Rejected>>,
+    policy: Option<http::HeaderValue>,
+) -> Result<Option<ResponseHook>, Box<Rejected>> {
+    if let Some(policy) = policy {
+        match decision {
+            Ok(_) => ResponseHeaders::insert(parts, X_RATELIMIT_POLICY.clone(), policy),
+            Err(ref mut rejected) => rejected.ctx.policy = Some(policy),
+        }
     }
+
+    decision
 }
 
-async fn get_user_key<K>(parts: &mut Parts) -> Result<K, K::Rejection>
+async fn get_user_key<K>(parts: &mut Parts, key_fn: Option<&KeyFn<K>>) -> Result<K, K::Rejection>
 where
     K: Key + FromRequestParts<()>,
 {
+    if let Some(key) = key_fn.and_then(|key_fn| key_fn(parts)).or_else(|| get_user_key_sync(parts)) {
+        return Ok(key);
+    }
+
+    match K::from_request_parts(parts, &()).await {
+        Ok(key) => Ok(key),
+        Err(rejection) => Err(rejection),
+    }
+}
+
+/// Extract the built-in key types that don't require the asynchronous extractor,
+/// returning `None` for any other key type.
+#[cfg_attr(not(feature = "real_ip"), allow(unused_variables))]
+fn get_user_key_sync<K: Key>(parts: &Parts) -> Option<K> {
     use core::mem::{size_of, transmute_copy};
 
     #[inline(always)]
@@ -536,7 +5552,7 @@ where
     // poor man's specialization
 
     if same_ty::<K, ()>() {
-        return Ok(unsafe { transmute_copy::<_, K>(&()) });
+        return Some(unsafe { transmute_copy::<_, K>(&()) });
     }
 
     #[cfg(feature = "real_ip")]
@@ -546,7 +5562,7 @@ where
             .or_else(|| real_ip::get_ip_from_parts(parts));
 
         if let Some(ip) = ip {
-            return Ok(unsafe { transmute_copy::<_, K>(&ip) });
+            return Some(unsafe { transmute_copy::<_, K>(&ip) });
         }
     }
 
@@ -557,19 +5573,29 @@ where
             .or_else(|| real_ip::get_ip_from_parts(parts));
 
         if let Some(ip) = ip {
-            return Ok(unsafe { transmute_copy::<_, K>(&real_ip::RealIpPrivacyMask::from(ip)) });
+            return Some(unsafe { transmute_copy::<_, K>(&real_ip::RealIpPrivacyMask::from(ip)) });
         }
     }
 
-    match K::from_request_parts(parts, &()).await {
-        Ok(key) => Ok(key),
-        Err(rejection) => Err(rejection),
+    #[cfg(feature = "tower_sessions")]
+    if same_ty::<K, session::SessionKey>() {
+        if let Some(key) = session::get_session_key_from_parts(parts) {
+            return Some(unsafe { transmute_copy::<_, K>(&key) });
+        }
     }
+
+    None
 }
 
-impl<I, K, B, H> Service<Request<B>> for RateLimitService<I, K, H>
+impl<I, K, B, H, ResBody> Service<Request<B>> for RateLimitService<I, K, H>
 where
-    I: Service<Request<B>, Future: TryFuture<Ok = I::Response, Error = I::Error>> + Clone + Send + 'static,
+    I: Service<
+            Request<B>,
+            Response = http::Response<ResBody>,
+            Future: TryFuture<Ok = I::Response, Error = I::Error>,
+        > + Clone
+        + Send
+        + 'static,
     K: Key + FromRequestParts<()>,
     H: BuildHasher + Send + Sync + 'static,
 {
@@ -578,6 +5604,14 @@ where
     type Future = RateLimitedResponse<B, I, K>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if let Some(ref store) = self.layer.builder.store {
+            if let Err(e) = ready!(store.poll_ready(cx)) {
+                if let store::FailurePolicy::Closed(status) = self.layer.builder.failure_policy {
+                    return Poll::Ready(Err(Error::Store(e.with_status(status))));
+                }
+            }
+        }
+
         match self.inner.poll_ready(cx) {
             Poll::Ready(Ok(())) => Poll::Ready(Ok(())),
             Poll::Ready(Err(e)) => Poll::Ready(Err(Error::Inner(e))),
@@ -585,109 +5619,443 @@ where
         }
     }
 
+    #[allow(clippy::result_large_err)]
     fn call(&mut self, req: Request<B>) -> Self::Future {
         // try to get the current time as close as possible to the request
-        let now = Instant::now();
+        let now = self.layer.now();
 
-        let path = match req.extensions().get::<AxumMatchedPath>() {
-            Some(path) => MatchedPath::Axum(path.clone()),
-            None => MatchedPath::Fallback,
+        let (mut parts, body) = req.into_parts();
+
+        let (path, method) = self.layer.request_route(&parts);
+
+        if self.layer.builder.allow_list.allows_client(&mut parts)
+            || (!self.layer.builder.fallback_exemptions.is_empty()
+                && self.layer.is_fallback_exempt(&path, &method))
+        {
+            return RateLimitedResponse::Inner {
+                f: self.inner.call(Request::from_parts(parts, body)),
+                hook: None,
+                headers: None,
+            };
+        }
+
+        // fast path for built-in keys and the in-memory rate limiter, which doesn't need to allocate
+        if self.layer.builder.store.is_none() && self.layer.builder.quota_resolver.is_none() {
+            let key_fn = self.layer.builder.key_fn.as_ref();
+
+            if let Some(key) = key_fn.and_then(|key_fn| key_fn(&parts)).or_else(|| get_user_key_sync(&parts)) {
+                let key = self.layer.normalize(key);
+                let mut key = RouteWithKey { key, path, method };
+
+                let layer = &self.layer;
+
+                if layer.builder.allow_list.allows_key(&key.key) {
+                    return RateLimitedResponse::Inner {
+                        f: self.inner.call(Request::from_parts(parts, body)),
+                        hook: None,
+                        headers: None,
+                    };
+                }
+
+                let checked = (layer.check_ban(&parts, &mut key, now))
+                    .and_then(|()| layer.check_server_quota(&parts, &key, now))
+                    .and_then(|()| layer.check_shared_quota(&parts, &key, now))
+                    .and_then(|shared| layer.check_parent_keys(&parts, &key, now).map(|()| shared));
+
+                let shared = match checked {
+                    Ok(shared) => shared,
+                    Err(ctx) => {
+                        return RateLimitedResponse::Rejected {
+                            error: Some(layer.rate_limited(ctx)),
+                        }
+                    }
+                };
+
+                let policy = layer.policy_header(&key, None);
+                let slot = layer.join_delay_queue(&key.key);
+
+                return match layer.req_peek_key_sync(key, now, slot.is_some(), |key, quota, res| {
+                    let delay = layer.delay_for(quota, &res);
+                    let decision = layer.apply_decision(&mut parts, key, quota, now, res);
+
+                    if decision.is_ok() {
+                        layer.charge_shared_quota(shared, now);
+                        layer.charge_server_quota(now);
+                        layer.charge_parent_keys(&key.key, now);
+                    }
+
+                    attach_policy(&mut parts, decision, policy).map(|hook| (hook, delay))
+                }) {
+                    Ok((hook, delay)) if delay.is_zero() => {
+                        let headers = parts.extensions.remove::<ResponseHeaders>().map(|h| h.0);
+
+                        RateLimitedResponse::Inner {
+                            f: self.inner.call(Request::from_parts(parts, body)),
+                            hook,
+                            headers,
+                        }
+                    }
+                    Ok((hook, delay)) => {
+                        let layer = layer.clone();
+
+                        RateLimitedResponse::RateLimiting {
+                            inner: self.inner.clone(),
+                            body: Some(body),
+                            f: Box::pin(async move {
+                                layer.wait(delay).await;
+                                drop(slot);
+                                Ok((parts, hook))
+                            }),
+                        }
+                    }
+                    Err(rejected) if rejected.challenge.is_none() => RateLimitedResponse::Rejected {
+                        error: Some(layer.rate_limited(rejected.ctx)),
+                    },
+                    Err(rejected) => {
+                        let layer = layer.clone();
+
+                        RateLimitedResponse::RateLimiting {
+                            inner: self.inner.clone(),
+                            body: Some(body),
+                            f: Box::pin(async move { Err(rejected.into_error(&layer).await) }),
+                        }
+                    }
+                };
+            }
+        }
+
+        let layer = self.layer.clone();
+
+        let f = async move {
+            #[cfg(feature = "tracing")]
+            let span = tracing::trace_span!("extract_key", method = %parts.method, route = &*path);
+
+            let key = get_user_key(&mut parts, layer.builder.key_fn.as_ref());
+
+            #[cfg(feature = "tracing")]
+            let key = tracing::Instrument::instrument(key, span);
+
+            let key = match key.await {
+                Ok(key) => key,
+                Err(rejection) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(method = %parts.method, route = &*path, "key extraction rejected");
+
+                    match layer.builder.key_fallback.as_ref().map(|f| f(&parts)) {
+                        Some(KeyFallback::Key(key)) => key,
+                        Some(KeyFallback::Respond(res)) => return Err(Error::KeyResponse(res)),
+                        Some(KeyFallback::Reject) | None => return Err(Error::KeyRejection(rejection)),
+                    }
+                }
+            };
+
+            let key = layer.normalize(key);
+            let mut key = RouteWithKey { key, path, method };
+
+            if layer.builder.allow_list.allows_key(&key.key) {
+                return Ok((parts, None));
+            }
+
+            let rate_limited = |ctx| layer.rate_limited(ctx);
+
+            layer.check_ban(&parts, &mut key, now).map_err(rate_limited)?;
+            layer.check_server_quota(&parts, &key, now).map_err(rate_limited)?;
+            let shared = layer.check_shared_quota(&parts, &key, now).map_err(rate_limited)?;
+            layer.check_parent_keys(&parts, &key, now).map_err(rate_limited)?;
+
+            let dynamic = layer.dynamic_quota(&parts, &key).await;
+            let policy = layer.policy_header(&key, dynamic);
+            let slot = layer.join_delay_queue(&key.key);
+
+            let res = layer.req_peek_key(key, dynamic, now, slot.is_some(), |key, quota, res| {
+                let delay = layer.delay_for(quota, &res);
+                let decision = layer.apply_decision(&mut parts, key, quota, now, res);
+
+                if decision.is_ok() {
+                    layer.charge_shared_quota(shared, now);
+                    layer.charge_server_quota(now);
+                    layer.charge_parent_keys(&key.key, now);
+                }
+
+                attach_policy(&mut parts, decision, policy).map(|hook| (hook, delay))
+            });
+
+            match res.await {
+                Ok(Ok((hook, delay))) => {
+                    if !delay.is_zero() {
+                        layer.wait(delay).await;
+                    }
+
+                    drop(slot);
+                    Ok((parts, hook))
+                }
+                Ok(Err(rejected)) => Err(rejected.into_error(&layer).await),
+                Err(e) => Err(Error::Store(e)),
+            }
         };
 
-        let (mut parts, body) = req.into_parts();
+        // keep the request's span current for the decision
+        #[cfg(feature = "opentelemetry")]
+        let f = opentelemetry::trace::FutureExt::with_current_context(f);
+
+        RateLimitedResponse::RateLimiting {
+            inner: self.inner.clone(),
+            body: Some(body), // once told me
+            f: Box::pin(f),
+        }
+    }
+}
+
+#[cfg(feature = "load")]
+impl<I, K: Key, H: BuildHasher> tower::load::Load for RateLimitService<I, K, H> {
+    type Metric = load::RateLimitLoad;
+
+    fn load(&self) -> Self::Metric {
+        self.layer.builder.load.load(self.layer.now())
+    }
+}
+
+impl<K, I, H> Layer<I> for RateLimitLayer<K, H>
+where
+    K: Key,
+    H: BuildHasher,
+{
+    type Service = RateLimitService<I, K, H>;
+
+    fn layer(&self, inner: I) -> Self::Service {
+        RateLimitService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+use tower::layer::util::Stack;
+
+impl<K, H: BuildHasher> RateLimitLayerBuilder<K, H>
+where
+    K: Key + FromRequestParts<()>,
+    H: Default + Clone + Send + Sync + 'static,
+{
+    /// Build the [`RateLimitLayer`].
+    ///
+    /// This will create a new rate limiter and, if the `tokio` feature is
+    /// enabled and the [GC interval](RateLimitLayerBuilder::with_gc_interval)
+    /// is a time [`Duration`], spawn a background task for garbage collection.
+    ///
+    /// By itself, `RateLimitLayer` cannot be directly inserted into an [`axum::Router`],
+    /// as it requires a [`HandleErrorLayer`] to handle rate limiting errors.
+    /// Use [`RateLimitLayerBuilder::handle_error`] or [`RateLimitLayerBuilder::default_handle_error`] to create a stack
+    /// with the rate limiter layer and the error-handler layer combined.
+    #[must_use]
+    pub fn build(mut self) -> RateLimitLayer<K, H> {
+        let limiter = self.build_limiter();
+
+        #[cfg(feature = "tokio")]
+        if let Some(gc) = self.spawn_gc(&limiter) {
+            gc.detach();
+        }
+
+        RateLimitLayer {
+            limiter,
+            builder: Arc::new(self),
+        }
+    }
+
+    /// Build the [`RateLimitLayer`] around the given existing [`RateLimitState`], rather than creating
+    /// a fresh empty table, such as state shared with another layer or pre-populated from a snapshot.
+    ///
+    /// This is the same as [`with_state`](RateLimitLayerBuilder::with_state) followed by
+    /// [`build`](RateLimitLayerBuilder::build).
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use axum_gcra::{RateLimitLayer, RateLimitState};
+    ///
+    /// let state = RateLimitState::<()>::default();
+    /// let restored = state.load_from("ratelimits.bin").unwrap_or(0);
+    ///
+    /// let layer = RateLimitLayer::builder().build_with_state(state);
+    /// ```
+    #[must_use]
+    pub fn build_with_state(self, state: RateLimitState<K, H>) -> RateLimitLayer<K, H> {
+        self.with_state(state).build()
+    }
+
+    /// Build the [`RateLimitLayer`] around the given [`Store`](store::Store), such as one pre-populated for tests
+    /// or shared with other instances. This is the same as [`with_store`](RateLimitLayerBuilder::with_store)
+    /// followed by [`build`](RateLimitLayerBuilder::build).
+    #[must_use]
+    pub fn build_with_store(self, store: impl store::Store<K>) -> RateLimitLayer<K, H> {
+        self.with_store(store).build()
+    }
+
+    /// Build the [`RateLimitLayer`], returning a [`GcHandle`] to control the background task
+    /// for garbage collection if the [GC interval](RateLimitLayerBuilder::with_gc_interval) is a time [`Duration`].
+    ///
+    /// Unlike with [`RateLimitLayerBuilder::build`], the task is aborted when the handle is dropped,
+    /// and it can be [shut down](GcHandle::shutdown) gracefully or have its [interval changed](GcHandle::set_interval).
+    #[cfg(feature = "tokio")]
+    #[must_use]
+    pub fn build_with_gc_handle(mut self) -> (RateLimitLayer<K, H>, Option<GcHandle>) {
+        let limiter = self.build_limiter();
+        let gc = self.spawn_gc(&limiter);
+
+        let layer = RateLimitLayer {
+            limiter,
+            builder: Arc::new(self),
+        };
+
+        (layer, gc)
+    }
+
+    #[cfg(feature = "tokio")]
+    fn spawn_gc(&self, limiter: &Arc<gcra::RateLimiter<RouteWithKey<K>, H>>) -> Option<GcHandle> {
+        let GCInterval::Time(d) = self.gc_interval else {
+            return None;
+        };
+
+        Some(gc::spawn(
+            limiter.clone(),
+            self.store.clone(),
+            self.clock.clone().unwrap_or_else(|| Arc::new(clock::SystemClock)),
+            self.shutdown.clone(),
+            d,
+            self.incremental_gc,
+        ))
+    }
+
+    /// Spawn the background task loading bans from the [ban store](RateLimitLayerBuilder::with_ban_store).
+    #[cfg(feature = "tokio")]
+    fn spawn_ban_sync(&mut self) {
+        let (Some(bans), Some((store, interval))) = (&self.bans, self.ban_store.take()) else {
+            return;
+        };
+
+        bans.set_store(store);
+
+        let (bans, signal) = (bans.clone(), self.shutdown.clone());
+
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(interval.max(Duration::from_millis(1)));
+
+            loop {
+                tokio::select! { biased;
+                    _ = signal.notify.notified() => break,
+                    _ = interval.tick() => {},
+                }
+
+                #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+                if let Err(e) = bans.sync().await {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(error = %e, "failed to load shared bans");
+                }
+            }
+        });
+    }
 
-        let layer = self.layer.clone();
+    /// Make the tables kept alongside the rate limiter follow the given [clock](RateLimitLayerBuilder::with_clock).
+    fn attach_clock(&self, clock: Arc<dyn clock::Clock>) {
+        if let Some(ref bans) = self.bans {
+            bans.set_clock(clock.clone());
+        }
 
-        RateLimitedResponse::RateLimiting {
-            inner: self.inner.clone(),
-            body: Some(body), // once told me
+        if let Some(ref violations) = self.violations {
+            violations.set_clock(clock.clone());
+        }
 
-            f: Box::pin(async move {
-                let key = RouteWithKey {
-                    key: get_user_key(&mut parts).await.map_err(Error::KeyRejection)?,
-                    path,
-                    method: parts.method.clone(),
-                };
+        if let Some(ref deny_list) = self.deny_list {
+            deny_list.set_clock(clock.clone());
+        }
 
-                let res = layer.req_peek_key(key, now, |key| {
-                    if let Some(ref set_ext) = layer.builder.set_ext {
-                        // set_extension will clone the key internally
-                        set_ext.set_extension(&mut parts.extensions, key, layer.clone());
-                    }
-                });
+        if let Some(ref warmup) = self.warmup {
+            warmup.set_clock(clock.clone());
+        }
 
-                match res.await {
-                    Ok(()) => Ok(parts),
-                    Err(e) => Err(Error::RateLimit(e)),
-                }
-            }),
+        if let Some(ref hitters) = self.heavy_hitters {
+            hitters.set_clock(clock.clone());
         }
+
+        #[cfg(feature = "load")]
+        self.load.set_clock(clock);
     }
-}
 
-impl<K, I, H> Layer<I> for RateLimitLayer<K, H>
-where
-    K: Key,
-    H: BuildHasher,
-{
-    type Service = RateLimitService<I, K, H>;
+    fn build_limiter(&mut self) -> Arc<gcra::RateLimiter<RouteWithKey<K>, H>> {
+        self.routes = (self.quotas.iter())
+            .map(|(route, &quota)| {
+                let interned = InternedRoute {
+                    id: gcra::stable_hash(route),
+                    path: Arc::from(&*route.path),
+                    quota,
+                };
 
-    fn layer(&self, inner: I) -> Self::Service {
-        RateLimitService {
-            inner,
-            layer: self.clone(),
+                (route.clone(), interned)
+            })
+            .collect();
+
+        if let Some(ref clock) = self.clock {
+            self.attach_clock(clock.clone());
         }
-    }
-}
 
-use tower::layer::util::Stack;
+        let limiter = match self.state.take() {
+            Some(state) => state.limiter,
+            None => {
+                let limiter = gcra::RateLimiter::with_shards(
+                    self.gc_requests.unwrap_or_else(|| self.gc_interval.to_requests()),
+                    self.shards.unwrap_or_else(gcra::default_shards),
+                    H::default(),
+                );
 
-impl<K, H: BuildHasher> RateLimitLayerBuilder<K, H>
-where
-    K: Key + FromRequestParts<()>,
-    H: Default + Send + Sync + 'static,
-{
-    /// Build the [`RateLimitLayer`].
-    ///
-    /// This will create a new rate limiter and, if the `tokio` feature is
-    /// enabled and the [GC interval](RateLimitLayerBuilder::with_gc_interval)
-    /// is a time [`Duration`], spawn a background task for garbage collection.
-    ///
-    /// By itself, `RateLimitLayer` cannot be directly inserted into an [`axum::Router`],
-    /// as it requires a [`HandleErrorLayer`] to handle rate limiting errors.
-    /// Use [`RateLimitLayerBuilder::handle_error`] or [`RateLimitLayerBuilder::default_handle_error`] to create a stack
-    /// with the rate limiter layer and the error-handler layer combined.
-    #[must_use]
-    pub fn build(self) -> RateLimitLayer<K, H> {
-        let limiter = Arc::new(gcra::RateLimiter::new(self.gc_interval.to_requests(), H::default()));
+                let mut limiter = limiter.with_overflow_policy(self.overflow);
 
-        #[cfg(feature = "tokio")]
-        if let GCInterval::Time(d) = self.gc_interval {
-            let limiter = limiter.clone();
-            let signal = self.shutdown.clone();
-
-            _ = tokio::task::spawn(async move {
-                let mut interval = tokio::time::interval(d);
-                loop {
-                    tokio::select! { biased;
-                        _ = signal.notify.notified() => break,
-                        _ = interval.tick() => {},
-                    }
+                if let Some(ref clock) = self.clock {
+                    limiter = limiter.with_start(clock.now());
+                }
 
-                    limiter.clean(Instant::now()).await;
+                if !self.route_ttls.is_empty() {
+                    let (ttls, idle) = (self.route_ttls.clone(), self.idle_timeout);
 
-                    // also close task if no more references to the limiter
-                    if Arc::strong_count(&limiter) == 1 {
-                        break;
-                    }
+                    limiter = limiter.with_idle_timeouts(move |key: &RouteWithKey<K>| {
+                        ttls.get(&key.as_route()).copied().or(idle)
+                    });
+                } else if let Some(timeout) = self.idle_timeout {
+                    limiter = limiter.with_idle_timeout(timeout);
+                }
+
+                if self.lifecycle.is_set() {
+                    limiter = limiter.with_lifecycle(std::mem::take(&mut self.lifecycle));
                 }
+
+                Arc::new(match self.max_entries {
+                    Some(max_entries) => limiter.with_max_entries(max_entries),
+                    None => limiter,
+                })
+            }
+        };
+
+        if let Some(path) = self.persist.take() {
+            _ = limiter.load_from(&path);
+
+            self.persist_on_drop = Some(PersistOnDrop {
+                path,
+                limiter: limiter.clone(),
             });
         }
 
-        RateLimitLayer {
-            limiter,
-            builder: Arc::new(self),
+        if let (Some(bans), Some(path)) = (&self.bans, self.ban_persist.take()) {
+            _ = bans.load_from(&path);
+
+            self.ban_persist_on_drop = Some(PersistBansOnDrop {
+                path,
+                bans: bans.clone(),
+            });
         }
+
+        #[cfg(feature = "tokio")]
+        self.spawn_ban_sync();
+
+        limiter
     }
 
     /// Create a new rate limiter layer with the provided error-handler callback.
@@ -698,6 +6066,7 @@ where
     /// # Example
     ///
     /// ```rust,no_run
+    /// # use axum_gcra::axum;
     /// use axum::{Router, http::StatusCode};
     /// use axum_gcra::RateLimitLayer;
     ///
@@ -713,7 +6082,48 @@ where
     where
         F: Fn(Error<Infallible, K::Rejection>) -> R + Clone,
     {
-        Stack::new(self.build(), HandleErrorLayer::new(cb))
+        self.build().handle_error(cb)
+    }
+
+    /// Create a new rate limiter layer with the provided asynchronous error-handler callback,
+    /// which returns a boxed future.
+    ///
+    /// Unlike [`RateLimitLayerBuilder::handle_error`], the callback does not need to be `Clone`,
+    /// as it's shared between all clones of the layer, which allows it to own things like database
+    /// connection pools or template engines directly.
+    ///
+    /// Returns a [`Stack`]-ed layer with the rate limiter layer and the error-handler layer combined
+    /// that can be directly inserted into an [`axum::Router`].
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use axum_gcra::axum;
+    /// use axum::{Router, http::StatusCode};
+    /// use axum_gcra::RateLimitLayer;
+    ///
+    /// let app = Router::<()>::new().route_layer(
+    ///     RateLimitLayer::<()>::builder().handle_error_async(|e| Box::pin(async move {
+    ///         // e.g. increment an abuse counter somewhere
+    ///         StatusCode::TOO_MANY_REQUESTS
+    ///     })));
+    /// ```
+    #[must_use]
+    #[allow(clippy::type_complexity)]
+    pub fn handle_error_async<F, R>(
+        self,
+        cb: F,
+    ) -> Stack<
+        RateLimitLayer<K, H>,
+        HandleErrorLayer<impl Fn(Error<Infallible, K::Rejection>) -> BoxFuture<'static, R> + Clone, ()>,
+    >
+    where
+        F: Fn(Error<Infallible, K::Rejection>) -> BoxFuture<'static, R> + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        let cb = Arc::new(cb);
+
+        self.handle_error(move |e| cb(e))
     }
 
     /// Create a new rate limiter layer with the default error-handler callback that simply returns the error
@@ -722,6 +6132,128 @@ where
     /// Returns a [`Stack`]-ed layer with the rate limiter layer and the error-handler layer combined
     /// that can be directly inserted into an [`axum::Router`].
     #[must_use]
+    #[allow(clippy::type_complexity)]
+    pub fn default_handle_error(
+        self,
+    ) -> Stack<
+        RateLimitLayer<K, H>,
+        HandleErrorLayer<impl Fn(Error<Infallible, K::Rejection>) -> Ready<Response> + Clone, ()>,
+    >
+    where
+        K::Rejection: IntoResponse,
+    {
+        self.build().default_handle_error()
+    }
+
+    /// Create a new rate limiter layer with an error-handler callback that redirects rate-limited
+    /// requests to the given URI with a `303 See Other` response, such as a static "slow down" page.
+    /// `303` is used so that browsers follow the redirect with a `GET` regardless of the original method.
+    ///
+    /// The rate limit headers (e.g. `retry-after`) are still included in the redirect response.
+    /// Key rejections are returned as-is, as with [`RateLimitLayerBuilder::default_handle_error`].
+    ///
+    /// Returns a [`Stack`]-ed layer with the rate limiter layer and the error-handler layer combined
+    /// that can be directly inserted into an [`axum::Router`].
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use axum_gcra::axum;
+    /// use axum::{Router, http::Uri};
+    /// use axum_gcra::RateLimitLayer;
+    ///
+    /// let app = Router::<()>::new().route_layer(
+    ///     RateLimitLayer::<()>::builder().reject_with_redirect(Uri::from_static("/slow-down.html")));
+    /// ```
+    #[must_use]
+    #[allow(clippy::type_complexity)]
+    pub fn reject_with_redirect(
+        self,
+        uri: http::Uri,
+    ) -> Stack<
+        RateLimitLayer<K, H>,
+        HandleErrorLayer<impl Fn(Error<Infallible, K::Rejection>) -> Ready<Response> + Clone, ()>,
+    >
+    where
+        K::Rejection: IntoResponse,
+    {
+        use http::{header::LOCATION, HeaderValue, StatusCode};
+
+        let location = HeaderValue::from_str(&uri.to_string()).expect("URIs are valid header values");
+
+        self.handle_error(move |e| {
+            core::future::ready(match e {
+                Error::RateLimit(ctx) => {
+                    let mut res = ctx.into_response();
+
+                    *res.status_mut() = StatusCode::SEE_OTHER;
+                    *res.body_mut() = Default::default();
+                    res.headers_mut().remove(http::header::CONTENT_TYPE);
+                    res.headers_mut().insert(LOCATION, location.clone());
+
+                    res
+                }
+                e => e.into_response(),
+            })
+        })
+    }
+
+    /// Create a new rate limiter layer with an error-handler callback that returns gRPC statuses,
+    /// such as for a [`tonic`] server.
+    ///
+    /// Rate-limited requests return `RESOURCE_EXHAUSTED` with a `google.rpc.RetryInfo` detail, see
+    /// [`grpc::status`] for more information. Key rejections are mapped to the closest gRPC status code
+    /// of their response, and store errors return `UNAVAILABLE`.
+    ///
+    /// Returns a [`Stack`]-ed layer with the rate limiter layer and the error-handler layer combined
+    /// that can be directly inserted into a [`tonic::transport::Server`] or [`axum::Router`].
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use axum_gcra::{RateLimitLayer, real_ip::RealIp};
+    ///
+    /// let server = tonic::transport::Server::builder()
+    ///     .layer(RateLimitLayer::<RealIp>::builder().grpc_handle_error());
+    /// ```
+    #[cfg(feature = "tonic")]
+    #[must_use]
+    #[allow(clippy::type_complexity)]
+    pub fn grpc_handle_error(
+        self,
+    ) -> Stack<
+        RateLimitLayer<K, H>,
+        HandleErrorLayer<impl Fn(Error<Infallible, K::Rejection>) -> Ready<Response> + Clone, ()>,
+    >
+    where
+        K::Rejection: IntoResponse,
+    {
+        self.handle_error(|e| core::future::ready(grpc::into_response(e)))
+    }
+}
+
+impl<K, H: BuildHasher> RateLimitLayer<K, H>
+where
+    K: Key + FromRequestParts<()>,
+{
+    /// Combine this rate limiter layer with the provided error-handler callback.
+    ///
+    /// See [`RateLimitLayerBuilder::handle_error`] for more information. This can be used to keep
+    /// a clone of the built layer around as a handle to the shared rate limiter.
+    #[must_use]
+    pub fn handle_error<F, R>(self, cb: F) -> Stack<RateLimitLayer<K, H>, HandleErrorLayer<F, ()>>
+    where
+        F: Fn(Error<Infallible, K::Rejection>) -> R + Clone,
+    {
+        Stack::new(self, HandleErrorLayer::new(cb))
+    }
+
+    /// Combine this rate limiter layer with the default error-handler callback.
+    ///
+    /// See [`RateLimitLayerBuilder::default_handle_error`] for more information. This can be used to keep
+    /// a clone of the built layer around as a handle to the shared rate limiter.
+    #[must_use]
+    #[allow(clippy::type_complexity)]
     pub fn default_handle_error(
         self,
     ) -> Stack<
@@ -740,6 +6272,8 @@ where
 pub mod extensions {
     use super::*;
 
+    use axum::extract::{rejection::ExtensionRejection, Extension};
+
     /// [`Request`] extension to access the internal rate limiter used during that request,
     /// such as to apply a penalty or reset the rate limit.
     ///
@@ -747,6 +6281,7 @@ pub mod extensions {
     /// exact same as those given to the [`RateLimitLayerBuilder`]/[`RateLimitLayer`].
     pub struct RateLimiter<K: Key = (), H: BuildHasher = RandomState> {
         pub(crate) key: RouteWithKey<K>,
+        pub(crate) quota: gcra::Quota,
         pub(crate) layer: RateLimitLayer<K, H>,
     }
 
@@ -757,6 +6292,7 @@ pub mod extensions {
         fn clone(&self) -> Self {
             Self {
                 key: self.key.clone(),
+                quota: self.quota,
                 layer: self.layer.clone(),
             }
         }
@@ -779,13 +6315,56 @@ pub mod extensions {
             &self.key.method
         }
 
-        /// Get the quota for the route that was rate limited.
+        /// Get the quota that was applied to the request, which may be the default quota.
         pub fn quota(&self) -> gcra::Quota {
-            self.layer.builder.quotas.get(&self.key.as_route()).copied().expect("no quota found for route")
+            self.quota
+        }
+
+        /// Get the current decayed number of rate limit violations of the key, if
+        /// [violation counters](crate::RateLimitLayerBuilder::with_violation_counters) are enabled, otherwise zero.
+        pub fn violations(&self) -> f64 {
+            self.layer.violations_at(&self.key.key, self.layer.now())
+        }
+
+        /// Check if another request with the same key to the same route would be allowed right now,
+        /// without consuming any quota. See [`gcra::RateLimiter::check`] for more information.
+        ///
+        /// Note that the current request has already been counted against the quota.
+        pub async fn check(&self) -> Result<(), RateLimitError> {
+            if let Some(tat) = self.store_peek().await {
+                return gcra::decide(tat, self.layer.unix_now(), self.quota, 1).map(|_| ());
+            }
+
+            self.layer.limiter.check(&self.key, self.quota, self.layer.now()).await
+        }
+
+        /// See [`gcra::RateLimiter::check_sync`] for more information.
+        pub fn check_sync(&self) -> Result<(), RateLimitError> {
+            self.layer.limiter.check_sync(&self.key, self.quota, self.layer.now())
+        }
+
+        /// Get the current [`Status`](gcra::Status) of the quota for this key and route,
+        /// without consuming any quota. See [`gcra::RateLimiter::status`] for more information.
+        pub async fn status(&self) -> gcra::Status {
+            if let Some(tat) = self.store_peek().await {
+                return gcra::Status::from_nanos(tat, self.layer.unix_now(), self.quota);
+            }
+
+            self.layer.limiter.status(&self.key, self.quota, self.layer.now()).await
+        }
+
+        /// See [`gcra::RateLimiter::status_sync`] for more information.
+        pub fn status_sync(&self) -> gcra::Status {
+            self.layer.limiter.status_sync(&self.key, self.quota, self.layer.now())
         }
 
         /// See [`gcra::RateLimiter::penalize`] for more information.
         pub async fn penalize(&self, penalty: Duration) -> bool {
+            if let Some(ref store) = self.layer.builder.store {
+                let delta = penalty.as_nanos().min(i64::MAX as u128) as i64;
+                return store.adjust(store::key_of(&self.key), delta).await.unwrap_or(false);
+            }
+
             self.layer.limiter.penalize(&self.key, penalty).await
         }
 
@@ -794,8 +6373,31 @@ pub mod extensions {
             self.layer.limiter.penalize_sync(&self.key, penalty)
         }
 
+        /// See [`gcra::RateLimiter::refund`] for more information.
+        ///
+        /// A single request costs the [emission interval](gcra::Quota::emission_interval) of the
+        /// [quota](RateLimiter::quota), so this request can be refunded with
+        /// `rl.refund(rl.quota().emission_interval())`.
+        pub async fn refund(&self, amount: Duration) -> bool {
+            if let Some(ref store) = self.layer.builder.store {
+                let delta = amount.as_nanos().min(i64::MAX as u128) as i64;
+                return store.adjust(store::key_of(&self.key), -delta).await.unwrap_or(false);
+            }
+
+            self.layer.limiter.refund(&self.key, amount).await
+        }
+
+        /// See [`gcra::RateLimiter::refund_sync`] for more information.
+        pub fn refund_sync(&self, amount: Duration) -> bool {
+            self.layer.limiter.refund_sync(&self.key, amount)
+        }
+
         /// See [`gcra::RateLimiter::reset`] for more information.
         pub async fn reset(&self) -> bool {
+            if let Some(ref store) = self.layer.builder.store {
+                return store.remove(store::key_of(&self.key)).await.unwrap_or(false);
+            }
+
             self.layer.limiter.reset(&self.key).await
         }
 
@@ -805,13 +6407,186 @@ pub mod extensions {
         }
 
         /// See [`gcra::RateLimiter::clean`] for more information.
-        pub async fn clean(&self, before: Instant) {
-            self.layer.limiter.clean(before).await;
+        pub async fn clean(&self, before: Instant) -> gcra::GCStats {
+            self.layer.limiter.clean(before).await
         }
 
         /// See [`gcra::RateLimiter::clean_sync`] for more information.
-        pub fn clean_sync(&self, before: Instant) {
-            self.layer.limiter.clean_sync(before);
+        pub fn clean_sync(&self, before: Instant) -> gcra::GCStats {
+            self.layer.limiter.clean_sync(before)
+        }
+
+        /// Create a per-connection [`MessageLimiter`](ws::MessageLimiter) with the [quota](RateLimiter::quota)
+        /// of the request, such as to throttle the messages of a WebSocket after the upgrade.
+        #[must_use]
+        pub fn message_limiter(&self) -> ws::MessageLimiter {
+            ws::MessageLimiter::new(self.quota)
+        }
+
+        /// If a custom store is used, peek at the current TAT of this entry.
+        ///
+        /// Store errors are treated as a missing entry.
+        async fn store_peek(&self) -> Option<Option<u64>> {
+            let store = self.layer.builder.store.as_ref()?;
+            Some(store.peek(store::key_of(&self.key)).await.unwrap_or(None))
+        }
+    }
+
+    /// Extractor that checks if another request with the same key to the same route
+    /// would be allowed right now, without consuming any quota.
+    ///
+    /// This requires the [`RateLimiter`] extension to be
+    /// [enabled](RateLimitLayerBuilder::with_extension), and the `K: Key` and `H: BuildHasher`
+    /// types must be the exact same as those given to the [`RateLimitLayerBuilder`]/[`RateLimitLayer`].
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use axum_gcra::axum;
+    /// use axum::{routing::get, Router};
+    /// use axum_gcra::{RateLimitLayer, extensions::RateLimitCheck};
+    ///
+    /// let app = Router::<()>::new()
+    ///     .route("/ratelimit/status", get(|check: RateLimitCheck| async move {
+    ///         format!("retry in {:?}", check.retry_after())
+    ///     }))
+    ///     .route_layer(RateLimitLayer::<()>::builder().with_extension(true).default_handle_error());
+    /// ```
+    pub struct RateLimitCheck<K: Key = (), H: BuildHasher = RandomState> {
+        result: Result<(), RateLimitError>,
+        _marker: std::marker::PhantomData<fn() -> (K, H)>,
+    }
+
+    impl<K: Key, H: BuildHasher> RateLimitCheck<K, H> {
+        /// Returns `true` if another request would be allowed.
+        #[inline]
+        #[must_use]
+        pub fn is_allowed(&self) -> bool {
+            self.result.is_ok()
+        }
+
+        /// Returns the amount of time until another request would be allowed, which is zero if allowed now.
+        #[must_use]
+        pub fn retry_after(&self) -> Duration {
+            match self.result {
+                Ok(()) => Duration::ZERO,
+                Err(e) => e.as_duration(),
+            }
+        }
+
+        /// Returns the result of the check.
+        #[inline]
+        pub fn result(&self) -> Result<(), RateLimitError> {
+            self.result
+        }
+    }
+
+    impl<K: Key, H: BuildHasher> Clone for RateLimitCheck<K, H> {
+        fn clone(&self) -> Self {
+            *self
+        }
+    }
+
+    impl<K: Key, H: BuildHasher> Copy for RateLimitCheck<K, H> {}
+
+    impl<K: Key, H: BuildHasher> fmt::Debug for RateLimitCheck<K, H> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("RateLimitCheck").field("result", &self.result).finish()
+        }
+    }
+
+    #[cfg_attr(not(feature = "axum-08"), async_trait::async_trait)]
+    impl<K, H, S> FromRequestParts<S> for RateLimitCheck<K, H>
+    where
+        K: Key + Clone,
+        H: BuildHasher + Send + Sync + 'static,
+        S: Send + Sync,
+    {
+        type Rejection = ExtensionRejection;
+
+        async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+            let Extension(rl) = Extension::<RateLimiter<K, H>>::from_request_parts(parts, state).await?;
+
+            Ok(RateLimitCheck {
+                result: rl.check().await,
+                _marker: std::marker::PhantomData,
+            })
+        }
+    }
+
+    /// Extractor that yields the current [`Status`](gcra::Status) of the quota for the key and route
+    /// of the request, such as to embed quota information in response bodies.
+    ///
+    /// This requires the [`RateLimiter`] extension to be
+    /// [enabled](RateLimitLayerBuilder::with_extension), and the `K: Key` and `H: BuildHasher`
+    /// types must be the exact same as those given to the [`RateLimitLayerBuilder`]/[`RateLimitLayer`].
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use axum_gcra::axum;
+    /// use axum::{routing::get, Router};
+    /// use axum_gcra::{RateLimitLayer, extensions::RateLimitStatus};
+    ///
+    /// let app = Router::<()>::new()
+    ///     .route("/", get(|status: RateLimitStatus| async move {
+    ///         format!("{}/{} requests remaining", status.remaining, status.limit)
+    ///     }))
+    ///     .route_layer(RateLimitLayer::<()>::builder().with_extension(true).default_handle_error());
+    /// ```
+    pub struct RateLimitStatus<K: Key = (), H: BuildHasher = RandomState> {
+        status: gcra::Status,
+        _marker: std::marker::PhantomData<fn() -> (K, H)>,
+    }
+
+    impl<K: Key, H: BuildHasher> RateLimitStatus<K, H> {
+        /// Returns the inner [`Status`](gcra::Status).
+        #[inline]
+        #[must_use]
+        pub fn into_inner(self) -> gcra::Status {
+            self.status
+        }
+    }
+
+    impl<K: Key, H: BuildHasher> Deref for RateLimitStatus<K, H> {
+        type Target = gcra::Status;
+
+        #[inline]
+        fn deref(&self) -> &Self::Target {
+            &self.status
+        }
+    }
+
+    impl<K: Key, H: BuildHasher> Clone for RateLimitStatus<K, H> {
+        fn clone(&self) -> Self {
+            *self
+        }
+    }
+
+    impl<K: Key, H: BuildHasher> Copy for RateLimitStatus<K, H> {}
+
+    impl<K: Key, H: BuildHasher> fmt::Debug for RateLimitStatus<K, H> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            fmt::Debug::fmt(&self.status, f)
+        }
+    }
+
+    #[cfg_attr(not(feature = "axum-08"), async_trait::async_trait)]
+    impl<K, H, S> FromRequestParts<S> for RateLimitStatus<K, H>
+    where
+        K: Key + Clone,
+        H: BuildHasher + Send + Sync + 'static,
+        S: Send + Sync,
+    {
+        type Rejection = ExtensionRejection;
+
+        async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+            let Extension(rl) = Extension::<RateLimiter<K, H>>::from_request_parts(parts, state).await?;
+
+            Ok(RateLimitStatus {
+                status: rl.status().await,
+                _marker: std::marker::PhantomData,
+            })
         }
     }
 }
@@ -0,0 +1,157 @@
+//! Load reporting for [`tower::load`], such as to compose with `tower::balance` and load-shedding layers.
+//!
+//! [`RateLimitService`](crate::RateLimitService) implements [`Load`](tower::load::Load) with
+//! [`RateLimitLoad`] as its metric, which is shared by all clones of a [`RateLimitLayer`](crate::RateLimitLayer).
+
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc,
+};
+
+use crate::clock::{Clock, Instant, Timeline};
+
+/// Length of the windows over which the rejection rate is measured, in nanoseconds.
+const WINDOW: u64 = 1_000_000_000;
+
+/// Load of a rate limiter, as reported by [`Load::load`](tower::load::Load::load).
+///
+/// Loads compare by the number of pending [delayed](crate::RateLimitLayerBuilder::with_delay) requests first,
+/// and then by the recent rejection rate.
+#[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd)]
+pub struct RateLimitLoad {
+    pending: usize,
+    rejection_rate: f64,
+}
+
+impl RateLimitLoad {
+    /// Get the number of [delayed](crate::RateLimitLayerBuilder::with_delay) requests currently waiting.
+    #[inline]
+    #[must_use]
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Get the fraction of requests rejected over roughly the last one to two seconds, from `0.0` to `1.0`.
+    #[inline]
+    #[must_use]
+    pub fn rejection_rate(&self) -> f64 {
+        self.rejection_rate
+    }
+}
+
+/// Counts of decisions within one window.
+#[derive(Default)]
+struct Window {
+    total: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl Window {
+    fn take(&self) -> (u64, u64) {
+        (
+            self.total.swap(0, Ordering::Relaxed),
+            self.rejected.swap(0, Ordering::Relaxed),
+        )
+    }
+
+    fn load(&self) -> (u64, u64) {
+        (
+            self.total.load(Ordering::Relaxed),
+            self.rejected.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Approximate, lock-free tracking of the load of a rate limiter.
+pub(crate) struct LoadTracker {
+    time: Timeline,
+    epoch: AtomicU64,
+    current: Window,
+    previous: Window,
+    pending: AtomicUsize,
+}
+
+/// Guard counting a delayed request as pending until dropped.
+pub(crate) struct Pending<'a>(&'a AtomicUsize);
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Default for LoadTracker {
+    fn default() -> Self {
+        LoadTracker {
+            time: Timeline::new(),
+            epoch: AtomicU64::new(0),
+            current: Window::default(),
+            previous: Window::default(),
+            pending: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl LoadTracker {
+    /// Set the clock of the layer, if not already used.
+    pub fn set_clock(&self, clock: Arc<dyn Clock>) {
+        self.time.set_clock(clock);
+    }
+
+    #[inline]
+    fn epoch(&self, now: Instant) -> u64 {
+        self.time.relative(now) / WINDOW
+    }
+
+    /// Move on to the window of the given epoch, if not already there.
+    fn rotate(&self, epoch: u64) {
+        let prev = self.epoch.load(Ordering::Relaxed);
+
+        if prev >= epoch {
+            return;
+        }
+
+        // only one thread moves the windows along
+        if self.epoch.compare_exchange(prev, epoch, Ordering::Relaxed, Ordering::Relaxed).is_err() {
+            return;
+        }
+
+        // concurrent decisions may land in either window, which is fine for a load estimate
+        let (total, rejected) = self.current.take();
+        let (total, rejected) = if epoch == prev + 1 { (total, rejected) } else { (0, 0) };
+
+        self.previous.total.store(total, Ordering::Relaxed);
+        self.previous.rejected.store(rejected, Ordering::Relaxed);
+    }
+
+    /// Record a rate limiting decision.
+    pub fn record(&self, rejected: bool, now: Instant) {
+        self.rotate(self.epoch(now));
+
+        self.current.total.fetch_add(1, Ordering::Relaxed);
+        self.current.rejected.fetch_add(rejected as u64, Ordering::Relaxed);
+    }
+
+    /// Count a delayed request as pending until the returned guard is dropped.
+    pub fn pending(&self) -> Pending<'_> {
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        Pending(&self.pending)
+    }
+
+    pub fn load(&self, now: Instant) -> RateLimitLoad {
+        self.rotate(self.epoch(now));
+
+        let (total, rejected) = self.current.load();
+        let (prev_total, prev_rejected) = self.previous.load();
+
+        let total = total + prev_total;
+
+        RateLimitLoad {
+            pending: self.pending.load(Ordering::Relaxed),
+            rejection_rate: match total {
+                0 => 0.0,
+                total => ((rejected + prev_rejected) as f64 / total as f64).min(1.0),
+            },
+        }
+    }
+}
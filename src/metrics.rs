@@ -0,0 +1,62 @@
+//! Metrics recorded through the [`metrics`](https://docs.rs/metrics) facade, such as for a Prometheus exporter.
+//!
+//! Install any `metrics` recorder, such as `metrics-exporter-prometheus`, to collect them.
+//! Per-request metrics are labelled with the `method` and `route` of the request,
+//! where the route is empty for the [global fallback](crate::RateLimitLayerBuilder::with_global_fallback).
+
+use std::time::Duration;
+
+use crate::{gcra::GCStats, Key, RouteWithKey};
+
+/// Counter of requests allowed by the rate limiter.
+pub const REQUESTS_ALLOWED: &str = "axum_gcra_requests_allowed_total";
+
+/// Counter of requests rejected by the rate limiter, labelled with a `reason`
+/// of `rate_limited`, `banned`, `denied` or `server` for the [server-wide quota](crate::RateLimitLayerBuilder::with_server_quota),
+/// or `shadow` or `warmup` for requests that were rate limited but forwarded anyway, see
+/// [`with_enforcement`](crate::RateLimitLayerBuilder::with_enforcement) and
+/// [`with_warmup`](crate::RateLimitLayerBuilder::with_warmup).
+pub const REQUESTS_THROTTLED: &str = "axum_gcra_requests_throttled_total";
+
+/// Histogram of the retry-after durations of rejected requests, in seconds.
+pub const RETRY_AFTER_SECONDS: &str = "axum_gcra_retry_after_seconds";
+
+/// Gauge of the approximate number of entries in the in-memory rate limiter table,
+/// updated after every garbage collection run.
+pub const KEYS: &str = "axum_gcra_keys";
+
+/// Counter of entries evicted by garbage collection.
+pub const GC_EVICTIONS: &str = "axum_gcra_gc_evictions_total";
+
+/// Counter of requests whose [`Store`](crate::store::Store) failed, labelled with the `policy` applied to them,
+/// which is `open`, `closed` or `local`, see [`FailurePolicy`](crate::store::FailurePolicy).
+pub const STORE_FAILURES: &str = "axum_gcra_store_failures_total";
+
+fn labels<K: Key>(key: &RouteWithKey<K>) -> [(&'static str, String); 2] {
+    [
+        ("method", key.method.as_str().to_owned()),
+        ("route", (*key.path).to_owned()),
+    ]
+}
+
+pub(crate) fn allowed<K: Key>(key: &RouteWithKey<K>) {
+    ::metrics::counter!(REQUESTS_ALLOWED, &labels(key)).increment(1);
+}
+
+pub(crate) fn throttled<K: Key>(key: &RouteWithKey<K>, retry_after: Duration, reason: &'static str) {
+    let [method, route] = labels(key);
+
+    ::metrics::histogram!(RETRY_AFTER_SECONDS, &[method.clone(), route.clone()]).record(retry_after.as_secs_f64());
+    ::metrics::counter!(REQUESTS_THROTTLED, &[method, route, ("reason", reason.to_owned())]).increment(1);
+}
+
+pub(crate) fn store_failure<K: Key>(key: &RouteWithKey<K>, policy: &'static str) {
+    let [method, route] = labels(key);
+
+    ::metrics::counter!(STORE_FAILURES, &[method, route, ("policy", policy.to_owned())]).increment(1);
+}
+
+pub(crate) fn gc(stats: GCStats, entries: usize) {
+    ::metrics::counter!(GC_EVICTIONS).increment(stats.evicted as u64);
+    ::metrics::gauge!(KEYS).set(entries as f64);
+}
@@ -0,0 +1,73 @@
+//! Recording of rate limit decisions on the active [OpenTelemetry](https://docs.rs/opentelemetry) span.
+//!
+//! Every decision sets the attributes below on the span of the current OpenTelemetry [`Context`],
+//! so APM tools show throttling inline with the request trace. Rejected requests also add
+//! a [`REJECTED_EVENT`] event to the span. Spans that are not recording are left untouched.
+//!
+//! The context is captured when the request enters the rate limiter, and is kept current
+//! while extracting asynchronous keys and waiting on a custom [`Store`](crate::store::Store).
+
+use std::{borrow::Cow, time::Duration};
+
+use opentelemetry::{trace::TraceContextExt, Context, KeyValue};
+
+use crate::{gcra::stable_hash, Key, RouteWithKey};
+
+/// Boolean attribute that is `true` if the request was allowed.
+pub const ALLOWED: &str = "ratelimit.allowed";
+
+/// Integer attribute of the retry-after duration of a rejected request, in milliseconds.
+pub const RETRY_AFTER_MS: &str = "ratelimit.retry_after_ms";
+
+/// String attribute of the stable 64-bit hash of the key, in hexadecimal,
+/// to correlate requests of the same key without recording the key itself.
+pub const KEY_HASH: &str = "ratelimit.key_hash";
+
+/// String attribute of the rate-limited route, which is empty for the
+/// [global fallback](crate::RateLimitLayerBuilder::with_global_fallback).
+pub const ROUTE: &str = "ratelimit.route";
+
+/// Integer attribute of the number of requests remaining after an allowed request.
+pub const REMAINING: &str = "ratelimit.remaining";
+
+/// Name of the span event added for rejected requests, with a `ratelimit.reason`
+/// attribute of `rate_limited`, `banned`, `denied` or `server` for the [server-wide quota](crate::RateLimitLayerBuilder::with_server_quota),
+/// or `shadow` or `warmup` for requests that were rate limited but forwarded anyway, see
+/// [`with_enforcement`](crate::RateLimitLayerBuilder::with_enforcement) and
+/// [`with_warmup`](crate::RateLimitLayerBuilder::with_warmup).
+pub const REJECTED_EVENT: &str = "ratelimit.rejected";
+
+fn common<K: Key>(key: &RouteWithKey<K>, allowed: bool) -> [KeyValue; 3] {
+    [
+        KeyValue::new(ALLOWED, allowed),
+        KeyValue::new(KEY_HASH, format!("{:016x}", stable_hash(&key.key))),
+        KeyValue::new(ROUTE, Cow::<'static, str>::Owned((*key.path).to_owned())),
+    ]
+}
+
+pub(crate) fn allowed<K: Key>(key: &RouteWithKey<K>, remaining: u64) {
+    let cx = Context::current();
+    let span = cx.span();
+
+    if !span.is_recording() {
+        return;
+    }
+
+    span.set_attributes(common(key, true));
+    span.set_attribute(KeyValue::new(REMAINING, remaining.min(i64::MAX as u64) as i64));
+}
+
+pub(crate) fn rejected<K: Key>(key: &RouteWithKey<K>, retry_after: Duration, reason: &'static str) {
+    let cx = Context::current();
+    let span = cx.span();
+
+    if !span.is_recording() {
+        return;
+    }
+
+    let retry_after_ms = retry_after.as_millis().min(i64::MAX as u128) as i64;
+
+    span.set_attributes(common(key, false));
+    span.set_attribute(KeyValue::new(RETRY_AFTER_MS, retry_after_ms));
+    span.add_event(REJECTED_EVENT, vec![KeyValue::new("ratelimit.reason", reason)]);
+}
@@ -0,0 +1,43 @@
+//! [RFC 7807](https://www.rfc-editor.org/rfc/rfc7807) `application/problem+json` response bodies
+//! for rejections, enabled with the `problem_json` cargo feature.
+
+use std::fmt::Write;
+
+use axum::response::Response;
+use http::{header::CONTENT_TYPE, HeaderValue, StatusCode};
+
+/// Build an `application/problem+json` response with the given status and detail message.
+///
+/// If `retry_after` is given (in seconds), it's included as a `retry_after` extension member.
+///
+/// NOTE: `detail` is written verbatim and must not contain characters requiring JSON escaping.
+pub(crate) fn response(status: StatusCode, detail: &str, retry_after: Option<u64>) -> Response {
+    let mut res = Response::new(body(status, detail, retry_after, "").into());
+
+    *res.status_mut() = status;
+    res.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/problem+json"));
+
+    res
+}
+
+/// Build the body of an `application/problem+json` response, see [`response`].
+///
+/// The `members` are appended verbatim, and must either be empty or start with a comma.
+pub(crate) fn body(status: StatusCode, detail: &str, retry_after: Option<u64>, members: &str) -> String {
+    let mut body = String::with_capacity(128 + members.len());
+
+    _ = write!(
+        body,
+        r#"{{"type":"about:blank","title":"{}","status":{},"detail":"{detail}""#,
+        status.canonical_reason().unwrap_or_default(),
+        status.as_u16(),
+    );
+
+    if let Some(retry_after) = retry_after {
+        _ = write!(body, r#","retry_after":{retry_after}"#);
+    }
+
+    body.push_str(members);
+    body.push('}');
+    body
+}
@@ -0,0 +1,237 @@
+//! Keys taken from a query parameter, such as `?api_key=...`, for legacy clients that cannot send headers.
+//!
+//! [`QueryKey`] is an extractor for the URL-decoded value of the query parameter named by a [`QueryParam`],
+//! such as the built-in [`ApiKey`] for `api_key`. Requests without the parameter are handled according to
+//! the [`QueryKeyFallback`] of the parameter, which rejects them with `400 Bad Request` by default.
+//!
+//! Note that query strings are often logged by proxies and servers, so prefer headers for new APIs.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! # use axum_gcra::axum;
+//! use std::time::Duration;
+//! use axum::{routing::get, Router};
+//! use axum_gcra::{gcra::Quota, query_key::{QueryKey, QueryKeyFallback, QueryParam}, RateLimitLayer};
+//!
+//! /// `?token=...`, or the `x-api-token` header for newer clients
+//! struct Token;
+//!
+//! impl QueryParam for Token {
+//!     const NAME: &'static str = "token";
+//!     const FALLBACK: QueryKeyFallback = QueryKeyFallback::Header("x-api-token");
+//! }
+//!
+//! let app = Router::<()>::new()
+//!     .route("/v1/items", get(|| async { "Hello, World!" }))
+//!     .route_layer(
+//!         RateLimitLayer::<QueryKey<Token>>::builder()
+//!             .with_default_quota(Quota::simple(Duration::from_millis(100)))
+//!             .default_handle_error(),
+//!     );
+//! ```
+
+use std::{
+    borrow::Cow,
+    fmt,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+};
+
+use axum::{extract::FromRequestParts, response::IntoResponse};
+use http::{request::Parts, StatusCode};
+
+/// Names the query parameter of a [`QueryKey`], see the [module documentation](self).
+pub trait QueryParam: Send + Sync + 'static {
+    /// Name of the query parameter.
+    const NAME: &'static str;
+
+    /// What to do with requests without the query parameter.
+    const FALLBACK: QueryKeyFallback = QueryKeyFallback::Reject;
+}
+
+/// What a [`QueryKey`] does with requests without its query parameter, or with an empty value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QueryKeyFallback {
+    /// Reject the request with [`MissingQueryKey`], which is the default.
+    Reject,
+
+    /// Use the value of the given header instead, such as `x-api-key`, and reject the request
+    /// with [`MissingQueryKey`] if there is no such header either.
+    Header(&'static str),
+
+    /// Key all such requests as [anonymous](QueryKey::is_anonymous), so they share a single bucket.
+    Shared,
+}
+
+/// The `api_key` query parameter, rejecting requests without it.
+#[derive(Debug, Clone, Copy)]
+pub struct ApiKey;
+
+impl QueryParam for ApiKey {
+    const NAME: &'static str = "api_key";
+}
+
+/// Rate limiter key of the URL-decoded value of the query parameter named by `P`,
+/// see the [module documentation](self).
+pub struct QueryKey<P = ApiKey> {
+    key: Option<String>,
+    _param: PhantomData<fn() -> P>,
+}
+
+impl<P> QueryKey<P> {
+    /// Create a new key with the given value, such as to [pre-register](crate::RateLimitLayerBuilder::add_key_quotas)
+    /// its quota.
+    #[must_use]
+    pub fn new(key: impl Into<String>) -> Self {
+        QueryKey {
+            key: Some(key.into()),
+            _param: PhantomData,
+        }
+    }
+
+    /// Create the key shared by all requests without the query parameter, see [`QueryKeyFallback::Shared`].
+    #[must_use]
+    pub const fn anonymous() -> Self {
+        QueryKey {
+            key: None,
+            _param: PhantomData,
+        }
+    }
+
+    /// Get the value of the key, or `None` if it is [anonymous](QueryKey::anonymous).
+    #[inline]
+    #[must_use]
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+
+    /// Returns `true` if this is the key shared by all requests without the query parameter.
+    #[inline]
+    #[must_use]
+    pub fn is_anonymous(&self) -> bool {
+        self.key.is_none()
+    }
+}
+
+impl<P> Clone for QueryKey<P> {
+    fn clone(&self) -> Self {
+        QueryKey {
+            key: self.key.clone(),
+            _param: PhantomData,
+        }
+    }
+}
+
+impl<P> PartialEq for QueryKey<P> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl<P> Eq for QueryKey<P> {}
+
+impl<P> Hash for QueryKey<P> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key.hash(state);
+    }
+}
+
+impl<P: QueryParam> fmt::Debug for QueryKey<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.key {
+            Some(ref key) => f.debug_tuple("QueryKey").field(&P::NAME).field(key).finish(),
+            None => f.debug_tuple("QueryKey").field(&P::NAME).field(&format_args!("anonymous")).finish(),
+        }
+    }
+}
+
+/// Query parameter of a [`QueryKey`] not found, returns a 400 Bad Request.
+///
+/// With the `problem_json` feature enabled, the response body is an RFC 7807 `application/problem+json` object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MissingQueryKey;
+
+impl fmt::Display for MissingQueryKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("missing API key")
+    }
+}
+
+impl std::error::Error for MissingQueryKey {}
+
+impl IntoResponse for MissingQueryKey {
+    fn into_response(self) -> axum::response::Response {
+        #[cfg(not(feature = "problem_json"))]
+        return StatusCode::BAD_REQUEST.into_response();
+
+        #[cfg(feature = "problem_json")]
+        return crate::problem::response(StatusCode::BAD_REQUEST, "missing API key", None);
+    }
+}
+
+#[cfg_attr(not(feature = "axum-08"), async_trait::async_trait)]
+impl<P: QueryParam, S: Send + Sync> FromRequestParts<S> for QueryKey<P> {
+    type Rejection = MissingQueryKey;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        if let Some(key) = parts.uri.query().and_then(find_param::<P>) {
+            return Ok(QueryKey::new(key));
+        }
+
+        match P::FALLBACK {
+            QueryKeyFallback::Reject => Err(MissingQueryKey),
+            QueryKeyFallback::Header(name) => match parts.headers.get(name).and_then(|v| v.to_str().ok()) {
+                Some(key) if !key.trim().is_empty() => Ok(QueryKey::new(key.trim())),
+                _ => Err(MissingQueryKey),
+            },
+            QueryKeyFallback::Shared => Ok(QueryKey::anonymous()),
+        }
+    }
+}
+
+/// Find the first non-empty value of the query parameter `P` in the given query string.
+fn find_param<P: QueryParam>(query: &str) -> Option<String> {
+    query.split('&').find_map(|pair| {
+        let (name, value) = pair.split_once('=')?;
+
+        if decode(name) != P::NAME {
+            return None;
+        }
+
+        Some(decode(value).into_owned()).filter(|value| !value.is_empty())
+    })
+}
+
+/// Decode a `application/x-www-form-urlencoded` component, replacing invalid UTF-8.
+fn decode(s: &str) -> Cow<'_, str> {
+    if !s.bytes().any(|b| b == b'%' || b == b'+') {
+        return Cow::Borrowed(s);
+    }
+
+    fn hex(b: u8) -> Option<u8> {
+        (b as char).to_digit(16).map(|d| d as u8)
+    }
+
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => match (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                (Some(hi), Some(lo)) => {
+                    out.push(hi << 4 | lo);
+                    i += 2;
+                }
+                _ => out.push(b'%'),
+            },
+            b => out.push(b),
+        }
+
+        i += 1;
+    }
+
+    Cow::Owned(String::from_utf8_lossy(&out).into_owned())
+}
@@ -5,16 +5,18 @@
 //! the request parts extensions. Or both.
 
 use std::{
+    any::Any,
     fmt::{self, Debug, Display},
     hash::Hash,
     net::{IpAddr, SocketAddr},
     ops::Deref,
     str::FromStr,
+    sync::Arc,
     task::{Context, Poll},
 };
 
 use axum::{extract::FromRequestParts, response::IntoResponse};
-use http::{header::HeaderName, request::Parts, HeaderValue, Request, StatusCode};
+use http::{header::HeaderName, request::Parts, HeaderMap, HeaderValue, Request, StatusCode};
 use tower::{Layer, Service};
 
 /// Wrapper around [`std::net::IpAddr`] that can be extracted from the request parts.
@@ -39,6 +41,7 @@ use tower::{Layer, Service};
 /// [`Router::into_make_service_with_connect_info`](axum::Router::into_make_service_with_connect_info),
 /// it will also try to get the IP address from the underlying socket via the [`ConnectInfo<SocketAddr>`](axum::extract::ConnectInfo) extension.
 /// This is optional as it may not work as expected if the server is behind a reverse proxy.
+/// With the `tonic` feature, the peer address of `tonic` servers is used the same way.
 ///
 /// The [`RealIpLayer`] can be also used to add the [`RealIp`] extension to the request if available, allowing
 /// other services or extractors to reuse it without rescanning the headers every time.
@@ -95,6 +98,24 @@ impl Display for RealIpPrivacyMask {
     }
 }
 
+impl FromStr for RealIp {
+    type Err = std::net::AddrParseError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        IpAddr::from_str(s).map(RealIp)
+    }
+}
+
+impl FromStr for RealIpPrivacyMask {
+    type Err = std::net::AddrParseError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        RealIp::from_str(s).map(RealIpPrivacyMask::from)
+    }
+}
+
 impl Deref for RealIp {
     type Target = IpAddr;
 
@@ -113,18 +134,185 @@ impl Deref for RealIpPrivacyMask {
     }
 }
 
+/// Get the IP address of a rate limiter key, if it is a [`RealIp`] or [`RealIpPrivacyMask`].
+pub(crate) fn key_ip<K: 'static>(key: &K) -> Option<IpAddr> {
+    let key: &dyn Any = key;
+
+    match key.downcast_ref::<RealIp>() {
+        Some(ip) => Some(ip.0),
+        None => key.downcast_ref::<RealIpPrivacyMask>().map(|ip| ip.0 .0),
+    }
+}
+
+/// Mask the IP address of a rate limiter key to the given IPv4 and IPv6 prefix lengths,
+/// if it is a [`RealIp`] or [`RealIpPrivacyMask`].
+pub(crate) fn mask_key_ip<K: 'static>(key: &mut K, v4: u8, v6: u8) {
+    let key: &mut dyn Any = key;
+
+    let ip = match key.downcast_mut::<RealIp>() {
+        Some(ip) => ip,
+        None => match key.downcast_mut::<RealIpPrivacyMask>() {
+            Some(ip) => &mut ip.0,
+            None => return,
+        },
+    };
+
+    let prefix = if ip.0.to_canonical().is_ipv4() { v4.min(32) } else { v6.min(128) };
+
+    if let Some(net) = IpNetwork::new(ip.0, prefix) {
+        ip.0 = net.addr();
+    }
+}
+
+/// Network of IP addresses in CIDR notation, such as `10.0.0.0/8` or `2001:db8::/32`,
+/// used to match client IPs in [deny lists](crate::deny::DenyList).
+///
+/// IPv4-mapped IPv6 addresses are matched as their IPv4 equivalent.
+///
+/// # Example
+///
+/// ```rust
+/// use axum_gcra::real_ip::IpNetwork;
+///
+/// let net: IpNetwork = "192.168.0.1/16".parse().unwrap();
+///
+/// assert_eq!(net.to_string(), "192.168.0.0/16");
+/// assert!(net.contains("192.168.42.7".parse().unwrap()));
+/// assert!(!net.contains("10.0.0.1".parse().unwrap()));
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    /// Create a new network from the given address and prefix length, clearing any host bits of the address.
+    ///
+    /// Returns `None` if the prefix length is longer than the address.
+    #[must_use]
+    pub fn new(addr: IpAddr, prefix: u8) -> Option<Self> {
+        let addr = match addr.to_canonical() {
+            IpAddr::V4(ip) if prefix <= 32 => IpAddr::V4(From::from(
+                ip.to_bits() & u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0),
+            )),
+            IpAddr::V6(ip) if prefix <= 128 => IpAddr::V6(From::from(
+                ip.to_bits() & u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0),
+            )),
+            _ => return None,
+        };
+
+        Some(IpNetwork { addr, prefix })
+    }
+
+    /// Get the first address of the network.
+    #[inline]
+    #[must_use]
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// Get the prefix length of the network.
+    #[inline]
+    #[must_use]
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Returns `true` if the given address is within this network.
+    #[must_use]
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        IpNetwork::new(ip, self.prefix).is_some_and(|net| net.addr == self.addr)
+    }
+}
+
+impl From<IpAddr> for IpNetwork {
+    /// Network containing only the given address.
+    #[inline]
+    fn from(addr: IpAddr) -> Self {
+        let addr = addr.to_canonical();
+
+        IpNetwork {
+            addr,
+            prefix: if addr.is_ipv4() { 32 } else { 128 },
+        }
+    }
+}
+
+impl From<RealIp> for IpNetwork {
+    #[inline]
+    fn from(ip: RealIp) -> Self {
+        IpNetwork::from(ip.0)
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = InvalidIpNetwork;
+
+    /// Parse a network in CIDR notation, or a single address without a prefix length.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((addr, prefix)) = s.split_once('/') else {
+            return IpAddr::from_str(s).map(IpNetwork::from).map_err(|_| InvalidIpNetwork);
+        };
+
+        match (IpAddr::from_str(addr), u8::from_str(prefix)) {
+            (Ok(addr), Ok(prefix)) => IpNetwork::new(addr, prefix).ok_or(InvalidIpNetwork),
+            _ => Err(InvalidIpNetwork),
+        }
+    }
+}
+
+impl Debug for IpNetwork {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Error returned when parsing an invalid [`IpNetwork`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InvalidIpNetwork;
+
+impl Display for InvalidIpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid IP network")
+    }
+}
+
+impl std::error::Error for InvalidIpNetwork {}
+
 /// IP Address not found, returns a 400 Bad Request.
+///
+/// With the `problem_json` feature enabled, the response body is an RFC 7807 `application/problem+json` object.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct IpAddrRejection;
 
+impl Display for IpAddrRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("unable to determine client IP address")
+    }
+}
+
+impl std::error::Error for IpAddrRejection {}
+
 impl IntoResponse for IpAddrRejection {
     fn into_response(self) -> axum::response::Response {
-        StatusCode::BAD_REQUEST.into_response()
+        #[cfg(not(feature = "problem_json"))]
+        return StatusCode::BAD_REQUEST.into_response();
+
+        #[cfg(feature = "problem_json")]
+        return crate::problem::response(StatusCode::BAD_REQUEST, "unable to determine client IP address", None);
     }
 }
 
-#[async_trait::async_trait]
-impl<S> FromRequestParts<S> for RealIp {
+#[cfg_attr(not(feature = "axum-08"), async_trait::async_trait)]
+impl<S: Send + Sync> FromRequestParts<S> for RealIp {
     type Rejection = IpAddrRejection;
 
     async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
@@ -134,13 +322,38 @@ impl<S> FromRequestParts<S> for RealIp {
 
         match get_ip_from_parts(parts) {
             Some(ip) => Ok(ip),
-            None => Err(IpAddrRejection),
+            None => {
+                #[cfg(feature = "tracing")]
+                warn_missing_connect_info(parts);
+
+                Err(IpAddrRejection)
+            }
         }
     }
 }
 
-#[async_trait::async_trait]
-impl<S> FromRequestParts<S> for RealIpPrivacyMask {
+/// Warn once if a client IP address could not be found because the router is not served with connection info,
+/// which is otherwise silent and results in `400 Bad Request` responses.
+#[cfg(feature = "tracing")]
+fn warn_missing_connect_info(parts: &Parts) {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    static WARNED: AtomicBool = AtomicBool::new(false);
+
+    if parts.extensions.get::<axum::extract::ConnectInfo<SocketAddr>>().is_some() {
+        return;
+    }
+
+    if !WARNED.swap(true, Ordering::Relaxed) {
+        tracing::warn!(
+            "unable to determine client IP address without proxy headers or connection info, serve the router \
+             with `into_make_service_with_real_ip()` or `into_make_service_with_connect_info::<SocketAddr>()`"
+        );
+    }
+}
+
+#[cfg_attr(not(feature = "axum-08"), async_trait::async_trait)]
+impl<S: Send + Sync> FromRequestParts<S> for RealIpPrivacyMask {
     type Rejection = IpAddrRejection;
 
     async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
@@ -198,6 +411,129 @@ impl<I> Layer<I> for RealIpLayer {
     }
 }
 
+/// [`Layer`] that adds the [`RealIp`] extension to the request parts from the `x-forwarded-for` headers,
+/// skipping the addresses of our own proxies.
+///
+/// When a request passes through several proxies that each append to `x-forwarded-for`, the header
+/// ends with the addresses of our own proxies, which differ depending on which edge node handled
+/// the request, while everything before the address our outermost proxy appended was sent by the client
+/// and cannot be trusted. This walks all `x-forwarded-for` headers from right to left, skipping addresses
+/// within the configured proxy networks, and uses the first address outside of them, which is the client
+/// as seen by our outermost proxy. Entries with ports, such as `203.0.113.7:1234` or `[2001:db8::1]:1234`,
+/// and quoted entries are accepted.
+///
+/// Requests without any such address fall back to the other [headers](RealIp) and the peer address,
+/// as with the [`RealIpLayer`].
+///
+/// # Example
+///
+/// ```rust
+/// use axum_gcra::real_ip::ForwardedFor;
+/// use http::HeaderMap;
+///
+/// let xff = ForwardedFor::new(["10.0.0.0/8".parse().unwrap()]);
+///
+/// let mut headers = HeaderMap::new();
+/// headers.append("x-forwarded-for", "203.0.113.7, 10.0.0.1".parse().unwrap());
+/// headers.append("x-forwarded-for", "10.0.3.4".parse().unwrap());
+///
+/// assert_eq!(xff.client_ip(&headers), Some("203.0.113.7".parse().unwrap()));
+///
+/// // entries sent by the client itself are ignored, even if they repeat the real client address
+/// let mut forged = HeaderMap::new();
+/// forged.append("x-forwarded-for", "198.51.100.1, 203.0.113.7, 198.51.100.1, 10.0.0.1".parse().unwrap());
+///
+/// assert_eq!(xff.client_ip(&forged), Some("198.51.100.1".parse().unwrap()));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ForwardedFor {
+    proxies: Arc<[IpNetwork]>,
+}
+
+impl ForwardedFor {
+    /// Skip addresses within the given networks of our own proxies.
+    #[must_use]
+    pub fn new(proxies: impl IntoIterator<Item = IpNetwork>) -> Self {
+        ForwardedFor {
+            proxies: proxies.into_iter().collect(),
+        }
+    }
+
+    /// Get the client address from the `x-forwarded-for` headers, see [`ForwardedFor`].
+    #[must_use]
+    pub fn client_ip(&self, headers: &HeaderMap) -> Option<IpAddr> {
+        (headers.get_all(HeaderName::from_static("x-forwarded-for")).iter())
+            .rev()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.rsplit(','))
+            .filter_map(parse_forwarded_entry)
+            .find(|&ip| !self.proxies.iter().any(|net| net.contains(ip)))
+    }
+}
+
+impl<I> Layer<I> for ForwardedFor {
+    type Service = ForwardedForService<I>;
+
+    fn layer(&self, inner: I) -> Self::Service {
+        ForwardedForService {
+            inner,
+            config: self.clone(),
+        }
+    }
+}
+
+/// [`Service`] that adds the [`RealIp`] extension to the request parts, see [`ForwardedFor`].
+#[derive(Debug, Clone)]
+pub struct ForwardedForService<I> {
+    inner: I,
+    config: ForwardedFor,
+}
+
+impl<B, I> Service<Request<B>> for ForwardedForService<I>
+where
+    I: Service<Request<B>>,
+{
+    type Response = I::Response;
+    type Error = I::Error;
+    type Future = I::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let (mut parts, body) = req.into_parts();
+
+        let ip = match self.config.client_ip(&parts.headers) {
+            Some(ip) => Some(RealIp(ip)),
+            None => get_ip_from_parts(&parts),
+        };
+
+        if let Some(ip) = ip {
+            parts.extensions.insert(ip);
+        }
+
+        self.inner.call(Request::from_parts(parts, body))
+    }
+}
+
+/// Parse an entry of `x-forwarded-for`, with an optional port and quotes.
+fn parse_forwarded_entry(entry: &str) -> Option<IpAddr> {
+    let entry = entry.trim().trim_matches('"');
+
+    if let Some(rest) = entry.strip_prefix('[') {
+        return IpAddr::from_str(rest.split(']').next()?).ok().map(|ip| ip.to_canonical());
+    }
+
+    if let Ok(ip) = IpAddr::from_str(entry) {
+        return Some(ip.to_canonical());
+    }
+
+    // IPv4 with a port, as IPv6 with a port must be bracketed
+    let (ip, _port) = entry.split_once(':')?;
+    IpAddr::from_str(ip).ok().map(|ip| ip.to_canonical())
+}
+
 pub(crate) fn get_ip_from_parts(parts: &Parts) -> Option<RealIp> {
     fn parse_ip(s: &HeaderValue) -> Option<IpAddr> {
         s.to_str()
@@ -230,5 +566,14 @@ pub(crate) fn get_ip_from_parts(parts: &Parts) -> Option<RealIp> {
         return Some(RealIp(info.ip()));
     }
 
+    #[cfg(feature = "tonic")]
+    if let Some(addr) = parts
+        .extensions
+        .get::<tonic::transport::server::TcpConnectInfo>()
+        .and_then(|info| info.remote_addr())
+    {
+        return Some(RealIp(addr.ip()));
+    }
+
     None
 }
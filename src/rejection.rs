@@ -0,0 +1,130 @@
+//! Configuration and rendering of the default rejection response.
+
+use std::{borrow::Cow, time::Duration};
+
+use axum::response::Response;
+use http::{header, HeaderMap, HeaderValue};
+
+use crate::{gcra::Quota, RateLimitError};
+
+/// Settings for the default rate limit rejection response, configured on the builder
+/// and shared with every [`RateLimitContext`](crate::RateLimitContext).
+#[derive(Debug, Default, Clone)]
+pub(crate) struct RejectionConfig {
+    pub html_template: Option<Cow<'static, str>>,
+    pub retry_after_http_date: bool,
+    pub retry_after_jitter: Duration,
+    #[cfg(feature = "problem_json")]
+    pub policy_body: bool,
+}
+
+impl RejectionConfig {
+    /// Render the response for a rate limit rejection of the given quota, which is limiting the given scope.
+    #[cfg_attr(not(feature = "problem_json"), allow(unused_variables))]
+    pub fn render(
+        &self,
+        error: RateLimitError,
+        path: &str,
+        prefers_html: bool,
+        quota: Quota,
+        scope: &str,
+    ) -> Response {
+        let error = self.jitter(error);
+
+        let mut res = axum::response::IntoResponse::into_response(error);
+
+        #[cfg(feature = "problem_json")]
+        if self.policy_body {
+            let policy = format!(
+                r#","policy":{{"limit":{},"window":{},"burst":{},"scope":"{scope}"}}"#,
+                quota.burst(),
+                quota.period().as_secs_f64(),
+                quota.burst(),
+            );
+
+            let status = http::StatusCode::TOO_MANY_REQUESTS;
+            let retry_after = Some(error.as_duration().as_secs().max(1));
+
+            *res.body_mut() = crate::problem::body(status, &error.to_string(), retry_after, &policy).into();
+        }
+
+        if self.retry_after_http_date {
+            // from the crate clock, as the standard system clock may not be available, see `clock`
+            let now = std::time::UNIX_EPOCH + Duration::from_nanos(crate::store::now());
+            let at = now + Duration::from_secs(error.as_duration().as_secs().max(1));
+
+            // HTTP-dates are always valid header values
+            let value = HeaderValue::from_str(&httpdate::fmt_http_date(at)).unwrap();
+            res.headers_mut().insert(header::RETRY_AFTER, value);
+        }
+
+        if let Some(ref template) = self.html_template {
+            if prefers_html {
+                let retry_after = error.as_duration();
+
+                let body = template
+                    .replace("{{retry_after}}", &retry_after.as_secs().max(1).to_string())
+                    .replace("{{retry_after_ms}}", &retry_after.as_millis().to_string())
+                    .replace("{{path}}", path);
+
+                *res.body_mut() = body.into();
+                res.headers_mut().insert(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static("text/html; charset=utf-8"),
+                );
+            }
+        }
+
+        res
+    }
+
+    /// Add a random amount of jitter in `[0, retry_after_jitter)` to the advertised error.
+    fn jitter(&self, error: RateLimitError) -> RateLimitError {
+        use std::hash::{BuildHasher, Hasher};
+
+        let max = self.retry_after_jitter.as_nanos() as u64;
+
+        if max == 0 {
+            return error;
+        }
+
+        // each new `RandomState` is seeded differently, which is plenty random for this
+        let random = std::collections::hash_map::RandomState::new().build_hasher().finish();
+
+        RateLimitError(error.0.saturating_add(random % max))
+    }
+}
+
+/// Check if the `Accept` header prefers `text/html` over plain text or JSON.
+///
+/// Wildcards are ignored, so clients must explicitly ask for `text/html`.
+pub(crate) fn prefers_html(headers: &HeaderMap) -> bool {
+    let mut html = 0.0f32;
+    let mut other = 0.0f32;
+
+    for value in headers.get_all(header::ACCEPT) {
+        let Ok(value) = value.to_str() else { continue };
+
+        for range in value.split(',') {
+            let mut params = range.split(';');
+
+            let media = params.next().unwrap_or_default().trim();
+
+            let q = params
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            if media.eq_ignore_ascii_case("text/html") {
+                html = html.max(q);
+            } else if ["application/json", "application/problem+json", "text/plain"]
+                .iter()
+                .any(|m| media.eq_ignore_ascii_case(m))
+            {
+                other = other.max(q);
+            }
+        }
+    }
+
+    html > 0.0 && html >= other
+}
@@ -0,0 +1,160 @@
+//! Dynamic quotas looked up per request, such as from a configuration service or database.
+//!
+//! A [`QuotaResolver`] is consulted for requests without a static quota, that is, requests whose route has no
+//! registered quota and whose key has no [pre-registered quota](crate::RateLimitLayerBuilder::add_key_quotas).
+//! It returns the quota to use, or `None` to use the default quota as usual. Set it with
+//! [`RateLimitLayerBuilder::with_quota_resolver`](crate::RateLimitLayerBuilder::with_quota_resolver).
+//!
+//! Resolvers are awaited before every such request, so slow lookups should be wrapped in a [`CachedQuotaResolver`],
+//! which remembers the resolved quota of each route and key for a configurable time.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//! use axum_gcra::{gcra::Quota, resolver::{CachedQuotaResolver, QuotaResolver}, PartsKey, RateLimitLayer, Route};
+//! use futures_util::future::BoxFuture;
+//! use http::request::Parts;
+//!
+//! struct PlanQuotas;
+//!
+//! impl QuotaResolver<PartsKey<String>> for PlanQuotas {
+//!     fn resolve<'a>(
+//!         &'a self,
+//!         route: &'a Route<'a>,
+//!         parts: &'a Parts,
+//!         account: &'a PartsKey<String>,
+//!     ) -> BoxFuture<'a, Option<Quota>> {
+//!         Box::pin(async move {
+//!             // ... look up the plan of the account in a database
+//!             # let requests_per_second = 10;
+//!             Some(Quota::simple(Duration::from_secs(1) / requests_per_second))
+//!         })
+//!     }
+//! }
+//!
+//! let layer = RateLimitLayer::<PartsKey<String>>::builder()
+//!     .with_key_fn(|parts| {
+//!         let account = parts.headers.get("x-account-id")?.to_str().ok()?;
+//!         Some(PartsKey(account.to_owned()))
+//!     })
+//!     .with_quota_resolver(CachedQuotaResolver::new(PlanQuotas, Duration::from_secs(60)))
+//!     .default_handle_error();
+//! ```
+
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use futures_util::future::BoxFuture;
+use http::request::Parts;
+use scc::HashMap;
+
+use crate::{
+    clock::Instant,
+    gcra::{stable_hash, Quota},
+    Key, RandomState, Route,
+};
+
+/// Number of cached lookups between cleanups of expired entries.
+const CLEAN_EVERY: u64 = 256;
+
+/// Looks up the quota of requests without a static quota, see the [module documentation](self).
+pub trait QuotaResolver<K>: Send + Sync + 'static {
+    /// Get the quota for requests of the given key to the given route, or `None` to use the default quota.
+    ///
+    /// The route is the method and path the request is limited by, as used to look up static quotas.
+    fn resolve<'a>(&'a self, route: &'a Route<'a>, parts: &'a Parts, key: &'a K) -> BoxFuture<'a, Option<Quota>>;
+}
+
+/// [`QuotaResolver`] that caches the quotas resolved by another resolver for each route and key,
+/// including any `None`s, for a fixed time.
+///
+/// The request parts are not part of the cache key, so the inner resolver should only look at them
+/// to derive information that is the same for all requests of the key.
+pub struct CachedQuotaResolver<R> {
+    resolver: R,
+    ttl: Duration,
+    max_entries: usize,
+    entries: HashMap<u64, (Option<Quota>, Instant), RandomState>,
+    inserted: AtomicU64,
+}
+
+impl<R> fmt::Debug for CachedQuotaResolver<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachedQuotaResolver")
+            .field("ttl", &self.ttl)
+            .field("max_entries", &self.max_entries)
+            .field("entries", &self.entries.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<R> CachedQuotaResolver<R> {
+    /// Cache the quotas resolved by the given resolver for the given time.
+    #[must_use]
+    pub fn new(resolver: R, ttl: Duration) -> Self {
+        CachedQuotaResolver {
+            resolver,
+            ttl,
+            max_entries: 100_000,
+            entries: HashMap::default(),
+            inserted: AtomicU64::new(0),
+        }
+    }
+
+    /// Set the maximum number of cached quotas, which defaults to 100,000.
+    ///
+    /// When full, expired entries are removed, and if none have expired, the whole cache is cleared.
+    #[must_use]
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Remove all cached quotas, such as after the configuration they were resolved from has changed.
+    pub fn clear(&self) {
+        self.entries.clear();
+    }
+
+    /// Get the inner resolver.
+    #[must_use]
+    pub fn get_ref(&self) -> &R {
+        &self.resolver
+    }
+
+    fn insert(&self, hash: u64, quota: Option<Quota>, now: Instant) {
+        let full = self.entries.len() >= self.max_entries;
+
+        if full || self.inserted.fetch_add(1, Ordering::Relaxed) % CLEAN_EVERY == CLEAN_EVERY - 1 {
+            self.entries.retain(|_, &mut (_, expires)| expires > now);
+
+            if full && self.entries.len() >= self.max_entries {
+                self.entries.clear();
+            }
+        }
+
+        self.entries.upsert(hash, (quota, now + self.ttl));
+    }
+}
+
+impl<K: Key, R: QuotaResolver<K>> QuotaResolver<K> for CachedQuotaResolver<R> {
+    fn resolve<'a>(&'a self, route: &'a Route<'a>, parts: &'a Parts, key: &'a K) -> BoxFuture<'a, Option<Quota>> {
+        let hash = stable_hash(&(route, key));
+        let now = Instant::now();
+
+        Box::pin(async move {
+            let cached = self.entries.read_async(&hash, |_, &(quota, expires)| (expires > now).then_some(quota));
+
+            if let Some(quota) = cached.await.flatten() {
+                return quota;
+            }
+
+            let quota = self.resolver.resolve(route, parts, key).await;
+            self.insert(hash, quota, Instant::now());
+            quota
+        })
+    }
+}
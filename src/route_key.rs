@@ -0,0 +1,156 @@
+//! Customizable derivation of the route identity of requests, which selects their quota and bucket.
+//!
+//! By default, requests are identified by their method and the axum [`MatchedPath`] of the route that
+//! matched them, such as `GET /users/:id`. A [`RouteKey`] can override this, such as to use the raw path
+//! for routers with rewrites, to fold localized or differently-cased paths onto the same bucket, or to
+//! ignore the method. Set it with [`RateLimitLayerBuilder::with_route_key`](crate::RateLimitLayerBuilder::with_route_key).
+//!
+//! Route identities are looked up in the quota table as-is, so quotas must be registered for the
+//! derived routes. Routes without a registered quota use the default quota as usual.
+
+use std::borrow::Cow;
+
+use axum::extract::MatchedPath;
+use http::{request::Parts, Method};
+
+use crate::Route;
+
+/// Derives the route identity of requests, see the [module documentation](self).
+///
+/// This is implemented for closures of `Fn(&Parts) -> Option<Route<'static>>`.
+pub trait RouteKey: Send + Sync + 'static {
+    /// Get the route of the request, or `None` to use the default of the method and matched path.
+    fn route_key(&self, parts: &Parts) -> Option<Route<'static>>;
+}
+
+impl<F> RouteKey for F
+where
+    F: Fn(&Parts) -> Option<Route<'static>> + Send + Sync + 'static,
+{
+    fn route_key(&self, parts: &Parts) -> Option<Route<'static>> {
+        self(parts)
+    }
+}
+
+/// Where [`PathRouteKey`] takes the path of the request from.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PathSource {
+    /// The axum [`MatchedPath`] of the route, such as `/users/:id`, which is the default.
+    #[default]
+    Matched,
+
+    /// The raw path of the request URI, such as `/users/123`, without the query string.
+    ///
+    /// Every distinct path is a separate bucket, so this is best combined with [normalization](PathRouteKey::normalized)
+    /// and used for a small set of known paths, such as static pages behind rewrites.
+    Raw,
+}
+
+/// Configurable [`RouteKey`] for common path transformations.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use axum_gcra::{gcra::Quota, route_key::PathRouteKey, RateLimitLayer, Route};
+/// # let quota = Quota::default();
+///
+/// // `/Login/`, `/login` and `POST /LOGIN` all share the quota of `GET /login`
+/// let layer = RateLimitLayer::<()>::builder()
+///     .with_route_key(PathRouteKey::raw().normalized().case_folded().without_method())
+///     .with_route(Route::get("/login"), quota)
+///     .default_handle_error();
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PathRouteKey {
+    source: PathSource,
+    normalize: bool,
+    case_fold: bool,
+    ignore_method: bool,
+}
+
+impl PathRouteKey {
+    /// Use the axum [`MatchedPath`] of the route, see [`PathSource::Matched`].
+    #[must_use]
+    pub const fn matched() -> Self {
+        PathRouteKey::new(PathSource::Matched)
+    }
+
+    /// Use the raw path of the request URI, see [`PathSource::Raw`].
+    #[must_use]
+    pub const fn raw() -> Self {
+        PathRouteKey::new(PathSource::Raw)
+    }
+
+    /// Use the path from the given source, without any transformations.
+    #[must_use]
+    pub const fn new(source: PathSource) -> Self {
+        PathRouteKey {
+            source,
+            normalize: false,
+            case_fold: false,
+            ignore_method: false,
+        }
+    }
+
+    /// Normalize paths by collapsing repeated slashes and removing trailing slashes, such that
+    /// `//users/` becomes `/users`.
+    #[must_use]
+    pub const fn normalized(mut self) -> Self {
+        self.normalize = true;
+        self
+    }
+
+    /// Fold paths to lowercase, such that `/Users` becomes `/users`.
+    #[must_use]
+    pub const fn case_folded(mut self) -> Self {
+        self.case_fold = true;
+        self
+    }
+
+    /// Ignore the method of requests, which are then all treated as `GET` requests,
+    /// so quotas should be registered with [`Route::get`].
+    #[must_use]
+    pub const fn without_method(mut self) -> Self {
+        self.ignore_method = true;
+        self
+    }
+}
+
+impl RouteKey for PathRouteKey {
+    fn route_key(&self, parts: &Parts) -> Option<Route<'static>> {
+        let path = match self.source {
+            PathSource::Matched => parts.extensions.get::<MatchedPath>()?.as_str(),
+            PathSource::Raw => parts.uri.path(),
+        };
+
+        let mut path = Cow::Borrowed(path);
+
+        if self.normalize {
+            path = normalize(&path).into();
+        }
+
+        if self.case_fold && path.bytes().any(|b| b.is_ascii_uppercase()) {
+            path = path.to_ascii_lowercase().into();
+        }
+
+        let method = if self.ignore_method { Method::GET } else { parts.method.clone() };
+
+        Some(Route::new(method, path.into_owned()))
+    }
+}
+
+/// Collapse repeated slashes and remove the trailing slash of a path, keeping the root as `/`.
+fn normalize(path: &str) -> String {
+    let mut out = String::with_capacity(path.len() + 1);
+
+    for segment in path.split('/').filter(|s| !s.is_empty()) {
+        out.push('/');
+        out.push_str(segment);
+    }
+
+    if out.is_empty() {
+        out.push('/');
+    }
+
+    out
+}
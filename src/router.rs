@@ -0,0 +1,97 @@
+//! Extension traits to install the rate limiter on an [`axum::Router`] in one call.
+
+use std::{hash::BuildHasher, net::SocketAddr};
+
+use axum::{
+    extract::{connect_info::IntoMakeServiceWithConnectInfo, FromRequestParts},
+    response::IntoResponse,
+    Router,
+};
+
+use crate::{
+    gcra::Quota,
+    real_ip::{RealIp, RealIpLayer},
+    Key, RateLimitLayer, RateLimitLayerBuilder,
+};
+
+/// Extension trait for [`axum::Router`] to install the rate limiter with sane defaults in one call.
+///
+/// This installs the rate limiter with the [default error handler](RateLimitLayerBuilder::default_handle_error)
+/// as a [route layer](Router::route_layer), so it must be called after adding the routes to be limited, and
+/// a [`RealIpLayer`] around the whole router, so that [`RealIp`] keys are extracted once per request.
+///
+/// Clients without any known IP address are rejected with `400 Bad Request`. Unless the app is behind a
+/// proxy that sets one of the [headers](RealIp) for the client address, serve it with
+/// [`into_make_service_with_real_ip`](RouterRealIpExt::into_make_service_with_real_ip)
+/// so the peer address can be used instead.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use axum_gcra::axum;
+/// use std::{net::SocketAddr, time::Duration};
+/// use axum::{routing::get, Router};
+/// use axum_gcra::{gcra::Quota, RouterRateLimitExt, RouterRealIpExt};
+///
+/// # async fn example() {
+/// let app = Router::new()
+///     .route("/", get(|| async { "Hello, World!" }))
+///     .rate_limit(Quota::simple(Duration::from_secs(1)));
+///
+/// let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+/// axum::serve(listener, app.into_make_service_with_real_ip()).await.unwrap();
+/// # }
+/// ```
+pub trait RouterRateLimitExt: Sized {
+    /// Rate limit all routes added so far by [`RealIp`], with the given default quota.
+    #[must_use]
+    fn rate_limit(self, quota: Quota) -> Self {
+        self.rate_limit_with(RateLimitLayer::<RealIp>::builder().with_default_quota(quota))
+    }
+
+    /// Rate limit all routes added so far with the given rate limiter configuration,
+    /// such as with per-route quotas or other keys.
+    #[must_use]
+    fn rate_limit_with<K, H>(self, builder: RateLimitLayerBuilder<K, H>) -> Self
+    where
+        K: Key + FromRequestParts<()>,
+        K::Rejection: IntoResponse + Send + 'static,
+        H: BuildHasher + Default + Clone + Send + Sync + 'static;
+}
+
+impl<S> RouterRateLimitExt for Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    fn rate_limit_with<K, H>(self, builder: RateLimitLayerBuilder<K, H>) -> Self
+    where
+        K: Key + FromRequestParts<()>,
+        K::Rejection: IntoResponse + Send + 'static,
+        H: BuildHasher + Default + Clone + Send + Sync + 'static,
+    {
+        self.route_layer(builder.default_handle_error()).layer(RealIpLayer)
+    }
+}
+
+/// Extension trait for [`axum::Router`] to serve it with everything [`RealIp`] needs to know the client address.
+///
+/// [`RealIp`] falls back to the peer address of the connection when there are no proxy headers,
+/// which is only available if the router is served with
+/// [`into_make_service_with_connect_info::<SocketAddr>()`](Router::into_make_service_with_connect_info).
+/// Forgetting this, or using a different address type, results in `400 Bad Request` responses for
+/// direct clients, so this trait pairs it with a [`RealIpLayer`] around the whole router,
+/// which must be the last step before serving.
+///
+/// See [`RouterRateLimitExt`] for an example.
+pub trait RouterRealIpExt {
+    /// Convert the router into a make-service that provides the peer address of each connection,
+    /// with a [`RealIpLayer`] around the whole router, for use with `axum::serve`.
+    #[must_use]
+    fn into_make_service_with_real_ip(self) -> IntoMakeServiceWithConnectInfo<Router, SocketAddr>;
+}
+
+impl RouterRealIpExt for Router {
+    fn into_make_service_with_real_ip(self) -> IntoMakeServiceWithConnectInfo<Router, SocketAddr> {
+        self.layer(RealIpLayer).into_make_service_with_connect_info::<SocketAddr>()
+    }
+}
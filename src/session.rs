@@ -0,0 +1,73 @@
+//! Rate limiter keys from [`tower-sessions`](https://docs.rs/tower-sessions), enabled with the
+//! `tower_sessions` cargo feature.
+//!
+//! This is exposed as [`SessionKey`], which keys requests by their session ID when a session exists,
+//! and by their [`RealIp`] otherwise, so that session-aware apps get per-user limits for free.
+
+use std::fmt::{self, Debug};
+
+use axum::extract::FromRequestParts;
+use http::request::Parts;
+use tower_sessions_core::{session::Id, Session};
+
+use crate::real_ip::{get_ip_from_parts, IpAddrRejection, RealIp};
+
+/// Rate limiter key using the session ID of the request from `tower-sessions`, falling back to
+/// the [`RealIp`] of the client if there is no session yet, such as before logging in.
+///
+/// The `SessionManagerLayer` of `tower-sessions` must be applied outside of the rate limiter, so the session
+/// is already in the request extensions. Sessions only have an ID once they have been saved, so new sessions
+/// are keyed by IP address until the response that creates them.
+///
+/// If neither is available, it will return a 400 Bad Request via [`IpAddrRejection`], the same as [`RealIp`].
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use axum_gcra::axum;
+/// use axum::{routing::get, Router};
+/// use axum_gcra::{session::SessionKey, RateLimitLayer};
+/// use tower_sessions::{MemoryStore, SessionManagerLayer};
+///
+/// let app = Router::<()>::new()
+///     .route("/", get(|| async { "Hello, World!" }))
+///     .route_layer(RateLimitLayer::<SessionKey>::builder().default_handle_error())
+///     .layer(SessionManagerLayer::new(MemoryStore::default()));
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SessionKey {
+    /// ID of the session of the request.
+    Session(Id),
+
+    /// IP address of a client without a session.
+    Ip(RealIp),
+}
+
+impl Debug for SessionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionKey::Session(id) => write!(f, "session:{id}"),
+            SessionKey::Ip(ip) => Debug::fmt(ip, f),
+        }
+    }
+}
+
+/// Get the session key of the request, if any.
+pub(crate) fn get_session_key_from_parts(parts: &Parts) -> Option<SessionKey> {
+    if let Some(id) = parts.extensions.get::<Session>().and_then(Session::id) {
+        return Some(SessionKey::Session(id));
+    }
+
+    let ip = parts.extensions.get::<RealIp>().copied().or_else(|| get_ip_from_parts(parts));
+
+    ip.map(SessionKey::Ip)
+}
+
+#[cfg_attr(not(feature = "axum-08"), async_trait::async_trait)]
+impl<S: Send + Sync> FromRequestParts<S> for SessionKey {
+    type Rejection = IpAddrRejection;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        get_session_key_from_parts(parts).ok_or(IpAddrRejection)
+    }
+}
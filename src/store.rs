@@ -0,0 +1,328 @@
+//! Pluggable storage backends for rate limiter entries.
+//!
+//! By default, the rate limiter stores its entries in a concurrent in-memory hash map, which is
+//! the fastest option for a single server instance. To share rate limits between multiple instances,
+//! or to persist them, a custom [`Store`] can be given to the [`RateLimitLayerBuilder`](crate::RateLimitLayerBuilder)
+//! with [`with_store`](crate::RateLimitLayerBuilder::with_store).
+//!
+//! Stores operate on timestamps given as nanoseconds since the UNIX epoch, so that multiple instances
+//! agree on the time base, and should use [`gcra::decide`](crate::gcra::decide) to implement the GCRA logic where possible.
+
+use std::{
+    error::Error,
+    fmt,
+    hash::{Hash, Hasher},
+    task::{Context, Poll},
+};
+
+use futures_util::future::BoxFuture;
+use http::StatusCode;
+
+use crate::{
+    clock::{SystemTime, UNIX_EPOCH},
+    gcra::{GCStats, Quota},
+    Key, RateLimitError, Route,
+};
+
+#[cfg(feature = "redis")]
+pub mod redis;
+
+#[cfg(feature = "redis")]
+pub use self::redis::RedisStore;
+
+#[cfg(feature = "memcached")]
+pub mod memcached;
+
+#[cfg(feature = "memcached")]
+pub use self::memcached::MemcachedStore;
+
+#[cfg(feature = "sql")]
+pub mod sql;
+
+#[cfg(feature = "sql")]
+pub use self::sql::SqlStore;
+
+#[cfg(feature = "tokio")]
+pub mod hybrid;
+
+#[cfg(feature = "tokio")]
+pub use self::hybrid::HybridStore;
+
+#[cfg(feature = "gossip")]
+pub mod gossip;
+
+#[cfg(feature = "gossip")]
+pub use self::gossip::GossipStore;
+
+#[cfg(feature = "governor")]
+pub mod governor;
+
+#[cfg(feature = "governor")]
+pub use self::governor::GovernorStore;
+
+pub mod sharded;
+
+pub use self::sharded::ShardedStore;
+
+/// Result type for [`Store`] operations.
+pub type StoreResult<T> = Result<T, StoreError>;
+
+/// Key of an entry in a [`Store`], which is the combination of the route and the user-provided key.
+///
+/// If the request fell back to the [global fallback](crate::RateLimitLayerBuilder::with_global_fallback)
+/// rate limiter, the route path will be empty.
+#[derive(Debug)]
+pub struct StoreKey<'a, K> {
+    /// The route of the entry.
+    pub route: Route<'a>,
+
+    /// The user-provided key of the entry.
+    pub key: &'a K,
+}
+
+impl<K: Hash> Hash for StoreKey<'_, K> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (*self.route.method).hash(state);
+        (*self.route.path).hash(state);
+        self.key.hash(state);
+    }
+}
+
+impl<K> Clone for StoreKey<'_, K> {
+    fn clone(&self) -> Self {
+        StoreKey {
+            route: self.route.clone(),
+            key: self.key,
+        }
+    }
+}
+
+/// Storage backend for rate limiter entries, which are the theoretical arrival times (TAT)
+/// of the next request for each key, in nanoseconds since the UNIX epoch.
+///
+/// Methods return boxed futures to keep the trait object-safe, as remote stores
+/// are dominated by network latency anyway.
+///
+/// # Example
+///
+/// ```rust
+/// use std::{collections::HashMap, sync::Mutex};
+/// use futures_util::future::BoxFuture;
+/// use axum_gcra::{gcra::{self, GCStats, Quota}, store::{Store, StoreKey, StoreResult}, RateLimitError};
+///
+/// /// Simple store for the unit key, keyed by the route only.
+/// #[derive(Default)]
+/// struct MutexStore(Mutex<HashMap<String, u64>>);
+///
+/// impl Store<()> for MutexStore {
+///     fn get_update<'a>(&'a self, key: StoreKey<'a, ()>, quota: Quota, n: u64, now: u64)
+///         -> BoxFuture<'a, StoreResult<Result<u64, RateLimitError>>>
+///     {
+///         let mut map = self.0.lock().unwrap();
+///         let id = format!("{} {}", key.route.method, key.route.path);
+///         let res = gcra::decide(map.get(&id).copied(), now, quota, n);
+///
+///         if let Ok(tat) = res {
+///             map.insert(id, tat);
+///         }
+///
+///         Box::pin(async move { Ok(res) })
+///     }
+///
+///     fn remove<'a>(&'a self, key: StoreKey<'a, ()>) -> BoxFuture<'a, StoreResult<bool>> {
+///         let id = format!("{} {}", key.route.method, key.route.path);
+///         let found = self.0.lock().unwrap().remove(&id).is_some();
+///         Box::pin(async move { Ok(found) })
+///     }
+///
+///     fn gc(&self, now: u64) -> BoxFuture<'_, StoreResult<GCStats>> {
+///         let mut stats = GCStats::default();
+///         self.0.lock().unwrap().retain(|_, tat| {
+///             stats.scanned += 1;
+///             stats.evicted += (*tat < now) as usize;
+///             *tat >= now
+///         });
+///         Box::pin(async move { Ok(stats) })
+///     }
+/// }
+///
+/// let layer = axum_gcra::RateLimitLayer::<()>::builder().with_store(MutexStore::default());
+/// ```
+pub trait Store<K: Key>: Send + Sync + 'static {
+    /// Atomically perform a request costing `n` requests for the given key at the given time,
+    /// returning the new TAT of the entry if allowed, or the rate limit error if not.
+    ///
+    /// Implementations should use [`gcra::decide`](crate::gcra::decide) to compute the new TAT.
+    fn get_update<'a>(
+        &'a self,
+        key: StoreKey<'a, K>,
+        quota: Quota,
+        n: u64,
+        now: u64,
+    ) -> BoxFuture<'a, StoreResult<Result<u64, RateLimitError>>>;
+
+    /// Remove the entry for the given key, returning `true` if it was found.
+    fn remove<'a>(&'a self, key: StoreKey<'a, K>) -> BoxFuture<'a, StoreResult<bool>>;
+
+    /// Remove any entries that have expired before the given time.
+    fn gc(&self, now: u64) -> BoxFuture<'_, StoreResult<GCStats>>;
+
+    /// Get the current TAT of the given key, if found, without modifying it.
+    ///
+    /// This is used for non-consuming checks. The default implementation returns `None`.
+    fn peek<'a>(&'a self, key: StoreKey<'a, K>) -> BoxFuture<'a, StoreResult<Option<u64>>> {
+        _ = key;
+        Box::pin(async { Ok(None) })
+    }
+
+    /// Add the given signed amount of nanoseconds to the TAT of the given key, returning `true` if it was found.
+    ///
+    /// This is used for penalties and refunds. The default implementation does nothing and returns `false`.
+    fn adjust<'a>(&'a self, key: StoreKey<'a, K>, delta: i64) -> BoxFuture<'a, StoreResult<bool>> {
+        _ = (key, delta);
+        Box::pin(async { Ok(false) })
+    }
+
+    /// Charge `n` requests for the given key at the given time even if they exceed the quota, such as for
+    /// requests that were already allowed elsewhere, returning the new TAT of the entry, if known.
+    ///
+    /// This is used by the [`HybridStore`] to push local consumption. Implementations should use
+    /// [`gcra::charge`](crate::gcra::charge) to compute the new TAT. The default implementation performs
+    /// the request with [`Store::get_update`], and if rejected, adds the cost on top of the entry with
+    /// [`Store::adjust`], which is not atomic.
+    fn charge<'a>(
+        &'a self,
+        key: StoreKey<'a, K>,
+        quota: Quota,
+        n: u64,
+        now: u64,
+    ) -> BoxFuture<'a, StoreResult<Option<u64>>> {
+        Box::pin(async move {
+            if let Ok(tat) = self.get_update(key.clone(), quota, n, now).await? {
+                return Ok(Some(tat));
+            }
+
+            // rejected requests imply an existing entry
+            let Some(prev) = self.peek(key.clone()).await? else {
+                return Ok(None);
+            };
+
+            let tat = crate::gcra::charge(Some(prev), now, quota, n);
+            let delta = (tat - prev).min(i64::MAX as u64) as i64;
+
+            Ok(self.adjust(key, delta).await?.then_some(tat))
+        })
+    }
+
+    /// Check whether the store can accept more requests, such as when a remote backend is saturated
+    /// or reconnecting, which is reported by [`RateLimitService`](crate::RateLimitService) from its
+    /// [`poll_ready`](tower::Service::poll_ready) so load balancers and retry layers can react to it.
+    ///
+    /// Returning `Poll::Pending` must arrange for the task to be woken once the store is ready again.
+    /// Errors are returned as [`Error::Store`](crate::Error::Store), after which the service should be
+    /// considered failed. The default implementation is always ready.
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<StoreResult<()>> {
+        _ = cx;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Error returned by a [`Store`], such as a network error.
+#[derive(Debug)]
+pub struct StoreError {
+    error: Box<dyn Error + Send + Sync>,
+    status: StatusCode,
+}
+
+impl StoreError {
+    /// Wrap the given error.
+    pub fn new(error: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        StoreError {
+            error: error.into(),
+            status: StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    /// Get the status of the response for this error, which is `503 Service Unavailable`
+    /// unless configured otherwise with [`FailurePolicy::Closed`].
+    #[must_use]
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Set the status of the response for this error.
+    #[must_use]
+    pub fn with_status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Returns the inner error.
+    #[must_use]
+    pub fn into_inner(self) -> Box<dyn Error + Send + Sync> {
+        self.error
+    }
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rate limiter store error: {}", self.error)
+    }
+}
+
+impl Error for StoreError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.error)
+    }
+}
+
+/// What happens to requests when the [`Store`] fails, such as on network errors,
+/// see [`RateLimitLayerBuilder::with_failure_policy`](crate::RateLimitLayerBuilder::with_failure_policy).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FailurePolicy {
+    /// Forward the request without rate limiting it, so store outages never take the service down.
+    ///
+    /// The request is allowed as if it were the first request of its key, with the same
+    /// [extensions](crate::RateLimitLayerBuilder::with_extension) as other allowed requests.
+    Open,
+
+    /// Reject the request as [`Error::Store`](crate::Error::Store), responding with the given status
+    /// by default. The default policy is to fail closed with `503 Service Unavailable`.
+    Closed(StatusCode),
+
+    /// Rate limit the request with the in-memory table of this instance instead, so limits stay
+    /// enforced per instance until the store recovers.
+    Local,
+}
+
+impl Default for FailurePolicy {
+    fn default() -> Self {
+        FailurePolicy::Closed(StatusCode::SERVICE_UNAVAILABLE)
+    }
+}
+
+impl FailurePolicy {
+    /// Name of the policy, as used for metrics.
+    #[cfg_attr(not(any(feature = "tracing", feature = "metrics")), allow(dead_code))]
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            FailurePolicy::Open => "open",
+            FailurePolicy::Closed(_) => "closed",
+            FailurePolicy::Local => "local",
+        }
+    }
+}
+
+/// Returns the current time in nanoseconds since the UNIX epoch, as used by [`Store`]s.
+#[must_use]
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64
+}
+
+/// Helper to construct a [`StoreKey`] from the internal key.
+pub(crate) fn key_of<K>(key: &crate::RouteWithKey<K>) -> StoreKey<'_, K> {
+    StoreKey {
+        route: key.as_route(),
+        key: &key.key,
+    }
+}
@@ -0,0 +1,352 @@
+//! Gossip-based synchronization between server instances, enabled with the `gossip` cargo feature.
+
+use std::{
+    fmt, io,
+    net::SocketAddr,
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
+
+use futures_util::future::BoxFuture;
+use scc::hash_map::{Entry as MapEntry, HashMap};
+use tokio::{net::UdpSocket, task::JoinHandle};
+
+use super::{Store, StoreKey, StoreResult};
+use crate::{
+    gcra::{self, GCStats, Quota},
+    Key, RateLimitError,
+};
+
+/// Magic bytes and version at the start of every datagram.
+const MAGIC: &[u8; 5] = b"GCRA\x02";
+
+/// Maximum datagram size, chosen to avoid IP fragmentation on typical networks.
+const MAX_DATAGRAM: usize = 1400;
+
+/// Size of the fixed part of an encoded delta: `t`, `tau`, `n`, the algorithm and the key length.
+const DELTA_HEADER: usize = 8 + 8 + 8 + 1 + 2;
+
+/// [`Store`] for deployments without a shared database, where each instance enforces rate limits
+/// locally and periodically broadcasts per-key consumption deltas to its peers over UDP,
+/// so the fleet converges on a shared view of heavy hitters.
+///
+/// Received deltas are charged to the local entry as if the requests had been made locally,
+/// keeping enforcement approximately global. As with [`HybridStore`](super::HybridStore),
+/// consumption is only exchanged every [interval](GossipStoreBuilder::with_interval), so with `N`
+/// instances a key may be over-admitted by up to a factor of `N` for the duration of one interval.
+/// Lost datagrams are not retransmitted, and simply lead to further over-admission.
+///
+/// To keep traffic low, only keys that consumed at least the
+/// [minimum delta](GossipStoreBuilder::with_min_delta) since the last broadcast are sent.
+///
+/// Deltas are sent directly to every configured peer, and are not authenticated, so this must
+/// only be used on a trusted private network. Entries are identified by the
+/// [`Debug`](fmt::Debug) representation of their key and route, which must agree between instances.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use axum_gcra::axum;
+/// use axum::Router;
+/// use axum_gcra::{RateLimitLayer, real_ip::RealIp, store::GossipStore};
+///
+/// # async fn example() -> std::io::Result<()> {
+/// let store = GossipStore::builder("10.0.0.1:7946".parse().unwrap())
+///     .with_peers(["10.0.0.2:7946".parse().unwrap(), "10.0.0.3:7946".parse().unwrap()])
+///     .build()
+///     .await?;
+///
+/// let app = Router::<()>::new().route_layer(
+///     RateLimitLayer::<RealIp>::builder().with_store(store).default_handle_error(),
+/// );
+/// # Ok(()) }
+/// ```
+#[derive(Clone)]
+pub struct GossipStore {
+    inner: Arc<GossipStoreInner>,
+}
+
+struct GossipStoreInner {
+    entries: HashMap<String, Entry>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+struct Entry {
+    tat: u64,
+    quota: Quota,
+
+    /// Number of requests admitted locally since the last broadcast.
+    pending: u64,
+}
+
+/// Builder for [`GossipStore`].
+#[derive(Debug, Clone)]
+pub struct GossipStoreBuilder {
+    bind: SocketAddr,
+    peers: Vec<SocketAddr>,
+    interval: Duration,
+    min_delta: u64,
+}
+
+impl GossipStore {
+    /// Begin building a new gossip store that listens for deltas on the given address.
+    #[must_use]
+    pub fn builder(bind: SocketAddr) -> GossipStoreBuilder {
+        GossipStoreBuilder {
+            bind,
+            peers: Vec::new(),
+            interval: Duration::from_millis(500),
+            min_delta: 1,
+        }
+    }
+}
+
+impl GossipStoreBuilder {
+    /// Add peers to send consumption deltas to.
+    #[must_use]
+    pub fn with_peers(mut self, peers: impl IntoIterator<Item = SocketAddr>) -> Self {
+        self.peers.extend(peers);
+        self
+    }
+
+    /// Set how often consumption deltas are broadcast to peers. The default is 500 milliseconds.
+    #[must_use]
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Set the minimum number of requests a key must have consumed since the last broadcast
+    /// to be included in the next one. The default is 1, which sends all keys.
+    ///
+    /// Consumption below the minimum is kept and sent once it reaches the minimum.
+    #[must_use]
+    pub fn with_min_delta(mut self, min_delta: u64) -> Self {
+        self.min_delta = min_delta.max(1);
+        self
+    }
+
+    /// Bind the UDP socket and spawn the background tasks that send and receive deltas.
+    ///
+    /// This must be called from within a tokio runtime.
+    pub async fn build(self) -> io::Result<GossipStore> {
+        let socket = Arc::new(UdpSocket::bind(self.bind).await?);
+
+        let inner = Arc::new(GossipStoreInner {
+            entries: HashMap::default(),
+            tasks: Mutex::default(),
+        });
+
+        let weak = Arc::downgrade(&inner);
+
+        *inner.tasks.lock().unwrap_or_else(|e| e.into_inner()) = vec![
+            tokio::task::spawn(GossipStoreInner::send_task(weak.clone(), socket.clone(), self)),
+            tokio::task::spawn(GossipStoreInner::recv_task(weak, socket)),
+        ];
+
+        Ok(GossipStore { inner })
+    }
+}
+
+impl Drop for GossipStoreInner {
+    fn drop(&mut self) {
+        for task in self.tasks.get_mut().unwrap_or_else(|e| e.into_inner()).iter() {
+            task.abort();
+        }
+    }
+}
+
+impl GossipStoreInner {
+    async fn send_task(store: Weak<Self>, socket: Arc<UdpSocket>, config: GossipStoreBuilder) {
+        let mut interval = tokio::time::interval(config.interval);
+        let mut datagrams = Vec::new();
+
+        loop {
+            interval.tick().await;
+
+            let Some(store) = store.upgrade() else { break };
+
+            store.collect(config.min_delta, &mut datagrams).await;
+            drop(store);
+
+            for datagram in datagrams.drain(..) {
+                for peer in &config.peers {
+                    // lost datagrams are acceptable
+                    _ = socket.send_to(&datagram, peer).await;
+                }
+            }
+        }
+    }
+
+    async fn recv_task(store: Weak<Self>, socket: Arc<UdpSocket>) {
+        let mut buf = vec![0; MAX_DATAGRAM];
+
+        loop {
+            let Ok(len) = socket.recv(&mut buf).await else { continue };
+
+            let Some(store) = store.upgrade() else { break };
+
+            store.apply(&buf[..len], super::now()).await;
+        }
+    }
+
+    /// Take all pending consumption of at least `min_delta` and encode it into datagrams.
+    async fn collect(&self, min_delta: u64, datagrams: &mut Vec<Vec<u8>>) {
+        let mut datagram = Vec::from(*MAGIC);
+
+        self.entries
+            .retain_async(|key, entry| {
+                if entry.pending < min_delta || key.len() > MAX_DATAGRAM - MAGIC.len() - DELTA_HEADER {
+                    return true;
+                }
+
+                if datagram.len() + DELTA_HEADER + key.len() > MAX_DATAGRAM {
+                    datagrams.push(std::mem::replace(&mut datagram, Vec::from(*MAGIC)));
+                }
+
+                datagram.extend_from_slice(&entry.quota.t.to_le_bytes());
+                datagram.extend_from_slice(&entry.quota.tau.to_le_bytes());
+                datagram.extend_from_slice(&std::mem::take(&mut entry.pending).to_le_bytes());
+                datagram.push(entry.quota.algorithm.to_u8());
+                datagram.extend_from_slice(&(key.len() as u16).to_le_bytes());
+                datagram.extend_from_slice(key.as_bytes());
+
+                true
+            })
+            .await;
+
+        if datagram.len() > MAGIC.len() {
+            datagrams.push(datagram);
+        }
+    }
+
+    /// Charge the deltas of a received datagram to the local entries.
+    async fn apply(&self, mut datagram: &[u8], now: u64) {
+        let Some(rest) = datagram.strip_prefix(MAGIC) else {
+            return;
+        };
+        datagram = rest;
+
+        fn u64_at(buf: &[u8], at: usize) -> u64 {
+            u64::from_le_bytes(buf[at..at + 8].try_into().unwrap())
+        }
+
+        while datagram.len() >= DELTA_HEADER {
+            let (t, tau, n) = (u64_at(datagram, 0), u64_at(datagram, 8), u64_at(datagram, 16));
+            let algorithm = gcra::Algorithm::from_u8(datagram[24]);
+            let len = u16::from_le_bytes([datagram[25], datagram[26]]) as usize;
+
+            let Some(key) = datagram.get(DELTA_HEADER..DELTA_HEADER + len) else {
+                return;
+            };
+            let Ok(key) = std::str::from_utf8(key) else { return };
+
+            datagram = &datagram[DELTA_HEADER + len..];
+
+            // skip deltas from peers with algorithms this instance does not know about
+            let Some(algorithm) = algorithm else { continue };
+
+            let quota = Quota { t, tau, algorithm };
+
+            match self.entries.entry_async(key.to_owned()).await {
+                MapEntry::Occupied(mut entry) => {
+                    let entry = entry.get_mut();
+                    entry.tat = gcra::charge(Some(entry.tat), now, quota, n);
+                }
+                MapEntry::Vacant(entry) => {
+                    // equivalent to `Gcra::empty` followed by the remote requests
+                    entry.insert_entry(Entry {
+                        tat: gcra::charge(None, now, quota, n),
+                        quota,
+                        pending: 0,
+                    });
+                }
+            }
+        }
+    }
+
+    fn gossip_key<K: fmt::Debug>(key: &StoreKey<'_, K>) -> String {
+        format!("{} {}:{:?}", key.route.method, key.route.path, key.key)
+    }
+}
+
+impl fmt::Debug for GossipStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GossipStore").field("entries", &self.inner.entries.len()).finish()
+    }
+}
+
+impl<K: Key> Store<K> for GossipStore {
+    fn get_update<'a>(
+        &'a self,
+        key: StoreKey<'a, K>,
+        quota: Quota,
+        n: u64,
+        now: u64,
+    ) -> BoxFuture<'a, StoreResult<Result<u64, RateLimitError>>> {
+        let key = GossipStoreInner::gossip_key(&key);
+
+        Box::pin(async move {
+            Ok(match self.inner.entries.entry_async(key).await {
+                MapEntry::Occupied(mut entry) => {
+                    let entry = entry.get_mut();
+                    gcra::decide(Some(entry.tat), now, quota, n).inspect(|&tat| {
+                        entry.tat = tat;
+                        entry.quota = quota;
+                        entry.pending += n;
+                    })
+                }
+                MapEntry::Vacant(entry) => gcra::decide(None, now, quota, n).inspect(|&tat| {
+                    entry.insert_entry(Entry { tat, quota, pending: n });
+                }),
+            })
+        })
+    }
+
+    /// Removes the entry on this instance only.
+    fn remove<'a>(&'a self, key: StoreKey<'a, K>) -> BoxFuture<'a, StoreResult<bool>> {
+        let key = GossipStoreInner::gossip_key(&key);
+
+        Box::pin(async move { Ok(self.inner.entries.remove_async(&key).await.is_some()) })
+    }
+
+    fn gc(&self, now: u64) -> BoxFuture<'_, StoreResult<GCStats>> {
+        Box::pin(async move {
+            let mut stats = GCStats::default();
+
+            // keep entries that have not been broadcast yet
+            self.inner
+                .entries
+                .retain_async(|_, entry| {
+                    let keep = entry.tat >= now || entry.pending > 0;
+                    stats.scanned += 1;
+                    stats.evicted += !keep as usize;
+                    keep
+                })
+                .await;
+
+            Ok(stats)
+        })
+    }
+
+    fn peek<'a>(&'a self, key: StoreKey<'a, K>) -> BoxFuture<'a, StoreResult<Option<u64>>> {
+        let key = GossipStoreInner::gossip_key(&key);
+
+        Box::pin(async move { Ok(self.inner.entries.read_async(&key, |_, entry| entry.tat).await) })
+    }
+
+    /// Adjusts the entry on this instance only.
+    fn adjust<'a>(&'a self, key: StoreKey<'a, K>, delta: i64) -> BoxFuture<'a, StoreResult<bool>> {
+        let key = GossipStoreInner::gossip_key(&key);
+
+        Box::pin(async move {
+            let found = self
+                .inner
+                .entries
+                .update_async(&key, |_, entry| entry.tat = entry.tat.saturating_add_signed(delta))
+                .await;
+
+            Ok(found.is_some())
+        })
+    }
+}
@@ -0,0 +1,143 @@
+//! Compatibility with the [`governor`] crate, enabled with the `governor` cargo feature.
+//!
+//! [`governor::Quota`]s convert into [`Quota`]s with [`From`], keeping the same replenish interval and burst size,
+//! and [`GovernorStore`] backs the rate limiter with any `governor` keyed state store, such as to ease
+//! migrating from `tower_governor`.
+
+use std::fmt;
+
+use futures_util::future::{self, BoxFuture};
+use governor::{
+    nanos::Nanos,
+    state::keyed::{DefaultKeyedStateStore, ShrinkableKeyedStateStore},
+};
+
+use super::{Store, StoreKey, StoreResult};
+use crate::{
+    gcra::{self, stable_hash, GCStats, Quota},
+    Key, RateLimitError,
+};
+
+/// [`Store`] backed by a [`governor`] keyed state store, which defaults to the
+/// [`DefaultKeyedStateStore`] of `governor`.
+///
+/// Entries are keyed by a stable hash of their route and key, and hold timestamps in nanoseconds since
+/// the UNIX epoch as with any other [`Store`], so the state store should not be shared with `governor`
+/// rate limiters, which use their own time base.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use axum_gcra::axum;
+/// use std::num::NonZeroU32;
+/// use axum::Router;
+/// use axum_gcra::{RateLimitLayer, real_ip::RealIp, store::GovernorStore};
+///
+/// // keep the tuned quota from `tower_governor`
+/// let quota = governor::Quota::per_second(NonZeroU32::new(10).unwrap());
+///
+/// let app = Router::<()>::new().route_layer(
+///     RateLimitLayer::<RealIp>::builder()
+///         .with_default_quota(quota.into())
+///         .with_store(GovernorStore::new())
+///         .default_handle_error(),
+/// );
+/// ```
+pub struct GovernorStore<S = DefaultKeyedStateStore<u64>> {
+    state: S,
+}
+
+impl GovernorStore {
+    /// Create a new store with an empty [`DefaultKeyedStateStore`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Default for GovernorStore {
+    fn default() -> Self {
+        GovernorStore::with_state(DefaultKeyedStateStore::default())
+    }
+}
+
+impl<S> GovernorStore<S> {
+    /// Create a new store backed by the given `governor` keyed state store.
+    #[must_use]
+    pub fn with_state(state: S) -> Self {
+        GovernorStore { state }
+    }
+
+    /// Get the underlying `governor` keyed state store.
+    #[must_use]
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+}
+
+impl<S> fmt::Debug for GovernorStore<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GovernorStore").finish_non_exhaustive()
+    }
+}
+
+impl<K: Key, S> Store<K> for GovernorStore<S>
+where
+    S: ShrinkableKeyedStateStore<u64> + Send + Sync + 'static,
+{
+    fn get_update<'a>(
+        &'a self,
+        key: StoreKey<'a, K>,
+        quota: Quota,
+        n: u64,
+        now: u64,
+    ) -> BoxFuture<'a, StoreResult<Result<u64, RateLimitError>>> {
+        // `governor` retries the closure on contention, same as the in-memory limiter
+        let res = self.state.measure_and_replace(&stable_hash(&key), |prev| {
+            let tat = gcra::decide(prev.map(u64::from), now, quota, n)?;
+            Ok((tat, Nanos::from(tat)))
+        });
+
+        Box::pin(future::ready(Ok(res)))
+    }
+
+    fn remove<'a>(&'a self, key: StoreKey<'a, K>) -> BoxFuture<'a, StoreResult<bool>> {
+        // state stores cannot remove single entries, but a zero state is treated as missing
+        let found = self
+            .state
+            .measure_and_replace(&stable_hash(&key), |prev| Ok::<_, ()>((prev.is_some(), Nanos::from(0))));
+
+        Box::pin(future::ready(Ok(found.unwrap_or(false))))
+    }
+
+    fn gc(&self, now: u64) -> BoxFuture<'_, StoreResult<GCStats>> {
+        let scanned = self.state.len();
+
+        self.state.retain_recent(Nanos::from(now));
+
+        Box::pin(future::ready(Ok(GCStats {
+            scanned,
+            evicted: scanned.saturating_sub(self.state.len()),
+        })))
+    }
+
+    fn peek<'a>(&'a self, key: StoreKey<'a, K>) -> BoxFuture<'a, StoreResult<Option<u64>>> {
+        // rejecting the update leaves the entry unchanged
+        let tat = match self.state.measure_and_replace(&stable_hash(&key), Err::<((), Nanos), _>) {
+            Err(prev) => prev.map(u64::from),
+            Ok(()) => None,
+        };
+
+        Box::pin(future::ready(Ok(tat)))
+    }
+
+    fn adjust<'a>(&'a self, key: StoreKey<'a, K>, delta: i64) -> BoxFuture<'a, StoreResult<bool>> {
+        let found = self.state.measure_and_replace(&stable_hash(&key), |prev| match prev {
+            // never adjust down to zero, which would be treated as missing
+            Some(tat) => Ok(((), Nanos::from(u64::from(tat).saturating_add_signed(delta).max(1)))),
+            None => Err(()),
+        });
+
+        Box::pin(future::ready(Ok(found.is_ok())))
+    }
+}
@@ -0,0 +1,318 @@
+//! Two-tier store combining a local in-memory rate limiter with a shared remote [`Store`].
+
+use std::{
+    borrow::Cow,
+    fmt,
+    hash::{Hash, Hasher},
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use futures_util::future::BoxFuture;
+use http::Method;
+use scc::{hash_map::Entry as MapEntry, Equivalent, HashMap};
+
+use super::{Store, StoreKey, StoreResult};
+use crate::{
+    gcra::{self, GCStats, Quota},
+    Key, RateLimitError, Route,
+};
+
+/// Two-tier [`Store`] that enforces rate limits with a local in-memory table on each instance,
+/// and periodically reconciles with a shared remote store such as [`RedisStore`](super::RedisStore).
+///
+/// Requests never wait on the remote store. Instead, consumption is accumulated locally and pushed
+/// to the remote store every [sync interval](HybridStore::new) with [`Store::charge`], after which each
+/// local entry is moved forward to the remote state, so that consumption on other instances is taken
+/// into account. Consumption is pushed even when the fleet as a whole has exceeded the quota.
+///
+/// This trades accuracy for latency: between two syncs, each instance may admit up to a full quota
+/// on its own, so with `N` instances a key may be over-admitted by at most a factor of `N`
+/// for the duration of one sync interval, after which the fleet converges on the shared limit.
+///
+/// Entries are only synced after they have been used locally, so keys that are active on a
+/// single instance cost no remote round-trips at all between uses.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use axum_gcra::axum;
+/// use std::time::Duration;
+/// use axum::Router;
+/// use axum_gcra::{RateLimitLayer, real_ip::RealIp, store::{HybridStore, Store}};
+///
+/// # fn example(remote: impl Store<RealIp>) {
+/// // e.g. a `RedisStore`
+/// let app = Router::<()>::new().route_layer(
+///     RateLimitLayer::<RealIp>::builder()
+///         .with_store(HybridStore::new(remote, Duration::from_millis(250)))
+///         .default_handle_error(),
+/// );
+/// # }
+/// ```
+pub struct HybridStore<K: Key, R> {
+    inner: Arc<HybridStoreInner<K, R>>,
+}
+
+struct HybridStoreInner<K, R> {
+    remote: R,
+    entries: HashMap<OwnedKey<K>, LocalEntry>,
+}
+
+struct LocalEntry {
+    tat: u64,
+    quota: Quota,
+
+    /// Number of requests admitted locally since the last sync.
+    pending: u64,
+}
+
+impl<K: Key, R> Clone for HybridStore<K, R> {
+    fn clone(&self) -> Self {
+        HybridStore {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<K, R> HybridStore<K, R>
+where
+    K: Key + Clone,
+    R: Store<K>,
+{
+    /// Create a new hybrid store in front of the given remote store, spawning a background task
+    /// that syncs with the remote store at the given interval.
+    ///
+    /// Shorter intervals reduce the over-admission window at the cost of more remote traffic.
+    ///
+    /// This must be called from within a tokio runtime.
+    #[must_use]
+    pub fn new(remote: R, sync_interval: Duration) -> Self {
+        let inner = Arc::new(HybridStoreInner {
+            remote,
+            entries: HashMap::default(),
+        });
+
+        tokio::task::spawn(HybridStoreInner::sync_task(Arc::downgrade(&inner), sync_interval));
+
+        HybridStore { inner }
+    }
+
+    /// Get the remote store.
+    #[must_use]
+    pub fn remote(&self) -> &R {
+        &self.inner.remote
+    }
+
+    /// Reconcile all locally used entries with the remote store right now.
+    ///
+    /// Returns the first remote error, if any, but still attempts to sync all entries.
+    /// Consumption that failed to sync is retried on the next sync.
+    pub async fn sync(&self) -> StoreResult<()> {
+        self.inner.sync().await
+    }
+}
+
+impl<K, R> HybridStoreInner<K, R>
+where
+    K: Key + Clone,
+    R: Store<K>,
+{
+    async fn sync_task(store: Weak<Self>, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+        interval.tick().await; // first tick is immediate
+
+        loop {
+            interval.tick().await;
+
+            // stop once all stores have been dropped
+            let Some(store) = store.upgrade() else { break };
+
+            // failed entries are retried on the next tick
+            _ = store.sync().await;
+        }
+    }
+
+    async fn sync(&self) -> StoreResult<()> {
+        let mut pending = Vec::new();
+
+        self.entries
+            .retain_async(|key, entry| {
+                if entry.pending > 0 {
+                    pending.push((key.clone(), entry.quota, std::mem::take(&mut entry.pending)));
+                }
+                true
+            })
+            .await;
+
+        let mut res = Ok(());
+
+        for (key, quota, n) in pending {
+            let store_key = key.store_key();
+
+            // local requests were already allowed, so they count even if the fleet
+            // as a whole has exceeded the quota, which then shows in the remote state
+            match self.remote.charge(store_key, quota, n, super::now()).await {
+                Ok(Some(tat)) => {
+                    self.entries.update_async(&key, |_, entry| entry.tat = entry.tat.max(tat)).await;
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    // put back the consumption so it's retried next time
+                    self.entries.update_async(&key, |_, entry| entry.pending += n).await;
+
+                    if res.is_ok() {
+                        res = Err(e);
+                    }
+                }
+            }
+        }
+
+        res
+    }
+}
+
+impl<K: Key, R> fmt::Debug for HybridStore<K, R>
+where
+    R: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HybridStore")
+            .field("remote", &self.inner.remote)
+            .field("entries", &self.inner.entries.len())
+            .finish()
+    }
+}
+
+impl<K, R> Store<K> for HybridStore<K, R>
+where
+    K: Key + Clone,
+    R: Store<K>,
+{
+    fn get_update<'a>(
+        &'a self,
+        key: StoreKey<'a, K>,
+        quota: Quota,
+        n: u64,
+        now: u64,
+    ) -> BoxFuture<'a, StoreResult<Result<u64, RateLimitError>>> {
+        Box::pin(async move {
+            let update = |entry: &mut LocalEntry| {
+                gcra::decide(Some(entry.tat), now, quota, n).inspect(|&tat| {
+                    entry.tat = tat;
+                    entry.quota = quota;
+                    entry.pending += n;
+                })
+            };
+
+            // fast path, without allocating an owned key
+            if let Some(res) = self.inner.entries.update_async(&key, |_, entry| update(entry)).await {
+                return Ok(res);
+            }
+
+            Ok(match self.inner.entries.entry_async(OwnedKey::from(&key)).await {
+                MapEntry::Occupied(mut entry) => update(entry.get_mut()),
+                MapEntry::Vacant(entry) => gcra::decide(None, now, quota, n).inspect(|&tat| {
+                    entry.insert_entry(LocalEntry { tat, quota, pending: n });
+                }),
+            })
+        })
+    }
+
+    fn remove<'a>(&'a self, key: StoreKey<'a, K>) -> BoxFuture<'a, StoreResult<bool>> {
+        Box::pin(async move {
+            let local = self.inner.entries.remove_async(&key).await.is_some();
+            let remote = self.inner.remote.remove(key).await?;
+
+            Ok(local || remote)
+        })
+    }
+
+    fn gc(&self, now: u64) -> BoxFuture<'_, StoreResult<GCStats>> {
+        Box::pin(async move {
+            let mut stats = GCStats::default();
+
+            // keep entries that have not been synced yet
+            self.inner
+                .entries
+                .retain_async(|_, entry| {
+                    let keep = entry.tat >= now || entry.pending > 0;
+                    stats.scanned += 1;
+                    stats.evicted += !keep as usize;
+                    keep
+                })
+                .await;
+
+            let remote = self.inner.remote.gc(now).await?;
+
+            stats.scanned += remote.scanned;
+            stats.evicted += remote.evicted;
+
+            Ok(stats)
+        })
+    }
+
+    fn peek<'a>(&'a self, key: StoreKey<'a, K>) -> BoxFuture<'a, StoreResult<Option<u64>>> {
+        Box::pin(async move { Ok(self.inner.entries.read_async(&key, |_, entry| entry.tat).await) })
+    }
+
+    fn adjust<'a>(&'a self, key: StoreKey<'a, K>, delta: i64) -> BoxFuture<'a, StoreResult<bool>> {
+        Box::pin(async move {
+            let local = self
+                .inner
+                .entries
+                .update_async(&key, |_, entry| entry.tat = entry.tat.saturating_add_signed(delta))
+                .await
+                .is_some();
+
+            let remote = self.inner.remote.adjust(key, delta).await?;
+
+            Ok(local || remote)
+        })
+    }
+}
+
+/// Owned version of [`StoreKey`] for the local table.
+#[derive(Clone, PartialEq, Eq)]
+struct OwnedKey<K> {
+    method: Method,
+    path: String,
+    key: K,
+}
+
+impl<K: Clone> From<&StoreKey<'_, K>> for OwnedKey<K> {
+    fn from(key: &StoreKey<'_, K>) -> Self {
+        OwnedKey {
+            method: key.route.method.clone().into_owned(),
+            path: key.route.path.clone().into_owned(),
+            key: key.key.clone(),
+        }
+    }
+}
+
+impl<K> OwnedKey<K> {
+    fn store_key(&self) -> StoreKey<'_, K> {
+        StoreKey {
+            route: Route {
+                method: Cow::Borrowed(&self.method),
+                path: Cow::Borrowed(&self.path),
+            },
+            key: &self.key,
+        }
+    }
+}
+
+// must hash identically to `StoreKey` for `Equivalent` lookups
+impl<K: Hash> Hash for OwnedKey<K> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.method.hash(state);
+        self.path.as_str().hash(state);
+        self.key.hash(state);
+    }
+}
+
+impl<K: Eq> Equivalent<OwnedKey<K>> for StoreKey<'_, K> {
+    fn equivalent(&self, key: &OwnedKey<K>) -> bool {
+        *self.route.method == key.method && *self.route.path == key.path && *self.key == key.key
+    }
+}
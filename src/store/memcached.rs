@@ -0,0 +1,365 @@
+//! [Memcached](https://memcached.org) storage backend, enabled with the `memcached` cargo feature.
+
+use std::{borrow::Cow, fmt, io, sync::Mutex, time::Duration};
+
+use futures_util::future::BoxFuture;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream},
+    net::TcpStream,
+};
+
+use super::{Store, StoreError, StoreKey, StoreResult};
+use crate::{
+    gcra::{self, GCStats, Quota},
+    Key, RateLimitError,
+};
+
+/// Maximum length of a memcached key.
+const MAX_KEY_LEN: usize = 250;
+
+/// Maximum relative expiration time, after which memcached treats it as a UNIX timestamp.
+const MAX_RELATIVE_EXPIRY: u64 = 60 * 60 * 24 * 30;
+
+/// [`Store`] backed by memcached, for deployments that already run memcached.
+///
+/// Entries are updated with `gets`/`cas` (compare-and-swap) operations, and expire on their own.
+/// Unlike [`RedisStore`](super::RedisStore), the update is not a single atomic operation,
+/// so the following races are possible, all of which are bounded:
+///
+/// - Concurrent updates to the same key retry the compare-and-swap up to
+///   [`with_max_retries`](MemcachedStore::with_max_retries) times. If all retries fail, the request
+///   is rate limited for one emission interval rather than let through, so contention cannot be
+///   used to bypass the limit.
+/// - Memcached may evict entries early under memory pressure, which resets them to a full quota.
+/// - Time is taken from each server instance rather than from memcached, so limits
+///   may be off by as much as the clock skew between instances.
+///
+/// Entries are named `{prefix}:{method} {path}:{key:?}` using the [`Debug`](fmt::Debug) representation
+/// of the key, with whitespace and control characters escaped. Names longer than memcached's limit of
+/// 250 bytes are shortened with a hash of the full name.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use axum_gcra::axum;
+/// use axum::Router;
+/// use axum_gcra::{RateLimitLayer, real_ip::RealIp, store::MemcachedStore};
+///
+/// let app = Router::<()>::new().route_layer(
+///     RateLimitLayer::<RealIp>::builder()
+///         .with_store(MemcachedStore::new("127.0.0.1:11211").with_prefix("myapp:rl"))
+///         .default_handle_error(),
+/// );
+/// ```
+pub struct MemcachedStore {
+    addr: Cow<'static, str>,
+    prefix: Cow<'static, str>,
+    max_retries: u32,
+    max_idle: usize,
+    pool: Mutex<Vec<Connection>>,
+}
+
+impl MemcachedStore {
+    /// Create a new memcached store for the server at the given address,
+    /// with the default key prefix of `"gcra"`.
+    ///
+    /// Connections are opened lazily and reused between requests.
+    #[must_use]
+    pub fn new(addr: impl Into<Cow<'static, str>>) -> Self {
+        MemcachedStore {
+            addr: addr.into(),
+            prefix: Cow::Borrowed("gcra"),
+            max_retries: 8,
+            max_idle: 16,
+            pool: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Set the prefix used for all memcached keys.
+    #[must_use]
+    pub fn with_prefix(mut self, prefix: impl Into<Cow<'static, str>>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Set the maximum number of compare-and-swap retries for a contended key before
+    /// a request is rate limited. The default is 8.
+    #[must_use]
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Set the maximum number of idle connections kept open. The default is 16.
+    #[must_use]
+    pub fn with_max_idle(mut self, max_idle: usize) -> Self {
+        self.max_idle = max_idle;
+        self
+    }
+
+    fn memcached_key<K: fmt::Debug>(&self, key: &StoreKey<'_, K>) -> String {
+        use fmt::Write;
+
+        let raw = format!("{}:{} {}:{:?}", self.prefix, key.route.method, key.route.path, key.key);
+
+        let mut escaped = String::with_capacity(raw.len());
+        for c in raw.chars() {
+            match c {
+                '%' | '\0'..=' ' | '\x7f' => _ = write!(escaped, "%{:02X}", c as u32),
+                c => escaped.push(c),
+            }
+        }
+
+        if escaped.len() > MAX_KEY_LEN {
+            // FNV-1a, which is stable across instances and versions
+            let hash = raw.bytes().fold(0xcbf29ce484222325u64, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3));
+
+            let mut end = MAX_KEY_LEN - 17;
+            while !escaped.is_char_boundary(end) {
+                end -= 1;
+            }
+
+            escaped.truncate(end);
+            _ = write!(escaped, "#{hash:016x}");
+        }
+
+        escaped
+    }
+
+    async fn acquire(&self) -> StoreResult<Connection> {
+        if let Some(conn) = self.pool.lock().unwrap_or_else(|e| e.into_inner()).pop() {
+            return Ok(conn);
+        }
+
+        let stream = TcpStream::connect(&*self.addr).await.map_err(StoreError::new)?;
+        stream.set_nodelay(true).map_err(StoreError::new)?;
+
+        Ok(Connection(BufStream::new(stream)))
+    }
+
+    /// Return the connection to the pool if the operation succeeded, otherwise discard it.
+    fn release<T>(&self, conn: Connection, res: io::Result<T>) -> StoreResult<T> {
+        let res = res.map_err(StoreError::new)?;
+
+        let mut pool = self.pool.lock().unwrap_or_else(|e| e.into_inner());
+        if pool.len() < self.max_idle {
+            pool.push(conn);
+        }
+
+        Ok(res)
+    }
+
+    async fn update(
+        &self,
+        conn: &mut Connection,
+        key: &str,
+        quota: Quota,
+        n: u64,
+        now: u64,
+    ) -> io::Result<Result<u64, RateLimitError>> {
+        for _ in 0..=self.max_retries {
+            let prev = conn.gets(key).await?;
+
+            let tat = match gcra::decide(prev.map(|(tat, _)| tat), now, quota, n) {
+                Ok(tat) => tat,
+                Err(e) => return Ok(Err(e)),
+            };
+
+            let stored = match prev {
+                Some((_, cas)) => conn.store("cas", key, tat, expiry(tat, now), Some(cas)).await?,
+                None => conn.store("add", key, tat, expiry(tat, now), None).await?,
+            };
+
+            if stored {
+                return Ok(Ok(tat));
+            }
+        }
+
+        // too much contention, so throttle instead of letting requests through
+        Ok(Err(RateLimitError(quota.interval())))
+    }
+
+    async fn adjust_by(&self, conn: &mut Connection, key: &str, delta: i64) -> io::Result<bool> {
+        for _ in 0..=self.max_retries {
+            let Some((tat, cas)) = conn.gets(key).await? else {
+                return Ok(false);
+            };
+
+            let tat = tat.saturating_add_signed(delta);
+
+            if conn.store("cas", key, tat, expiry(tat, super::now()), Some(cas)).await? {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+impl fmt::Debug for MemcachedStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemcachedStore")
+            .field("addr", &self.addr)
+            .field("prefix", &self.prefix)
+            .field("max_retries", &self.max_retries)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Expiration time in seconds for the given TAT.
+fn expiry(tat: u64, now: u64) -> u64 {
+    let secs = Duration::from_nanos(tat.saturating_sub(now)).as_secs() + 1;
+    secs.min(MAX_RELATIVE_EXPIRY)
+}
+
+impl<K: Key> Store<K> for MemcachedStore {
+    fn get_update<'a>(
+        &'a self,
+        key: StoreKey<'a, K>,
+        quota: Quota,
+        n: u64,
+        now: u64,
+    ) -> BoxFuture<'a, StoreResult<Result<u64, RateLimitError>>> {
+        let key = self.memcached_key(&key);
+
+        Box::pin(async move {
+            let mut conn = self.acquire().await?;
+            let res = self.update(&mut conn, &key, quota, n, now).await;
+            self.release(conn, res)
+        })
+    }
+
+    fn remove<'a>(&'a self, key: StoreKey<'a, K>) -> BoxFuture<'a, StoreResult<bool>> {
+        let key = self.memcached_key(&key);
+
+        Box::pin(async move {
+            let mut conn = self.acquire().await?;
+            let res = conn.delete(&key).await;
+            self.release(conn, res)
+        })
+    }
+
+    fn gc(&self, _now: u64) -> BoxFuture<'_, StoreResult<GCStats>> {
+        // entries expire on their own
+        Box::pin(async { Ok(GCStats::default()) })
+    }
+
+    fn peek<'a>(&'a self, key: StoreKey<'a, K>) -> BoxFuture<'a, StoreResult<Option<u64>>> {
+        let key = self.memcached_key(&key);
+
+        Box::pin(async move {
+            let mut conn = self.acquire().await?;
+            let res = conn.gets(&key).await;
+            Ok(self.release(conn, res)?.map(|(tat, _)| tat))
+        })
+    }
+
+    fn adjust<'a>(&'a self, key: StoreKey<'a, K>, delta: i64) -> BoxFuture<'a, StoreResult<bool>> {
+        let key = self.memcached_key(&key);
+
+        Box::pin(async move {
+            let mut conn = self.acquire().await?;
+            let res = self.adjust_by(&mut conn, &key, delta).await;
+            self.release(conn, res)
+        })
+    }
+}
+
+/// Minimal memcached text protocol connection.
+struct Connection(BufStream<TcpStream>);
+
+fn protocol_error(line: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected memcached response: {line:?}"),
+    )
+}
+
+impl Connection {
+    async fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.0.read_line(&mut line).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        line.truncate(line.trim_end().len());
+        Ok(line)
+    }
+
+    /// Get the value and CAS token of the given key.
+    async fn gets(&mut self, key: &str) -> io::Result<Option<(u64, u64)>> {
+        self.0.write_all(format!("gets {key}\r\n").as_bytes()).await?;
+        self.0.flush().await?;
+
+        let line = self.read_line().await?;
+        if line == "END" {
+            return Ok(None);
+        }
+
+        // VALUE <key> <flags> <bytes> <cas>
+        let mut parts = line.split(' ');
+        let (Some("VALUE"), Some(_), Some(_), Some(len), Some(cas)) =
+            (parts.next(), parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(protocol_error(&line));
+        };
+
+        let len: usize = len.parse().map_err(|_| protocol_error(&line))?;
+        let cas: u64 = cas.parse().map_err(|_| protocol_error(&line))?;
+
+        let mut data = vec![0; len + 2]; // including trailing \r\n
+        self.0.read_exact(&mut data).await?;
+
+        let end = self.read_line().await?;
+        if end != "END" {
+            return Err(protocol_error(&end));
+        }
+
+        let value = std::str::from_utf8(&data[..len]).ok().and_then(|v| v.parse().ok());
+
+        // treat garbage values as missing entries, which `cas` will then overwrite
+        Ok(Some((value.unwrap_or(0), cas)))
+    }
+
+    /// Perform an `add` or `cas` storage command, returning `true` if the value was stored.
+    async fn store(
+        &mut self,
+        cmd: &str,
+        key: &str,
+        value: u64,
+        exptime: u64,
+        cas: Option<u64>,
+    ) -> io::Result<bool> {
+        let value = value.to_string();
+
+        let header = match cas {
+            Some(cas) => format!("{cmd} {key} 0 {exptime} {} {cas}\r\n", value.len()),
+            None => format!("{cmd} {key} 0 {exptime} {}\r\n", value.len()),
+        };
+
+        self.0.write_all(header.as_bytes()).await?;
+        self.0.write_all(value.as_bytes()).await?;
+        self.0.write_all(b"\r\n").await?;
+        self.0.flush().await?;
+
+        let line = self.read_line().await?;
+        match &*line {
+            "STORED" => Ok(true),
+            "NOT_STORED" | "EXISTS" | "NOT_FOUND" => Ok(false),
+            _ => Err(protocol_error(&line)),
+        }
+    }
+
+    /// Delete the given key, returning `true` if it was found.
+    async fn delete(&mut self, key: &str) -> io::Result<bool> {
+        self.0.write_all(format!("delete {key}\r\n").as_bytes()).await?;
+        self.0.flush().await?;
+
+        let line = self.read_line().await?;
+        match &*line {
+            "DELETED" => Ok(true),
+            "NOT_FOUND" => Ok(false),
+            _ => Err(protocol_error(&line)),
+        }
+    }
+}
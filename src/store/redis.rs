@@ -0,0 +1,552 @@
+//! [Redis](https://redis.io) storage backend, enabled with the `redis` cargo feature.
+//!
+//! Redis Cluster is supported with the `redis_cluster` feature by using a
+//! [`ClusterConnection`](redis::cluster_async::ClusterConnection), and Redis Sentinel with the
+//! `redis_sentinel` feature by using a [`SentinelConnection`].
+//!
+//! [Bans](crate::RateLimitLayerBuilder::ban_after) can also be shared through Redis with a [`RedisBanStore`],
+//! independently of where the rate limiter entries are stored.
+
+use std::{
+    borrow::Cow,
+    fmt,
+    num::NonZeroU64,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    task::{Context, Poll, Waker},
+};
+
+use futures_util::future::BoxFuture;
+use redis::{aio::ConnectionLike, Script};
+
+use super::{Store, StoreError, StoreKey, StoreResult};
+use crate::{
+    ban::{BanStore, StoredBan},
+    gcra::{stable_hash, Algorithm, GCStats, Quota},
+    Key, RateLimitError,
+};
+
+/// Atomically perform the GCRA update, see `Gcra::decide_n`.
+///
+/// Timestamps are in microseconds, as Lua numbers are doubles and
+/// cannot represent UNIX timestamps in nanoseconds exactly.
+const UPDATE_SCRIPT: &str = r"
+local now = tonumber(ARGV[1])
+local t = tonumber(ARGV[2])
+local tau = tonumber(ARGV[3])
+local n = tonumber(ARGV[4])
+
+local tat = tonumber(redis.call('GET', KEYS[1])) or (now + t)
+local base = math.max(now + t, tat)
+
+local next = base + t * (n - 1) - tau
+if now < next then
+    return {0, next - now}
+end
+
+local new = base + t * n
+redis.call('SET', KEYS[1], new, 'PX', math.ceil((new - now) / 1000) + 1)
+return {1, new}
+";
+
+/// Atomically add a signed offset to an existing entry, used for penalties and refunds.
+const ADJUST_SCRIPT: &str = r"
+local tat = tonumber(redis.call('GET', KEYS[1]))
+if not tat then
+    return 0
+end
+
+local now = tonumber(ARGV[2])
+local new = math.max(0, tat + tonumber(ARGV[1]))
+
+if new <= now then
+    redis.call('DEL', KEYS[1])
+else
+    redis.call('SET', KEYS[1], new, 'PX', math.ceil((new - now) / 1000) + 1)
+end
+
+return 1
+";
+
+/// [`Store`] backed by Redis, so that rate limits are enforced consistently across
+/// multiple server replicas.
+///
+/// Each entry is stored as a single integer key with an expiry equal to its theoretical
+/// arrival time, so Redis takes care of garbage collection itself. Entries are named
+/// `{prefix}:{hash}:{key:?}:{method} {path}`, with a hash of the key that is stable across replicas
+/// as a [hash tag](https://redis.io/docs/latest/operate/oss_and_stack/reference/cluster-spec/#hash-tags)
+/// in hexadecimal, so all entries of a single client live on the same Redis Cluster shard. The
+/// [`Debug`](fmt::Debug) representation of the key follows it, so any braces in it cannot affect the hash tag.
+///
+/// Only [GCRA](crate::gcra::Algorithm::Gcra) quotas are supported, as the Lua script works in microseconds,
+/// and requests with other quotas fail with a [`StoreError`].
+///
+/// The connection type `C` can be any cloneable asynchronous Redis connection,
+/// such as [`ConnectionManager`](redis::aio::ConnectionManager),
+/// [`MultiplexedConnection`](redis::aio::MultiplexedConnection), a cluster connection or a
+/// [`SentinelConnection`], and is cloned for each request.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use axum_gcra::axum;
+/// use axum::Router;
+/// use axum_gcra::{RateLimitLayer, real_ip::RealIp, store::RedisStore};
+///
+/// # async fn example() -> redis::RedisResult<()> {
+/// let client = redis::Client::open("redis://127.0.0.1/")?;
+/// let conn = redis::aio::ConnectionManager::new(client).await?;
+///
+/// let app = Router::<()>::new().route_layer(
+///     RateLimitLayer::<RealIp>::builder()
+///         .with_store(RedisStore::new(conn).with_prefix("myapp:rl"))
+///         .default_handle_error(),
+/// );
+/// # Ok(()) }
+/// ```
+pub struct RedisStore<C> {
+    conn: C,
+    prefix: Cow<'static, str>,
+    update: Script,
+    adjust: Script,
+    in_flight: InFlight,
+}
+
+/// Limit on concurrent rate limit checks, see [`RedisStore::with_max_in_flight`].
+struct InFlight {
+    max: usize,
+    current: AtomicUsize,
+
+    /// Tasks waiting in [`Store::poll_ready`] for the number of checks to drop below the limit.
+    waiters: Mutex<Vec<Waker>>,
+}
+
+/// Counts a check against the [`InFlight`] limit until dropped.
+struct InFlightGuard<'a>(&'a InFlight);
+
+impl InFlight {
+    fn enter(&self) -> InFlightGuard<'_> {
+        self.current.fetch_add(1, Ordering::AcqRel);
+        InFlightGuard(self)
+    }
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.current.load(Ordering::Acquire) < self.max {
+            return Poll::Ready(());
+        }
+
+        let mut waiters = self.waiters.lock().unwrap_or_else(|e| e.into_inner());
+
+        // check again after registering, as a check may have finished in the meantime
+        if self.current.load(Ordering::Acquire) < self.max {
+            return Poll::Ready(());
+        }
+
+        if !waiters.iter().any(|w| w.will_wake(cx.waker())) {
+            waiters.push(cx.waker().clone());
+        }
+
+        Poll::Pending
+    }
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        if self.0.current.fetch_sub(1, Ordering::AcqRel) == self.0.max {
+            let waiters = std::mem::take(&mut *self.0.waiters.lock().unwrap_or_else(|e| e.into_inner()));
+            waiters.into_iter().for_each(Waker::wake);
+        }
+    }
+}
+
+impl<C> RedisStore<C> {
+    /// Create a new Redis store using the given connection, with the default key prefix of `"gcra"`.
+    #[must_use]
+    pub fn new(conn: C) -> Self {
+        RedisStore {
+            conn,
+            prefix: Cow::Borrowed("gcra"),
+            update: Script::new(UPDATE_SCRIPT),
+            adjust: Script::new(ADJUST_SCRIPT),
+            in_flight: InFlight {
+                max: usize::MAX,
+                current: AtomicUsize::new(0),
+                waiters: Mutex::default(),
+            },
+        }
+    }
+
+    /// Limit the number of rate limit checks awaiting Redis at once, such as while it is saturated
+    /// or reconnecting. The default is unlimited.
+    ///
+    /// Once the limit is reached, the store reports that it is not [ready](Store::poll_ready),
+    /// so [`RateLimitService::poll_ready`](tower::Service::poll_ready) returns `Poll::Pending`
+    /// until a check completes, allowing load balancers and other tower layers to apply backpressure.
+    /// Callers that don't wait for readiness, such as
+    /// [`RateLimitLayer::check_many`](crate::RateLimitLayer::check_many), are not limited.
+    #[must_use]
+    pub fn with_max_in_flight(mut self, max: usize) -> Self {
+        self.in_flight.max = max.max(1);
+        self
+    }
+
+    /// Set the prefix used for all Redis keys, such as to share a Redis instance between applications.
+    #[must_use]
+    pub fn with_prefix(mut self, prefix: impl Into<Cow<'static, str>>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Get the prefix used for all Redis keys.
+    #[must_use]
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    fn redis_key<K: Key>(&self, key: &StoreKey<'_, K>) -> String {
+        format!(
+            "{}:{{{:016x}}}:{:?}:{} {}",
+            self.prefix,
+            stable_hash(key.key),
+            key.key,
+            key.route.method,
+            key.route.path
+        )
+    }
+}
+
+impl<C: fmt::Debug> fmt::Debug for RedisStore<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisStore")
+            .field("conn", &self.conn)
+            .field("prefix", &self.prefix)
+            .field("max_in_flight", &self.in_flight.max)
+            .finish()
+    }
+}
+
+#[inline]
+const fn micros(nanos: u64) -> u64 {
+    nanos / 1000
+}
+
+impl<K, C> Store<K> for RedisStore<C>
+where
+    K: Key,
+    C: ConnectionLike + Clone + Send + Sync + 'static,
+{
+    fn get_update<'a>(
+        &'a self,
+        key: StoreKey<'a, K>,
+        quota: Quota,
+        n: u64,
+        now: u64,
+    ) -> BoxFuture<'a, StoreResult<Result<u64, RateLimitError>>> {
+        if quota.algorithm() != Algorithm::Gcra {
+            return Box::pin(async { Err(StoreError::new("RedisStore only supports GCRA quotas")) });
+        }
+
+        let mut conn = self.conn.clone();
+        let mut invocation = self.update.key(self.redis_key(&key));
+
+        invocation.arg(micros(now)).arg(micros(quota.t).max(1)).arg(micros(quota.tau)).arg(n);
+
+        Box::pin(async move {
+            let _guard = self.in_flight.enter();
+
+            let (allowed, value): (u8, u64) = invocation.invoke_async(&mut conn).await.map_err(StoreError::new)?;
+
+            let nanos = value.saturating_mul(1000);
+
+            Ok(match (allowed, NonZeroU64::new(nanos)) {
+                (1, _) => Ok(nanos),
+                (_, Some(retry)) => Err(RateLimitError(retry)),
+                (_, None) => Err(RateLimitError(NonZeroU64::MIN)),
+            })
+        })
+    }
+
+    fn remove<'a>(&'a self, key: StoreKey<'a, K>) -> BoxFuture<'a, StoreResult<bool>> {
+        let mut conn = self.conn.clone();
+        let mut cmd = redis::cmd("DEL");
+        cmd.arg(self.redis_key(&key));
+
+        Box::pin(async move {
+            let removed: u64 = cmd.query_async(&mut conn).await.map_err(StoreError::new)?;
+            Ok(removed > 0)
+        })
+    }
+
+    fn gc(&self, _now: u64) -> BoxFuture<'_, StoreResult<GCStats>> {
+        // entries expire on their own
+        Box::pin(async { Ok(GCStats::default()) })
+    }
+
+    fn peek<'a>(&'a self, key: StoreKey<'a, K>) -> BoxFuture<'a, StoreResult<Option<u64>>> {
+        let mut conn = self.conn.clone();
+        let mut cmd = redis::cmd("GET");
+        cmd.arg(self.redis_key(&key));
+
+        Box::pin(async move {
+            let tat: Option<u64> = cmd.query_async(&mut conn).await.map_err(StoreError::new)?;
+            Ok(tat.map(|tat| tat.saturating_mul(1000)))
+        })
+    }
+
+    fn adjust<'a>(&'a self, key: StoreKey<'a, K>, delta: i64) -> BoxFuture<'a, StoreResult<bool>> {
+        let mut conn = self.conn.clone();
+        let mut invocation = self.adjust.key(self.redis_key(&key));
+        invocation.arg(delta / 1000).arg(micros(super::now()));
+
+        Box::pin(async move {
+            let found: u8 = invocation.invoke_async(&mut conn).await.map_err(StoreError::new)?;
+            Ok(found == 1)
+        })
+    }
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<StoreResult<()>> {
+        self.in_flight.poll_ready(cx).map(Ok)
+    }
+}
+
+/// Remove expired bans, then list the remaining bans as flat `hash, until, key` triples.
+///
+/// Bans are kept in a sorted set scored by the end of the ban in milliseconds since the UNIX epoch,
+/// with the [`Debug`](fmt::Debug) representations of the keys in a separate hash.
+const LOAD_BANS_SCRIPT: &str = r"
+local now = tonumber(ARGV[1])
+
+for _, hash in ipairs(redis.call('ZRANGEBYSCORE', KEYS[1], '-inf', now)) do
+    redis.call('HDEL', KEYS[2], hash)
+end
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now)
+
+local bans = redis.call('ZRANGE', KEYS[1], 0, -1, 'WITHSCORES')
+local out = {}
+for i = 1, #bans, 2 do
+    out[#out + 1] = bans[i]
+    out[#out + 1] = bans[i + 1]
+    out[#out + 1] = redis.call('HGET', KEYS[2], bans[i]) or ''
+end
+
+return out
+";
+
+/// [`BanStore`] backed by Redis, so that [bans](crate::RateLimitLayerBuilder::ban_after) are shared
+/// across replicas and survive restarts, see [`RateLimitLayerBuilder::with_ban_store`](crate::RateLimitLayerBuilder::with_ban_store).
+///
+/// All bans are stored in a sorted set named `{prefix}:{bans}` and a hash named `{prefix}:{bans}:keys`,
+/// which share a hash tag so they live on the same Redis Cluster shard. Expired bans are removed when loaded.
+///
+/// The connection type `C` is the same as for [`RedisStore`].
+pub struct RedisBanStore<C> {
+    conn: C,
+    prefix: Cow<'static, str>,
+    load: Script,
+}
+
+impl<C> RedisBanStore<C> {
+    /// Create a new Redis ban store using the given connection, with the default key prefix of `"gcra"`.
+    #[must_use]
+    pub fn new(conn: C) -> Self {
+        RedisBanStore {
+            conn,
+            prefix: Cow::Borrowed("gcra"),
+            load: Script::new(LOAD_BANS_SCRIPT),
+        }
+    }
+
+    /// Set the prefix used for all Redis keys, such as to share a Redis instance between applications.
+    #[must_use]
+    pub fn with_prefix(mut self, prefix: impl Into<Cow<'static, str>>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Get the prefix used for all Redis keys.
+    #[must_use]
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    fn redis_keys(&self) -> (String, String) {
+        (
+            format!("{}:{{bans}}", self.prefix),
+            format!("{}:{{bans}}:keys", self.prefix),
+        )
+    }
+}
+
+impl<C: fmt::Debug> fmt::Debug for RedisBanStore<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisBanStore").field("conn", &self.conn).field("prefix", &self.prefix).finish()
+    }
+}
+
+impl<C> BanStore for RedisBanStore<C>
+where
+    C: ConnectionLike + Clone + Send + Sync + 'static,
+{
+    fn ban<'a>(&'a self, ban: &'a StoredBan) -> BoxFuture<'a, StoreResult<()>> {
+        let mut conn = self.conn.clone();
+        let (bans, keys) = self.redis_keys();
+        let hash = format!("{:016x}", ban.hash);
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        pipe.cmd("ZADD").arg(bans).arg(ban.until / 1_000_000).arg(&hash).ignore();
+        pipe.cmd("HSET").arg(keys).arg(&hash).arg(&ban.key).ignore();
+
+        Box::pin(async move { pipe.query_async(&mut conn).await.map_err(StoreError::new) })
+    }
+
+    fn unban(&self, hash: u64) -> BoxFuture<'_, StoreResult<()>> {
+        let mut conn = self.conn.clone();
+        let (bans, keys) = self.redis_keys();
+        let hash = format!("{hash:016x}");
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        pipe.cmd("ZREM").arg(bans).arg(&hash).ignore();
+        pipe.cmd("HDEL").arg(keys).arg(&hash).ignore();
+
+        Box::pin(async move { pipe.query_async(&mut conn).await.map_err(StoreError::new) })
+    }
+
+    fn load(&self, now: u64) -> BoxFuture<'_, StoreResult<Vec<StoredBan>>> {
+        let mut conn = self.conn.clone();
+        let (bans, keys) = self.redis_keys();
+
+        let mut invocation = self.load.key(bans);
+        invocation.key(keys).arg(now / 1_000_000);
+
+        Box::pin(async move {
+            let flat: Vec<String> = invocation.invoke_async(&mut conn).await.map_err(StoreError::new)?;
+
+            let invalid = || StoreError::new("invalid ban entry in Redis");
+
+            flat.chunks_exact(3)
+                .map(|ban| {
+                    Ok(StoredBan {
+                        hash: u64::from_str_radix(&ban[0], 16).map_err(|_| invalid())?,
+                        until: (ban[1].parse::<f64>().map_err(|_| invalid())? as u64).saturating_mul(1_000_000),
+                        key: ban[2].clone(),
+                    })
+                })
+                .collect()
+        })
+    }
+}
+
+#[cfg(feature = "redis_sentinel")]
+pub use self::sentinel::SentinelConnection;
+
+#[cfg(feature = "redis_sentinel")]
+mod sentinel {
+    use std::sync::{Arc, Mutex};
+
+    use redis::{
+        aio::{ConnectionLike, MultiplexedConnection},
+        sentinel::SentinelClient,
+        Cmd, ErrorKind, Pipeline, RedisError, RedisFuture, RedisResult, Value,
+    };
+
+    /// Cloneable asynchronous connection to the current Redis Sentinel master,
+    /// for use with [`RedisStore`](super::RedisStore).
+    ///
+    /// The master is resolved through the sentinels when first connecting, and again
+    /// after connection errors or `READONLY` replies, such as after a failover.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use axum_gcra::store::{RedisStore, redis::SentinelConnection};
+    /// use redis::sentinel::{SentinelClient, SentinelServerType};
+    ///
+    /// # async fn example() -> redis::RedisResult<()> {
+    /// let client = SentinelClient::build(
+    ///     vec!["redis://127.0.0.1:26379/"],
+    ///     "mymaster".to_owned(),
+    ///     None,
+    ///     SentinelServerType::Master,
+    /// )?;
+    ///
+    /// let store = RedisStore::new(SentinelConnection::new(client).await?);
+    /// # Ok(()) }
+    /// ```
+    #[derive(Clone)]
+    pub struct SentinelConnection {
+        client: Arc<tokio::sync::Mutex<SentinelClient>>,
+        conn: Arc<Mutex<Option<MultiplexedConnection>>>,
+        db: i64,
+    }
+
+    impl SentinelConnection {
+        /// Connect to the current master of the given sentinel client.
+        pub async fn new(mut client: SentinelClient) -> RedisResult<Self> {
+            let conn = client.get_async_connection().await?;
+
+            Ok(SentinelConnection {
+                db: conn.get_db(),
+                client: Arc::new(tokio::sync::Mutex::new(client)),
+                conn: Arc::new(Mutex::new(Some(conn))),
+            })
+        }
+
+        async fn connection(&self) -> RedisResult<MultiplexedConnection> {
+            if let Some(conn) = self.conn.lock().unwrap_or_else(|e| e.into_inner()).clone() {
+                return Ok(conn);
+            }
+
+            let mut client = self.client.lock().await;
+
+            // another task may have reconnected while waiting for the lock
+            if let Some(conn) = self.conn.lock().unwrap_or_else(|e| e.into_inner()).clone() {
+                return Ok(conn);
+            }
+
+            let conn = client.get_async_connection().await?;
+            *self.conn.lock().unwrap_or_else(|e| e.into_inner()) = Some(conn.clone());
+            Ok(conn)
+        }
+
+        fn check<T>(&self, res: RedisResult<T>) -> RedisResult<T> {
+            fn is_stale(e: &RedisError) -> bool {
+                e.is_io_error() || e.is_connection_dropped() || e.kind() == ErrorKind::ReadOnly
+            }
+
+            if matches!(res, Err(ref e) if is_stale(e)) {
+                *self.conn.lock().unwrap_or_else(|e| e.into_inner()) = None;
+            }
+
+            res
+        }
+    }
+
+    impl ConnectionLike for SentinelConnection {
+        fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+            Box::pin(async move {
+                let mut conn = self.connection().await?;
+                self.check(conn.req_packed_command(cmd).await)
+            })
+        }
+
+        fn req_packed_commands<'a>(
+            &'a mut self,
+            cmd: &'a Pipeline,
+            offset: usize,
+            count: usize,
+        ) -> RedisFuture<'a, Vec<Value>> {
+            Box::pin(async move {
+                let mut conn = self.connection().await?;
+                self.check(conn.req_packed_commands(cmd, offset, count).await)
+            })
+        }
+
+        fn get_db(&self) -> i64 {
+            self.db
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Consistent-hash sharding of keys across multiple [`Store`] instances.
+
+use std::{
+    fmt,
+    task::{Context, Poll},
+};
+
+use futures_util::future::BoxFuture;
+
+use super::{Store, StoreKey, StoreResult};
+use crate::{
+    gcra::{stable_hash, GCStats, Quota},
+    Key, RateLimitError,
+};
+
+/// [`Store`] that spreads keys across several store instances by consistent hashing,
+/// such as to split a very large keyspace across multiple Redis nodes without cluster mode.
+///
+/// Each key is always routed to the same shard, and adding or removing a shard only moves
+/// about `1/N` of the keys to a different shard, which then start over with a full quota.
+///
+/// Shards are placed on the hash ring by their position in the list, so all instances sharing
+/// the same shards must list them in the same order. Key hashes are computed from the key's
+/// [`Hash`](std::hash::Hash) implementation with a fixed hash function, so they agree between
+/// processes running the same build.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use axum_gcra::axum;
+/// use axum::Router;
+/// use axum_gcra::{RateLimitLayer, real_ip::RealIp, store::{ShardedStore, Store}};
+///
+/// # fn example(shards: Vec<impl Store<RealIp>>) {
+/// // e.g. one `RedisStore` per node
+/// let app = Router::<()>::new().route_layer(
+///     RateLimitLayer::<RealIp>::builder()
+///         .with_store(ShardedStore::new(shards))
+///         .default_handle_error(),
+/// );
+/// # }
+/// ```
+pub struct ShardedStore<S> {
+    shards: Vec<S>,
+    virtual_nodes: usize,
+
+    /// Sorted points on the hash ring, and the shard that owns each.
+    ring: Vec<(u64, usize)>,
+}
+
+impl<S> ShardedStore<S> {
+    /// Create a new sharded store over the given shards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no shards are given.
+    #[must_use]
+    pub fn new(shards: impl IntoIterator<Item = S>) -> Self {
+        let shards: Vec<S> = shards.into_iter().collect();
+
+        assert!(!shards.is_empty(), "ShardedStore requires at least one shard");
+
+        let mut store = ShardedStore {
+            shards,
+            virtual_nodes: 0,
+            ring: Vec::new(),
+        };
+
+        store.build_ring(128);
+        store
+    }
+
+    /// Set the number of points each shard occupies on the hash ring. The default is 128.
+    ///
+    /// More points spread keys more evenly between shards, at the cost of a slightly slower lookup.
+    #[must_use]
+    pub fn with_virtual_nodes(mut self, virtual_nodes: usize) -> Self {
+        self.build_ring(virtual_nodes.max(1));
+        self
+    }
+
+    /// Get the shards of this store, in the order given.
+    #[must_use]
+    pub fn shards(&self) -> &[S] {
+        &self.shards
+    }
+
+    /// Get the shard responsible for the given key.
+    #[must_use]
+    pub fn shard_for<K: Key>(&self, key: &StoreKey<'_, K>) -> &S {
+        let hash = stable_hash(key);
+
+        // first point at or after the hash, wrapping around the ring
+        let idx = self.ring.partition_point(|&(point, _)| point < hash);
+
+        &self.shards[self.ring.get(idx).unwrap_or(&self.ring[0]).1]
+    }
+
+    fn build_ring(&mut self, virtual_nodes: usize) {
+        self.virtual_nodes = virtual_nodes;
+        self.ring.clear();
+
+        for shard in 0..self.shards.len() {
+            for node in 0..virtual_nodes {
+                self.ring.push((stable_hash(&(shard as u64, node as u64)), shard));
+            }
+        }
+
+        self.ring.sort_unstable();
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for ShardedStore<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShardedStore")
+            .field("shards", &self.shards)
+            .field("virtual_nodes", &self.virtual_nodes)
+            .finish()
+    }
+}
+
+impl<K: Key, S: Store<K>> Store<K> for ShardedStore<S> {
+    fn get_update<'a>(
+        &'a self,
+        key: StoreKey<'a, K>,
+        quota: Quota,
+        n: u64,
+        now: u64,
+    ) -> BoxFuture<'a, StoreResult<Result<u64, RateLimitError>>> {
+        self.shard_for(&key).get_update(key, quota, n, now)
+    }
+
+    fn remove<'a>(&'a self, key: StoreKey<'a, K>) -> BoxFuture<'a, StoreResult<bool>> {
+        self.shard_for(&key).remove(key)
+    }
+
+    /// Runs garbage collection on every shard, returning the first error, if any,
+    /// after attempting all shards.
+    fn gc(&self, now: u64) -> BoxFuture<'_, StoreResult<GCStats>> {
+        Box::pin(async move {
+            let mut stats = GCStats::default();
+            let mut res = Ok(());
+
+            for shard in &self.shards {
+                match shard.gc(now).await {
+                    Ok(shard) => {
+                        stats.scanned += shard.scanned;
+                        stats.evicted += shard.evicted;
+                    }
+                    Err(e) if res.is_ok() => res = Err(e),
+                    Err(_) => {}
+                }
+            }
+
+            res.map(|_| stats)
+        })
+    }
+
+    fn peek<'a>(&'a self, key: StoreKey<'a, K>) -> BoxFuture<'a, StoreResult<Option<u64>>> {
+        self.shard_for(&key).peek(key)
+    }
+
+    fn adjust<'a>(&'a self, key: StoreKey<'a, K>, delta: i64) -> BoxFuture<'a, StoreResult<bool>> {
+        self.shard_for(&key).adjust(key, delta)
+    }
+
+    fn charge<'a>(
+        &'a self,
+        key: StoreKey<'a, K>,
+        quota: Quota,
+        n: u64,
+        now: u64,
+    ) -> BoxFuture<'a, StoreResult<Option<u64>>> {
+        self.shard_for(&key).charge(key, quota, n, now)
+    }
+
+    /// Ready only once all shards are ready, as the next request may go to any of them.
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<StoreResult<()>> {
+        let mut ready = true;
+
+        for shard in &self.shards {
+            match shard.poll_ready(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => ready = false,
+            }
+        }
+
+        if ready {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
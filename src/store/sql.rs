@@ -0,0 +1,337 @@
+//! SQL storage backend using [`sqlx`], enabled with the `sqlite` and/or `postgres` cargo features.
+
+use std::{
+    borrow::Cow,
+    fmt,
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
+
+use futures_util::future::BoxFuture;
+use scc::hash_map::{Entry as MapEntry, HashMap};
+use sqlx::{AnyPool, Row};
+
+use super::{Store, StoreError, StoreKey, StoreResult};
+use crate::{
+    gcra::{self, GCStats, Quota},
+    Key, RateLimitError,
+};
+
+/// Persistent [`Store`] backed by an SQL database using [`sqlx`], such as SQLite or Postgres,
+/// so that rate limits survive restarts without extra infrastructure.
+///
+/// This is intended for low-traffic deployments such as admin panels. Entries are kept in memory
+/// and loaded from the database when the store is built, and changes are written back in batches
+/// every [flush interval](SqlStoreBuilder::with_flush_interval), so at most one interval of
+/// consumption is lost if the process crashes. Garbage collection evicts expired entries from
+/// both memory and the database, with the same semantics as the in-memory rate limiter,
+/// and also runs on every flush.
+///
+/// The store is not meant to be shared by multiple server instances at the same time,
+/// as each instance only reads the database on startup.
+///
+/// The [`AnyPool`] requires the relevant drivers to be installed first
+/// with [`sqlx::any::install_default_drivers`].
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use axum_gcra::axum;
+/// use axum::Router;
+/// use axum_gcra::{RateLimitLayer, real_ip::RealIp, store::SqlStore};
+///
+/// # async fn example() -> Result<(), sqlx::Error> {
+/// sqlx::any::install_default_drivers();
+///
+/// let pool = sqlx::AnyPool::connect("sqlite://ratelimits.db?mode=rwc").await?;
+/// let store = SqlStore::builder(pool).with_table("admin_rate_limits").build().await?;
+///
+/// let app = Router::<()>::new().route_layer(
+///     RateLimitLayer::<RealIp>::builder()
+///         .with_store(store.clone())
+///         .default_handle_error(),
+/// );
+///
+/// // ...serve the app, then before exiting:
+/// store.flush().await?;
+/// # Ok(()) }
+/// ```
+#[derive(Clone)]
+pub struct SqlStore {
+    inner: Arc<SqlStoreInner>,
+}
+
+struct SqlStoreInner {
+    pool: AnyPool,
+    table: Cow<'static, str>,
+    entries: HashMap<String, Entry>,
+    removed: Mutex<Vec<String>>,
+}
+
+struct Entry {
+    tat: u64,
+    dirty: bool,
+}
+
+/// Builder for [`SqlStore`].
+pub struct SqlStoreBuilder {
+    pool: AnyPool,
+    table: Cow<'static, str>,
+    flush_interval: Duration,
+}
+
+impl SqlStore {
+    /// Begin building a new SQL store using the given connection pool.
+    #[must_use]
+    pub fn builder(pool: AnyPool) -> SqlStoreBuilder {
+        SqlStoreBuilder {
+            pool,
+            table: Cow::Borrowed("gcra_rate_limits"),
+            flush_interval: Duration::from_secs(5),
+        }
+    }
+
+    /// Write all pending changes to the database right now, such as before shutting down.
+    pub async fn flush(&self) -> Result<(), sqlx::Error> {
+        self.inner.flush().await
+    }
+}
+
+impl SqlStoreBuilder {
+    /// Set the name of the table used to store entries, which is created if it does not exist.
+    ///
+    /// The default is `gcra_rate_limits`. Only ASCII letters, digits and underscores are allowed.
+    #[must_use]
+    pub fn with_table(mut self, table: impl Into<Cow<'static, str>>) -> Self {
+        self.table = table.into();
+        self
+    }
+
+    /// Set how often pending changes are written to the database. The default is 5 seconds.
+    #[must_use]
+    pub fn with_flush_interval(mut self, flush_interval: Duration) -> Self {
+        self.flush_interval = flush_interval;
+        self
+    }
+
+    /// Create the table if necessary, load all unexpired entries and spawn the
+    /// background task that writes changes back to the database.
+    pub async fn build(self) -> Result<SqlStore, sqlx::Error> {
+        if self.table.is_empty() || !self.table.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+            return Err(sqlx::Error::Configuration(
+                format!("invalid rate limit table name: {:?}", self.table).into(),
+            ));
+        }
+
+        let table = &self.table;
+
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {table} (key TEXT PRIMARY KEY NOT NULL, tat BIGINT NOT NULL)"
+        ))
+        .execute(&self.pool)
+        .await?;
+
+        let rows = sqlx::query(&format!("SELECT key, tat FROM {table} WHERE tat >= $1"))
+            .bind(super::now() as i64)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let entries = HashMap::default();
+
+        for row in rows {
+            let key: String = row.try_get(0)?;
+            let tat: i64 = row.try_get(1)?;
+
+            _ = entries.insert(
+                key,
+                Entry {
+                    tat: tat as u64,
+                    dirty: false,
+                },
+            );
+        }
+
+        let inner = Arc::new(SqlStoreInner {
+            pool: self.pool,
+            table: self.table,
+            entries,
+            removed: Mutex::new(Vec::new()),
+        });
+
+        tokio::task::spawn(SqlStoreInner::flush_task(Arc::downgrade(&inner), self.flush_interval));
+
+        Ok(SqlStore { inner })
+    }
+}
+
+impl SqlStoreInner {
+    async fn flush_task(store: Weak<SqlStoreInner>, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+        interval.tick().await; // first tick is immediate
+
+        loop {
+            interval.tick().await;
+
+            // stop once all stores have been dropped
+            let Some(store) = store.upgrade() else { break };
+
+            // errors are retried on the next tick
+            _ = store.gc(super::now()).await;
+            _ = store.flush().await;
+        }
+    }
+
+    async fn flush(&self) -> Result<(), sqlx::Error> {
+        let removed = std::mem::take(&mut *self.removed.lock().unwrap_or_else(|e| e.into_inner()));
+
+        let mut dirty = Vec::new();
+        self.entries
+            .retain_async(|key, entry| {
+                if entry.dirty {
+                    entry.dirty = false;
+                    dirty.push((key.clone(), entry.tat));
+                }
+                true
+            })
+            .await;
+
+        if removed.is_empty() && dirty.is_empty() {
+            return Ok(());
+        }
+
+        match self.write(&removed, &dirty).await {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                // put back any changes for the next flush
+                self.removed.lock().unwrap_or_else(|e| e.into_inner()).extend(removed);
+
+                for (key, _) in dirty {
+                    self.entries.update_async(&key, |_, entry| entry.dirty = true).await;
+                }
+
+                Err(e)
+            }
+        }
+    }
+
+    async fn gc(&self, now: u64) -> Result<GCStats, sqlx::Error> {
+        let mut stats = GCStats::default();
+
+        self.entries
+            .retain_async(|_, entry| {
+                let keep = entry.tat >= now;
+                stats.scanned += 1;
+                stats.evicted += !keep as usize;
+                keep
+            })
+            .await;
+
+        sqlx::query(&format!("DELETE FROM {} WHERE tat < $1", self.table))
+            .bind(now as i64)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(stats)
+    }
+
+    async fn write(&self, removed: &[String], dirty: &[(String, u64)]) -> Result<(), sqlx::Error> {
+        let table = &self.table;
+        let delete = format!("DELETE FROM {table} WHERE key = $1");
+        let upsert = format!(
+            "INSERT INTO {table} (key, tat) VALUES ($1, $2) ON CONFLICT (key) DO UPDATE SET tat = excluded.tat"
+        );
+
+        let mut tx = self.pool.begin().await?;
+
+        // deletions first, so re-added entries are kept
+        for key in removed {
+            sqlx::query(&delete).bind(key).execute(&mut *tx).await?;
+        }
+
+        for (key, tat) in dirty {
+            sqlx::query(&upsert).bind(key).bind(*tat as i64).execute(&mut *tx).await?;
+        }
+
+        tx.commit().await
+    }
+
+    fn sql_key<K: fmt::Debug>(key: &StoreKey<'_, K>) -> String {
+        format!("{} {}:{:?}", key.route.method, key.route.path, key.key)
+    }
+}
+
+impl fmt::Debug for SqlStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SqlStore")
+            .field("table", &self.inner.table)
+            .field("entries", &self.inner.entries.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K: Key> Store<K> for SqlStore {
+    fn get_update<'a>(
+        &'a self,
+        key: StoreKey<'a, K>,
+        quota: Quota,
+        n: u64,
+        now: u64,
+    ) -> BoxFuture<'a, StoreResult<Result<u64, RateLimitError>>> {
+        let key = SqlStoreInner::sql_key(&key);
+
+        Box::pin(async move {
+            Ok(match self.inner.entries.entry_async(key).await {
+                MapEntry::Occupied(mut entry) => {
+                    let entry = entry.get_mut();
+                    gcra::decide(Some(entry.tat), now, quota, n).inspect(|&tat| {
+                        entry.tat = tat;
+                        entry.dirty = true;
+                    })
+                }
+                MapEntry::Vacant(entry) => gcra::decide(None, now, quota, n).inspect(|&tat| {
+                    entry.insert_entry(Entry { tat, dirty: true });
+                }),
+            })
+        })
+    }
+
+    fn remove<'a>(&'a self, key: StoreKey<'a, K>) -> BoxFuture<'a, StoreResult<bool>> {
+        let key = SqlStoreInner::sql_key(&key);
+
+        Box::pin(async move {
+            let found = self.inner.entries.remove_async(&key).await.is_some();
+
+            // the entry may still be in the database even if not in memory
+            self.inner.removed.lock().unwrap_or_else(|e| e.into_inner()).push(key);
+
+            Ok(found)
+        })
+    }
+
+    fn gc(&self, now: u64) -> BoxFuture<'_, StoreResult<GCStats>> {
+        Box::pin(async move { self.inner.gc(now).await.map_err(StoreError::new) })
+    }
+
+    fn peek<'a>(&'a self, key: StoreKey<'a, K>) -> BoxFuture<'a, StoreResult<Option<u64>>> {
+        let key = SqlStoreInner::sql_key(&key);
+
+        Box::pin(async move { Ok(self.inner.entries.read_async(&key, |_, entry| entry.tat).await) })
+    }
+
+    fn adjust<'a>(&'a self, key: StoreKey<'a, K>, delta: i64) -> BoxFuture<'a, StoreResult<bool>> {
+        let key = SqlStoreInner::sql_key(&key);
+
+        Box::pin(async move {
+            let found = self
+                .inner
+                .entries
+                .update_async(&key, |_, entry| {
+                    entry.tat = entry.tat.saturating_add_signed(delta);
+                    entry.dirty = true;
+                })
+                .await;
+
+            Ok(found.is_some())
+        })
+    }
+}
@@ -0,0 +1,160 @@
+//! Helpers for integration tests of rate limit configurations, enabled with the `testing` cargo feature.
+//!
+//! [`TestLimiter`] builds a rate limiter driven by a [`ManualClock`], so tests can [advance](TestLimiter::advance)
+//! time by hand and assert exact throttle boundaries without sleeping, and [`request_from`] builds requests
+//! with forged client IP headers, as understood by [`RealIp`](crate::real_ip::RealIp), to act as different clients.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use axum_gcra::axum;
+//! use std::time::Duration;
+//! use axum::{routing::get, Router};
+//! use axum_gcra::{gcra::Quota, real_ip::RealIp, testing::{self, TestLimiter}};
+//! use http::StatusCode;
+//! use tower::ServiceExt;
+//!
+//! # #[tokio::main(flavor = "current_thread")] async fn main() {
+//! let limiter = TestLimiter::<RealIp>::new(Quota::simple(Duration::from_secs(1)));
+//!
+//! let app = Router::new()
+//!     .route("/", get(|| async { "Hello, World!" }))
+//!     .route_layer(limiter.layer().default_handle_error());
+//!
+//! let status = |ip: [u8; 4]| {
+//!     let app = app.clone();
+//!     async move { app.oneshot(testing::get_from("/", ip)).await.unwrap().status() }
+//! };
+//!
+//! assert_eq!(status([1, 1, 1, 1]).await, StatusCode::OK);
+//! assert_eq!(status([1, 1, 1, 1]).await, StatusCode::TOO_MANY_REQUESTS);
+//!
+//! // other clients have their own quota
+//! assert_eq!(status([2, 2, 2, 2]).await, StatusCode::OK);
+//!
+//! limiter.advance(Duration::from_millis(999));
+//! assert_eq!(status([1, 1, 1, 1]).await, StatusCode::TOO_MANY_REQUESTS);
+//!
+//! limiter.advance(Duration::from_millis(1));
+//! assert_eq!(status([1, 1, 1, 1]).await, StatusCode::OK);
+//! # }
+//! ```
+
+use std::{fmt, hash::BuildHasher, net::IpAddr, time::Duration};
+
+use axum::{body::Body, extract::FromRequestParts};
+use http::{header::HeaderName, Method, Request};
+
+use crate::{
+    clock::{Clock, Instant, ManualClock},
+    gcra::Quota,
+    Key, RateLimitLayer, RateLimitLayerBuilder,
+};
+
+/// Rate limiter driven by a [`ManualClock`] for tests, see the [module documentation](self).
+pub struct TestLimiter<K: Key = (), H: BuildHasher = std::collections::hash_map::RandomState> {
+    clock: ManualClock,
+    layer: RateLimitLayer<K, H>,
+}
+
+impl<K: Key, H: BuildHasher> Clone for TestLimiter<K, H> {
+    fn clone(&self) -> Self {
+        TestLimiter {
+            clock: self.clock.clone(),
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+impl<K: Key, H: BuildHasher> fmt::Debug for TestLimiter<K, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TestLimiter").field("elapsed", &self.clock.elapsed()).finish_non_exhaustive()
+    }
+}
+
+impl<K, H> TestLimiter<K, H>
+where
+    K: Key + FromRequestParts<()>,
+    H: BuildHasher + Default + Clone + Send + Sync + 'static,
+{
+    /// Create a new test rate limiter with the given default quota.
+    #[must_use]
+    pub fn new(quota: Quota) -> Self {
+        Self::from_builder(RateLimitLayerBuilder::new().with_default_quota(quota))
+    }
+
+    /// Create a new test rate limiter from an existing builder, such as the one used by the app,
+    /// replacing its [clock](RateLimitLayerBuilder::with_clock) with a [`ManualClock`].
+    #[must_use]
+    pub fn from_builder(builder: RateLimitLayerBuilder<K, H>) -> Self {
+        let clock = ManualClock::new();
+        let layer = builder.with_clock(clock.clone()).build();
+
+        TestLimiter { clock, layer }
+    }
+}
+
+impl<K: Key, H: BuildHasher> TestLimiter<K, H> {
+    /// Move the clock of the rate limiter forward by the given duration.
+    pub fn advance(&self, duration: Duration) {
+        self.clock.advance(duration);
+    }
+
+    /// Get the clock of the rate limiter.
+    #[must_use]
+    pub fn clock(&self) -> &ManualClock {
+        &self.clock
+    }
+
+    /// Get the current time of the rate limiter.
+    #[must_use]
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// Get the rate limiter layer, which shares its state with all other clones.
+    #[must_use]
+    pub fn layer(&self) -> RateLimitLayer<K, H> {
+        self.layer.clone()
+    }
+}
+
+static X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// Create a request builder with a forged `x-forwarded-for` header for the given client IP address.
+///
+/// # Example
+///
+/// ```rust
+/// # use axum_gcra::axum;
+/// use axum::body::Body;
+/// use axum_gcra::testing::request_from;
+///
+/// let req = request_from([10, 0, 0, 1]).method("POST").uri("/login").body(Body::empty()).unwrap();
+///
+/// assert_eq!(req.headers()["x-forwarded-for"], "10.0.0.1");
+/// ```
+#[must_use]
+pub fn request_from(ip: impl Into<IpAddr>) -> http::request::Builder {
+    Request::builder().header(X_FORWARDED_FOR.clone(), ip.into().to_string())
+}
+
+/// Create an empty request with the given method and URI from the given client IP address, see [`request_from`].
+///
+/// # Panics
+///
+/// Panics if the URI is invalid.
+#[must_use]
+pub fn request(method: Method, uri: &str, ip: impl Into<IpAddr>) -> Request<Body> {
+    request_from(ip).method(method).uri(uri).body(Body::empty()).expect("invalid request URI")
+}
+
+/// Create an empty `GET` request to the given URI from the given client IP address, see [`request`].
+///
+/// # Panics
+///
+/// Panics if the URI is invalid.
+#[must_use]
+pub fn get_from(uri: &str, ip: impl Into<IpAddr>) -> Request<Body> {
+    request(Method::GET, uri, ip)
+}
@@ -0,0 +1,155 @@
+//! Typed versions of the rate limit headers emitted by the rate limiter, enabled with the `axum-extra` cargo feature.
+//!
+//! These implement [`headers::Header`], so they can be used with `axum_extra::TypedHeader` or
+//! [`HeaderMapExt`](headers::HeaderMapExt) to parse and construct the headers of rate-limited responses
+//! in handlers, clients and tests, instead of matching strings.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use axum_gcra::axum;
+//! use std::time::Duration;
+//! use axum::response::IntoResponse;
+//! use headers::HeaderMapExt;
+//! use axum_gcra::typed_headers::{RateLimitRemaining, RetryAfter};
+//! # use axum_gcra::gcra::{Quota, RateLimiter};
+//! # let limiter = RateLimiter::<()>::default();
+//! # let quota = Quota::simple(Duration::from_secs(10));
+//! # let now = std::time::Instant::now();
+//! # limiter.req_sync((), quota, now).unwrap();
+//! # let error = limiter.req_sync((), quota, now).unwrap_err();
+//!
+//! let res = error.into_response();
+//!
+//! assert_eq!(res.headers().typed_get::<RetryAfter>(), Some(RetryAfter(Duration::from_secs(10))));
+//! assert_eq!(res.headers().typed_get::<RateLimitRemaining>(), Some(RateLimitRemaining(0)));
+//! ```
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use headers::{Error, Header};
+use http::{HeaderName, HeaderValue};
+
+use crate::RateLimitError;
+
+static RETRY_AFTER: HeaderName = http::header::RETRY_AFTER;
+static RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+static X_RATELIMIT_RESET: HeaderName = HeaderName::from_static("x-ratelimit-reset");
+static RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+
+fn decode_one<'i, I: Iterator<Item = &'i HeaderValue>>(values: &mut I) -> Result<&'i str, Error> {
+    let value = values.next().ok_or_else(Error::invalid)?;
+    value.to_str().map_err(|_| Error::invalid())
+}
+
+fn decode_u64<'i, I: Iterator<Item = &'i HeaderValue>>(values: &mut I) -> Result<u64, Error> {
+    decode_one(values)?.trim().parse().map_err(|_| Error::invalid())
+}
+
+fn encode_u64<E: Extend<HeaderValue>>(value: u64, values: &mut E) {
+    values.extend(std::iter::once(HeaderValue::from(value)));
+}
+
+/// Whole seconds of a duration, but at least one, as emitted by the rate limiter.
+fn whole_secs(duration: Duration) -> u64 {
+    duration.as_secs().max(1)
+}
+
+/// `retry-after` header with the time until the next request can be made.
+///
+/// Decodes both delay seconds and [HTTP-dates](crate::RateLimitLayerBuilder::with_retry_after_http_date),
+/// the latter relative to the current time, and encodes as whole seconds, but at least one second,
+/// the same as the rate limiter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RetryAfter(pub Duration);
+
+impl Header for RetryAfter {
+    fn name() -> &'static HeaderName {
+        &RETRY_AFTER
+    }
+
+    fn decode<'i, I: Iterator<Item = &'i HeaderValue>>(values: &mut I) -> Result<Self, Error> {
+        let value = decode_one(values)?.trim();
+
+        if let Ok(secs) = value.parse() {
+            return Ok(RetryAfter(Duration::from_secs(secs)));
+        }
+
+        let at = httpdate::parse_http_date(value).map_err(|_| Error::invalid())?;
+
+        let now = UNIX_EPOCH + Duration::from_nanos(crate::store::now());
+
+        Ok(RetryAfter(at.duration_since(now).unwrap_or_default()))
+    }
+
+    fn encode<E: Extend<HeaderValue>>(&self, values: &mut E) {
+        encode_u64(whole_secs(self.0), values);
+    }
+}
+
+impl From<RateLimitError> for RetryAfter {
+    fn from(error: RateLimitError) -> Self {
+        RetryAfter(error.as_duration())
+    }
+}
+
+/// `ratelimit-reset` header with the number of seconds until the quota resets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RateLimitReset(pub u64);
+
+impl Header for RateLimitReset {
+    fn name() -> &'static HeaderName {
+        &RATELIMIT_RESET
+    }
+
+    fn decode<'i, I: Iterator<Item = &'i HeaderValue>>(values: &mut I) -> Result<Self, Error> {
+        decode_u64(values).map(RateLimitReset)
+    }
+
+    fn encode<E: Extend<HeaderValue>>(&self, values: &mut E) {
+        encode_u64(self.0, values);
+    }
+}
+
+impl From<RateLimitError> for RateLimitReset {
+    fn from(error: RateLimitError) -> Self {
+        RateLimitReset(whole_secs(error.as_duration()))
+    }
+}
+
+/// Legacy `x-ratelimit-reset` header, the same as [`RateLimitReset`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct XRateLimitReset(pub u64);
+
+impl Header for XRateLimitReset {
+    fn name() -> &'static HeaderName {
+        &X_RATELIMIT_RESET
+    }
+
+    fn decode<'i, I: Iterator<Item = &'i HeaderValue>>(values: &mut I) -> Result<Self, Error> {
+        decode_u64(values).map(XRateLimitReset)
+    }
+
+    fn encode<E: Extend<HeaderValue>>(&self, values: &mut E) {
+        encode_u64(self.0, values);
+    }
+}
+
+/// `ratelimit-remaining` header with the number of requests remaining in the quota,
+/// which is always zero for rate-limited responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RateLimitRemaining(pub u64);
+
+impl Header for RateLimitRemaining {
+    fn name() -> &'static HeaderName {
+        &RATELIMIT_REMAINING
+    }
+
+    fn decode<'i, I: Iterator<Item = &'i HeaderValue>>(values: &mut I) -> Result<Self, Error> {
+        decode_u64(values).map(RateLimitRemaining)
+    }
+
+    fn encode<E: Extend<HeaderValue>>(&self, values: &mut E) {
+        encode_u64(self.0, values);
+    }
+}
@@ -0,0 +1,190 @@
+//! Decaying per-key counters of rate limit violations.
+//!
+//! See [`RateLimitLayerBuilder::with_violation_counters`](crate::RateLimitLayerBuilder::with_violation_counters).
+
+use std::{
+    fmt,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use scc::HashMap;
+
+use crate::{
+    clock::{Clock, Instant, Timeline},
+    gcra::{stable_hash, GCStats},
+    Key, RandomState,
+};
+
+/// Number of violations between cleanups of decayed entries.
+const CLEAN_EVERY: u64 = 1024;
+
+/// Counts below this are considered fully decayed and removed on cleanup.
+const NEGLIGIBLE: f64 = 0.01;
+
+/// Table of decaying violation counts per key, shared by all clones of a [`RateLimitLayer`](crate::RateLimitLayer).
+///
+/// Every request rejected by the rate limiter adds one to the count of its key, across all routes,
+/// and counts halve every [half-life](Violations::half_life). A count close to zero means the key
+/// has not been rejected recently, while a count well above one marks a chronic offender.
+///
+/// Requests rejected by [bans](crate::RateLimitLayerBuilder::ban_after) or the
+/// [deny list](crate::RateLimitLayerBuilder::with_deny_list) are not counted.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use axum_gcra::{RateLimitLayer, real_ip::RealIp};
+///
+/// # fn example(ip: RealIp) {
+/// let layer = RateLimitLayer::<RealIp>::builder()
+///     .with_violation_counters(Duration::from_secs(10 * 60))
+///     .build();
+///
+/// let violations = layer.violations().unwrap();
+///
+/// if violations.count(&ip) > 50.0 {
+///     println!("{ip:?} is a chronic abuser");
+/// }
+/// # }
+/// ```
+pub struct Violations<K> {
+    inner: Arc<ViolationsInner>,
+    _marker: PhantomData<fn(&K)>,
+}
+
+struct ViolationsInner {
+    time: Timeline,
+    half_life: u64,
+    entries: HashMap<u64, ViolationEntry, RandomState>,
+
+    /// Number of violations recorded, used to schedule cleanup.
+    recorded: AtomicU64,
+}
+
+struct ViolationEntry {
+    count: f64,
+    last: u64,
+}
+
+impl ViolationEntry {
+    #[inline]
+    fn decayed(&self, now: u64, half_life: u64) -> f64 {
+        let elapsed = now.saturating_sub(self.last) as f64 / half_life as f64;
+
+        self.count * (-elapsed).exp2()
+    }
+}
+
+impl<K> Clone for Violations<K> {
+    fn clone(&self) -> Self {
+        Violations {
+            inner: self.inner.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<K> fmt::Debug for Violations<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Violations").field("half_life", &self.half_life()).finish_non_exhaustive()
+    }
+}
+
+impl<K> Violations<K> {
+    /// Get the duration after which violation counts are halved.
+    #[inline]
+    #[must_use]
+    pub fn half_life(&self) -> Duration {
+        Duration::from_nanos(self.inner.half_life)
+    }
+}
+
+impl<K: Key> Violations<K> {
+    /// Create a new violation table whose counts halve every `half_life`.
+    #[must_use]
+    pub fn new(half_life: Duration) -> Self {
+        Violations {
+            inner: Arc::new(ViolationsInner {
+                time: Timeline::new(),
+                half_life: (half_life.as_nanos() as u64).max(1),
+                entries: HashMap::default(),
+                recorded: AtomicU64::new(0),
+            }),
+            _marker: PhantomData,
+        }
+    }
+
+    #[inline]
+    fn relative(&self, ts: Instant) -> u64 {
+        self.inner.time.relative(ts)
+    }
+
+    /// Set the clock of the layer this violation table is attached to, if not already used.
+    pub(crate) fn set_clock(&self, clock: Arc<dyn Clock>) {
+        self.inner.time.set_clock(clock);
+    }
+
+    /// Get the current decayed violation count of the given key.
+    #[must_use]
+    pub fn count(&self, key: &K) -> f64 {
+        self.count_at(key, self.inner.time.now())
+    }
+
+    /// Forget all violations of the given key. Returns `true` if the key was found.
+    pub fn reset(&self, key: &K) -> bool {
+        self.inner.entries.remove(&stable_hash(key)).is_some()
+    }
+
+    /// Remove the entries of keys whose counts have decayed to almost zero.
+    pub fn clean(&self) -> GCStats {
+        let now = self.relative(self.inner.time.now());
+        let mut stats = GCStats::default();
+
+        self.inner.entries.retain(|_, entry| {
+            let keep = entry.decayed(now, self.inner.half_life) >= NEGLIGIBLE;
+            stats.scanned += 1;
+            stats.evicted += !keep as usize;
+            keep
+        });
+
+        stats
+    }
+
+    /// Get the decayed violation count of the given key at the given time.
+    pub(crate) fn count_at(&self, key: &K, now: Instant) -> f64 {
+        let now = self.relative(now);
+
+        let count =
+            self.inner.entries.read(&stable_hash(key), |_, entry| entry.decayed(now, self.inner.half_life));
+
+        count.unwrap_or(0.0)
+    }
+
+    /// Record a violation for the given key at the given time, returning the new decayed count.
+    pub(crate) fn record(&self, key: &K, now: Instant) -> f64 {
+        let now = self.relative(now);
+        let half_life = self.inner.half_life;
+
+        if self.inner.recorded.fetch_add(1, Ordering::Relaxed) % CLEAN_EVERY == CLEAN_EVERY - 1 {
+            self.inner.entries.retain(|_, entry| entry.decayed(now, half_life) >= NEGLIGIBLE);
+        }
+
+        let mut occupied = self
+            .inner
+            .entries
+            .entry(stable_hash(key))
+            .or_insert_with(|| ViolationEntry { count: 0.0, last: now });
+
+        let entry = occupied.get_mut();
+
+        entry.count = entry.decayed(now, half_life) + 1.0;
+        entry.last = now.max(entry.last);
+        entry.count
+    }
+}
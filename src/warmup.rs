@@ -0,0 +1,102 @@
+//! Grace period for the first requests of new keys.
+//!
+//! See [`RateLimitLayerBuilder::with_warmup`](crate::RateLimitLayerBuilder::with_warmup).
+
+use std::{
+    fmt,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use scc::{hash_map::Entry, HashMap};
+
+use crate::{
+    clock::{Clock, Instant, Timeline},
+    gcra::stable_hash,
+    RandomState,
+};
+
+/// Number of new keys between cleanups of idle entries.
+const CLEAN_EVERY: u64 = 1024;
+
+/// Table of when keys were first seen and how many requests they made since, by stable key hash.
+pub(crate) struct Warmup {
+    time: Timeline,
+    requests: u64,
+    period: u64,
+    entries: HashMap<u64, WarmupEntry, RandomState>,
+
+    /// Number of new keys seen, used to schedule cleanup.
+    inserted: AtomicU64,
+}
+
+struct WarmupEntry {
+    first: u64,
+    last: u64,
+    count: u64,
+}
+
+impl fmt::Debug for Warmup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Warmup")
+            .field("requests", &self.requests)
+            .field("period", &Duration::from_nanos(self.period))
+            .finish_non_exhaustive()
+    }
+}
+
+impl Warmup {
+    pub fn new(requests: u64, period: Duration) -> Self {
+        Warmup {
+            time: Timeline::new(),
+            requests,
+            period: period.as_nanos() as u64,
+            entries: HashMap::default(),
+            inserted: AtomicU64::new(0),
+        }
+    }
+
+    /// Set the clock of the layer, if not already used.
+    pub fn set_clock(&self, clock: Arc<dyn Clock>) {
+        self.time.set_clock(clock);
+    }
+
+    /// Count a request of the given key, returning `true` if it is still within its grace period.
+    pub fn record<K: Hash>(&self, key: &K, now: Instant) -> bool {
+        let now = self.time.relative(now);
+
+        let (in_grace, inserted) = match self.entries.entry(stable_hash(key)) {
+            Entry::Occupied(mut entry) => {
+                let entry = entry.get_mut();
+                entry.last = entry.last.max(now);
+
+                let in_grace = now.saturating_sub(entry.first) <= self.period && entry.count < self.requests;
+                entry.count += in_grace as u64;
+
+                (in_grace, false)
+            }
+            Entry::Vacant(entry) => {
+                let count = (self.requests > 0) as u64;
+                entry.insert_entry(WarmupEntry {
+                    first: now,
+                    last: now,
+                    count,
+                });
+
+                (count > 0, true)
+            }
+        };
+
+        if inserted && self.inserted.fetch_add(1, Ordering::Relaxed) % CLEAN_EVERY == CLEAN_EVERY - 1 {
+            // forget keys that have been idle for a whole period, which start over as new keys,
+            // but keep active keys so that they cannot get another grace period
+            self.entries.retain(|_, entry| now.saturating_sub(entry.last) <= self.period);
+        }
+
+        in_grace
+    }
+}
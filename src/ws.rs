@@ -0,0 +1,122 @@
+//! Message-rate limiting for long-lived connections, such as WebSockets.
+//!
+//! Once a connection is upgraded, HTTP-level rate limiting no longer applies to the messages sent over it.
+//! A [`MessageLimiter`] applies the same [`Quota`] math to individual messages of a single connection,
+//! and can be derived from the [route quota](crate::extensions::RateLimiter::message_limiter) of the
+//! upgrade request.
+
+use std::time::Duration;
+
+use crate::{
+    clock::Instant,
+    gcra::{self, Quota, RateLimitError, Status},
+};
+
+/// Per-connection rate limiter for inbound messages, such as within `axum::extract::ws` loops.
+///
+/// Each limiter has its own state, so every connection gets the full quota regardless of the others,
+/// and no locking is involved. To share a budget between all connections of a key, use the
+/// [`RateLimiter`](crate::extensions::RateLimiter) extension directly instead.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use axum_gcra::axum;
+/// use axum::{extract::ws::{Message, WebSocketUpgrade}, routing::get, Extension, Router};
+/// use axum_gcra::{extensions::RateLimiter, real_ip::RealIp, RateLimitLayer};
+///
+/// let app = Router::<()>::new()
+///     .route("/ws", get(|ws: WebSocketUpgrade, Extension(rl): Extension<RateLimiter<RealIp>>| async move {
+///         // messages share the quota of the upgrade route
+///         let mut limiter = rl.message_limiter();
+///
+///         ws.on_upgrade(move |mut socket| async move {
+///             while let Some(Ok(msg)) = socket.recv().await {
+///                 if limiter.check().is_err() {
+///                     let _ = socket.send(Message::Close(None)).await;
+///                     break;
+///                 }
+///
+///                 // handle msg...
+///             }
+///         })
+///     }))
+///     .route_layer(RateLimitLayer::<RealIp>::builder().with_extension(true).default_handle_error());
+/// ```
+#[derive(Debug, Clone)]
+pub struct MessageLimiter {
+    quota: Quota,
+    start: Instant,
+    tat: Option<u64>,
+}
+
+impl MessageLimiter {
+    /// Create a new message limiter with the given quota and a full budget.
+    #[must_use]
+    pub fn new(quota: Quota) -> Self {
+        MessageLimiter {
+            quota,
+            start: Instant::now(),
+            tat: None,
+        }
+    }
+
+    /// Get the quota applied to messages.
+    #[inline]
+    #[must_use]
+    pub fn quota(&self) -> Quota {
+        self.quota
+    }
+
+    #[inline]
+    fn relative(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.start).as_nanos() as u64
+    }
+
+    /// Count one message against the quota, returning an error if it exceeds it.
+    ///
+    /// Rejected messages are not counted.
+    #[inline]
+    pub fn check(&mut self) -> Result<(), RateLimitError> {
+        self.check_n(1)
+    }
+
+    /// Count a message costing `n` messages against the quota, such as by its size,
+    /// returning an error if it exceeds it. Rejected messages are not counted.
+    pub fn check_n(&mut self, n: u64) -> Result<(), RateLimitError> {
+        let now = self.relative(Instant::now());
+
+        self.tat = Some(gcra::decide(self.tat, now, self.quota, n)?);
+
+        Ok(())
+    }
+
+    /// Wait until one message is allowed, then count it against the quota,
+    /// such as to slow down a client instead of disconnecting it.
+    #[cfg(feature = "tokio")]
+    pub async fn throttle(&mut self) {
+        while let Err(e) = self.check() {
+            tokio::time::sleep(e.as_duration()).await;
+        }
+    }
+
+    /// Get the current [`Status`] of the quota, without counting anything.
+    #[must_use]
+    pub fn status(&self) -> Status {
+        Status::from_nanos(self.tat, self.relative(Instant::now()), self.quota)
+    }
+
+    /// Get the amount of time until another message would be allowed, which is zero if allowed now.
+    #[must_use]
+    pub fn retry_after(&self) -> Duration {
+        match gcra::decide(self.tat, self.relative(Instant::now()), self.quota, 1) {
+            Ok(_) => Duration::ZERO,
+            Err(e) => e.as_duration(),
+        }
+    }
+
+    /// Reset the limiter to a full budget.
+    pub fn reset(&mut self) {
+        self.tat = None;
+    }
+}
@@ -0,0 +1,75 @@
+//! Bans of keys whose stable hashes collide, see [`axum_gcra::ban::Bans`].
+
+use std::{
+    hash::{Hash, Hasher},
+    time::Duration,
+};
+
+use axum_gcra::{
+    axum::{body::Body, routing::get, Router},
+    ban::{BanPolicy, Bans},
+    gcra::Quota,
+    PartsKey, RateLimitLayer,
+};
+use http::{Request, StatusCode};
+use tower::ServiceExt;
+
+/// Key that only hashes its first byte, so that keys starting with the same byte always collide,
+/// as an attacker could otherwise arrange with a brute-force search.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Client(String);
+
+impl Hash for Client {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.as_bytes().first().hash(state);
+    }
+}
+
+fn client(name: &str) -> Client {
+    Client(name.to_owned())
+}
+
+#[test]
+fn ban_does_not_apply_to_colliding_key() {
+    let bans = Bans::new(BanPolicy {
+        violations: 1,
+        within: Duration::from_secs(60),
+        ban_for: Duration::from_secs(3600),
+    });
+
+    bans.ban(&client("attacker"), Duration::from_secs(3600));
+
+    assert!(bans.is_banned(&client("attacker")));
+    assert!(!bans.is_banned(&client("alice")));
+
+    // and the colliding key can't lift the ban either
+    assert!(!bans.unban(&client("alice")));
+    assert!(bans.is_banned(&client("attacker")));
+}
+
+#[tokio::test]
+async fn violations_do_not_ban_colliding_key() {
+    let layer = RateLimitLayer::<PartsKey<Client>>::builder()
+        .with_default_quota(Quota::simple(Duration::from_secs(3600)))
+        .with_key_fn(|parts| Some(PartsKey(client(parts.headers.get("x-client")?.to_str().ok()?))))
+        .ban_after(2, Duration::from_secs(60), Duration::from_secs(3600))
+        .default_handle_error();
+
+    let app = Router::new().route("/", get(|| async {})).route_layer(layer);
+
+    let status = |name: &'static str| {
+        let req = Request::get("/").header("x-client", name).body(Body::empty()).unwrap();
+        let app = app.clone();
+        async move { app.oneshot(req).await.unwrap().status() }
+    };
+
+    // the attacker uses up its quota and gets banned
+    assert_eq!(status("attacker").await, StatusCode::OK);
+
+    for _ in 0..3 {
+        assert_ne!(status("attacker").await, StatusCode::OK);
+    }
+
+    // while the victim with the same hash is unaffected
+    assert_eq!(status("alice").await, StatusCode::OK);
+}
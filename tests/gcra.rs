@@ -0,0 +1,123 @@
+//! Properties of the core GCRA decision, see [`axum_gcra::gcra::decide`].
+
+use std::{
+    num::NonZeroU64,
+    time::{Duration, Instant},
+};
+
+use axum_gcra::gcra::{decide, GcraPolicy, Quota};
+use proptest::prelude::*;
+
+/// Emission interval in nanoseconds, burst size and the initial state of the entry, if any,
+/// which may be far behind or ahead of the first request.
+fn quota_and_state() -> impl Strategy<Value = (u64, u64, Option<u64>)> {
+    (1..1_000u64, 1..16u64)
+        .prop_flat_map(|(t, burst)| (Just(t), Just(burst), proptest::option::of(0..START + t * (burst + 2))))
+}
+
+/// Time of the first request, so that the initial state can lie before it.
+const START: u64 = 1_000_000;
+
+fn quota(t: u64, burst: u64) -> Quota {
+    Quota::new(Duration::from_nanos(t), NonZeroU64::new(burst).unwrap())
+}
+
+/// Emission interval and an explicit tolerance in nanoseconds, which may be below the emission interval.
+fn policy() -> impl Strategy<Value = GcraPolicy> {
+    (1..1_000u64)
+        .prop_flat_map(|t| (Just(t), 0..t * 16))
+        .prop_map(|(t, tau)| GcraPolicy::new(Duration::from_nanos(t), Duration::from_nanos(tau)))
+}
+
+proptest! {
+    /// The state only ever moves forward, and only when a request is allowed.
+    #[test]
+    fn tat_is_monotonic(
+        (t, burst, mut state) in quota_and_state(),
+        requests in proptest::collection::vec((0..2_000u64, 1..4u64), 1..64),
+    ) {
+        let quota = quota(t, burst);
+        let mut now = START;
+
+        for (dt, n) in requests {
+            now += dt;
+
+            if let Ok(tat) = decide(state, now, quota, n) {
+                prop_assert!(tat > now);
+                prop_assert!(state.is_none_or(|prev| tat > prev));
+                state = Some(tat);
+            }
+        }
+    }
+
+    /// No more than `burst` requests are allowed at once, and no more than one more per emission interval,
+    /// regardless of the initial state.
+    #[test]
+    fn admissions_never_exceed_burst(
+        (t, burst, mut state) in quota_and_state(),
+        gaps in proptest::collection::vec(0..3_000u64, 1..128),
+    ) {
+        let quota = quota(t, burst);
+        let mut now = START;
+        let mut admitted = Vec::new();
+
+        for dt in gaps {
+            now += dt;
+
+            if let Ok(tat) = decide(state, now, quota, 1) {
+                admitted.push(now);
+                state = Some(tat);
+            }
+        }
+
+        for (i, &first) in admitted.iter().enumerate() {
+            for (j, &last) in admitted.iter().enumerate().skip(i) {
+                prop_assert!((j - i + 1) as u64 <= burst + (last - first) / t);
+            }
+        }
+    }
+
+    /// A rejected request is allowed once the returned wait has passed.
+    #[test]
+    fn retry_after_wait_is_allowed(
+        (t, burst, state) in quota_and_state(),
+        dt in 0..2_000u64,
+        n in 1..4u64,
+    ) {
+        let quota = quota(t, burst);
+        let now = START + dt;
+
+        if let Err(error) = decide(state, now, quota, n.min(burst)) {
+            let wait = error.as_duration().as_nanos() as u64;
+
+            prop_assert!(decide(state, now + wait - 1, quota, n.min(burst)).is_err());
+            prop_assert!(decide(state, now + wait, quota, n.min(burst)).is_ok());
+        }
+    }
+
+    /// Any explicit tolerance allows a fresh key exactly its burst at once, with a burst of at least one,
+    /// and the policy agrees with the quota built from it once that burst is used up.
+    #[test]
+    fn policy_allows_its_burst(policy in policy()) {
+        let quota = Quota::from(policy);
+        let burst = policy.burst();
+        prop_assert!(burst >= 1);
+
+        let mut state = None;
+
+        for _ in 0..burst {
+            let tat = decide(state, START, quota, 1);
+            prop_assert!(tat.is_ok());
+            state = tat.ok();
+        }
+
+        let error = decide(state, START, quota, 1);
+        prop_assert!(error.is_err());
+
+        let base = Instant::now();
+        let (tat, now) = (base + Duration::from_nanos(state.unwrap()), base + Duration::from_nanos(START));
+
+        prop_assert_eq!(policy.remaining(tat, now), 0);
+        prop_assert_eq!(policy.retry_after(tat, now, 1), error.unwrap_err().as_duration());
+    }
+}
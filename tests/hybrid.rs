@@ -0,0 +1,92 @@
+//! Convergence of [`HybridStore`] instances sharing a remote store.
+
+#![cfg(feature = "tokio")]
+
+use std::{num::NonZeroU64, sync::Mutex, time::Duration};
+
+use axum_gcra::{
+    gcra::{self, GCStats, Quota},
+    store::{self, HybridStore, Store, StoreKey, StoreResult},
+    RateLimitError, Route,
+};
+use futures_util::future::BoxFuture;
+
+/// Remote store for the unit key on a single route.
+#[derive(Default)]
+struct Remote(Mutex<Option<u64>>);
+
+impl Store<()> for &'static Remote {
+    fn get_update<'a>(
+        &'a self,
+        _: StoreKey<'a, ()>,
+        quota: Quota,
+        n: u64,
+        now: u64,
+    ) -> BoxFuture<'a, StoreResult<Result<u64, RateLimitError>>> {
+        let mut tat = self.0.lock().unwrap();
+        let res = gcra::decide(*tat, now, quota, n);
+
+        if let Ok(new) = res {
+            *tat = Some(new);
+        }
+
+        Box::pin(async move { Ok(res) })
+    }
+
+    fn remove<'a>(&'a self, _: StoreKey<'a, ()>) -> BoxFuture<'a, StoreResult<bool>> {
+        let found = self.0.lock().unwrap().take().is_some();
+        Box::pin(async move { Ok(found) })
+    }
+
+    fn gc(&self, _: u64) -> BoxFuture<'_, StoreResult<GCStats>> {
+        Box::pin(async { Ok(GCStats::default()) })
+    }
+
+    fn peek<'a>(&'a self, _: StoreKey<'a, ()>) -> BoxFuture<'a, StoreResult<Option<u64>>> {
+        let tat = *self.0.lock().unwrap();
+        Box::pin(async move { Ok(tat) })
+    }
+
+    fn adjust<'a>(&'a self, _: StoreKey<'a, ()>, delta: i64) -> BoxFuture<'a, StoreResult<bool>> {
+        let mut tat = self.0.lock().unwrap();
+        let found = tat.as_mut().map(|tat| *tat = tat.saturating_add_signed(delta)).is_some();
+        Box::pin(async move { Ok(found) })
+    }
+}
+
+/// Consumption of every instance is recorded remotely, even once the fleet has exceeded the quota,
+/// so that all instances converge on the shared limit.
+#[tokio::test]
+async fn instances_converge_when_exhausted() {
+    let remote: &'static Remote = Box::leak(Box::default());
+    let quota = Quota::new(Duration::from_secs(1), NonZeroU64::new(5).unwrap());
+    let route = Route::get("/");
+    let key = || StoreKey {
+        route: route.clone(),
+        key: &(),
+    };
+
+    let stores = [(); 3].map(|_| HybridStore::new(remote, Duration::from_secs(3600)));
+    let now = store::now();
+
+    // each instance admits a full burst on its own before syncing
+    for store in &stores {
+        for _ in 0..5 {
+            assert!(store.get_update(key(), quota, 1, now).await.unwrap().is_ok());
+        }
+    }
+
+    for store in &stores {
+        store.sync().await.unwrap();
+    }
+
+    // all 15 requests are recorded, not just the 5 the remote quota would have allowed,
+    // as of some time between the requests and now since the sync uses the current time
+    let tat = remote.0.lock().unwrap().unwrap();
+    assert!((gcra::charge(None, now, quota, 15)..=gcra::charge(None, store::now(), quota, 15)).contains(&tat));
+
+    // the last instance to sync has caught up with the whole fleet, so it admits nothing until
+    // the overshoot has passed, even though its own requests would have allowed more by then
+    let later = now + 9 * quota.emission_interval().as_nanos() as u64;
+    assert!(stores[2].get_update(key(), quota, 1, later).await.unwrap().is_err());
+}
@@ -0,0 +1,93 @@
+//! Model of the lock-free update loop of [`Gcra`](axum_gcra::gcra::Gcra), checked for all interleavings
+//! with [`loom`].
+//!
+//! Run with `cargo rustc --release --test loom -- --cfg loom` and then the printed test binary. Setting
+//! `--cfg loom` in `RUSTFLAGS` instead would also apply it to tokio, which then drops its networking APIs.
+
+#![cfg(loom)]
+
+use std::{num::NonZeroU64, time::Duration};
+
+use axum_gcra::gcra::{decide, Quota, RateLimitError};
+use loom::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+/// Emission interval of the modelled quota, in nanoseconds.
+const T: u64 = 100;
+
+/// Same loop as `Gcra::req_n_tat`, on a loom atomic.
+fn req(tat: &AtomicU64, quota: Quota, now: u64) -> Result<u64, RateLimitError> {
+    let mut prev = tat.load(Ordering::Acquire);
+
+    loop {
+        let next = decide(Some(prev), now, quota, 1)?;
+
+        match tat.compare_exchange_weak(prev, next, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => return Ok(next),
+            Err(next_prev) => prev = next_prev,
+        }
+    }
+}
+
+/// Concurrent requests at the same time are admitted exactly up to the burst size, each
+/// with its own slot, and the final state accounts for every admitted request.
+#[test]
+fn concurrent_requests_respect_burst() {
+    loom::model(|| {
+        let quota = Quota::new(Duration::from_nanos(T), NonZeroU64::new(2).unwrap());
+        let now = 1_000;
+
+        // an entry that has caught up, which is equivalent to a fresh one
+        let tat = Arc::new(AtomicU64::new(0));
+
+        let threads: Vec<_> = (0..3)
+            .map(|_| {
+                let tat = tat.clone();
+                thread::spawn(move || req(&tat, quota, now))
+            })
+            .collect();
+
+        let mut admitted: Vec<u64> = threads.into_iter().filter_map(|t| t.join().unwrap().ok()).collect();
+        admitted.sort_unstable();
+
+        assert_eq!(admitted, [now + 2 * T, now + 3 * T]);
+        assert_eq!(tat.load(Ordering::Acquire), now + 3 * T);
+    });
+}
+
+/// A request racing with a refund is either rejected before the refund or admitted after it,
+/// and the refund is never overwritten by the request.
+#[test]
+fn refund_is_not_lost() {
+    loom::model(|| {
+        let quota = Quota::new(Duration::from_nanos(T), NonZeroU64::MIN);
+        let now = 1_000;
+
+        // one request was just admitted, so the next one must wait
+        let tat = Arc::new(AtomicU64::new(now + 2 * T));
+
+        let refund = {
+            let tat = tat.clone();
+
+            // same as `Gcra::refund`
+            thread::spawn(move || {
+                _ = tat.fetch_update(Ordering::Release, Ordering::Relaxed, |prev| {
+                    Some(prev.saturating_sub(T))
+                });
+            })
+        };
+
+        let res = req(&tat, quota, now);
+        refund.join().unwrap();
+
+        match res {
+            Ok(next) => assert_eq!((next, tat.load(Ordering::Acquire)), (now + 2 * T, now + 2 * T)),
+            Err(_) => assert_eq!(tat.load(Ordering::Acquire), now + T),
+        }
+    });
+}